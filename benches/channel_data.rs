@@ -0,0 +1,105 @@
+//! Measures the throughput of large `ChannelData` transfers from and to
+//! a scripted server, for each cipher; the server runs in the same
+//! process, so its own encryption is part of the figures.
+//!
+//! Run with `cargo bench --bench channel_data`.
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use std::io::Read;
+use std::time::Instant;
+use coolssh::RunResult;
+use common::*;
+
+const TOTAL: usize = 64 << 20;
+const CHUNK: usize = 0x8000;
+
+fn download(cipher: &'static str, mac: &'static str) {
+    let start = Instant::now();
+    let received = scripted(|stream| {
+        let conn = connect(stream);
+        // the scripted server doesn't wait for window adjusts
+        conn.set_channel_window(u32::MAX, CHUNK as u32).unwrap();
+        let RunResult::Accepted(mut run) = conn.run("cat big-file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut buf = vec![0; CHUNK];
+        let mut received = 0;
+        loop {
+            match run.read(&mut buf).unwrap() {
+                0 => break received,
+                len => received += len,
+            }
+        }
+    }, |mut server| {
+        server.cipher = cipher;
+        server.mac = mac;
+        server.initial_window = u32::MAX;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        let chunk = vec![0x5a; CHUNK];
+        for _ in 0..TOTAL / CHUNK {
+            server.send_data(channel, &chunk);
+        }
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+    });
+
+    assert_eq!(received, TOTAL);
+    let elapsed = start.elapsed();
+    let throughput = TOTAL as f64 / (1 << 20) as f64 / elapsed.as_secs_f64();
+    println!("download: {:<32} {:>8.1} MiB/s", cipher, throughput);
+}
+
+fn upload(cipher: &'static str, mac: &'static str) {
+    let start = Instant::now();
+    scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("cat > big-file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // split into packets by the client
+        let data = vec![0x5a; 1 << 20];
+        for _ in 0..TOTAL / data.len() {
+            run.write_stdin(&data).unwrap();
+        }
+    }, |mut server| {
+        server.cipher = cipher;
+        server.mac = mac;
+        server.initial_window = u32::MAX;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        let mut received = 0;
+        while received < TOTAL {
+            received += server.recv_data().len();
+        }
+
+        assert_eq!(received, TOTAL);
+    });
+
+    let elapsed = start.elapsed();
+    let throughput = TOTAL as f64 / (1 << 20) as f64 / elapsed.as_secs_f64();
+    println!("upload:   {:<32} {:>8.1} MiB/s", cipher, throughput);
+}
+
+const CIPHERS: [&str; 3] = ["aes256-ctr", "aes256-gcm@openssh.com", "chacha20-poly1305@openssh.com"];
+
+fn main() {
+    for cipher in CIPHERS {
+        download(cipher, "hmac-sha2-256");
+    }
+
+    for cipher in CIPHERS {
+        upload(cipher, "hmac-sha2-256");
+    }
+}
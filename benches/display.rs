@@ -0,0 +1,47 @@
+//! Compares the String-returning helpers with the `Display` adapters.
+//!
+//! Run with `cargo bench --bench display`.
+
+use std::fmt::Write as _;
+use std::hint::black_box;
+use std::time::Instant;
+use coolssh::{create_ed25519_keypair, dump_ed25519_pk_openssh, PublicKey};
+
+const ITERATIONS: u32 = 100_000;
+
+fn bench<F: FnMut()>(name: &str, mut func: F) {
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        func();
+    }
+    let elapsed = start.elapsed();
+    println!("{:<32} {:>8.1} ns/iter", name, elapsed.as_nanos() as f64 / ITERATIONS as f64);
+}
+
+fn main() {
+    let hex_keypair = create_ed25519_keypair();
+    let key = PublicKey::from_ed25519_hex_keypair(&hex_keypair).unwrap();
+
+    // reused across iterations, as a logging layer would
+    let mut sink = String::with_capacity(256);
+
+    bench("openssh: String helper", || {
+        black_box(dump_ed25519_pk_openssh(&hex_keypair, "bench"));
+    });
+
+    bench("openssh: Display adapter", || {
+        sink.clear();
+        write!(sink, "{}", key.openssh_display("bench")).unwrap();
+        black_box(&sink);
+    });
+
+    bench("fingerprint: to_string()", || {
+        black_box(key.fingerprint_display().to_string());
+    });
+
+    bench("fingerprint: Display adapter", || {
+        sink.clear();
+        write!(sink, "{}", key.fingerprint_display()).unwrap();
+        black_box(&sink);
+    });
+}
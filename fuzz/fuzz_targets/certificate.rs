@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use coolssh::fuzzing::parse_certificate;
+
+fuzz_target!(|blob: &[u8]| {
+    let _ = parse_certificate(blob);
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use coolssh::fuzzing::depad;
+
+fuzz_target!(|packet: &[u8]| {
+    if let Ok(payload) = depad(packet) {
+        assert!(payload.end <= packet.len());
+    }
+});
@@ -0,0 +1,21 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use coolssh::fuzzing::verify_host_signature;
+
+const ALGORITHMS: [&str; 5] = [
+    "ssh-ed25519",
+    "ssh-ed25519-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp256",
+    "rsa-sha2-256",
+    "rsa-sha2-512",
+];
+
+// first byte: algorithm, second byte: where the host key ends
+fuzz_target!(|input: &[u8]| {
+    if let [algorithm, split, rest @ ..] = input {
+        let algorithm = ALGORITHMS[*algorithm as usize % ALGORITHMS.len()];
+        let (host_key, signature) = rest.split_at((*split as usize).min(rest.len()));
+        let _ = verify_host_signature(algorithm, host_key, signature, b"exchange hash");
+    }
+});
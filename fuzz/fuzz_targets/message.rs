@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use coolssh::fuzzing::parse_message;
+
+fuzz_target!(|payload: &[u8]| {
+    if let Ok(message) = parse_message(payload) {
+        // dumping may fail (e.g. for unknown channel requests), but
+        // what is dumped must parse again as the same kind of message
+        if let Ok(owned) = message.to_owned_message() {
+            assert_eq!(owned.typ(), message.typ());
+        }
+    }
+});
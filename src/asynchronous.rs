@@ -0,0 +1,377 @@
+use std::sync::{Mutex as SyncMutex, PoisonError};
+use tokio::io::{
+    AsyncRead, AsyncWrite, AsyncReadExt, AsyncBufReadExt, AsyncWriteExt,
+    BufReader, BufWriter, ReadHalf, WriteHalf, split,
+};
+use tokio::sync::{Mutex, MutexGuard};
+use super::{Result, Error};
+use super::connection::{ConnectionInner, Auth, ConnectOptions};
+use super::keygen::{PublicKey, fingerprint_sha256};
+use super::messages::{NegotiatedAlgorithms, ChannelClose, ChannelRequest, DisconnectReasonCode};
+use super::run::{
+    Channel, RunResult, RunOptions, RunEvent, ExitStatus, WaitOutput, QuickRunOutput,
+    bytes_output, string_output, blind_output, checked_output,
+};
+use super::stream::{Source, Sink};
+use super::observer::{TransferHook, TransferProgress, Transcript};
+use super::command::RemoteCommand;
+
+impl<R: AsyncRead + Unpin> Source for BufReader<R> {
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        AsyncReadExt::read_exact(self, buf).await?;
+        Ok(())
+    }
+
+    async fn read_line(&mut self, line: &mut Vec<u8>, max: usize) -> Result<usize> {
+        Ok(AsyncReadExt::take(self, max as u64).read_until(b'\n', line).await?)
+    }
+}
+
+impl<W: AsyncWrite + Unpin> Sink for BufWriter<W> {
+    async fn write(&mut self, buf: &[u8]) -> Result<()> {
+        Ok(AsyncWriteExt::write_all(self, buf).await?)
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Ok(AsyncWriteExt::flush(self).await?)
+    }
+}
+
+type Inner<S> = ConnectionInner<BufReader<ReadHalf<S>>, BufWriter<WriteHalf<S>>>;
+
+/// Same as [`Connection`](crate::Connection), over any tokio stream
+///
+/// Futures returned by this type and by [`AsyncRun`] must be polled to
+/// completion: dropping one in the middle of a packet (e.g. with
+/// `tokio::time::timeout`) leaves the connection unusable.
+pub struct AsyncConnection<S> {
+    inner: Mutex<Inner<S>>,
+    /// channels of the dropped [`AsyncRun`]s, with the server
+    /// channel number if a ChannelClose must be sent
+    dropped: SyncMutex<Vec<(u32, Option<u32>)>>,
+}
+
+impl<S: AsyncRead + AsyncWrite> AsyncConnection<S> {
+    pub async fn new(stream: S, auth: Auth<'_>) -> Result<Self> {
+        Self::new_with_options(stream, auth, ConnectOptions::default()).await
+    }
+
+    pub async fn new_with_options(stream: S, auth: Auth<'_>, options: ConnectOptions) -> Result<Self> {
+        let (reader, writer) = split(stream);
+        let inner = ConnectionInner::establish(BufReader::new(reader), BufWriter::new(writer), auth, options).await?;
+
+        Ok(Self {
+            inner: Mutex::new(inner),
+            dropped: SyncMutex::new(Vec::new()),
+        })
+    }
+
+    /// Locks the state shared by the channels, then
+    /// closes the channels of dropped [`AsyncRun`]s
+    async fn inner(&self) -> Result<MutexGuard<'_, Inner<S>>> {
+        let mut inner = self.inner.lock().await;
+        let dropped = core::mem::take(&mut *self.dropped.lock().unwrap_or_else(PoisonError::into_inner));
+
+        for (client_channel, server_channel) in dropped {
+            inner.forget_channel(client_channel);
+            if let Some(recipient_channel) = server_channel {
+                inner.writer.send(&ChannelClose {
+                    recipient_channel,
+                }).await?;
+            }
+        }
+
+        Ok(inner)
+    }
+
+    /// The algorithms which were agreed upon with the server
+    pub async fn algorithms(&self) -> NegotiatedAlgorithms<'static> {
+        self.inner.lock().await.algorithms()
+    }
+
+    /// Text which the server sent to be displayed before authentication, if any
+    pub async fn banner(&self) -> Option<String> {
+        self.inner.lock().await.banner()
+    }
+
+    /// Version header of the server, without line terminator
+    pub async fn server_version(&self) -> String {
+        self.inner.lock().await.server_version()
+    }
+
+    /// Lines which the server sent before its version
+    pub async fn pre_version_lines(&self) -> Vec<String> {
+        self.inner.lock().await.pre_version_lines()
+    }
+
+    /// Signature algorithms which the server accepts for public
+    /// key authentication, if it listed them (RFC 8308)
+    pub async fn server_sig_algs(&self) -> Option<String> {
+        self.inner.lock().await.server_sig_algs()
+    }
+
+    /// Exchange hash of the first key exchange, which identifies the connection
+    pub async fn session_id(&self) -> Vec<u8> {
+        self.inner.lock().await.session_id()
+    }
+
+    /// Public host key of the server, as presented during the last key exchange
+    pub async fn host_key(&self) -> PublicKey {
+        self.inner.lock().await.host_key()
+    }
+
+    /// See [`Connection::host_key_fingerprint`]
+    pub async fn host_key_fingerprint(&self) -> String {
+        fingerprint_sha256(self.host_key().await.blob())
+    }
+
+    /// Notifies the server that the connection is being closed, then closes it;
+    /// unlike [`Connection`](crate::Connection), dropping doesn't do this.
+    pub async fn disconnect(self, reason_code: DisconnectReasonCode, description: &str) -> Result<()> {
+        self.disconnect_localized(reason_code, description, "").await
+    }
+
+    /// See [`Connection::disconnect_localized`](crate::Connection::disconnect_localized)
+    pub async fn disconnect_localized(self, reason_code: DisconnectReasonCode, description: &str, language_tag: &str) -> Result<()> {
+        self.inner.lock().await.disconnect(reason_code, description, language_tag).await
+    }
+
+    /// See [`Connection::send_ignore`](crate::Connection::send_ignore)
+    pub async fn send_ignore(&self, len: usize) -> Result<()> {
+        self.inner.lock().await.send_ignore(len).await
+    }
+
+    /// See [`Connection::send_debug`](crate::Connection::send_debug)
+    pub async fn send_debug(&self, message: &str) -> Result<()> {
+        self.inner.lock().await.send_debug(message).await
+    }
+
+    /// See [`Connection::set_channel_window`](crate::Connection::set_channel_window)
+    pub async fn set_channel_window(&self, initial_window: u32, max_packet_size: u32) -> Result<()> {
+        self.inner.lock().await.set_channel_window(initial_window, max_packet_size)
+    }
+
+    /// See [`Connection::set_transcript`](crate::Connection::set_transcript)
+    pub async fn set_transcript<T: std::io::Write + Send + 'static>(&self, sink: T) {
+        self.inner.lock().await.transcript = Transcript::new(sink);
+    }
+
+    /// Opens a session channel and executes `command` in it
+    pub async fn run(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<AsyncRun<'_, S>>> {
+        self.run_with_options(command, env, RunOptions::default()).await
+    }
+
+    /// See [`Connection::run_with_options`](crate::Connection::run_with_options)
+    pub async fn run_with_options(&self, command: &str, env: &[(&str, &str)], options: RunOptions) -> Result<RunResult<AsyncRun<'_, S>>> {
+        let prelude = |recipient_channel| env.iter().map(|(name, value)| ChannelRequest::EnvironmentVariable {
+            recipient_channel,
+            want_reply: true,
+            name,
+            value,
+        }).collect();
+
+        self.start_session(options, prelude, |recipient_channel| ChannelRequest::Exec {
+            recipient_channel,
+            want_reply: true,
+            command,
+        }).await
+    }
+
+    /// See [`Connection::run_command`](crate::Connection::run_command)
+    pub async fn run_command(&self, command: &RemoteCommand) -> Result<RunResult<AsyncRun<'_, S>>> {
+        let env: Vec<_> = command.get_env().collect();
+        self.run(&command.to_string(), &env).await
+    }
+
+    /// Opens a session channel and starts the `name` subsystem (e.g. `sftp`) in it
+    pub async fn subsystem(&self, name: &str) -> Result<RunResult<AsyncRun<'_, S>>> {
+        self.start_session(RunOptions::default(), |_| Vec::new(), |recipient_channel| ChannelRequest::Subsystem {
+            recipient_channel,
+            want_reply: true,
+            subsystem_name: name,
+        }).await
+    }
+
+    async fn start_session<'b, P, F>(&self, options: RunOptions, prelude: P, start: F) -> Result<RunResult<AsyncRun<'_, S>>>
+        where P: FnOnce(u32) -> Vec<ChannelRequest<'b>>,
+              F: FnOnce(u32) -> ChannelRequest<'b>
+    {
+        Ok(match Channel::open(&mut *self.inner().await?, options, prelude, start).await? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(channel) => RunResult::Accepted(AsyncRun {
+                conn: self,
+                channel,
+            }),
+        })
+    }
+
+    async fn quick_run_internal(&self, command: &str, env: &[(&str, &str)], get_output: bool) -> Result<RunResult<QuickRunOutput>> {
+        match self.run(command, env).await? {
+            RunResult::Refused => Ok(RunResult::Refused),
+            RunResult::Accepted(mut run) => {
+                let mut output = match get_output {
+                    true => Some(Vec::new()),
+                    false => None,
+                };
+
+                loop {
+                    match run.poll().await? {
+                        RunEvent::Data(data) | RunEvent::ExtDataStderr(data) => {
+                            if let Some(o) = output.as_mut() { o.extend_from_slice(data) }
+                        },
+                        RunEvent::Stopped(exit_status) => return Ok(RunResult::Accepted((output, exit_status))),
+                        _ => (),
+                    }
+                }
+            },
+        }
+    }
+
+    /// Runs `command` to completion and returns its output (stdout and stderr)
+    pub async fn quick_run_bytes(&self, command: &str) -> Result<RunResult<(Vec<u8>, ExitStatus)>> {
+        self.quick_run_bytes_with_env(command, &[]).await
+    }
+
+    /// Same as [`Self::quick_run_bytes`], for UTF-8 output
+    pub async fn quick_run(&self, command: &str) -> Result<RunResult<(String, ExitStatus)>> {
+        self.quick_run_with_env(command, &[]).await
+    }
+
+    /// Runs `command` to completion, discarding its output
+    pub async fn quick_run_blind(&self, command: &str) -> Result<RunResult<ExitStatus>> {
+        self.quick_run_blind_with_env(command, &[]).await
+    }
+
+    /// Same as [`Self::quick_run_bytes`], setting the environment variables in `env` first
+    pub async fn quick_run_bytes_with_env(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<(Vec<u8>, ExitStatus)>> {
+        Ok(bytes_output(self.quick_run_internal(command, env, true).await?))
+    }
+
+    /// Same as [`Self::quick_run`], setting the environment variables in `env` first
+    pub async fn quick_run_with_env(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<(String, ExitStatus)>> {
+        string_output(self.quick_run_internal(command, env, true).await?)
+    }
+
+    /// Same as [`Self::quick_run_blind`], setting the environment variables in `env` first
+    pub async fn quick_run_blind_with_env(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<ExitStatus>> {
+        Ok(blind_output(self.quick_run_internal(command, env, false).await?))
+    }
+
+    /// See [`Connection::quick_run_checked`](crate::Connection::quick_run_checked)
+    pub async fn quick_run_checked(&self, command: &str) -> Result<RunResult<String>> {
+        let output = match self.run(command, &[]).await? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(mut run) => {
+                let mut output = WaitOutput::default();
+                loop {
+                    match run.poll().await? {
+                        RunEvent::Data(data) => output.stdout.extend_from_slice(data),
+                        RunEvent::ExtDataStderr(data) => output.stderr.extend_from_slice(data),
+                        RunEvent::Stopped(exit_status) => {
+                            output.exit_status = Some(exit_status);
+                            break RunResult::Accepted(output);
+                        },
+                        _ => (),
+                    }
+                }
+            },
+        };
+
+        checked_output(output)
+    }
+}
+
+/// Same as [`Run`](crate::Run), for an [`AsyncConnection`]
+pub struct AsyncRun<'a, S> {
+    conn: &'a AsyncConnection<S>,
+    channel: Channel,
+}
+
+impl<'a, S: AsyncRead + AsyncWrite> AsyncRun<'a, S> {
+    /// See [`Run::refused_env`](crate::Run::refused_env)
+    pub fn refused_env(&self) -> &[String] {
+        self.channel.refused_env()
+    }
+
+    /// See [`Run::set_transfer_progress`](crate::Run::set_transfer_progress)
+    pub fn set_transfer_progress<F: FnMut(TransferProgress) + Send + 'static>(&mut self, callback: F) {
+        self.channel.transfer_progress = TransferHook::new(callback);
+    }
+
+    /// Returns the next event
+    pub async fn poll(&mut self) -> Result<RunEvent<'_>> {
+        self.channel.poll(&mut *self.conn.inner().await?).await
+    }
+
+    /// Tries to send `data` over the run channel and calls `event_callback`
+    /// if an event occurs during the transmission.
+    ///
+    /// Use this if the protocol you're using is full-duplex.
+    pub async fn write_poll<WPE: From<Error>, F: FnMut(RunEvent) -> core::result::Result<(), WPE>>(
+        &mut self,
+        data: &[u8],
+        event_callback: F,
+    ) -> core::result::Result<(), WPE> {
+        self.channel.write_poll(&mut *self.conn.inner().await?, data, event_callback).await
+    }
+
+    /// Sends `data` to the standard input of the remote process; output
+    /// received in the meantime is returned by the next calls to [`Self::poll`].
+    pub async fn write_stdin(&mut self, data: &[u8]) -> Result<()> {
+        self.channel.write_stdin(&mut *self.conn.inner().await?, data).await
+    }
+
+    /// See [`Run::close_write`](crate::Run::close_write)
+    pub async fn close_write(&mut self) -> Result<()> {
+        self.channel.close_write(&mut *self.conn.inner().await?).await
+    }
+
+    /// Closes the standard input of the remote process
+    #[deprecated(note = "renamed to `AsyncRun::close_write`")]
+    pub async fn eof(&mut self) -> Result<()> {
+        self.close_write().await
+    }
+
+    /// See [`Run::signal`](crate::Run::signal)
+    pub async fn signal(&mut self, name: &str) -> Result<()> {
+        self.channel.send_request(&mut *self.conn.inner().await?, |recipient_channel| ChannelRequest::Signal {
+            recipient_channel,
+            signal_name: name,
+        }).await
+    }
+
+    /// Tries to send `data` over the run channel and returns the `on_event` error
+    /// if an event occurs during the transmission.
+    ///
+    /// Use this if the protocol you're using is half-duplex.
+    pub async fn write<WPE: From<Error>>(&mut self, data: &[u8], on_event: WPE) -> core::result::Result<(), WPE> {
+        let mut on_event = Some(on_event);
+        self.write_poll(data, |data| {
+            log::error!("Unexpected RunEvent in AsyncRun::write(): {:?}", data);
+            Err(on_event.take().unwrap())
+        }).await
+    }
+}
+
+// the channel is closed by the next operation on the connection
+impl<'a, S> Drop for AsyncRun<'a, S> {
+    fn drop(&mut self) {
+        let mut dropped = self.conn.dropped.lock().unwrap_or_else(PoisonError::into_inner);
+        dropped.push((self.channel.client_channel(), self.channel.closing()));
+    }
+}
+
+impl<S> core::fmt::Debug for AsyncConnection<S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncConnection").finish()
+    }
+}
+
+impl<'a, S> core::fmt::Debug for AsyncRun<'a, S> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("AsyncRun")
+            .field("conn", self.conn)
+            .field("channel", &self.channel)
+            .finish()
+    }
+}
@@ -0,0 +1,163 @@
+//! OpenSSH certificates (`PROTOCOL.certkeys`): keys signed
+//! by a certificate authority, for a limited time and set of names
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::{ErrorKind, Result, Error};
+use super::parsedump::ParseDump;
+use super::hostkey::verify_host_signature;
+use super::keygen::PublicKey;
+
+pub const ED25519_CERT: &str = "ssh-ed25519-cert-v01@openssh.com";
+
+pub const USER_CERT: u32 = 1;
+pub const HOST_CERT: u32 = 2;
+
+/// Fields of an `ssh-ed25519-cert-v01@openssh.com` blob
+pub struct Certificate<'a> {
+    /// the certified ed25519 key
+    pub public_key: &'a [u8],
+    pub cert_type: u32,
+    pub key_id: &'a str,
+    /// packed strings; empty if the certificate is valid for any name
+    principals: &'a [u8],
+    /// seconds since the UNIX epoch
+    pub valid_after: u64,
+    pub valid_before: u64,
+    pub critical_options: &'a [u8],
+    /// blob of the authority's public key
+    pub signature_key: &'a [u8],
+    /// signature blob of the authority
+    signature: &'a [u8],
+    /// what `signature` covers
+    signed: &'a [u8],
+}
+
+impl<'a> Certificate<'a> {
+    pub fn parse(blob: &'a [u8]) -> Result<Self> {
+        let (algorithm, mut i) = <&str>::parse(blob)?;
+        if algorithm != ED25519_CERT {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("unsupported certificate type: {}", algorithm)));
+        }
+
+        let (_nonce, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let (public_key, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let (_serial, inc) = u64::parse(&blob[i..])?;
+        i += inc;
+        let (cert_type, inc) = u32::parse(&blob[i..])?;
+        i += inc;
+        let (key_id, inc) = <&str>::parse(&blob[i..])?;
+        i += inc;
+        let (principals, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let (valid_after, inc) = u64::parse(&blob[i..])?;
+        i += inc;
+        let (valid_before, inc) = u64::parse(&blob[i..])?;
+        i += inc;
+        let (critical_options, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let (_extensions, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let (_reserved, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let (signature_key, inc) = <&[u8]>::parse(&blob[i..])?;
+        i += inc;
+        let signed = &blob[..i];
+        let (signature, _) = <&[u8]>::parse(&blob[i..])?;
+
+        if public_key.len() != 32 {
+            return Err(Error::new(ErrorKind::InvalidData).context("invalid certificate (wrong key length)"));
+        }
+
+        Ok(Self {
+            public_key,
+            cert_type,
+            key_id,
+            principals,
+            valid_after,
+            valid_before,
+            critical_options,
+            signature_key,
+            signature,
+            signed,
+        })
+    }
+
+    /// Blob of the certified key, as if it wasn't certified
+    pub fn key_blob(&self) -> Result<Vec<u8>> {
+        let mut blob = Vec::new();
+        "ssh-ed25519".dump(&mut blob)?;
+        self.public_key.dump(&mut blob)?;
+        Ok(blob)
+    }
+
+    /// Whether the certificate is valid for `name`
+    pub fn has_principal(&self, name: &str) -> Result<bool> {
+        let mut principals = self.principals;
+        if principals.is_empty() {
+            return Ok(true);
+        }
+
+        while !principals.is_empty() {
+            let (principal, inc) = <&str>::parse(principals)?;
+            if principal == name {
+                return Ok(true);
+            }
+
+            principals = &principals[inc..];
+        }
+
+        Ok(false)
+    }
+
+    /// Checks the signature of the authority, then the validity period
+    pub fn verify(&self) -> Result<()> {
+        let (signature_type, _) = <&str>::parse(self.signature)?;
+        verify_host_signature(signature_type, self.signature_key, self.signature, self.signed)?;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+        match (self.valid_after..self.valid_before).contains(&now) {
+            true => Ok(()),
+            false => Err(Error::new(ErrorKind::InvalidData).context(format!("certificate {:?} isn't valid at this time", self.key_id))),
+        }
+    }
+}
+
+/// See [`ConnectOptions::host_cert_authorities`](crate::ConnectOptions::host_cert_authorities)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostCertAuthorities {
+    pub hostname: String,
+    pub authorities: Vec<PublicKey>,
+}
+
+impl HostCertAuthorities {
+    /// Checks that `host_key` is a host certificate for `self.hostname`,
+    /// signed by one of `self.authorities`
+    pub fn check(&self, host_key: &[u8]) -> Result<()> {
+        let (algorithm, _) = <&str>::parse(host_key)?;
+        if algorithm != ED25519_CERT {
+            return Err(Error::new(ErrorKind::UntrustedHostKey).context(format!("the server presented an uncertified {} host key", algorithm)));
+        }
+
+        let certificate = Certificate::parse(host_key)?;
+        if certificate.cert_type != HOST_CERT {
+            return Err(Error::new(ErrorKind::UntrustedHostKey).context("the server presented a user certificate"));
+        }
+
+        if !self.authorities.iter().any(|authority| authority.blob() == certificate.signature_key) {
+            return Err(Error::new(ErrorKind::UntrustedHostKey).context("the host certificate is signed by an unknown authority"));
+        }
+
+        // none are defined for host certificates
+        if !certificate.critical_options.is_empty() {
+            return Err(Error::new(ErrorKind::UntrustedHostKey).context("unknown critical options in the host certificate"));
+        }
+
+        if !certificate.has_principal(&self.hostname)? {
+            return Err(Error::new(ErrorKind::UntrustedHostKey).context(format!("the host certificate isn't valid for {}", self.hostname)));
+        }
+
+        certificate.verify().map_err(|_| Error::new(ErrorKind::UntrustedHostKey).context("invalid certificate signature"))
+    }
+}
@@ -0,0 +1,95 @@
+use chacha20::ChaCha20Legacy;
+use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+use poly1305::Poly1305;
+use poly1305::universal_hash::KeyInit;
+use super::{ErrorKind, Result, Error, U32};
+use super::transport::{Transport, tags_match};
+
+pub const CHACHA20_POLY1305: &str = "chacha20-poly1305@openssh.com";
+
+const TAG_SIZE: usize = 16;
+
+/// chacha20-poly1305@openssh.com (OpenSSH's PROTOCOL.chacha20poly1305)
+///
+/// The packet length is encrypted with its own key, and
+/// the sequence number is used as the nonce.
+pub struct ChaCha20Poly1305 {
+    main_key: [u8; 32],
+    header_key: [u8; 32],
+}
+
+impl ChaCha20Poly1305 {
+    /// `key` must be 64 bytes long
+    pub fn new(key: &[u8]) -> Self {
+        let mut main_key = [0; 32];
+        let mut header_key = [0; 32];
+        main_key.copy_from_slice(&key[..32]);
+        header_key.copy_from_slice(&key[32..]);
+
+        Self {
+            main_key,
+            header_key,
+        }
+    }
+
+    fn cipher(key: &[u8; 32], packet_number: u32) -> ChaCha20Legacy {
+        let nonce = (packet_number as u64).to_be_bytes();
+        ChaCha20Legacy::new(key.into(), &nonce.into())
+    }
+
+    /// Computes the poly1305 tag of `data`; the first block of the main
+    /// keystream is the poly1305 key, the payload uses the next ones.
+    fn tag(&self, packet_number: u32, data: &[u8]) -> [u8; TAG_SIZE] {
+        let mut poly_key = [0; 32];
+        Self::cipher(&self.main_key, packet_number).apply_keystream(&mut poly_key);
+        Poly1305::new(&poly_key.into()).compute_unpadded(data).into()
+    }
+}
+
+impl Transport for ChaCha20Poly1305 {
+    fn block_size(&self) -> usize {
+        8
+    }
+
+    fn mac_size(&self) -> usize {
+        TAG_SIZE
+    }
+
+    fn aligns_length(&self) -> bool {
+        false
+    }
+
+    /// The tag covers the encrypted length, so it's decrypted on a copy
+    fn packet_length(&mut self, packet_number: u32, length: &mut [u8; U32]) -> u32 {
+        let mut length = *length;
+        Self::cipher(&self.header_key, packet_number).apply_keystream(&mut length);
+        u32::from_be_bytes(length)
+    }
+
+    fn open(&mut self, packet_number: u32, packet: &mut [u8], mac: &[u8]) -> Result<()> {
+        if !tags_match(mac, &self.tag(packet_number, packet)) {
+            return Err(Error::new(ErrorKind::MacMismatch).context("poly1305"));
+        }
+
+        let (length, payload) = packet.split_at_mut(U32);
+        Self::cipher(&self.header_key, packet_number).apply_keystream(length);
+
+        let mut cipher = Self::cipher(&self.main_key, packet_number);
+        cipher.seek(64);
+        cipher.apply_keystream(payload);
+
+        Ok(())
+    }
+
+    fn seal(&mut self, packet_number: u32, packet: &mut Vec<u8>) {
+        let (length, payload) = packet.split_at_mut(U32);
+        Self::cipher(&self.header_key, packet_number).apply_keystream(length);
+
+        let mut cipher = Self::cipher(&self.main_key, packet_number);
+        cipher.seek(64);
+        cipher.apply_keystream(payload);
+
+        let tag = self.tag(packet_number, packet);
+        packet.extend_from_slice(&tag);
+    }
+}
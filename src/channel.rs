@@ -0,0 +1,94 @@
+//! Session channels driven by the caller
+
+use std::time::Duration;
+use super::{Connection, Result, TcpStream};
+use super::stream::Stream;
+use super::run::{Run, RunResult, RunEvent, ExitStatus};
+
+impl<S: Stream> Connection<S> {
+    /// Opens a session channel without starting anything in it,
+    /// for protocols which this crate doesn't implement
+    pub fn open_session(&self) -> Result<RunResult<Channel<'_, S>>> {
+        Ok(match self.start_bare_session()? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(run) => RunResult::Accepted(Channel { run }),
+        })
+    }
+}
+
+/// A session channel, opened by [`Connection::open_session`], whose
+/// requests are sent by the caller (RFC 4254, section 6):
+///
+/// ```no_run
+/// use coolssh::{Connection, RunResult};
+///
+/// let conn = Connection::connect("example.com:22", ("user", "<hex keypair>").into(), Default::default()).unwrap();
+/// let RunResult::Accepted(mut channel) = conn.open_session().unwrap() else {
+///     panic!("the server refused the channel");
+/// };
+///
+/// // an `exec` request, by hand: the command is an SSH string
+/// let command = b"uptime";
+/// let mut data = (command.len() as u32).to_be_bytes().to_vec();
+/// data.extend_from_slice(command);
+///
+/// if channel.send_request("exec", true, &data).unwrap() {
+///     let mut output = Vec::new();
+///     std::io::copy(&mut channel.into_run(), &mut output).unwrap();
+/// }
+/// ```
+///
+/// The channel is closed when dropped.
+#[derive(Debug)]
+pub struct Channel<'a, S: Stream = TcpStream> {
+    run: Run<'a, S>,
+}
+
+impl<'a, S: Stream> Channel<'a, S> {
+    /// Sends a `request_type` request, whose request-specific fields
+    /// are `data`, already encoded; returns whether the server accepted
+    /// it, or true if `want_reply` is false.
+    ///
+    /// Data received while waiting for the reply is kept for [`Self::poll`].
+    pub fn send_request(&mut self, request_type: &str, want_reply: bool, data: &[u8]) -> Result<bool> {
+        self.run.request(request_type, want_reply, data)
+    }
+
+    /// Sends `data`, waiting for the server to adjust its window if needed
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.run.write_stdin(data)
+    }
+
+    /// Tells the server that no more data will be sent
+    pub fn eof(&mut self) -> Result<()> {
+        self.run.close_write()
+    }
+
+    /// See [`Run::poll`]
+    pub fn poll(&mut self) -> Result<RunEvent<'_>> {
+        self.run.poll()
+    }
+
+    /// See [`Run::wait_event`]
+    pub fn wait_event(&mut self, timeout: Option<Duration>) -> Result<RunEvent<'_>> {
+        self.run.wait_event(timeout)
+    }
+
+    /// Waits for the server to close the channel, discarding what it sends
+    pub fn wait_close(&mut self) -> Result<ExitStatus> {
+        if let Some(exit_status) = self.run.closed() {
+            return Ok(exit_status);
+        }
+
+        loop {
+            if let RunEvent::Stopped(exit_status) = self.run.wait_event(None)? {
+                break Ok(exit_status);
+            }
+        }
+    }
+
+    /// Uses the channel as a [`Run`], e.g. once a process was started
+    pub fn into_run(self) -> Run<'a, S> {
+        self.run
+    }
+}
@@ -1,8 +1,8 @@
-use super::{Result, Error, U8, Write};
-use super::parsedump::ParseDump;
-use super::messages::MessageType;
-use super::check_msg_type;
+use super::{ErrorKind, Result, Error, U8};
+use super::parsedump::{ParseDump, Buffer};
+use super::messages::{MessageType, check_msg_type};
 
+/// `SSH_MSG_CHANNEL_REQUEST` (RFC 4254, section 5.4), by request type
 #[derive(Copy, Clone, Debug)]
 pub enum ChannelRequest<'a> {
     Exec {
@@ -20,6 +20,59 @@ pub enum ChannelRequest<'a> {
         recipient_channel: u32,
         exit_status: u32,
     },
+    ExitSignal {
+        recipient_channel: u32,
+        /// without the `SIG` prefix (e.g. `KILL`)
+        signal_name: &'a str,
+        core_dumped: bool,
+        error_message: &'a str,
+        language_tag: &'a str,
+    },
+    Subsystem {
+        recipient_channel: u32,
+        want_reply: bool,
+        subsystem_name: &'a str,
+    },
+    PtyReq {
+        recipient_channel: u32,
+        want_reply: bool,
+        term: &'a str,
+        width_chars: u32,
+        height_rows: u32,
+        width_pixels: u32,
+        height_pixels: u32,
+        /// encoded terminal modes (RFC 4254, section 8)
+        modes: &'a [u8],
+    },
+    Shell {
+        recipient_channel: u32,
+        want_reply: bool,
+    },
+    WindowChange {
+        recipient_channel: u32,
+        width_chars: u32,
+        height_rows: u32,
+        width_pixels: u32,
+        height_pixels: u32,
+    },
+    X11Req {
+        recipient_channel: u32,
+        want_reply: bool,
+        single_connection: bool,
+        auth_protocol: &'a str,
+        /// hex-encoded
+        auth_cookie: &'a str,
+        screen: u32,
+    },
+    AuthAgentReq {
+        recipient_channel: u32,
+        want_reply: bool,
+    },
+    Signal {
+        recipient_channel: u32,
+        /// without the `SIG` prefix (e.g. `TERM`)
+        signal_name: &'a str,
+    },
     Other {
         recipient_channel: u32,
         request_type: &'a str,
@@ -27,6 +80,44 @@ pub enum ChannelRequest<'a> {
     },
 }
 
+impl<'a> ChannelRequest<'a> {
+    /// The name of the request, e.g. `exec`
+    pub fn request_type(&self) -> &'a str {
+        match self {
+            Self::Exec { .. } => "exec",
+            Self::EnvironmentVariable { .. } => "env",
+            Self::ExitStatus { .. } => "exit-status",
+            Self::ExitSignal { .. } => "exit-signal",
+            Self::Subsystem { .. } => "subsystem",
+            Self::PtyReq { .. } => "pty-req",
+            Self::Shell { .. } => "shell",
+            Self::WindowChange { .. } => "window-change",
+            Self::X11Req { .. } => "x11-req",
+            Self::AuthAgentReq { .. } => "auth-agent-req@openssh.com",
+            Self::Signal { .. } => "signal",
+            Self::Other { request_type, .. } => request_type,
+        }
+    }
+
+    /// Whether the server must answer with ChannelSuccess or ChannelFailure
+    pub fn want_reply(&self) -> bool {
+        match self {
+            Self::Exec { want_reply, .. }
+            | Self::EnvironmentVariable { want_reply, .. }
+            | Self::Subsystem { want_reply, .. }
+            | Self::PtyReq { want_reply, .. }
+            | Self::Shell { want_reply, .. }
+            | Self::X11Req { want_reply, .. }
+            | Self::AuthAgentReq { want_reply, .. }
+            | Self::Other { want_reply, .. } => *want_reply,
+            Self::ExitStatus { .. }
+            | Self::ExitSignal { .. }
+            | Self::WindowChange { .. }
+            | Self::Signal { .. } => false,
+        }
+    }
+}
+
 impl<'a, 'b: 'a> ParseDump<'b> for ChannelRequest<'a> {
     fn parse(bytes: &'b[u8]) -> Result<(Self, usize)> {
         check_msg_type!(ChannelRequest, MessageType::ChannelRequest, bytes);
@@ -64,10 +155,106 @@ impl<'a, 'b: 'a> ParseDump<'b> for ChannelRequest<'a> {
                     value,
                 }, i))
             },
+            "subsystem" => {
+                let (subsystem_name, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+
+                Ok((Self::Subsystem {
+                    recipient_channel,
+                    want_reply,
+                    subsystem_name,
+                }, i))
+            },
+            "pty-req" => {
+                let (term, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+                let (width_chars, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (height_rows, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (width_pixels, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (height_pixels, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (modes, inc) = <&'a [u8]>::parse(&bytes[i..])?;
+                i += inc;
+
+                Ok((Self::PtyReq {
+                    recipient_channel,
+                    want_reply,
+                    term,
+                    width_chars,
+                    height_rows,
+                    width_pixels,
+                    height_pixels,
+                    modes,
+                }, i))
+            },
+            "shell" => Ok((Self::Shell {
+                recipient_channel,
+                want_reply,
+            }, i)),
+            "window-change" => {
+                if want_reply {
+                    return Err(Error::new(ErrorKind::InvalidData).context("\"window-change\" Channel Request with want_reply=true"));
+                }
+
+                let (width_chars, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (height_rows, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (width_pixels, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+                let (height_pixels, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+
+                Ok((Self::WindowChange {
+                    recipient_channel,
+                    width_chars,
+                    height_rows,
+                    width_pixels,
+                    height_pixels,
+                }, i))
+            },
+            "x11-req" => {
+                let (single_connection, inc) = <bool>::parse(&bytes[i..])?;
+                i += inc;
+                let (auth_protocol, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+                let (auth_cookie, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+                let (screen, inc) = u32::parse(&bytes[i..])?;
+                i += inc;
+
+                Ok((Self::X11Req {
+                    recipient_channel,
+                    want_reply,
+                    single_connection,
+                    auth_protocol,
+                    auth_cookie,
+                    screen,
+                }, i))
+            },
+            "auth-agent-req@openssh.com" => Ok((Self::AuthAgentReq {
+                recipient_channel,
+                want_reply,
+            }, i)),
+            "signal" => {
+                if want_reply {
+                    return Err(Error::new(ErrorKind::InvalidData).context("\"signal\" Channel Request with want_reply=true"));
+                }
+
+                let (signal_name, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+
+                Ok((Self::Signal {
+                    recipient_channel,
+                    signal_name,
+                }, i))
+            },
             "exit-status" => {
                 if want_reply {
-                    log::error!("\"exit-status\" Channel Request with want_reply=true");
-                    return Err(Error::InvalidData);
+                    return Err(Error::new(ErrorKind::InvalidData).context("\"exit-status\" Channel Request with want_reply=true"));
                 }
 
                 let (exit_status, inc) = u32::parse(&bytes[i..])?;
@@ -78,6 +265,28 @@ impl<'a, 'b: 'a> ParseDump<'b> for ChannelRequest<'a> {
                     exit_status,
                 }, i))
             },
+            "exit-signal" => {
+                if want_reply {
+                    return Err(Error::new(ErrorKind::InvalidData).context("\"exit-signal\" Channel Request with want_reply=true"));
+                }
+
+                let (signal_name, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+                let (core_dumped, inc) = <bool>::parse(&bytes[i..])?;
+                i += inc;
+                let (error_message, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+                let (language_tag, inc) = <&'a str>::parse(&bytes[i..])?;
+                i += inc;
+
+                Ok((Self::ExitSignal {
+                    recipient_channel,
+                    signal_name,
+                    core_dumped,
+                    error_message,
+                    language_tag,
+                }, i))
+            },
             _ => Ok((Self::Other {
                 recipient_channel,
                 request_type,
@@ -86,7 +295,7 @@ impl<'a, 'b: 'a> ParseDump<'b> for ChannelRequest<'a> {
         }
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
         (MessageType::ChannelRequest as u8).dump(sink)?;
 
         match self {
@@ -109,6 +318,21 @@ impl<'a, 'b: 'a> ParseDump<'b> for ChannelRequest<'a> {
                 false.dump(sink)?;
                 exit_status.dump(sink)?;
             },
+            Self::ExitSignal {
+                recipient_channel,
+                signal_name,
+                core_dumped,
+                error_message,
+                language_tag,
+            } => {
+                recipient_channel.dump(sink)?;
+                "exit-signal".dump(sink)?;
+                false.dump(sink)?;
+                signal_name.dump(sink)?;
+                core_dumped.dump(sink)?;
+                error_message.dump(sink)?;
+                language_tag.dump(sink)?;
+            },
             Self::EnvironmentVariable {
                 recipient_channel,
                 want_reply,
@@ -121,12 +345,138 @@ impl<'a, 'b: 'a> ParseDump<'b> for ChannelRequest<'a> {
                 name.dump(sink)?;
                 value.dump(sink)?;
             },
+            Self::Subsystem {
+                recipient_channel,
+                want_reply,
+                subsystem_name,
+            } => {
+                recipient_channel.dump(sink)?;
+                "subsystem".dump(sink)?;
+                want_reply.dump(sink)?;
+                subsystem_name.dump(sink)?;
+            },
+            Self::PtyReq {
+                recipient_channel,
+                want_reply,
+                term,
+                width_chars,
+                height_rows,
+                width_pixels,
+                height_pixels,
+                modes,
+            } => {
+                recipient_channel.dump(sink)?;
+                "pty-req".dump(sink)?;
+                want_reply.dump(sink)?;
+                term.dump(sink)?;
+                width_chars.dump(sink)?;
+                height_rows.dump(sink)?;
+                width_pixels.dump(sink)?;
+                height_pixels.dump(sink)?;
+                modes.dump(sink)?;
+            },
+            Self::Shell {
+                recipient_channel,
+                want_reply,
+            } => {
+                recipient_channel.dump(sink)?;
+                "shell".dump(sink)?;
+                want_reply.dump(sink)?;
+            },
+            Self::WindowChange {
+                recipient_channel,
+                width_chars,
+                height_rows,
+                width_pixels,
+                height_pixels,
+            } => {
+                recipient_channel.dump(sink)?;
+                "window-change".dump(sink)?;
+                false.dump(sink)?;
+                width_chars.dump(sink)?;
+                height_rows.dump(sink)?;
+                width_pixels.dump(sink)?;
+                height_pixels.dump(sink)?;
+            },
+            Self::X11Req {
+                recipient_channel,
+                want_reply,
+                single_connection,
+                auth_protocol,
+                auth_cookie,
+                screen,
+            } => {
+                recipient_channel.dump(sink)?;
+                "x11-req".dump(sink)?;
+                want_reply.dump(sink)?;
+                single_connection.dump(sink)?;
+                auth_protocol.dump(sink)?;
+                auth_cookie.dump(sink)?;
+                screen.dump(sink)?;
+            },
+            Self::AuthAgentReq {
+                recipient_channel,
+                want_reply,
+            } => {
+                recipient_channel.dump(sink)?;
+                "auth-agent-req@openssh.com".dump(sink)?;
+                want_reply.dump(sink)?;
+            },
+            Self::Signal {
+                recipient_channel,
+                signal_name,
+            } => {
+                recipient_channel.dump(sink)?;
+                "signal".dump(sink)?;
+                false.dump(sink)?;
+                signal_name.dump(sink)?;
+            },
             Self::Other { .. } => {
-                log::error!("ChannelRequest::Other has no binary representation (coolssh programmer error)");
-                return Err(Error::InvalidData);
+                return Err(Error::new(ErrorKind::InvalidData).context("ChannelRequest::Other has no binary representation (coolssh programmer error)"));
             },
         }
 
         Ok(())
     }
-}
\ No newline at end of file
+}
+/// A channel request of any type, with its request-specific
+/// data already encoded; see [`Channel::send_request`](crate::Channel::send_request)
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct RawChannelRequest<'a> {
+    pub recipient_channel: u32,
+    pub request_type: &'a str,
+    pub want_reply: bool,
+    pub data: &'a [u8],
+}
+
+#[cfg(feature = "std")]
+impl<'a, 'b: 'a> ParseDump<'b> for RawChannelRequest<'a> {
+    fn parse(bytes: &'b[u8]) -> Result<(Self, usize)> {
+        check_msg_type!(ChannelRequest, MessageType::ChannelRequest, bytes);
+        let mut i = U8;
+
+        let (recipient_channel, inc) = u32::parse(&bytes[i..])?;
+        i += inc;
+        let (request_type, inc) = <&'a str>::parse(&bytes[i..])?;
+        i += inc;
+        let (want_reply, inc) = bool::parse(&bytes[i..])?;
+        i += inc;
+
+        Ok((Self {
+            recipient_channel,
+            request_type,
+            want_reply,
+            data: &bytes[i..],
+        }, bytes.len()))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        (MessageType::ChannelRequest as u8).dump(sink)?;
+        self.recipient_channel.dump(sink)?;
+        self.request_type.dump(sink)?;
+        self.want_reply.dump(sink)?;
+        sink.put(self.data);
+        Ok(())
+    }
+}
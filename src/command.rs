@@ -0,0 +1,92 @@
+//! Building command lines for the remote shell
+
+use std::borrow::Cow;
+use core::fmt;
+
+/// A program, its arguments and environment variables, for
+/// [`Connection::run_command`](crate::Connection::run_command):
+///
+/// ```no_run
+/// use coolssh::{Connection, RemoteCommand};
+///
+/// let conn = Connection::connect("example.com:22", ("user", "<hex keypair>").into(), Default::default()).unwrap();
+/// let command = RemoteCommand::new("ls").arg("-l").arg("My Documents").env("LANG", "C");
+/// assert_eq!(command.to_string(), "ls -l 'My Documents'");
+/// let run = conn.run_command(&command).unwrap();
+/// ```
+///
+/// The server hands the command line to the user's shell, so the
+/// program and its arguments are quoted with [`shell_quote`].
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RemoteCommand {
+    program: String,
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+}
+
+impl RemoteCommand {
+    pub fn new(program: &str) -> Self {
+        Self {
+            program: program.into(),
+            args: Vec::new(),
+            env: Vec::new(),
+        }
+    }
+
+    /// Appends an argument
+    pub fn arg(mut self, arg: &str) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    /// Appends several arguments
+    pub fn args<I: IntoIterator<Item = A>, A: AsRef<str>>(mut self, args: I) -> Self {
+        self.args.extend(args.into_iter().map(|arg| arg.as_ref().into()));
+        self
+    }
+
+    /// Sets an environment variable with an `env` request; servers
+    /// usually only accept those listed in their `AcceptEnv` option
+    pub fn env(mut self, name: &str, value: &str) -> Self {
+        self.env.push((name.into(), value.into()));
+        self
+    }
+
+    /// The environment variables, in the order they were set
+    pub fn get_env(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.env.iter().map(|(name, value)| (name.as_str(), value.as_str()))
+    }
+}
+
+/// The quoted command line
+impl fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", shell_quote(&self.program))?;
+        for arg in &self.args {
+            write!(f, " {}", shell_quote(arg))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Quotes `word` for a POSIX shell, so that it's passed as a single
+/// argument whatever it contains; words which don't need quoting
+/// are returned unchanged.
+///
+/// ```
+/// use coolssh::shell_quote;
+///
+/// assert_eq!(shell_quote("file.txt"), "file.txt");
+/// assert_eq!(shell_quote("it's; rm -rf ~"), r"'it'\''s; rm -rf ~'");
+/// ```
+pub fn shell_quote(word: &str) -> Cow<'_, str> {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "_-+=@%:,./".contains(c);
+    if !word.is_empty() && word.chars().all(safe) {
+        return Cow::Borrowed(word);
+    }
+
+    // single quotes can't be escaped inside single quotes:
+    // the quoted string is closed, then reopened
+    Cow::Owned(format!("'{}'", word.replace('\'', r"'\''")))
+}
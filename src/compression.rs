@@ -0,0 +1,73 @@
+use flate2::{Compress, Decompress, Compression, FlushCompress, FlushDecompress, Status};
+use super::{ErrorKind, Result, Error};
+
+pub const ZLIB: &str = "zlib";
+/// zlib, enabled once the user is authenticated
+pub const ZLIB_OPENSSH: &str = "zlib@openssh.com";
+
+/// Compression algorithms we advertise when compression is enabled
+pub const COMPRESSION_ALGORITHMS_ZLIB: &str = "zlib@openssh.com,zlib,none";
+
+/// Compresses payloads; each packet flushes the stream, which is
+/// shared by all packets of one direction (RFC 4253, section 6.2)
+pub struct Compressor(Compress);
+
+impl Compressor {
+    pub fn new() -> Self {
+        Self(Compress::new(Compression::default(), true))
+    }
+
+    pub fn compress(&mut self, payload: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        let start = self.0.total_in();
+
+        loop {
+            output.reserve(payload.len() / 2 + 64);
+            let consumed = (self.0.total_in() - start) as usize;
+
+            self.0.compress_vec(&payload[consumed..], output, FlushCompress::Partial).map_err(|e| Error::new(ErrorKind::InvalidData).context(format!("couldn't compress payload: {}", e)))?;
+
+            let consumed = (self.0.total_in() - start) as usize;
+            if consumed == payload.len() && output.len() < output.capacity() {
+                break Ok(());
+            }
+        }
+    }
+}
+
+pub struct Decompressor {
+    stream: Decompress,
+    /// upper bound on the size of a decompressed payload
+    max_payload: usize,
+}
+
+impl Decompressor {
+    /// `max_payload` is the maximum `packet_length`: the payloads which
+    /// we invite (see `channel_packet_length`) fit in it uncompressed
+    pub fn new(max_payload: usize) -> Self {
+        Self {
+            stream: Decompress::new(true),
+            max_payload,
+        }
+    }
+
+    pub fn decompress(&mut self, payload: &[u8], output: &mut Vec<u8>) -> Result<()> {
+        let start = self.stream.total_in();
+
+        loop {
+            output.reserve(payload.len() * 2 + 64);
+            let consumed = (self.stream.total_in() - start) as usize;
+
+            let status = self.stream.decompress_vec(&payload[consumed..], output, FlushDecompress::Sync).map_err(|e| Error::new(ErrorKind::InvalidData).context(format!("couldn't decompress payload: {}", e)))?;
+
+            if output.len() > self.max_payload {
+                break Err(Error::new(ErrorKind::InvalidData).context("decompressed payload is too big"));
+            }
+
+            let consumed = (self.stream.total_in() - start) as usize;
+            let done = consumed == payload.len() && output.len() < output.capacity();
+            if done || status == Status::StreamEnd {
+                break Ok(());
+            }
+        }
+    }
+}
@@ -1,18 +1,95 @@
-use super::{
-    Cipher, Hmac, VERSION_HEADER, Keypair, Rng, ed25519_blob_len, Error,
-    TcpStream, BufReader, BufWriter, BufRead, Result, Write, sha256,
+use super::{ErrorKind, 
+    VERSION_HEADER, Keypair, Error, RsaKey, EcdsaP256Key,
+    TcpStream, BufReader, BufWriter, Result, IoErrorKind, sha256,
+};
+use super::userauth::{
+    sign_userauth, AuthSigner, Ed25519Signer, UserauthInfoRequest, UserauthInfoResponse, USERAUTH_INFO_REQUEST,
 };
-use super::{KeyIvInit, Verifier};
-use super::userauth::sign_userauth;
 use super::messages::{
     UnsignedMpInt, ServiceRequest, ServiceAccept, UserauthRequest, Blob,
-    Kexinit, KexdhInit, KexdhReply, ExchangeHash, Newkeys, Message,
+    Kexinit, Newkeys, Message, OwnedMessage, MessageType, NegotiatedAlgorithms, UserauthBanner, GlobalRequest,
+    Disconnect, DisconnectReasonCode, IgnoreData, DebugMessage,
+};
+use super::kex::{
+    curve25519_sha256, diffie_hellman_group_exchange_sha256, diffie_hellman_group14_sha256,
+    KexContext, KexOutput, GexGroupSize,
 };
-use super::parsedump::ParseDump;
-use super::keygen::decode_hex;
-use super::packets::{PacketReader, PacketWriter};
+use super::parsedump::{ParseDump, NameList};
+use super::keygen::{decode_hex, PublicKey, fingerprint_sha256};
+use super::packets::{PacketReader, PacketWriter, RandomSource, MAX_PACKET_LENGTH};
+use super::transport::DirectionKeys;
+use super::hmac::MAC_ALGORITHMS;
+#[cfg(feature = "zlib")]
+use super::compression::{ZLIB, ZLIB_OPENSSH, COMPRESSION_ALGORITHMS_ZLIB};
+use super::hostkey::{HOST_KEY_ALGORITHMS, CERT_HOST_KEY_ALGORITHMS};
+use super::proxy::Socks5;
+use super::certificate::{HostCertAuthorities, Certificate, USER_CERT};
+use super::stream::{Stream, Shared, Source, Sink, block_on};
+use super::forward::Forwarder;
+use super::run::ChannelWindow;
+use super::observer::{Observer, PacketObserver, Progress, ConnectProgress, DebugHook, GlobalRequestHook, Transcript};
+#[cfg(feature = "keylog")]
+use super::{Write, observer::KeyLog, keygen::encode_hex};
+use std::net::ToSocketAddrs;
+use std::time::{Duration, Instant};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::collections::{HashMap, VecDeque};
+use zeroize::Zeroizing;
+
+const KEXINIT: u8 = 20;
+const CHANNEL_OPEN: u8 = 90;
+const GLOBAL_REQUEST: u8 = 80;
+const REQUEST_SUCCESS: u8 = 81;
+const REQUEST_FAILURE: u8 = 82;
+
+/// Unanswered keepalives after which the server is considered
+/// unreachable (like OpenSSH's `ServerAliveCountMax`)
+const KEEPALIVE_MAX_UNANSWERED: u32 = 3;
+
+/// Lines which the server may send before its version
+const MAX_PRE_VERSION_LINES: usize = 1024;
+/// Longest line accepted before the version, terminator included
+const MAX_PRE_VERSION_LINE_LENGTH: usize = 8192;
+/// Longest version line, terminator included (RFC 4253, section 4.2)
+const MAX_VERSION_LENGTH: usize = 255;
+
+/// Key exchange methods we support, by order of preference
+macro_rules! kex_algorithms {
+    () => (concat!(
+        "curve25519-sha256,",
+        // same method, as named before RFC 8731, for older servers
+        "curve25519-sha256@libssh.org,",
+        "diffie-hellman-group-exchange-sha256,",
+        "diffie-hellman-group14-sha256",
+    ))
+}
 
+const KEX_ALGORITHMS: &str = kex_algorithms!();
+/// Extensions are only requested during the first key exchange
+const FIRST_KEX_ALGORITHMS: &str = concat!(kex_algorithms!(), ",ext-info-c");
+
+const ENCRYPTION_ALGORITHMS: &str = concat!(
+    "chacha20-poly1305@openssh.com,",
+    "aes256-gcm@openssh.com,",
+    "aes128-gcm@openssh.com,",
+    "aes256-ctr,",
+    "aes192-ctr,",
+    "aes128-ctr",
+);
+const COMPRESSION_ALGORITHMS: &str = "none";
+
+/// Credentials used to log in
+///
+/// New variants may be added in minor releases.
+#[derive(Copy, Clone)]
+#[non_exhaustive]
 pub enum Auth<'a> {
+    /// The `none` method, which only servers without authentication
+    /// accept; the others reply with the methods they allow, which
+    /// [`ErrorKind::AuthRejected`] lists
+    None {
+        username: &'a str,
+    },
     Password {
         username: &'a str,
         password: &'a str,
@@ -21,294 +98,1255 @@ pub enum Auth<'a> {
         username: &'a str,
         /// 128-character hex-encoded keypair
         hex_keypair: &'a str,
+    },
+    /// An ed25519 keypair with an OpenSSH user certificate
+    Ed25519Certificate {
+        username: &'a str,
+        /// 128-character hex-encoded keypair
+        hex_keypair: &'a str,
+        /// `ssh-ed25519-cert-v01@openssh.com` blob, see [`PublicKey::from_openssh`]
+        certificate: &'a [u8],
+    },
+    /// An RSA key, signing with `rsa-sha2-256`
+    Rsa {
+        username: &'a str,
+        key: &'a RsaKey,
+    },
+    /// An `ecdsa-sha2-nistp256` key
+    EcdsaP256 {
+        username: &'a str,
+        key: &'a EcdsaP256Key,
+    },
+    /// A key which signs by itself, see [`AuthSigner`]
+    Signer {
+        username: &'a str,
+        signer: &'a dyn AuthSigner,
+    },
+    /// The `keyboard-interactive` method (RFC 4256): `responses` answer
+    /// the prompts of the server in order, across its info requests;
+    /// e.g. `&[password]` where the server only prompts for the password.
+    /// Prompts left over are answered with an empty string.
+    KeyboardInteractive {
+        username: &'a str,
+        responses: &'a [&'a str],
+    },
+    /// Tries these in order until one succeeds, skipping the methods
+    /// which the server doesn't allow after a rejection; e.g. a key,
+    /// then `Password`, then `KeyboardInteractive` with the same password
+    Any(&'a [Auth<'a>]),
+}
+
+impl<'a> Auth<'a> {
+    /// The SSH name of the authentication method
+    fn method_name(&self) -> &'static str {
+        match self {
+            Self::None { .. } => "none",
+            Self::Password { .. } => "password",
+            Self::KeyboardInteractive { .. } => "keyboard-interactive",
+            Self::Ed25519 { .. } | Self::Ed25519Certificate { .. } | Self::Rsa { .. } | Self::EcdsaP256 { .. } | Self::Signer { .. } => "publickey",
+            Self::Any(_) => "any",
+        }
+    }
+
+    fn username(&self) -> Option<&'a str> {
+        match *self {
+            Self::None { username }
+            | Self::Password { username, .. }
+            | Self::Ed25519 { username, .. }
+            | Self::Ed25519Certificate { username, .. }
+            | Self::Rsa { username, .. }
+            | Self::EcdsaP256 { username, .. }
+            | Self::Signer { username, .. }
+            | Self::KeyboardInteractive { username, .. } => Some(username),
+            Self::Any(_) => None,
+        }
+    }
+
+    fn flatten(self, methods: &mut Vec<Auth<'a>>) {
+        match self {
+            Self::Any(list) => list.iter().for_each(|auth| auth.flatten(methods)),
+            auth => methods.push(auth),
+        }
     }
 }
 
-pub struct Connection {
-    pub(crate) reader: PacketReader<TcpStream>,
-    pub(crate) writer: PacketWriter<TcpStream>,
-    pub(crate) next_client_channel: u32,
+/// The authentication methods which can continue after a
+/// rejection, as listed by the server (see [`ErrorKind::AuthRejected`])
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct AuthMethods {
+    pub publickey: bool,
+    pub password: bool,
+    pub keyboard_interactive: bool,
+    pub hostbased: bool,
+    /// Any other method, e.g. `gssapi-with-mic`
+    pub other: bool,
 }
 
-impl Connection {
-    pub fn new(stream: TcpStream, auth: Auth) -> Result<Self> {
-        let mut reader = BufReader::new(stream.try_clone()?);
-        let mut writer = BufWriter::new(stream);
-
-        writer.write(VERSION_HEADER)?;
-        writer.write(b"\r\n")?;
-        writer.flush()?;
-
-        let peer_version = {
-            let mut peer_version = String::new();
-
-            loop {
-                reader.read_line(&mut peer_version)?;
-                let sw = |prefix| peer_version.starts_with(prefix);
-                match sw("SSH-2.0-") || sw("SSH-1.99-") {
-                    true => break,
-                    _    => continue,
-                }
+impl AuthMethods {
+    /// Parses the comma-separated name-list of a `UserauthFailure`
+    pub(crate) fn parse(name_list: &str) -> Self {
+        let mut methods = Self::default();
+        for name in name_list.split(',').filter(|name| !name.is_empty()) {
+            match name {
+                "publickey" => methods.publickey = true,
+                "password" => methods.password = true,
+                "keyboard-interactive" => methods.keyboard_interactive = true,
+                "hostbased" => methods.hostbased = true,
+                _ => methods.other = true,
             }
+        }
 
-            let lf = peer_version.pop();
-            let cr = peer_version.pop();
+        methods
+    }
 
-            if (cr, lf) != (Some('\r'), Some('\n')) {
-                log::error!("Invalid Version Header: {}", peer_version);
-                return Err(Error::InvalidData);
-            }
+    /// True if the server listed no method at all
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+/// When to run a new key exchange (RFC 4253, section 9)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct RekeyLimits {
+    /// in either direction
+    bytes: u64,
+    interval: Duration,
+}
+
+impl Default for RekeyLimits {
+    fn default() -> Self {
+        Self {
+            bytes: 1 << 30,
+            interval: Duration::from_secs(3600),
+        }
+    }
+}
+
+/// Socket parameters, for [`Connection::connect`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct TcpOptions {
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    nodelay: bool,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            nodelay: true,
+        }
+    }
+}
+
+/// Tunable parameters of the SSH handshake
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectOptions {
+    gex_group_size: GexGroupSize,
+    rekey_limits: RekeyLimits,
+    tcp: TcpOptions,
+    #[cfg(feature = "zlib")]
+    compression: bool,
+    observer: Observer,
+    progress: Progress,
+    debug_callback: DebugHook,
+    global_request_handler: GlobalRequestHook,
+    random: RandomSource,
+    host_cert_authorities: Option<HostCertAuthorities>,
+    max_packet_length: Option<u32>,
+    proxy: Option<Socks5>,
+    probe_auth: bool,
+    #[cfg(feature = "keylog")]
+    key_log: KeyLog,
+}
 
-            peer_version
+impl ConnectOptions {
+    /// Modulus sizes, in bits, to request if `diffie-hellman-group-exchange-sha256`
+    /// is negotiated (defaults: 2048, 3072, 8192)
+    pub fn gex_group_size(mut self, min: u32, preferred: u32, max: u32) -> Self {
+        self.gex_group_size = GexGroupSize {
+            min,
+            preferred,
+            max,
         };
+        self
+    }
 
-        log::info!("peer_version: {}", peer_version);
-
-        let mut reader = PacketReader::new(reader);
-        let mut writer = PacketWriter::new(writer);
-
-        let client_kexinit = Kexinit {
-            cookie: [0; 16],
-            kex_algorithms: "curve25519-sha256",
-            server_host_key_algorithms: "ssh-ed25519",
-            encryption_algorithms_client_to_server: "aes256-ctr",
-            encryption_algorithms_server_to_client: "aes256-ctr",
-            mac_algorithms_client_to_server: "hmac-sha2-256",
-            mac_algorithms_server_to_client: "hmac-sha2-256",
-            compression_algorithms_client_to_server: "none",
-            compression_algorithms_server_to_client: "none",
-            languages_client_to_server: "",
-            languages_server_to_client: "",
-            first_kex_packet_follows: false,
-            nop: 0,
+    /// Runs a new key exchange once `bytes` were transferred in either
+    /// direction or `interval` elapsed (defaults: 1GiB, one hour)
+    pub fn rekey_limits(mut self, bytes: u64, interval: Duration) -> Self {
+        self.rekey_limits = RekeyLimits {
+            bytes,
+            interval,
         };
+        self
+    }
 
-        let mut client_kexinit_payload = Vec::new();
-        client_kexinit.dump(&mut client_kexinit_payload)?;
-        let client_kexinit_payload = &client_kexinit_payload.into_boxed_slice();
-
-        writer.send(&client_kexinit)?;
-
-        let server_kexinit_payload = reader.recv_raw()?.to_vec();
-        let server_kexinit_payload = &server_kexinit_payload.into_boxed_slice();
-        let (server_kexinit, _) = Kexinit::parse(server_kexinit_payload)?;
-        server_kexinit.check_compat(&client_kexinit)?;
-
-        let secret_key = x25519_dalek::EphemeralSecret::new(Rng);
-        let public_key = x25519_dalek::PublicKey::from(&secret_key);
-        let client_ephemeral_pubkey = public_key.as_bytes().as_slice();
-
-        writer.send(&KexdhInit {
-            client_ephemeral_pubkey,
-        })?;
-
-        let shared_secret_array;
-        let (exchange_hash, shared_secret) = {
-            let KexdhReply {
-                server_public_host_key,
-                server_ephemeral_pubkey,
-                exchange_hash_signature: Blob {
-                    blob_len: _,
-                    header: _,
-                    content: signature,
-                },
-            } = reader.recv()?;
+    /// Bounds the TCP connection attempts of [`Connection::connect`], and
+    /// each read until authentication completes (default: none)
+    pub fn connect_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.tcp.connect_timeout = timeout;
+        self
+    }
 
-            let Blob {
-                blob_len: _,
-                header: _,
-                content: host_pubkey_bytes,
-            } = server_public_host_key;
+    /// Read timeout of the socket once [`Connection::connect`]
+    /// has authenticated (default: none)
+    pub fn read_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.tcp.read_timeout = timeout;
+        self
+    }
 
-            if server_ephemeral_pubkey.len() != 32 || signature.len() != 64 || host_pubkey_bytes.len() != 32 {
-                log::error!("Invalid Server KexdhReply (wrong field length)");
-                return Err(Error::InvalidData);
-            }
+    /// Write timeout of the socket opened by [`Connection::connect`] (default: none)
+    pub fn write_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.tcp.write_timeout = timeout;
+        self
+    }
 
-            shared_secret_array = {
-                let mut sep_array = [0; 32];
-                sep_array.copy_from_slice(server_ephemeral_pubkey);
-                secret_key.diffie_hellman(&sep_array.into())
-            };
+    /// Sets `TCP_NODELAY` on the socket opened by [`Connection::connect`] (default: true)
+    pub fn nodelay(mut self, enabled: bool) -> Self {
+        self.tcp.nodelay = enabled;
+        self
+    }
 
-            let host_pubkey = ed25519_dalek::PublicKey::from_bytes(host_pubkey_bytes).map_err(|e| {
-                log::error!("Couldn't reconstruct server public key: {}", e);
-                Error::InvalidData
-            })?;
+    /// Passes every packet exchanged with the server to `observer`,
+    /// starting with the key exchange (default: none)
+    pub fn packet_observer<O: PacketObserver + 'static>(mut self, observer: O) -> Self {
+        self.observer = Observer::new(observer);
+        self
+    }
 
-            let signature = {
-                let mut sig_array = [0; 64];
-                sig_array.copy_from_slice(signature);
-                ed25519_dalek::Signature::from(sig_array)
-            };
+    /// Reports the steps of the handshake to `callback` as they
+    /// complete, e.g. to display them (default: none)
+    pub fn progress<F: FnMut(ConnectProgress) + Send + 'static>(mut self, callback: F) -> Self {
+        self.progress = Progress::new(callback);
+        self
+    }
+
+    /// Gives the `Debug` messages of the server to `callback`, e.g. to
+    /// display those with `always_display` set; they are logged and
+    /// skipped otherwise.
+    pub fn debug_callback<F: FnMut(DebugMessage) + Send + 'static>(mut self, callback: F) -> Self {
+        self.debug_callback = DebugHook::new(callback);
+        self
+    }
+
+    /// Gives the global requests of the server (RFC 4254, section 4) to
+    /// `handler`, with their name and request-specific data, e.g. for
+    /// `hostkeys-00@openssh.com`; when the server wants a reply, it's
+    /// `RequestSuccess` if `handler` returns true, or `RequestFailure`.
+    ///
+    /// Without a handler, all of them fail.
+    pub fn global_request_handler<F: FnMut(&str, &[u8]) -> bool + Send + 'static>(mut self, handler: F) -> Self {
+        self.global_request_handler = GlobalRequestHook::new(handler);
+        self
+    }
+
+    /// Fills the KEXINIT cookies and the padding of packets with `source`
+    /// instead of the operating system's generator, e.g. to make them
+    /// reproducible in tests; keys are always generated by the latter.
+    pub fn random_source<F: FnMut(&mut [u8]) + Send + 'static>(mut self, source: F) -> Self {
+        self.random = RandomSource::new(source);
+        self
+    }
+
+    /// Makes [`Connection::connect`] go through a SOCKS5 proxy; the
+    /// server's address is still resolved locally (default: none)
+    pub fn proxy(mut self, proxy: Socks5) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Refuses packets whose `packet_length` exceeds `bytes`, with
+    /// [`ErrorKind::PacketTooLarge`], so that a broken or malicious server
+    /// can't make us allocate arbitrary amounts of memory (default: 256KiB).
+    /// The maximum packet size of channels must fit in it, see
+    /// [`Connection::set_channel_window`].
+    pub fn max_packet_length(mut self, bytes: u32) -> Self {
+        self.max_packet_length = Some(bytes);
+        self
+    }
+
+    /// Only accepts `ssh-ed25519-cert-v01@openssh.com` host certificates which
+    /// are valid for `hostname` and signed by one of `authorities`, like the
+    /// `@cert-authority` lines of OpenSSH's `known_hosts` (default: any host key)
+    pub fn host_cert_authorities(mut self, hostname: &str, authorities: Vec<PublicKey>) -> Self {
+        self.host_cert_authorities = Some(HostCertAuthorities {
+            hostname: hostname.into(),
+            authorities,
+        });
+        self
+    }
+
+    /// Starts authentication with a `none` request, like OpenSSH, so that
+    /// the methods which the server doesn't allow aren't tried; servers
+    /// which don't require authentication accept it (default: false)
+    ///
+    /// The allowed methods are reported to [`progress`](Self::progress).
+    pub fn probe_auth(mut self, enabled: bool) -> Self {
+        self.probe_auth = enabled;
+        self
+    }
 
-            let shared_secret = UnsignedMpInt(shared_secret_array.as_bytes());
-
-            let exchange_hash = sha256(&ExchangeHash {
-                client_header: VERSION_HEADER,
-                server_header: peer_version.as_bytes(),
-                client_kexinit_payload,
-                server_kexinit_payload,
-                server_public_host_key,
-                client_ephemeral_pubkey,
-                server_ephemeral_pubkey,
-                shared_secret,
-            })?;
-
-            host_pubkey.verify(&exchange_hash, &signature).map_err(|e| {
-                log::error!("Exchange hash couldn't be verified: {}", e);
-                Error::InvalidData
-            })?;
-
-            (exchange_hash, shared_secret)
+    /// Writes the secrets of each key exchange to `sink`, one per line:
+    /// the client's KEXINIT cookie, a label, and the secret in hex; the
+    /// labels are `SHARED_SECRET` (as a big-endian unsigned integer),
+    /// then `CLIENT_TO_SERVER_IV`, `_KEY` and `_MAC_KEY`, and their
+    /// `SERVER_TO_CLIENT_` twins, if the negotiated algorithms use them.
+    ///
+    /// This lets Wireshark's SSH dissector decrypt captures, and anyone
+    /// reading `sink` as well: only use it to debug (default: none).
+    #[cfg(feature = "keylog")]
+    pub fn key_log<W: Write + Send + 'static>(mut self, sink: W) -> Self {
+        self.key_log = KeyLog::new(sink);
+        self
+    }
+
+    /// Offers `zlib@openssh.com` and `zlib` compression (default: false)
+    #[cfg(feature = "zlib")]
+    pub fn compression(mut self, enabled: bool) -> Self {
+        self.compression = enabled;
+        self
+    }
+}
+
+/// Resolves `addr` and connects to the first address which accepts,
+/// configuring the socket for the handshake as specified by `options`
+fn tcp_connect<A: ToSocketAddrs>(addr: A, options: &ConnectOptions) -> Result<TcpStream> {
+    let tcp = options.tcp;
+    let mut last_error = Error::from(ErrorKind::TcpError(IoErrorKind::InvalidInput));
+
+    for addr in addr.to_socket_addrs()? {
+        let stream = match (&options.proxy, tcp.connect_timeout) {
+            (Some(proxy), timeout) => proxy.connect(addr, timeout),
+            (None, Some(timeout)) => TcpStream::connect_timeout(&addr, timeout).map_err(Error::from),
+            (None, None) => TcpStream::connect(addr).map_err(Error::from),
         };
 
-        let session_id = exchange_hash;
+        match stream {
+            Ok(stream) => {
+                stream.set_nodelay(tcp.nodelay)?;
+                stream.set_write_timeout(tcp.write_timeout)?;
+                stream.set_read_timeout(tcp.connect_timeout)?;
+                return Ok(stream);
+            },
+            Err(error) => {
+                log::info!("Couldn't connect to {}: {:?}", addr, error);
+                last_error = error;
+            },
+        }
+    }
+
+    Err(last_error)
+}
+
+/// The transport once the first key exchange completed
+struct Handshake<R, W> {
+    reader: PacketReader<R>,
+    writer: PacketWriter<W>,
+    kex_state: KexState,
+    negotiated: NegotiatedAlgorithms<'static>,
+    /// lines sent by the server before its version
+    pre_version: Vec<String>,
+}
+
+/// Exchanges versions and runs the first key exchange
+async fn handshake<R: Source, W: Sink>(mut reader: R, mut writer: W, options: ConnectOptions) -> Result<Handshake<R, W>> {
+    let GexGroupSize {
+        min,
+        preferred,
+        max,
+    } = options.gex_group_size;
+
+    if min > preferred || preferred > max {
+        return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid DH group exchange sizes: {}/{}/{}", min, preferred, max)));
+    }
+
+    writer.write_all(&[VERSION_HEADER, b"\r\n"].concat()).await?;
+
+    let (peer_version, pre_version) = read_version(&mut reader).await?;
+
+    options.progress.report(ConnectProgress::VersionExchanged {
+        server_version: &peer_version,
+    });
 
-        writer.send(&Newkeys {})?;
-        let _: Newkeys = reader.recv()?;
+    let mut reader = PacketReader::new(reader);
+    let mut writer = PacketWriter::new(writer);
+    reader.observer = options.observer.clone();
+    reader.debug_hook = options.debug_callback.clone();
+    reader.global_requests = options.global_request_handler.clone();
+    writer.observer = options.observer.clone();
+    writer.random = options.random.clone();
+    reader.max_packet_length = options.max_packet_length.unwrap_or(MAX_PACKET_LENGTH);
 
-        log::trace!("Got server Newkeys");
+    let mut kex_state = KexState {
+        peer_version,
+        options,
+        session_id: Vec::new(),
+        host_key: Vec::new(),
+        authenticated: false,
+        last_kex: Instant::now(),
+    };
 
-        let kex = KeyExchangeOutput::new(shared_secret, &exchange_hash, &session_id)?;
-        writer.set_encryptor(Cipher::new(&kex.c2s_key.into(), &kex.c2s_iv.into()), Hmac::new(&kex.c2s_hmac), 32);
-        reader.set_decryptor(Cipher::new(&kex.s2c_key.into(), &kex.s2c_iv.into()), Hmac::new(&kex.s2c_hmac), 32, 32);
+    let negotiated = key_exchange(&mut reader, &mut writer, &mut kex_state, None).await?;
+    kex_state.options.progress.report(ConnectProgress::KexDone {
+        algorithms: negotiated,
+        session_id: &kex_state.session_id,
+    });
+
+    Ok(Handshake {
+        reader,
+        writer,
+        kex_state,
+        negotiated,
+        pre_version,
+    })
+}
+
+/// See [`Connection::fetch_host_key`]
+async fn fetch_host_key<R: Source, W: Sink>(reader: R, writer: W, options: ConnectOptions) -> Result<PublicKey> {
+    let Handshake { mut writer, kex_state, .. } = handshake(reader, writer, options).await?;
+
+    // best effort, the key was received
+    let _ = writer.send(&Disconnect {
+        reason_code: DisconnectReasonCode::ByApplication,
+        description: "",
+        language_tag: "",
+    }).await;
+
+    PublicKey::from_blob(&kex_state.host_key)
+}
+
+/// What's needed to run the key exchanges following the first one
+struct KexState {
+    peer_version: String,
+    options: ConnectOptions,
+    /// exchange hash of the first key exchange
+    session_id: Vec<u8>,
+    /// blob of the server's public host key
+    host_key: Vec<u8>,
+    /// enables `zlib@openssh.com`
+    authenticated: bool,
+    last_kex: Instant,
+}
+
+/// See [`Connection::set_keepalive`]
+struct Keepalive {
+    interval: Option<Duration>,
+    /// when the last packet was received or the last keepalive was sent
+    last_traffic: Instant,
+    unanswered: u32,
+}
+
+/// An authenticated connection, over which channels
+/// ([`Run`](crate::Run)s) can be opened concurrently
+pub struct Connection<S: Stream = TcpStream> {
+    inner: Mutex<BlockingInner<S>>,
+}
+
+type BlockingInner<S> = ConnectionInner<BufReader<Shared<S>>, BufWriter<Shared<S>>>;
+
+/// What channels share: the transport and the demultiplexer
+pub(crate) struct ConnectionInner<R, W> {
+    pub(crate) reader: PacketReader<R>,
+    pub(crate) writer: PacketWriter<W>,
+    next_client_channel: u32,
+    algorithms: NegotiatedAlgorithms<'static>,
+    kex_state: KexState,
+    /// messages received for each open channel (by client channel
+    /// number) while another channel was reading
+    channels: HashMap<u32, VecDeque<OwnedMessage>>,
+    /// UserauthBanner messages, concatenated
+    banner: Option<String>,
+    /// lines sent by the server before its version
+    pre_version: Vec<String>,
+    keepalive: Keepalive,
+    /// a Disconnect message was sent
+    disconnected: bool,
+    /// channels opened by the server
+    pub(crate) forwarder: Forwarder,
+    /// advertised for the channels we open
+    pub(crate) channel_window: ChannelWindow,
+    /// see [`Connection::set_transcript`]
+    pub(crate) transcript: Transcript,
+}
+
+impl Connection {
+    /// Resolves `addr`, connects to the first address which accepts,
+    /// configures the socket as specified by `options` and logs in
+    pub fn connect<A: ToSocketAddrs>(addr: A, auth: Auth, options: ConnectOptions) -> Result<Self> {
+        let stream = tcp_connect(addr, &options)?;
+        let read_timeout = options.tcp.read_timeout;
+
+        let conn = Self::new_with_options(stream, auth, options)?;
+        conn.inner().reader.inner.get_ref().lock().set_read_timeout(read_timeout)?;
+        Ok(conn)
+    }
+
+    /// Connects like [`Self::connect`], but only runs the key exchange,
+    /// like `ssh-keyscan`; returns the host key of the server as
+    /// `(algorithm, blob, SHA256 fingerprint)`, e.g. to populate
+    /// `known_hosts` before connecting.
+    ///
+    /// Which host key algorithm is negotiated depends on `options`,
+    /// e.g. certificates are requested when authorities are set.
+    pub fn fetch_host_key<A: ToSocketAddrs>(addr: A, options: ConnectOptions) -> Result<(String, Vec<u8>, String)> {
+        let (reader, writer) = Shared::new(tcp_connect(addr, &options)?);
+        let host_key = block_on(fetch_host_key(BufReader::new(reader), BufWriter::new(writer), options))?;
+        let fingerprint = fingerprint_sha256(host_key.blob());
+
+        Ok((host_key.algorithm().to_string(), host_key.blob().to_vec(), fingerprint))
+    }
+
+    /// Write timeout of the socket; a blocked write
+    /// fails with [`ErrorKind::TcpError`]
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.inner().reader.inner.get_ref().lock().set_write_timeout(timeout)?)
+    }
+
+    /// Enables or disables `TCP_NODELAY` on the socket
+    pub fn set_nodelay(&self, enabled: bool) -> Result<()> {
+        Ok(self.inner().reader.inner.get_ref().lock().set_nodelay(enabled)?)
+    }
+}
+
+impl<S: Stream> Connection<S> {
+    pub fn new(stream: S, auth: Auth) -> Result<Self> {
+        Self::new_with_options(stream, auth, ConnectOptions::default())
+    }
+
+    pub fn new_with_options(stream: S, auth: Auth, options: ConnectOptions) -> Result<Self> {
+        let (reader, writer) = Shared::new(stream);
+        let inner = block_on(ConnectionInner::establish(BufReader::new(reader), BufWriter::new(writer), auth, options))?;
+
+        Ok(Self {
+            inner: Mutex::new(inner),
+        })
+    }
+
+    /// Locks the state shared by the channels; a panic while it was
+    /// locked doesn't prevent the other [`Run`](crate::Run)s from being dropped.
+    pub(crate) fn inner(&self) -> MutexGuard<'_, BlockingInner<S>> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The algorithms which were agreed upon with the server
+    pub fn algorithms(&self) -> NegotiatedAlgorithms<'static> {
+        self.inner().algorithms()
+    }
+
+    /// Text which the server sent to be displayed before authentication
+    /// (e.g. legal notices), if any
+    pub fn banner(&self) -> Option<String> {
+        self.inner().banner()
+    }
+
+    /// Version header of the server, without line terminator
+    /// (e.g. `SSH-2.0-OpenSSH_9.6`)
+    pub fn server_version(&self) -> String {
+        self.inner().server_version()
+    }
+
+    /// Lines which the server sent before its version, without line
+    /// terminators; some servers greet with text there (RFC 4253, section 4.2)
+    pub fn pre_version_lines(&self) -> Vec<String> {
+        self.inner().pre_version_lines()
+    }
+
+    /// Signature algorithms which the server accepts for public key
+    /// authentication (comma-separated), if it listed them (RFC 8308)
+    pub fn server_sig_algs(&self) -> Option<String> {
+        self.inner().server_sig_algs()
+    }
+
+    /// Exchange hash of the first key exchange, which identifies the connection
+    pub fn session_id(&self) -> Vec<u8> {
+        self.inner().session_id()
+    }
+
+    /// Public host key of the server, as presented during the last key exchange;
+    /// `host_key().fingerprint_display()` formats it like OpenSSH does
+    pub fn host_key(&self) -> PublicKey {
+        self.inner().host_key()
+    }
+
+    /// Fingerprint of [`Self::host_key`], as `SHA256:<base64>`
+    pub fn host_key_fingerprint(&self) -> String {
+        fingerprint_sha256(self.host_key().blob())
+    }
+
+    /// Sends a `keepalive@openssh.com` request when nothing was received
+    /// for `interval`, failing with [`ErrorKind::ConnectionLost`] after three
+    /// unanswered ones; `None` disables keepalives (the default).
+    ///
+    /// Keepalives are sent while [`Run::poll`](crate::Run::poll) waits for
+    /// data, so the stream's read timeout must be shorter than `interval`;
+    /// [`Run::wait_event`](crate::Run::wait_event) and the `quick_run`
+    /// methods shorten it by themselves.
+    pub fn set_keepalive(&self, interval: Option<Duration>) {
+        self.inner().set_keepalive(interval)
+    }
+
+    /// Notifies the server that the connection is being closed, then closes it;
+    /// dropping the connection does the same with [`DisconnectReasonCode::ByApplication`].
+    pub fn disconnect(self, reason_code: DisconnectReasonCode, description: &str) -> Result<()> {
+        self.disconnect_localized(reason_code, description, "")
+    }
+
+    /// Same as [`Self::disconnect`], with the language of `description`
+    /// as an RFC 3066 tag (e.g. `fr-FR`)
+    pub fn disconnect_localized(self, reason_code: DisconnectReasonCode, description: &str, language_tag: &str) -> Result<()> {
+        block_on(self.inner().disconnect(reason_code, description, language_tag))
+    }
+
+    /// Read timeout of the stream, after which [`Run::poll`](crate::Run::poll)
+    /// returns [`RunEvent::None`](crate::RunEvent::None) and blocking
+    /// calls fail with [`ErrorKind::Timeout`]; `None` waits forever.
+    ///
+    /// It's suspended during key exchanges and while
+    /// [`Run::wait_event`](crate::Run::wait_event) waits.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+        self.inner().reader.inner.set_read_timeout(timeout)
+    }
+
+    /// The read timeout set with [`Self::set_read_timeout`]
+    pub fn read_timeout(&self) -> Result<Option<Duration>> {
+        self.inner().reader.inner.read_timeout()
+    }
+
+    /// Sends an `Ignore` message of `len` random bytes, which the server
+    /// discards; e.g. to pad traffic or to keep idle NAT mappings alive
+    pub fn send_ignore(&self, len: usize) -> Result<()> {
+        block_on(self.inner().send_ignore(len))
+    }
+
+    /// Sends a `Debug` message, which the server may log
+    pub fn send_debug(&self, message: &str) -> Result<()> {
+        block_on(self.inner().send_debug(message))
+    }
+
+    /// Gives access to the internal stream, allowing to change
+    /// its parameters
+    pub fn mutate_stream<F: Fn(&mut S)>(&mut self, func: F) {
+        func(&mut self.inner().reader.inner.get_ref().lock())
+    }
+}
+
+impl<R: Source, W: Sink> ConnectionInner<R, W> {
+    /// Exchanges versions, runs the first key exchange and authenticates
+    pub(crate) async fn establish(reader: R, writer: W, auth: Auth<'_>, options: ConnectOptions) -> Result<Self> {
+        let Handshake {
+            mut reader,
+            mut writer,
+            mut kex_state,
+            negotiated,
+            pre_version,
+        } = handshake(reader, writer, options).await?;
+
+        let session_id = kex_state.session_id.clone();
+        let progress = kex_state.options.progress.clone();
 
         log::trace!("Sending ServiceRequest");
 
         writer.send(&ServiceRequest {
             service_name: "ssh-userauth",
-        })?;
+        }).await?;
 
         log::trace!("Awaiting ServiceAccept");
-        let _: ServiceAccept = reader.recv()?;
+        let _: ServiceAccept = reader.recv().await?;
         log::trace!("Got ServiceAccept");
 
-        let service_name = "ssh-connection";
-        match auth {
-            Auth::Password {
-                username,
-                password,
-            } => {
-                writer.send(&UserauthRequest::Password {
-                    username,
-                    service_name,
-                    password,
-                    new_password: None,
-                })?;
-            },
-            Auth::Ed25519 {
-                username,
-                hex_keypair,
-            } => {
-                let algorithm = "ssh-ed25519";
-                let keypair = {
-                    let bytes: [u8; 64] = decode_hex(hex_keypair).ok_or(Error::InvalidKeypair)?;
-                    Keypair::from_bytes(&bytes).ok().ok_or(Error::InvalidKeypair)?
-                };
-
-                let ed25519_pub = Blob {
-                    blob_len: ed25519_blob_len(32),
-                    header: algorithm,
-                    content: keypair.public.as_bytes().as_slice(),
-                };
-
-                writer.send(&UserauthRequest::PublicKey {
-                    username,
-                    service_name,
-                    algorithm,
-                    blob: ed25519_pub,
-                    signature: None,
-                })?;
-
-                log::trace!("Awaiting UserauthPkOk");
-                match reader.recv()? {
-                    Message::UserauthPkOk(_) => Ok((/* nice */)),
-                    Message::UserauthFailure(_) => Err(Error::AuthenticationFailure),
-                    msg => {
-                        log::error!("Expected UserauthPkOk, got {:?}", msg);
-                        Err(Error::UnexpectedMessageType(msg.typ()))
-                    },
-                }?;
-                log::trace!("Got UserauthPkOk");
-
-                let signature = sign_userauth(&keypair, &session_id, username, service_name, &ed25519_pub)?;
-
-                writer.send(&UserauthRequest::PublicKey {
-                    username,
-                    service_name,
-                    algorithm,
-                    blob: ed25519_pub,
-                    signature: Some(Blob {
-                        blob_len: ed25519_blob_len(64),
-                        header: algorithm,
-                        content: &signature,
-                    }),
-                })?;
-            },
+        let mut methods = Vec::new();
+        auth.flatten(&mut methods);
+
+        if kex_state.options.probe_auth {
+            if let Some(username) = methods.first().and_then(Auth::username) {
+                methods.insert(0, Auth::None { username });
+            }
+        }
+
+        // the last UserauthFailure, with the methods which can continue
+        let mut rejection: Option<Rejection> = None;
+        let mut authenticated = false;
+        let mut banner = None;
+        let mut i = 0;
+
+        while let Some(&auth) = methods.get(i) {
+            if let Some(Rejection { allowed_auth, .. }) = &rejection {
+                if !allowed_auth.split(',').any(|name| name == auth.method_name()) {
+                    log::info!("Skipping {:?}: the server only allows {}", auth, allowed_auth);
+                    i += 1;
+                    continue;
+                }
+            }
+
+            let method = auth.method_name();
+            match authenticate(&mut reader, &mut writer, &session_id, auth, &mut banner).await? {
+                None => {
+                    progress.report(ConnectProgress::AuthAccepted { method });
+                    authenticated = true;
+                    break;
+                },
+                Some(failure) if failure.partial_success => {
+                    // multi-factor authentication: this method is done, and
+                    // the next one may have been skipped or rejected so far
+                    progress.report(ConnectProgress::AuthPartialSuccess { method, allowed: &failure.allowed_auth });
+                    methods.remove(i);
+                    rejection = Some(failure);
+                    i = 0;
+                },
+                Some(failure) => {
+                    progress.report(ConnectProgress::AuthRejected { method, allowed: &failure.allowed_auth });
+                    rejection = Some(failure);
+                    i += 1;
+                },
+            }
+        }
+
+        if !authenticated {
+            return Err(match rejection {
+                Some(Rejection { allowed_auth, partial_success }) => ErrorKind::AuthRejected {
+                    methods_left: AuthMethods::parse(&allowed_auth),
+                    partial: partial_success,
+                },
+                None => ErrorKind::AuthenticationFailure,
+            }.into());
         }
 
-        log::trace!("Awaiting UserauthSuccess");
-        match reader.recv()? {
-            Message::UserauthSuccess(_) => Ok((/* nice */)),
-            Message::UserauthFailure(_) => Err(Error::AuthenticationFailure),
-            msg => {
-                log::error!("Expected UserauthSuccess, got {:?}", msg);
-                Err(Error::UnexpectedMessageType(msg.typ()))
-            },
-        }?;
         log::trace!("Got UserauthSuccess");
 
+        kex_state.authenticated = true;
+        #[cfg(feature = "zlib")]
+        enable_compression(&mut reader, &mut writer, &negotiated, ZLIB_OPENSSH);
+
         Ok(Self {
             reader,
             writer,
             next_client_channel: 0,
+            algorithms: negotiated,
+            kex_state,
+            channels: HashMap::new(),
+            banner,
+            pre_version,
+            keepalive: Keepalive {
+                interval: None,
+                last_traffic: Instant::now(),
+                unanswered: 0,
+            },
+            disconnected: false,
+            forwarder: Forwarder::default(),
+            channel_window: ChannelWindow::default(),
+            transcript: Transcript::default(),
         })
     }
 
-    /// Gives access to the internal stream, allowing to change
-    /// its parameters
-    pub fn mutate_stream<F: Fn(&mut TcpStream)>(&mut self, func: F) {
-        func(self.reader.inner.get_mut())
+    pub(crate) fn set_channel_window(&mut self, initial_window: u32, max_packet_size: u32) -> Result<()> {
+        let window = ChannelWindow {
+            initial_window,
+            max_packet_size,
+        };
+
+        window.check(self.reader.max_packet_length)?;
+        self.channel_window = window;
+        Ok(())
+    }
+
+    pub(crate) fn algorithms(&self) -> NegotiatedAlgorithms<'static> {
+        self.algorithms
+    }
+
+    pub(crate) fn banner(&self) -> Option<String> {
+        self.banner.clone()
+    }
+
+    pub(crate) fn server_version(&self) -> String {
+        self.kex_state.peer_version.clone()
+    }
+
+    pub(crate) fn pre_version_lines(&self) -> Vec<String> {
+        self.pre_version.clone()
+    }
+
+    pub(crate) fn server_sig_algs(&self) -> Option<String> {
+        self.reader.server_sig_algs.clone()
+    }
+
+    pub(crate) fn session_id(&self) -> Vec<u8> {
+        self.kex_state.session_id.clone()
+    }
+
+    pub(crate) fn host_key(&self) -> PublicKey {
+        // checked by the signature verification
+        PublicKey::from_blob(&self.kex_state.host_key).unwrap()
+    }
+
+    pub(crate) fn set_keepalive(&mut self, interval: Option<Duration>) {
+        self.keepalive.interval = interval;
+        self.keepalive.last_traffic = Instant::now();
+        self.keepalive.unanswered = 0;
+    }
+
+    /// Allocates a client channel number and starts queueing its messages
+    pub(crate) fn open_channel(&mut self) -> u32 {
+        let client_channel = self.allocate_channel();
+        self.channels.insert(client_channel, VecDeque::new());
+        client_channel
+    }
+
+    /// Allocates a client channel number
+    pub(crate) fn allocate_channel(&mut self) -> u32 {
+        let client_channel = self.next_client_channel;
+        self.next_client_channel += 1;
+        client_channel
+    }
+
+    /// Drops the messages queued for `client_channel`, and those
+    /// which will be received for it
+    pub(crate) fn forget_channel(&mut self, client_channel: u32) {
+        self.channels.remove(&client_channel);
+    }
+
+    /// Receives the next message for `client_channel`, queueing those
+    /// which are for other channels; the message is then available
+    /// from [`PacketReader::last`].
+    ///
+    /// Fails with [`ErrorKind::UnknownChannel`] if a message is for a
+    /// channel number which was never allocated.
+    pub(crate) async fn recv_channel(&mut self, client_channel: u32) -> Result<()> {
+        loop {
+            if let Some(message) = self.channels.get_mut(&client_channel).and_then(VecDeque::pop_front) {
+                self.reader.replay(message.into_payload());
+                return Ok(());
+            }
+
+            self.recv_next().await?;
+            let payload = self.reader.last_payload();
+            match recipient_channel(payload) {
+                Some(recipient) if recipient != client_channel => match self.channels.get_mut(&recipient) {
+                    Some(queue) => queue.push_back(OwnedMessage::from_payload(payload.to_vec())?),
+                    // the server may not have received our ChannelClose yet
+                    None if recipient < self.next_client_channel => {
+                        log::debug!("Ignoring message for closed channel {}", recipient);
+                    },
+                    None => {
+                        return Err(Error::new(ErrorKind::UnknownChannel(recipient)).context(format!("got a message for unknown channel {}", recipient)));
+                    },
+                },
+                _ => return Ok(()),
+            }
+        }
+    }
+
+    /// Receives the next message which isn't part of a key exchange, running
+    /// the key exchanges which are due or which the server starts; the message
+    /// is then available from [`PacketReader::last`].
+    ///
+    /// Channels opened by the server are handled meanwhile.
+    pub(crate) async fn recv_next(&mut self) -> Result<()> {
+        self.rekey_if_due().await?;
+
+        loop {
+            self.flush_relays().await?;
+
+            let msg_type = match self.reader.recv_payload().await {
+                Ok(payload) => payload.first().copied(),
+                Err(e) if e.kind() == ErrorKind::Timeout => {
+                    self.keepalive_if_due().await?;
+                    return Err(ErrorKind::Timeout.into());
+                },
+                Err(e) => return Err(e),
+            };
+
+            self.keepalive.last_traffic = Instant::now();
+            self.keepalive.unanswered = 0;
+
+            match msg_type {
+                Some(KEXINIT) => {
+                    let server_kexinit = self.reader.last_payload().to_vec();
+                    self.rekey(Some(server_kexinit)).await?;
+                },
+                Some(REQUEST_SUCCESS | REQUEST_FAILURE) => log::trace!("Got a keepalive reply"),
+                Some(GLOBAL_REQUEST) => {
+                    let payload = self.reader.last_payload().to_vec();
+                    self.answer_global_request(&payload).await?;
+                },
+                Some(CHANNEL_OPEN) => {
+                    let payload = self.reader.last_payload().to_vec();
+                    self.accept_channel(&payload).await?;
+                },
+                _ => match recipient_channel(self.reader.last_payload()) {
+                    Some(recipient) if self.forwarder.relays(recipient) => {
+                        let payload = self.reader.last_payload().to_vec();
+                        self.relay_message(recipient, &payload).await?;
+                    },
+                    _ => break Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Replies to a GlobalRequest with `want_reply` (those without
+    /// it are handled by the packet reader)
+    async fn answer_global_request(&mut self, payload: &[u8]) -> Result<()> {
+        let (global_req, len) = GlobalRequest::parse(payload)?;
+        let reply = match self.reader.global_requests.handle(global_req.request_name, &payload[len..]) {
+            true => Message::RequestSuccess,
+            false => Message::RequestFailure,
+        };
+
+        log::info!("Answering global request (type = {}): {:?}", global_req.request_name, reply);
+        self.writer.send(&reply).await
+    }
+
+    /// How long reads may block before a keepalive is due,
+    /// if they are enabled
+    pub(crate) fn keepalive_due_in(&self) -> Option<Duration> {
+        let interval = self.keepalive.interval?;
+        Some(interval.saturating_sub(self.keepalive.last_traffic.elapsed()))
+    }
+
+    /// Sends a keepalive if nothing was received during the keepalive
+    /// interval, or fails if the previous ones weren't answered
+    async fn keepalive_if_due(&mut self) -> Result<()> {
+        let Some(interval) = self.keepalive.interval else {
+            return Ok(());
+        };
+
+        if self.keepalive.last_traffic.elapsed() < interval {
+            return Ok(());
+        }
+
+        if self.keepalive.unanswered >= KEEPALIVE_MAX_UNANSWERED {
+            return Err(Error::new(ErrorKind::ConnectionLost).context(format!("the server didn't answer {} keepalives", self.keepalive.unanswered)));
+        }
+
+        log::trace!("Sending keepalive");
+        self.writer.send(&GlobalRequest {
+            request_name: "keepalive@openssh.com",
+            want_reply: true,
+        }).await?;
+
+        self.keepalive.last_traffic = Instant::now();
+        self.keepalive.unanswered += 1;
+        Ok(())
+    }
+
+    /// Sends a Disconnect message, unless one was already sent
+    pub(crate) async fn disconnect(&mut self, reason_code: DisconnectReasonCode, description: &str, language_tag: &str) -> Result<()> {
+        if self.disconnected {
+            return Ok(());
+        }
+
+        self.disconnected = true;
+        self.writer.send(&Disconnect {
+            reason_code,
+            description,
+            language_tag,
+        }).await
+    }
+
+    /// See [`Connection::send_ignore`]
+    pub(crate) async fn send_ignore(&mut self, len: usize) -> Result<()> {
+        let mut data = vec![0; len];
+        self.writer.random.fill(&mut data);
+        self.send(&IgnoreData { data: &data }).await
+    }
+
+    /// See [`Connection::send_debug`]
+    pub(crate) async fn send_debug(&mut self, message: &str) -> Result<()> {
+        self.send(&DebugMessage {
+            always_display: false,
+            message,
+            language_tag: "",
+        }).await
+    }
+
+    /// Sends a message, running a key exchange first if one is due
+    pub(crate) async fn send<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
+        self.rekey_if_due().await?;
+        self.writer.send(message).await
+    }
+
+    /// Same as [`Self::send`] with a `ChannelData`, see [`PacketWriter::send_channel_data`]
+    pub(crate) async fn send_channel_data(&mut self, recipient_channel: u32, data: &[u8]) -> Result<()> {
+        self.rekey_if_due().await?;
+        self.writer.send_channel_data(recipient_channel, data).await
+    }
+
+    /// Same as [`Self::send`], until the next call to [`Self::flush`]
+    pub(crate) async fn queue<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
+        self.rekey_if_due().await?;
+        self.writer.queue(message).await
+    }
+
+    pub(crate) async fn flush(&mut self) -> Result<()> {
+        self.writer.flush().await
+    }
+
+    /// Runs a new key exchange if enough data was transferred
+    /// or enough time elapsed since the last one (RFC 4253, section 9)
+    pub(crate) async fn rekey_if_due(&mut self) -> Result<()> {
+        let RekeyLimits { bytes, interval } = self.kex_state.options.rekey_limits;
+        let transferred = self.reader.bytes.max(self.writer.bytes);
+        match transferred >= bytes || self.kex_state.last_kex.elapsed() >= interval {
+            true => self.rekey(None).await,
+            false => Ok(()),
+        }
+    }
+
+    /// Runs a new key exchange, started by the server if `server_kexinit`
+    /// is set; otherwise, messages which the server sends before replying
+    /// are deferred.
+    async fn rekey(&mut self, server_kexinit: Option<Vec<u8>>) -> Result<()> {
+        log::info!("Re-keying");
+
+        let read_timeout = self.reader.inner.read_timeout()?;
+        // the key exchange can't be interrupted
+        self.reader.inner.set_read_timeout(None)?;
+
+        let result = key_exchange(&mut self.reader, &mut self.writer, &mut self.kex_state, server_kexinit).await;
+        self.reader.inner.set_read_timeout(read_timeout)?;
+
+        self.algorithms = result?;
+        Ok(())
     }
 }
 
+/// Channel messages start with the recipient channel number
+/// (RFC 4254, section 5); only the client's numbers are used
+/// as recipients here.
+fn recipient_channel(payload: &[u8]) -> Option<u32> {
+    match payload.first() {
+        Some(91..=100) => Some(u32::from_be_bytes(payload.get(1..5)?.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+/// Reads the server's version line, which may end with a bare LF;
+/// returns it with the lines which came before it (RFC 4253, section 4.2)
+async fn read_version<R: Source>(reader: &mut R) -> Result<(String, Vec<String>)> {
+    let mut pre_version = Vec::new();
+
+    loop {
+        let mut line = Vec::new();
+        reader.read_line(&mut line, MAX_PRE_VERSION_LINE_LENGTH).await?;
+
+        if line.last() != Some(&b'\n') {
+            return Err(match line.len() {
+                MAX_PRE_VERSION_LINE_LENGTH => {
+                    let message = format!("the server sent a line of more than {} bytes before its version", MAX_PRE_VERSION_LINE_LENGTH);
+                    Error::new(ErrorKind::InvalidData).context(message)
+                },
+                _ => Error::new(ErrorKind::TcpError(IoErrorKind::UnexpectedEof)).context("the server closed the connection before sending its version"),
+            });
+        }
+
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+
+        if line.starts_with(b"SSH-") {
+            let version = String::from_utf8(line).map_err(|_| Error::new(ErrorKind::InvalidData).context("non-UTF-8 version"))?;
+            // without CR LF
+            if version.len() > MAX_VERSION_LENGTH - 2 {
+                return Err(Error::new(ErrorKind::InvalidData).context(format!("version of more than {} bytes", MAX_VERSION_LENGTH)));
+            }
+
+            return match version.starts_with("SSH-2.0-") || version.starts_with("SSH-1.99-") {
+                true => Ok((version, pre_version)),
+                false => Err(Error::new(ErrorKind::Unimplemented).context(format!("unsupported protocol version: {}", version))),
+            };
+        }
+
+        if pre_version.len() == MAX_PRE_VERSION_LINES {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("the server sent more than {} lines before its version", MAX_PRE_VERSION_LINES)));
+        }
+
+        pre_version.push(String::from_utf8_lossy(&line).into_owned());
+    }
+}
+
+/// Runs a key exchange and switches to the new keys; `server_kexinit`
+/// is the payload of the server's KEXINIT if it was already received
+async fn key_exchange<R: Source, W: Sink>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    state: &mut KexState,
+    server_kexinit: Option<Vec<u8>>,
+) -> Result<NegotiatedAlgorithms<'static>> {
+    #[cfg(feature = "zlib")]
+    let compression_algorithms = match state.options.compression {
+        true => COMPRESSION_ALGORITHMS_ZLIB,
+        false => COMPRESSION_ALGORITHMS,
+    };
+    #[cfg(not(feature = "zlib"))]
+    let compression_algorithms = COMPRESSION_ALGORITHMS;
+
+    let mut cookie = [0; 16];
+    writer.random.fill(&mut cookie);
+
+    let client_kexinit = Kexinit {
+        cookie,
+        kex_algorithms: NameList(match state.session_id.is_empty() {
+            true => FIRST_KEX_ALGORITHMS,
+            false => KEX_ALGORITHMS,
+        }),
+        server_host_key_algorithms: NameList(match state.options.host_cert_authorities {
+            Some(_) => CERT_HOST_KEY_ALGORITHMS,
+            None => HOST_KEY_ALGORITHMS,
+        }),
+        encryption_algorithms_client_to_server: NameList(ENCRYPTION_ALGORITHMS),
+        encryption_algorithms_server_to_client: NameList(ENCRYPTION_ALGORITHMS),
+        mac_algorithms_client_to_server: NameList(MAC_ALGORITHMS),
+        mac_algorithms_server_to_client: NameList(MAC_ALGORITHMS),
+        compression_algorithms_client_to_server: NameList(compression_algorithms),
+        compression_algorithms_server_to_client: NameList(compression_algorithms),
+        languages_client_to_server: NameList(""),
+        languages_server_to_client: NameList(""),
+        first_kex_packet_follows: false,
+        nop: 0,
+    };
+
+    let mut client_kexinit_payload = Vec::new();
+    client_kexinit.dump(&mut client_kexinit_payload)?;
+    let client_kexinit_payload = &client_kexinit_payload.into_boxed_slice();
+
+    writer.send(&client_kexinit).await?;
+    reader.in_kex = true;
+
+    // the server may still be sending other messages
+    let server_kexinit_payload = match server_kexinit {
+        Some(payload) => payload,
+        None => loop {
+            let payload = reader.recv_raw().await?.to_vec();
+            match payload.first() {
+                Some(&KEXINIT) => break payload,
+                _ => reader.defer(payload),
+            }
+        },
+    };
+    let server_kexinit_payload = &server_kexinit_payload.into_boxed_slice();
+    let (server_kexinit, _) = Kexinit::parse(server_kexinit_payload)?;
+    let negotiated = server_kexinit.check_compat(&client_kexinit)?;
+    log::info!("negotiated: {:?}", negotiated);
+
+    if server_kexinit.wrong_guess(&client_kexinit) {
+        let guessed = reader.recv_raw().await?;
+        log::info!("Ignoring the server's guessed key exchange packet (type = {:?})", guessed.first());
+    }
+
+    let kex_context = KexContext {
+        client_header: VERSION_HEADER,
+        server_header: state.peer_version.as_bytes(),
+        client_kexinit_payload,
+        server_kexinit_payload,
+        host_key_algorithm: negotiated.host_key,
+    };
+
+    let KexOutput {
+        exchange_hash,
+        shared_secret,
+        host_key,
+    } = match negotiated.kex {
+        "curve25519-sha256" | "curve25519-sha256@libssh.org" => curve25519_sha256(reader, writer, &kex_context).await?,
+        "diffie-hellman-group-exchange-sha256" => {
+            let group_size = state.options.gex_group_size;
+            diffie_hellman_group_exchange_sha256(reader, writer, &kex_context, group_size).await?
+        },
+        "diffie-hellman-group14-sha256" => diffie_hellman_group14_sha256(reader, writer, &kex_context).await?,
+        _ => unreachable!(),
+    };
+
+    if state.session_id.is_empty() {
+        state.session_id = exchange_hash.to_vec();
+    }
+
+    if let Some(authorities) = &state.options.host_cert_authorities {
+        authorities.check(&host_key)?;
+    }
+
+    state.host_key = host_key;
+
+    writer.send(&Newkeys {}).await?;
+    let _: Newkeys = reader.recv().await?;
+    reader.in_kex = false;
+
+    log::trace!("Got server Newkeys");
+
+    let kex = KeyExchangeOutput::new(UnsignedMpInt(&shared_secret), &exchange_hash, &state.session_id, &negotiated)?;
+    #[cfg(feature = "keylog")]
+    log_keys(&state.options.key_log, &cookie, &shared_secret, &kex);
+
+    let transport = kex.client_to_server.transport(negotiated.encryption_client_to_server, negotiated.mac_client_to_server);
+    writer.set_encryptor(transport);
+
+    let transport = kex.server_to_client.transport(negotiated.encryption_server_to_client, negotiated.mac_server_to_client);
+    reader.set_decryptor(transport);
+
+    #[cfg(feature = "zlib")]
+    enable_compression(reader, writer, &negotiated, ZLIB);
+    #[cfg(feature = "zlib")]
+    if state.authenticated {
+        enable_compression(reader, writer, &negotiated, ZLIB_OPENSSH);
+    }
+
+    state.last_kex = Instant::now();
+    Ok(negotiated)
+}
+
+/// Starts compressing the directions for which `algorithm` was negotiated
+#[cfg(feature = "zlib")]
+fn enable_compression<R, W>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    negotiated: &NegotiatedAlgorithms,
+    algorithm: &str,
+) {
+    if negotiated.compression_client_to_server == algorithm {
+        writer.enable_compression();
+    }
+
+    if negotiated.compression_server_to_client == algorithm {
+        reader.enable_decompression();
+    }
+}
+
+/// Key material of both directions, sized for the negotiated algorithms
 pub struct KeyExchangeOutput {
-    c2s_iv:   [u8; 16],
-    s2c_iv:   [u8; 16],
-    c2s_key:  [u8; 32],
-    s2c_key:  [u8; 32],
-    c2s_hmac: [u8; 32],
-    s2c_hmac: [u8; 32],
+    client_to_server: DirectionKeys,
+    server_to_client: DirectionKeys,
 }
 
 impl KeyExchangeOutput {
-    fn fill_array<const N: usize>(
+    /// Derives `len` bytes of key material (RFC 4253, section 7.2)
+    fn derive(
         dumped_shared_secret: &[u8],
         exchange_hash: &[u8],
         session_id: &[u8],
         magic_byte: u8,
-    ) -> Result<[u8; N]> {
-        let mut out_key = [0u8; N];
-        let mut progress = 0;
+        len: usize,
+    ) -> Result<Zeroizing<Vec<u8>>> {
+        let mut out_key = Zeroizing::new(Vec::with_capacity(len));
 
-        let mut appendage = sha256(&[
+        let mut appendage = Zeroizing::new(sha256(&[
             dumped_shared_secret,
             exchange_hash,
             &[magic_byte],
             session_id,
-        ].as_slice())?;
+        ].as_slice())?);
 
         loop {
-            let len = appendage.len().min(N - progress);
-            out_key[progress..][..len].copy_from_slice(&appendage[..len]);
-            progress += len;
+            let missing = appendage.len().min(len - out_key.len());
+            out_key.extend_from_slice(&appendage[..missing]);
 
-            if progress != N {
-                appendage = sha256(&[
+            if out_key.len() != len {
+                *appendage = sha256(&[
                     dumped_shared_secret,
                     exchange_hash,
-                    &out_key[..progress],
+                    out_key.as_slice(),
                 ].as_slice())?;
             } else {
                 break;
@@ -318,32 +1356,296 @@ impl KeyExchangeOutput {
         Ok(out_key)
     }
 
-    pub fn new(shared_secret: UnsignedMpInt, exchange_hash: &[u8], session_id: &[u8]) -> Result<Self> {
-        let mut dumped_shared_secret = Vec::new();
-        shared_secret.dump(&mut dumped_shared_secret)?;
+    pub fn new(
+        shared_secret: UnsignedMpInt,
+        exchange_hash: &[u8],
+        session_id: &[u8],
+        negotiated: &NegotiatedAlgorithms,
+    ) -> Result<Self> {
+        let mut dumped_shared_secret = Zeroizing::new(Vec::new());
+        shared_secret.dump(&mut *dumped_shared_secret)?;
         let dumped_shared_secret = dumped_shared_secret.as_slice();
 
-        let kex_output_16 = |magic_byte| Self::fill_array(dumped_shared_secret, exchange_hash, session_id, magic_byte);
-        let c2s_iv:   [u8; 16] = kex_output_16(b'A')?;
-        let s2c_iv:   [u8; 16] = kex_output_16(b'B')?;
-
-        let kex_output_32 = |magic_byte| Self::fill_array(dumped_shared_secret, exchange_hash, session_id, magic_byte);
-        let c2s_key:  [u8; 32] = kex_output_32(b'C')?;
-        let s2c_key:  [u8; 32] = kex_output_32(b'D')?;
-        let c2s_hmac: [u8; 32] = kex_output_32(b'E')?;
-        let s2c_hmac: [u8; 32] = kex_output_32(b'F')?;
+        let derive = |magic_byte, len| Self::derive(dumped_shared_secret, exchange_hash, session_id, magic_byte, len);
+        let direction = |magic_bytes: [u8; 3], cipher, mac| -> Result<DirectionKeys> {
+            let (iv_len, key_len, mac_key_len) = DirectionKeys::lengths(cipher, mac);
+            Ok(DirectionKeys {
+                iv: derive(magic_bytes[0], iv_len)?,
+                key: derive(magic_bytes[1], key_len)?,
+                mac_key: derive(magic_bytes[2], mac_key_len)?,
+            })
+        };
 
         Ok(Self {
-            c2s_iv,
-            s2c_iv,
-            c2s_key,
-            s2c_key,
-            c2s_hmac,
-            s2c_hmac,
+            client_to_server: direction(*b"ACE", negotiated.encryption_client_to_server, negotiated.mac_client_to_server)?,
+            server_to_client: direction(*b"BDF", negotiated.encryption_server_to_client, negotiated.mac_server_to_client)?,
         })
     }
 }
 
+/// See [`ConnectOptions::key_log`]
+#[cfg(feature = "keylog")]
+fn log_keys(key_log: &KeyLog, cookie: &[u8; 16], shared_secret: &[u8], kex: &KeyExchangeOutput) {
+    if key_log.0.is_none() {
+        return;
+    }
+
+    let cookie = encode_hex(cookie);
+    let log = |label: &str, secret: &[u8]| if !secret.is_empty() {
+        let secret = Zeroizing::new(encode_hex(secret));
+        key_log.write_line(format_args!("{} {} {}", cookie, label, secret.as_str()));
+    };
+
+    log("SHARED_SECRET", shared_secret);
+    for (direction, keys) in [("CLIENT_TO_SERVER", &kex.client_to_server), ("SERVER_TO_CLIENT", &kex.server_to_client)] {
+        log(&format!("{}_IV", direction), &keys.iv);
+        log(&format!("{}_KEY", direction), &keys.key);
+        log(&format!("{}_MAC_KEY", direction), &keys.mac_key);
+    }
+}
+
+fn decode_keypair(hex_keypair: &str) -> Result<Keypair> {
+    let bytes: Zeroizing<[u8; 64]> = Zeroizing::new(decode_hex(hex_keypair).ok_or(ErrorKind::InvalidKeypair)?);
+    Keypair::from_bytes(&*bytes).map_err(|_| ErrorKind::InvalidKeypair.into())
+}
+
+/// A `publickey` authentication attempt (RFC 4252, section 7)
+struct PublicKeyRequest<'a> {
+    username: &'a str,
+    signer: &'a dyn AuthSigner,
+}
+
+impl<'a> PublicKeyRequest<'a> {
+    /// Checks that the server accepts the key, then proves that
+    /// we own it; returns `Some(rejection)` if it was rejected
+    /// before the signature was sent.
+    async fn send<R: Source, W: Sink>(
+        &self,
+        reader: &mut PacketReader<R>,
+        writer: &mut PacketWriter<W>,
+        session_id: &[u8],
+        banner: &mut Option<String>,
+    ) -> Result<Option<Rejection>> {
+        let service_name = "ssh-connection";
+        let algorithm = self.signer.algorithm();
+        let public_key = self.signer.public_blob();
+
+        writer.send(&UserauthRequest::PublicKey {
+            username: self.username,
+            service_name,
+            algorithm,
+            blob: &public_key,
+            signature: None,
+        }).await?;
+
+        log::trace!("Awaiting UserauthPkOk");
+        match recv_auth_reply(reader, banner).await? {
+            AuthReply::PkOk => (/* nice */),
+            AuthReply::Failure(rejection) => return Ok(Some(rejection)),
+            AuthReply::Success => {
+                return Err(Error::new(ErrorKind::UnexpectedMessageType(MessageType::UserauthSuccess)).context("expected UserauthPkOk, got UserauthSuccess"));
+            },
+        }
+        log::trace!("Got UserauthPkOk");
+
+        let signature = sign_userauth(self.signer, session_id, self.username, service_name, algorithm, &public_key)?;
+        let header = self.signer.signature_algorithm();
+
+        writer.send(&UserauthRequest::PublicKey {
+            username: self.username,
+            service_name,
+            algorithm,
+            blob: &public_key,
+            signature: Some(Blob {
+                header,
+                content: &signature,
+            }),
+        }).await?;
+
+        Ok(None)
+    }
+}
+
+/// Tries one authentication method; returns `None` if it succeeded
+/// or the server's rejection
+async fn authenticate<R: Source, W: Sink>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    session_id: &[u8],
+    auth: Auth<'_>,
+    banner: &mut Option<String>,
+) -> Result<Option<Rejection>> {
+    let service_name = "ssh-connection";
+    match auth {
+        Auth::None {
+            username,
+        } => {
+            writer.send(&UserauthRequest::None {
+                username,
+                service_name,
+            }).await?;
+        },
+        Auth::Password {
+            username,
+            password,
+        } => {
+            writer.send(&UserauthRequest::Password {
+                username,
+                service_name,
+                password,
+                new_password: None,
+            }).await?;
+        },
+        Auth::Ed25519 {
+            username,
+            hex_keypair,
+        } => {
+            let signer = Ed25519Signer { keypair: decode_keypair(hex_keypair)?, certificate: None };
+            let request = PublicKeyRequest { username, signer: &signer };
+            if let Some(rejection) = request.send(reader, writer, session_id, banner).await? {
+                return Ok(Some(rejection));
+            }
+        },
+        Auth::Ed25519Certificate {
+            username,
+            hex_keypair,
+            certificate,
+        } => {
+            let keypair = decode_keypair(hex_keypair)?;
+
+            let parsed = Certificate::parse(certificate).map_err(|_| ErrorKind::InvalidKeypair)?;
+            if parsed.cert_type != USER_CERT || parsed.public_key != keypair.public.as_bytes() {
+                return Err(Error::new(ErrorKind::InvalidKeypair).context("the certificate isn't a user certificate for this keypair"));
+            }
+
+            let signer = Ed25519Signer { keypair, certificate: Some(certificate) };
+            let request = PublicKeyRequest { username, signer: &signer };
+            if let Some(rejection) = request.send(reader, writer, session_id, banner).await? {
+                return Ok(Some(rejection));
+            }
+        },
+        Auth::Rsa {
+            username,
+            key,
+        } => {
+            let request = PublicKeyRequest { username, signer: key };
+            if let Some(rejection) = request.send(reader, writer, session_id, banner).await? {
+                return Ok(Some(rejection));
+            }
+        },
+        Auth::EcdsaP256 {
+            username,
+            key,
+        } => {
+            let request = PublicKeyRequest { username, signer: key };
+            if let Some(rejection) = request.send(reader, writer, session_id, banner).await? {
+                return Ok(Some(rejection));
+            }
+        },
+        Auth::Signer {
+            username,
+            signer,
+        } => {
+            let request = PublicKeyRequest { username, signer };
+            if let Some(rejection) = request.send(reader, writer, session_id, banner).await? {
+                return Ok(Some(rejection));
+            }
+        },
+        Auth::KeyboardInteractive {
+            username,
+            responses,
+        } => {
+            writer.send(&UserauthRequest::KeyboardInteractive {
+                username,
+                service_name,
+                submethods: "",
+            }).await?;
+
+            answer_info_requests(reader, writer, responses, banner).await?;
+        },
+        Auth::Any(_) => unreachable!("flattened by the caller"),
+    }
+
+    log::trace!("Awaiting UserauthSuccess");
+    match recv_auth_reply(reader, banner).await? {
+        AuthReply::Success => Ok(None),
+        AuthReply::Failure(rejection) => Ok(Some(rejection)),
+        AuthReply::PkOk => Err(Error::new(ErrorKind::UnexpectedMessageType(MessageType::UserauthPkOk)).context("expected UserauthSuccess, got UserauthPkOk")),
+    }
+}
+
+enum AuthReply {
+    Success,
+    Failure(Rejection),
+    PkOk,
+}
+
+/// An owned `UserauthFailure`
+struct Rejection {
+    /// the methods which can continue
+    allowed_auth: String,
+    /// true if the method succeeded but more are required
+    partial_success: bool,
+}
+
+/// Answers the info requests of the `keyboard-interactive` method, until
+/// the server sends something else, which is left for [`recv_auth_reply`]
+async fn answer_info_requests<R: Source, W: Sink>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    responses: &[&str],
+    banner: &mut Option<String>,
+) -> Result<()> {
+    let mut responses = responses.iter().copied();
+    loop {
+        let payload = reader.recv_payload().await?;
+        match payload.first() {
+            Some(&USERAUTH_INFO_REQUEST) => {
+                let request: UserauthInfoRequest = reader.last()?;
+                log::trace!("Got UserauthInfoRequest {:?} with {} prompts", request.name, request.prompts.len());
+                if !request.instruction.is_empty() {
+                    log::info!("Keyboard-interactive instruction: {}", request.instruction);
+                }
+
+                let responses = request.prompts.iter().map(|_| responses.next().unwrap_or_default()).collect();
+                writer.send(&UserauthInfoResponse { responses }).await?;
+            },
+            Some(&number) if number == MessageType::UserauthBanner as u8 => {
+                let UserauthBanner { message, .. } = reader.last()?;
+                log::info!("Banner: {}", message);
+                banner.get_or_insert_with(String::new).push_str(message);
+            },
+            _ => {
+                let payload = payload.to_vec();
+                reader.defer(payload);
+                break Ok(());
+            },
+        }
+    }
+}
+
+/// Receives the response to a UserauthRequest, collecting
+/// the banners which the server can send meanwhile
+async fn recv_auth_reply<R: Source>(reader: &mut PacketReader<R>, banner: &mut Option<String>) -> Result<AuthReply> {
+    loop {
+        match reader.recv().await? {
+            Message::UserauthBanner(UserauthBanner { message, .. }) => {
+                log::info!("Banner: {}", message);
+                banner.get_or_insert_with(String::new).push_str(message);
+            },
+            Message::UserauthSuccess(_) => break Ok(AuthReply::Success),
+            Message::UserauthFailure(failure) => break Ok(AuthReply::Failure(Rejection {
+                allowed_auth: failure.allowed_auth.into(),
+                partial_success: failure.partial_success,
+            })),
+            Message::UserauthPkOk(_) => break Ok(AuthReply::PkOk),
+            msg => {
+                break Err(Error::new(ErrorKind::UnexpectedMessageType(msg.typ())).context(format!("expected a reply to UserauthRequest, got {:?}", msg)));
+            },
+        }
+    }
+}
+
 impl<'a> From<(&'a str, &'a str)> for Auth<'a> {
     fn from(tuple: (&'a str, &'a str)) -> Auth<'a> {
         let (username, hex_keypair) = tuple;
@@ -354,7 +1656,47 @@ impl<'a> From<(&'a str, &'a str)> for Auth<'a> {
     }
 }
 
-impl core::fmt::Debug for Connection {
+// doesn't leak secrets in logs
+impl<'a> core::fmt::Debug for Auth<'a> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::None { username } => f.debug_struct("None")
+                .field("username", username)
+                .finish(),
+            Self::Password { username, .. } => f.debug_struct("Password")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Ed25519 { username, .. } => f.debug_struct("Ed25519")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Ed25519Certificate { username, .. } => f.debug_struct("Ed25519Certificate")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Rsa { username, .. } => f.debug_struct("Rsa")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::EcdsaP256 { username, .. } => f.debug_struct("EcdsaP256")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Signer { username, signer } => f.debug_struct("Signer")
+                .field("username", username)
+                .field("algorithm", &signer.algorithm())
+                .finish_non_exhaustive(),
+            Self::KeyboardInteractive { username, .. } => f.debug_struct("KeyboardInteractive")
+                .field("username", username)
+                .finish_non_exhaustive(),
+            Self::Any(list) => f.debug_tuple("Any").field(list).finish(),
+        }
+    }
+}
+
+impl<S: Stream> Drop for Connection<S> {
+    fn drop(&mut self) {
+        let _ = block_on(self.inner().disconnect(DisconnectReasonCode::ByApplication, "", ""));
+    }
+}
+
+impl<S: Stream> core::fmt::Debug for Connection<S> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("Connection").finish()
     }
@@ -0,0 +1,137 @@
+//! Destinations as users type them: `user@host:port` or
+//! `ssh://user@host:port` (RFC 3986 and the SSH URI draft)
+
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::vec::IntoIter;
+use core::fmt;
+use super::{Result, Error, ErrorKind, IoResult};
+
+const DEFAULT_PORT: u16 = 22;
+
+/// Where to connect, and as whom:
+///
+/// ```no_run
+/// use coolssh::{Connection, Destination};
+///
+/// let destination = Destination::parse("git@[2001:db8::1]:2222").unwrap();
+/// let username = destination.username.as_deref().unwrap_or("root");
+/// let conn = Connection::connect(&destination, (username, "<hex keypair>").into(), Default::default()).unwrap();
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Destination {
+    pub username: Option<String>,
+    /// Hostname or IP address, without the brackets of IPv6 literals
+    pub host: String,
+    pub port: u16,
+}
+
+impl Destination {
+    /// Parses `[user@]host[:port]` or `ssh://[user@]host[:port][/]`;
+    /// IPv6 literals go in brackets when followed by a port. The port
+    /// defaults to 22.
+    pub fn parse(destination: &str) -> Result<Self> {
+        let invalid = |message: &str| Error::new(ErrorKind::InvalidData).context(format!("{}: {:?}", message, destination));
+
+        let (is_url, rest) = match destination.strip_prefix("ssh://") {
+            Some(rest) => (true, rest.strip_suffix('/').unwrap_or(rest)),
+            None => (false, destination),
+        };
+
+        if is_url && rest.contains(['/', '?', '#']) {
+            return Err(invalid("SSH URLs can't have a path, query or fragment"));
+        }
+
+        let (username, host_port) = match rest.rsplit_once('@') {
+            Some((username, host_port)) => {
+                // connection parameters (`;fingerprint=...`) aren't supported
+                let username = match is_url {
+                    true => percent_decode(username.split(';').next().unwrap_or("")).ok_or_else(|| invalid("invalid percent-encoding"))?,
+                    false => username.into(),
+                };
+
+                if username.is_empty() {
+                    return Err(invalid("empty username"));
+                }
+
+                (Some(username), host_port)
+            },
+            None => (None, rest),
+        };
+
+        let (host, port) = if let Some(bracketed) = host_port.strip_prefix('[') {
+            let (host, after) = bracketed.split_once(']').ok_or_else(|| invalid("unclosed bracket"))?;
+            match after {
+                "" => (host, None),
+                after => (host, Some(after.strip_prefix(':').ok_or_else(|| invalid("unexpected characters after the host"))?)),
+            }
+        } else if host_port.matches(':').count() > 1 {
+            // an IPv6 literal without brackets, hence without port
+            (host_port, None)
+        } else {
+            match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            }
+        };
+
+        if host.is_empty() {
+            return Err(invalid("empty host"));
+        }
+
+        let port = match port {
+            Some(port) => port.parse().map_err(|_| invalid("invalid port"))?,
+            None => DEFAULT_PORT,
+        };
+
+        Ok(Self {
+            username,
+            host: host.into(),
+            port,
+        })
+    }
+}
+
+/// Resolves the host, so that a `&Destination` can be given to [`Connection::connect`](crate::Connection::connect)
+impl ToSocketAddrs for Destination {
+    type Iter = IntoIter<SocketAddr>;
+
+    fn to_socket_addrs(&self) -> IoResult<Self::Iter> {
+        (self.host.as_str(), self.port).to_socket_addrs()
+    }
+}
+
+/// Formats it back as `[user@]host[:port]`, omitting port 22
+impl fmt::Display for Destination {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(username) = &self.username {
+            write!(f, "{}@", username)?;
+        }
+
+        match self.host.contains(':') {
+            true => write!(f, "[{}]", self.host)?,
+            false => write!(f, "{}", self.host)?,
+        }
+
+        match self.port {
+            DEFAULT_PORT => Ok(()),
+            port => write!(f, ":{}", port),
+        }
+    }
+}
+
+fn percent_decode(encoded: &str) -> Option<String> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                let hex = core::str::from_utf8(&hex).ok()?;
+                decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            },
+            byte => decoded.push(byte),
+        }
+    }
+
+    String::from_utf8(decoded).ok()
+}
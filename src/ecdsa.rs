@@ -0,0 +1,119 @@
+//! ECDSA client keys over NIST P-256 (RFC 5656)
+
+use alloc::{format, vec::Vec};
+use p256::ecdsa::{SigningKey, Signature, signature::Signer};
+use super::{ErrorKind, Result, Error};
+use super::parsedump::ParseDump;
+use super::messages::UnsignedMpInt;
+use super::keygen::{decode_openssh_key, invalid_keypair};
+use super::userauth::AuthSigner;
+
+const ECDSA_P256: &str = "ecdsa-sha2-nistp256";
+const NISTP256: &str = "nistp256";
+
+/// An `ecdsa-sha2-nistp256` private key, for [`Auth::EcdsaP256`](crate::Auth::EcdsaP256):
+///
+/// ```no_run
+/// use coolssh::{Connection, Auth, EcdsaP256Key};
+///
+/// let file = std::fs::read("/home/user/.ssh/id_ecdsa").unwrap();
+/// let key = EcdsaP256Key::from_openssh(&file, None).unwrap();
+/// let auth = Auth::EcdsaP256 { username: "user", key: &key };
+/// let conn = Connection::connect("example.com:22", auth, Default::default()).unwrap();
+/// ```
+#[derive(Clone)]
+pub struct EcdsaP256Key {
+    signing_key: SigningKey,
+}
+
+impl EcdsaP256Key {
+    /// Loads an `ecdsa-sha2-nistp256` key from an OpenSSH private key
+    /// file (e.g. `~/.ssh/id_ecdsa`); `passphrase` is required for encrypted keys.
+    pub fn from_openssh(file: &[u8], passphrase: Option<&str>) -> Result<Self> {
+        let private_key = decode_openssh_key(file, passphrase)?;
+        Self::parse_private_key(&private_key).map_err(invalid_keypair)
+    }
+
+    /// Uses a 32-byte big-endian private scalar
+    pub fn from_bytes(secret: &[u8]) -> Result<Self> {
+        let signing_key = SigningKey::from_bytes(secret).map_err(|_| ErrorKind::InvalidKeypair)?;
+        Ok(Self { signing_key })
+    }
+
+    fn parse_private_key(private_key: &[u8]) -> Result<Self> {
+        let (algorithm, mut i) = <&str>::parse(private_key)?;
+        if algorithm != ECDSA_P256 {
+            return Err(Error::new(ErrorKind::Unimplemented).context(format!("unsupported private key algorithm: {}", algorithm)));
+        }
+
+        let (curve, inc) = <&str>::parse(&private_key[i..])?;
+        i += inc;
+        let (public_point, inc) = <&[u8]>::parse(&private_key[i..])?;
+        i += inc;
+        let (secret, _) = UnsignedMpInt::parse(&private_key[i..])?;
+
+        if curve != NISTP256 {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid ECDSA curve: {}", curve)));
+        }
+
+        // the mpint may have a leading zero, or fewer than 32 bytes
+        let start = secret.0.iter().position(|b| *b != 0).unwrap_or(secret.0.len());
+        let secret = &secret.0[start..];
+        if secret.len() > 32 {
+            return Err(Error::new(ErrorKind::InvalidData).context("ECDSA private scalar is too big"));
+        }
+
+        let mut scalar = [0; 32];
+        scalar[32 - secret.len()..].copy_from_slice(secret);
+        let key = Self::from_bytes(&scalar)?;
+
+        match key.public_point() == public_point {
+            true => Ok(key),
+            false => Err(Error::new(ErrorKind::InvalidData).context("corrupted private key")),
+        }
+    }
+
+    /// The uncompressed SEC1 public point
+    fn public_point(&self) -> Vec<u8> {
+        self.signing_key.verifying_key().to_encoded_point(false).as_bytes().to_vec()
+    }
+
+    /// The `ecdsa-sha2-nistp256` public key blob (RFC 5656, section 3.1)
+    pub fn public_key_blob(&self) -> Vec<u8> {
+        let mut blob = Vec::new();
+        // dumping to a Vec doesn't fail
+        let _ = ECDSA_P256.dump(&mut blob);
+        let _ = NISTP256.dump(&mut blob);
+        let _ = self.public_point().as_slice().dump(&mut blob);
+        blob
+    }
+}
+
+impl AuthSigner for EcdsaP256Key {
+    fn algorithm(&self) -> &str {
+        ECDSA_P256
+    }
+
+    fn public_blob(&self) -> Vec<u8> {
+        self.public_key_blob()
+    }
+
+    /// Deterministic (RFC 6979) signature of the SHA-256 digest of
+    /// `data`, as the `r` and `s` mpints of RFC 5656, section 3.1.2
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature: Signature = self.signing_key.sign(data);
+        let (r, s) = signature.split_bytes();
+
+        let mut scalars = Vec::new();
+        UnsignedMpInt(&r).dump(&mut scalars)?;
+        UnsignedMpInt(&s).dump(&mut scalars)?;
+        Ok(scalars)
+    }
+}
+
+// doesn't leak secrets in logs
+impl core::fmt::Debug for EcdsaP256Key {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("EcdsaP256Key").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,210 @@
+//! The error type of this crate
+
+use alloc::{borrow::Cow, boxed::Box, format};
+use core::fmt;
+#[cfg(feature = "std")]
+use std::io::{Error as IoError, ErrorKind as IoErrorKind};
+use super::{MessageType, DisconnectReasonCode};
+#[cfg(feature = "std")]
+use super::{AuthMethods, SftpStatus};
+
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// Fatal errors: an [`ErrorKind`], which can be matched on, with
+/// an optional message and source giving more context
+///
+/// Errors are equal if their kinds and messages are; sources
+/// aren't compared.
+///
+/// ```no_run
+/// use coolssh::{Connection, ErrorKind};
+///
+/// match Connection::connect("localhost:22", ("user", "<hex keypair>").into(), Default::default()) {
+///     Ok(_conn) => (),
+///     Err(error) if error.kind() == ErrorKind::Timeout => println!("the server is busy"),
+///     Err(error) => println!("couldn't connect: {}", error),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: Option<Cow<'static, str>>,
+    source: Option<Box<dyn core::error::Error + Send + Sync>>,
+}
+
+/// What went wrong, see [`Error::kind`]
+///
+/// New variants may be added in minor releases.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// No data to be read / send buffer is full.
+    Timeout,
+    /// Errors related to the TCP socket
+    #[cfg(feature = "std")]
+    TcpError(IoErrorKind),
+    /// Invalid data type/encoding/size
+    InvalidData,
+    /// A packet failed its integrity check, i.e. its MAC or AEAD tag
+    /// didn't match: it was corrupted or tampered with
+    MacMismatch,
+    /// The proxy rejected our credentials, or there were none to try
+    AuthenticationFailure,
+    /// The server rejected our last authentication attempt
+    #[cfg(feature = "std")]
+    AuthRejected {
+        /// The methods which can continue, according to the server
+        methods_left: AuthMethods,
+        /// True if the last method succeeded but the server requires more
+        partial: bool,
+    },
+    /// The keypair given in [`Auth`](crate::Auth) couldn't be decoded
+    InvalidKeypair,
+    /// The private key file is encrypted and no passphrase was given
+    PassphraseRequired,
+    /// The channel was closed by the server
+    ProcessHasExited,
+    /// A valid message which makes no sense at this point
+    UnexpectedMessageType(MessageType),
+    /// A message number which is unknown (or, for 30 to 49,
+    /// which was received outside of a key exchange)
+    UnknownMessageType(u8),
+    /// The SFTP server reported a failure
+    #[cfg(feature = "std")]
+    Sftp(SftpStatus),
+    /// This can be raised instead of UnexpectedMessageType, if the peer sends random bytes
+    Unimplemented,
+    /// The server stopped answering keepalives
+    ConnectionLost,
+    /// The server sent a message for a channel which was never opened
+    UnknownChannel(u32),
+    /// The server's host key isn't trusted (see [`ConnectOptions::host_cert_authorities`](crate::ConnectOptions::host_cert_authorities))
+    UntrustedHostKey,
+    /// The server announced a packet larger than [`ConnectOptions::max_packet_length`](crate::ConnectOptions::max_packet_length)
+    PacketTooLarge,
+    /// The remote command didn't exit with code zero (see
+    /// [`Connection::quick_run_checked`](crate::Connection::quick_run_checked));
+    /// `code` is `None` if it was killed or if its status is unknown.
+    CommandFailed {
+        code: Option<u32>,
+    },
+    /// The server closed the connection with a Disconnect message,
+    /// whose description is the [message](Error::message) of the error
+    Disconnected {
+        reason: DisconnectReasonCode,
+    },
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind) -> Self {
+        Self {
+            kind,
+            message: None,
+            source: None,
+        }
+    }
+
+    /// Describes what was going on, e.g. which field couldn't be parsed;
+    /// if there already is a message, the new one is prepended to it.
+    pub fn context(mut self, message: impl Into<Cow<'static, str>>) -> Self {
+        let message = message.into();
+        self.message = Some(match self.message.take() {
+            Some(previous) => format!("{}: {}", message, previous).into(),
+            None => message,
+        });
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> Option<&str> {
+        self.message.as_deref()
+    }
+}
+
+impl PartialEq for Error {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.message == other.message
+    }
+}
+
+impl Eq for Error {}
+
+impl From<ErrorKind> for Error {
+    fn from(kind: ErrorKind) -> Self {
+        Self::new(kind)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Timeout => write!(f, "timed out"),
+            #[cfg(feature = "std")]
+            Self::TcpError(kind) => write!(f, "I/O error ({})", kind),
+            Self::InvalidData => write!(f, "invalid data"),
+            Self::MacMismatch => write!(f, "packet integrity check failed"),
+            Self::AuthenticationFailure => write!(f, "authentication failed"),
+            #[cfg(feature = "std")]
+            Self::AuthRejected { methods_left, partial: false } => write!(f, "authentication rejected ({:?} left)", methods_left),
+            #[cfg(feature = "std")]
+            Self::AuthRejected { methods_left, partial: true } => write!(f, "partial authentication ({:?} left)", methods_left),
+            Self::InvalidKeypair => write!(f, "invalid keypair"),
+            Self::PassphraseRequired => write!(f, "a passphrase is required"),
+            Self::ProcessHasExited => write!(f, "the channel was closed"),
+            Self::UnexpectedMessageType(typ) => write!(f, "unexpected message: {:?}", typ),
+            Self::UnknownMessageType(typ) => write!(f, "unknown message type: {}", typ),
+            #[cfg(feature = "std")]
+            Self::Sftp(status) => write!(f, "SFTP failure: {:?}", status),
+            Self::Unimplemented => write!(f, "unimplemented message"),
+            Self::ConnectionLost => write!(f, "the server stopped answering keepalives"),
+            Self::UnknownChannel(channel) => write!(f, "unknown channel: {}", channel),
+            Self::UntrustedHostKey => write!(f, "untrusted host key"),
+            Self::PacketTooLarge => write!(f, "packet too large"),
+            Self::CommandFailed { code: Some(code) } => write!(f, "the command failed with exit code {}", code),
+            Self::CommandFailed { code: None } => write!(f, "the command failed"),
+            Self::Disconnected { reason } => write!(f, "disconnected by the server ({:?})", reason),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.message {
+            Some(message) => write!(f, "{}: {}", self.kind, message),
+            None => write!(f, "{}", self.kind),
+        }
+    }
+}
+
+impl core::error::Error for Error {
+    fn source(&self) -> Option<&(dyn core::error::Error + 'static)> {
+        self.source.as_ref().map(|source| source.as_ref() as _)
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<IoError> for Error {
+    fn from(err: IoError) -> Self {
+        Self {
+            kind: ErrorKind::TcpError(err.kind()),
+            message: None,
+            source: Some(Box::new(err)),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl From<Error> for IoError {
+    fn from(err: Error) -> Self {
+        let kind = match err.kind {
+            ErrorKind::Timeout => IoErrorKind::TimedOut,
+            ErrorKind::TcpError(kind) => kind,
+            _ => IoErrorKind::Other,
+        };
+
+        IoError::new(kind, err)
+    }
+}
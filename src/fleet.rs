@@ -0,0 +1,109 @@
+//! Running a command on many hosts at once, like `pssh`
+
+use std::net::ToSocketAddrs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use core::fmt;
+use super::{Connection, Auth, ConnectOptions, RunResult, ExitStatus, Result};
+
+const DEFAULT_CONCURRENCY: usize = 8;
+
+/// Runs commands on several hosts, with a bounded number of
+/// connections at once:
+///
+/// ```no_run
+/// use coolssh::{Fleet, RunResult};
+///
+/// let fleet = Fleet::new(("user", "<hex keypair>").into(), Default::default()).concurrency(16);
+/// let hosts = ["web1.example.com:22", "web2.example.com:22", "db.example.com:22"];
+///
+/// fleet.run(&hosts, "uptime", |output| match output.result {
+///     Ok(RunResult::Accepted((stdout, _status))) => print!("{}: {}", output.host, stdout),
+///     Ok(RunResult::Refused) => println!("{}: refused", output.host),
+///     Err(error) => println!("{}: {}", output.host, error),
+/// });
+/// ```
+///
+/// Every host is logged into with the same credentials.
+pub struct Fleet<'a> {
+    auth: Auth<'a>,
+    options: ConnectOptions,
+    concurrency: usize,
+}
+
+/// What running the command on one host gave, see [`Fleet::run`]
+#[derive(Debug)]
+pub struct HostOutput {
+    /// Position of the host in the list
+    pub index: usize,
+    /// The host, as displayed
+    pub host: String,
+    pub result: Result<RunResult<(String, ExitStatus)>>,
+}
+
+impl<'a> Fleet<'a> {
+    pub fn new(auth: Auth<'a>, options: ConnectOptions) -> Self {
+        Self {
+            auth,
+            options,
+            concurrency: DEFAULT_CONCURRENCY,
+        }
+    }
+
+    /// How many hosts are connected to at once (default: 8)
+    pub fn concurrency(mut self, max: usize) -> Self {
+        self.concurrency = max.max(1);
+        self
+    }
+
+    /// Connects to each host and runs `command` with
+    /// [`Connection::quick_run`], passing the outputs to
+    /// `callback` as they complete
+    pub fn run<A, F>(&self, hosts: &[A], command: &str, mut callback: F)
+    where
+        A: ToSocketAddrs + fmt::Display + Sync,
+        F: FnMut(HostOutput),
+    {
+        let next = AtomicUsize::new(0);
+        let (sender, receiver) = mpsc::channel();
+
+        std::thread::scope(|scope| {
+            for _ in 0..self.concurrency.min(hosts.len()) {
+                let (next, sender) = (&next, sender.clone());
+                scope.spawn(move || loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(host) = hosts.get(index) else {
+                        break;
+                    };
+
+                    let result = Connection::connect(host, self.auth, self.options.clone())
+                        .and_then(|conn| conn.quick_run(command));
+
+                    let output = HostOutput {
+                        index,
+                        host: host.to_string(),
+                        result,
+                    };
+
+                    if sender.send(output).is_err() {
+                        break;
+                    }
+                });
+            }
+
+            drop(sender);
+            receiver.iter().for_each(&mut callback);
+        });
+    }
+
+    /// Same as [`Self::run`], returning the outputs in the order of `hosts`
+    pub fn run_all<A>(&self, hosts: &[A], command: &str) -> Vec<HostOutput>
+    where
+        A: ToSocketAddrs + fmt::Display + Sync,
+    {
+        let mut outputs = Vec::with_capacity(hosts.len());
+        self.run(hosts, command, |output| outputs.push(output));
+        outputs.sort_by_key(|output| output.index);
+        outputs
+    }
+}
@@ -0,0 +1,427 @@
+//! Channels opened by the server (e.g. X11 connections, RFC 4254,
+//! section 6.3.2, or OpenSSH agent connections), relayed to local streams
+
+use std::collections::HashMap;
+use std::io::{Read, Write, Result as IoResult, Error as IoError, ErrorKind as IoErrorKind};
+use std::net::{TcpStream, Shutdown};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use super::{Result, Connection};
+use super::connection::ConnectionInner;
+use super::parsedump::ParseDump;
+use super::stream::{Stream, Source, Sink};
+use super::packets::PacketWriter;
+use super::messages::{
+    ChannelOpen, ChannelOpenConfirmation, ChannelOpenFailure, ChannelRequest,
+    ChannelData, ChannelEof, ChannelClose, ChannelWindowAdjust, ChannelFailure, Message,
+};
+
+const RELAY_WINDOW_SIZE: u32 = 0x200000;
+const RELAY_WIN_TELL_TRIGGER: u32 = RELAY_WINDOW_SIZE / 2;
+const RELAY_MAX_PACKET_SIZE: u32 = 0x8000;
+
+const OPEN_ADMINISTRATIVELY_PROHIBITED: u32 = 1;
+const OPEN_CONNECT_FAILED: u32 = 2;
+
+/// A local stream which channels opened by the server are relayed to
+pub trait LocalStream: Read + Write + Send {
+    /// Another handle to the stream, which is read from by a separate thread
+    fn try_clone_reader(&self) -> IoResult<Box<dyn Read + Send>>;
+
+    /// Makes reads from the other handle return, once the channel is closed
+    fn shutdown(&self) -> IoResult<()>;
+}
+
+impl LocalStream for TcpStream {
+    fn try_clone_reader(&self) -> IoResult<Box<dyn Read + Send>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn shutdown(&self) -> IoResult<()> {
+        TcpStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+#[cfg(unix)]
+impl LocalStream for std::os::unix::net::UnixStream {
+    fn try_clone_reader(&self) -> IoResult<Box<dyn Read + Send>> {
+        Ok(Box::new(self.try_clone()?))
+    }
+
+    fn shutdown(&self) -> IoResult<()> {
+        std::os::unix::net::UnixStream::shutdown(self, Shutdown::Both)
+    }
+}
+
+type Connector = Box<dyn FnMut() -> IoResult<Box<dyn LocalStream>> + Send>;
+
+/// X11 forwarding parameters, see [`Connection::set_x11_forwarding`]
+pub struct X11Forwarding {
+    auth_protocol: String,
+    auth_cookie: String,
+    screen: u32,
+    single_connection: bool,
+    connector: Connector,
+}
+
+impl X11Forwarding {
+    /// `auth_cookie` is hex-encoded, as listed by `xauth list`; it is
+    /// sent to the server as is, so only forward X11 to trusted servers.
+    ///
+    /// By default, connections are relayed to the X server of `$DISPLAY`.
+    pub fn new(auth_protocol: &str, auth_cookie: &str) -> Self {
+        Self {
+            auth_protocol: auth_protocol.into(),
+            auth_cookie: auth_cookie.into(),
+            screen: 0,
+            single_connection: false,
+            connector: Box::new(connect_display),
+        }
+    }
+
+    /// The screen number to display on (default: 0)
+    pub fn screen(mut self, screen: u32) -> Self {
+        self.screen = screen;
+        self
+    }
+
+    /// Only forward the first X11 connection (default: false)
+    pub fn single_connection(mut self, enabled: bool) -> Self {
+        self.single_connection = enabled;
+        self
+    }
+
+    /// Opens the local stream which each X11 connection is relayed to
+    pub fn connector<F>(mut self, connector: F) -> Self
+        where F: FnMut() -> IoResult<Box<dyn LocalStream>> + Send + 'static
+    {
+        self.connector = Box::new(connector);
+        self
+    }
+}
+
+// doesn't leak the cookie in logs
+impl core::fmt::Debug for X11Forwarding {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("X11Forwarding")
+            .field("auth_protocol", &self.auth_protocol)
+            .field("screen", &self.screen)
+            .field("single_connection", &self.single_connection)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Connects to the X server of `$DISPLAY` (`[host]:display[.screen]`)
+fn connect_display() -> IoResult<Box<dyn LocalStream>> {
+    let invalid = || IoError::new(IoErrorKind::NotFound, "DISPLAY is unset or invalid");
+    let display = std::env::var("DISPLAY").map_err(|_| invalid())?;
+    let (host, number) = display.rsplit_once(':').ok_or_else(invalid)?;
+    let number = number.split('.').next().unwrap_or(number);
+    let number: u16 = number.parse().map_err(|_| invalid())?;
+
+    #[cfg(unix)]
+    if host.is_empty() || host == "unix" {
+        let path = format!("/tmp/.X11-unix/X{}", number);
+        return Ok(Box::new(std::os::unix::net::UnixStream::connect(path)?));
+    }
+
+    let host = match host.is_empty() {
+        true => "localhost",
+        false => host,
+    };
+
+    Ok(Box::new(TcpStream::connect((host, 6000 + number))?))
+}
+
+/// Connects to the agent listening on `$SSH_AUTH_SOCK`
+fn connect_agent() -> IoResult<Box<dyn LocalStream>> {
+    let path = std::env::var_os("SSH_AUTH_SOCK")
+        .ok_or_else(|| IoError::new(IoErrorKind::NotFound, "SSH_AUTH_SOCK is unset"))?;
+
+    #[cfg(unix)]
+    return Ok(Box::new(std::os::unix::net::UnixStream::connect(path)?));
+
+    #[cfg(not(unix))]
+    return Err(IoError::new(IoErrorKind::Unsupported, format!("can't connect to {:?}", path)));
+}
+
+impl<S: Stream> Connection<S> {
+    /// Requests agent forwarding for the sessions opened afterwards by
+    /// [`Self::run`] and [`Self::shell`], relaying the agent connections
+    /// of remote commands (e.g. `git` or `ssh`) to `$SSH_AUTH_SOCK`.
+    ///
+    /// Like for [`Self::set_x11_forwarding`], the stream's read timeout
+    /// must be set for the agent's replies to go through.
+    pub fn set_agent_forwarding(&self, enabled: bool) {
+        self.inner().forwarder.agent = match enabled {
+            true => Some(Box::new(connect_agent)),
+            false => None,
+        };
+    }
+
+    /// Requests X11 forwarding for the sessions opened afterwards by
+    /// [`Self::run`] and [`Self::shell`]; `None` disables it.
+    ///
+    /// X11 connections are relayed while the connection reads
+    /// (e.g. in [`Run::poll`](crate::Run::poll)), so the stream's read
+    /// timeout must be set for replies of the X server to go through.
+    pub fn set_x11_forwarding(&self, x11: Option<X11Forwarding>) {
+        let mut inner = self.inner();
+        inner.forwarder.x11 = x11;
+        inner.forwarder.x11_opened = false;
+    }
+}
+
+/// Channels opened by the server, and what they may be relayed to
+#[derive(Default)]
+pub(crate) struct Forwarder {
+    x11: Option<X11Forwarding>,
+    /// an X11 channel was opened (see [`X11Forwarding::single_connection`])
+    x11_opened: bool,
+    agent: Option<Connector>,
+    /// by client channel number
+    relays: HashMap<u32, Relay>,
+}
+
+/// A channel opened by the server, relayed to a local stream
+struct Relay {
+    server_channel: u32,
+    local: Box<dyn LocalStream>,
+    /// filled by the reading thread; `None` marks the end of the stream
+    received: Receiver<Option<Vec<u8>>>,
+    /// read from the local stream, waiting for the server's window
+    outgoing: Vec<u8>,
+    local_eof: bool,
+    close_sent: bool,
+    server_window: usize,
+    server_max_packet_size: usize,
+    client_window: u32,
+}
+
+impl Forwarder {
+    /// Whether `client_channel` is relayed
+    pub(crate) fn relays(&self, client_channel: u32) -> bool {
+        self.relays.contains_key(&client_channel)
+    }
+
+    /// The requests to send before starting a session, for the enabled forwardings
+    fn requests(&self, recipient_channel: u32) -> impl Iterator<Item = ChannelRequest<'_>> {
+        let x11 = self.x11.as_ref().map(|x11| ChannelRequest::X11Req {
+            recipient_channel,
+            want_reply: false,
+            single_connection: x11.single_connection,
+            auth_protocol: &x11.auth_protocol,
+            auth_cookie: &x11.auth_cookie,
+            screen: x11.screen,
+        });
+
+        let agent = self.agent.as_ref().map(|_| ChannelRequest::AuthAgentReq {
+            recipient_channel,
+            want_reply: false,
+        });
+
+        x11.into_iter().chain(agent)
+    }
+
+    /// Opens the local stream for a channel of type `channel_type`
+    fn connect(&mut self, channel_type: &str) -> core::result::Result<Box<dyn LocalStream>, (u32, String)> {
+        let connector = match (channel_type, &mut self.x11) {
+            ("x11", Some(x11)) if !(x11.single_connection && self.x11_opened) => {
+                self.x11_opened = true;
+                Some(&mut x11.connector)
+            },
+            ("auth-agent@openssh.com", _) => self.agent.as_mut(),
+            _ => None,
+        };
+
+        match connector {
+            Some(connector) => connector().map_err(|error| (OPEN_CONNECT_FAILED, error.to_string())),
+            None => Err((OPEN_ADMINISTRATIVELY_PROHIBITED, format!("{} channels are not accepted", channel_type))),
+        }
+    }
+}
+
+impl Relay {
+    fn new(server_channel: u32, server_window: u32, server_max_packet_size: u32, local: Box<dyn LocalStream>) -> IoResult<Self> {
+        let mut reader = local.try_clone_reader()?;
+        let (sender, received) = mpsc::channel();
+
+        std::thread::spawn(move || {
+            let mut buf = vec![0; RELAY_MAX_PACKET_SIZE as usize];
+            loop {
+                let chunk = match reader.read(&mut buf) {
+                    Ok(0) | Err(_) => None,
+                    Ok(len) => Some(buf[..len].to_vec()),
+                };
+
+                let end = chunk.is_none();
+                if sender.send(chunk).is_err() || end {
+                    break;
+                }
+            }
+        });
+
+        Ok(Self {
+            server_channel,
+            local,
+            received,
+            outgoing: Vec::new(),
+            local_eof: false,
+            close_sent: false,
+            server_window: server_window as _,
+            server_max_packet_size: server_max_packet_size as _,
+            client_window: RELAY_WINDOW_SIZE,
+        })
+    }
+
+    /// Sends what was read from the local stream, as the server's window allows
+    async fn flush<W: Sink>(&mut self, writer: &mut PacketWriter<W>) -> Result<()> {
+        while !self.local_eof {
+            match self.received.try_recv() {
+                Ok(Some(chunk)) => self.outgoing.extend_from_slice(&chunk),
+                Ok(None) | Err(TryRecvError::Disconnected) => self.local_eof = true,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+
+        while !self.outgoing.is_empty() && self.server_window > 0 && !self.close_sent {
+            let len = self.outgoing.len().min(self.server_window).min(self.server_max_packet_size);
+            writer.send(&ChannelData {
+                recipient_channel: self.server_channel,
+                data: &self.outgoing[..len],
+            }).await?;
+
+            self.outgoing.drain(..len);
+            self.server_window -= len;
+        }
+
+        if self.local_eof && self.outgoing.is_empty() && !self.close_sent {
+            writer.send(&ChannelEof {
+                recipient_channel: self.server_channel,
+            }).await?;
+
+            writer.send(&ChannelClose {
+                recipient_channel: self.server_channel,
+            }).await?;
+
+            self.close_sent = true;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for Relay {
+    fn drop(&mut self) {
+        let _ = self.local.shutdown();
+    }
+}
+
+impl<R: Source, W: Sink> ConnectionInner<R, W> {
+    /// Queues the requests of the enabled forwardings on a session
+    /// channel; see [`Self::queue`]
+    pub(crate) async fn request_forwarding(&mut self, recipient_channel: u32) -> Result<()> {
+        self.rekey_if_due().await?;
+        for request in self.forwarder.requests(recipient_channel) {
+            self.writer.queue(&request).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Accepts or rejects a ChannelOpen sent by the server
+    pub(crate) async fn accept_channel(&mut self, payload: &[u8]) -> Result<()> {
+        // the sender's fields are named from the server's point of view
+        let (ChannelOpen {
+            channel_type,
+            client_channel: server_channel,
+            client_initial_window_size: server_window,
+            client_max_packet_size: server_max_packet_size,
+        }, _) = ChannelOpen::parse(payload)?;
+
+        let relay = self.forwarder.connect(channel_type).and_then(|local| {
+            Relay::new(server_channel, server_window, server_max_packet_size, local)
+                .map_err(|error| (OPEN_CONNECT_FAILED, error.to_string()))
+        });
+
+        match relay {
+            Ok(relay) => {
+                log::info!("Accepting {} channel {}", channel_type, server_channel);
+                let client_channel = self.allocate_channel();
+                self.forwarder.relays.insert(client_channel, relay);
+
+                self.writer.send(&ChannelOpenConfirmation {
+                    client_channel: server_channel,
+                    server_channel: client_channel,
+                    server_initial_window_size: RELAY_WINDOW_SIZE,
+                    server_max_packet_size: RELAY_MAX_PACKET_SIZE,
+                }).await
+            },
+            Err((reason_code, description)) => {
+                log::info!("Rejecting {} channel {}: {}", channel_type, server_channel, description);
+                self.writer.send(&ChannelOpenFailure {
+                    client_channel: server_channel,
+                    reason_code,
+                    description: &description,
+                    language_tag: "",
+                }).await
+            },
+        }
+    }
+
+    /// Handles a message for the relayed channel `client_channel`
+    pub(crate) async fn relay_message(&mut self, client_channel: u32, payload: &[u8]) -> Result<()> {
+        let Some(relay) = self.forwarder.relays.get_mut(&client_channel) else {
+            return Ok(());
+        };
+
+        match Message::parse(payload)?.0 {
+            Message::ChannelData(ChannelData { data, .. }) => {
+                if relay.local.write_all(data).is_err() {
+                    log::warn!("Couldn't relay data of channel {}", client_channel);
+                    relay.local_eof = true;
+                }
+
+                relay.client_window = relay.client_window.saturating_sub(data.len() as u32);
+                if relay.client_window < RELAY_WIN_TELL_TRIGGER {
+                    self.writer.send(&ChannelWindowAdjust {
+                        recipient_channel: relay.server_channel,
+                        bytes_to_add: RELAY_WINDOW_SIZE - relay.client_window,
+                    }).await?;
+
+                    relay.client_window = RELAY_WINDOW_SIZE;
+                }
+            },
+            Message::ChannelWindowAdjust(ChannelWindowAdjust { bytes_to_add, .. }) => {
+                relay.server_window += bytes_to_add as usize;
+            },
+            Message::ChannelClose(_) => {
+                if !relay.close_sent {
+                    self.writer.send(&ChannelClose {
+                        recipient_channel: relay.server_channel,
+                    }).await?;
+                }
+
+                self.forwarder.relays.remove(&client_channel);
+                return Ok(());
+            },
+            Message::ChannelRequest(ChannelRequest::Other { request_type, want_reply: true, .. }) => {
+                log::info!("Refusing {} request on relayed channel {}", request_type, client_channel);
+                self.writer.send(&ChannelFailure {
+                    recipient_channel: relay.server_channel,
+                }).await?;
+            },
+            msg => log::trace!("Ignoring {:?} on relayed channel {}", msg.typ(), client_channel),
+        }
+
+        relay.flush(&mut self.writer).await
+    }
+
+    /// Sends what was read from the local streams of relayed channels
+    pub(crate) async fn flush_relays(&mut self) -> Result<()> {
+        for relay in self.forwarder.relays.values_mut() {
+            relay.flush(&mut self.writer).await?;
+        }
+
+        Ok(())
+    }
+}
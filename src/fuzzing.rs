@@ -0,0 +1,29 @@
+//! Entry points for fuzzers (e.g. the `cargo fuzz` targets in `fuzz/`),
+//! reaching parsers which aren't otherwise public; they must return
+//! errors, never panic, whatever their input.
+
+use core::ops::Range;
+use super::{Message, Result};
+use super::parsedump::ParseDump;
+use super::certificate::Certificate;
+
+/// Parses a message payload, as received in a packet
+pub fn parse_message(payload: &[u8]) -> Result<Message<'_>> {
+    Message::parse(payload).map(|(message, _)| message)
+}
+
+/// Checks the padding of a decrypted packet, from its `padding_length`
+/// byte to the end of its padding; returns the range of its payload
+pub fn depad(packet: &[u8]) -> Result<Range<usize>> {
+    super::packets::depad(packet)
+}
+
+/// Parses an OpenSSH certificate blob, then checks its signature
+pub fn parse_certificate(blob: &[u8]) -> Result<()> {
+    Certificate::parse(blob)?.verify()
+}
+
+/// Checks a signature of the exchange hash, as sent by a server
+pub fn verify_host_signature(algorithm: &str, host_key: &[u8], signature: &[u8], data: &[u8]) -> Result<()> {
+    super::hostkey::verify_host_signature(algorithm, host_key, signature, data)
+}
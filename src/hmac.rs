@@ -1,52 +1,129 @@
-use sha2::{Sha256, Digest};
+use sha2::{Sha256, Sha512, Digest};
+use sha2::digest::core_api::BlockSizeUser;
+use sha1::Sha1;
+
+/// Largest MAC we produce (hmac-sha2-512)
+pub const MAX_MAC_SIZE: usize = 64;
+
+/// MAC algorithms we support, by order of preference
+pub const MAC_ALGORITHMS: &str = concat!(
+    "hmac-sha2-256-etm@openssh.com,",
+    "hmac-sha2-512-etm@openssh.com,",
+    "hmac-sha1-etm@openssh.com,",
+    "hmac-sha2-256,",
+    "hmac-sha2-512,",
+    "hmac-sha1",
+);
 
 #[derive(Clone)]
-pub struct Hmac {
-    ih: Sha256,
-    output_xor: [u8; 64],
+pub struct Hmac<D> {
+    ih: D,
+    oh: D,
 }
 
-fn xor(mut array: [u8; 64], byte: u8) -> [u8; 64] {
-    for b in array.iter_mut() {
-        *b ^= byte;
+fn xor(array: &[u8], byte: u8) -> [u8; 128] {
+    let mut xored = [0; 128];
+    for (dst, b) in xored.iter_mut().zip(array) {
+        *dst = *b ^ byte;
     }
 
-    array
+    xored
 }
 
-impl Hmac {
+impl<D: Digest + BlockSizeUser> Hmac<D> {
     pub fn new(key: impl AsRef<[u8]>) -> Self {
         let key = key.as_ref();
+        let block_size = D::block_size();
 
-        let stack_array: [u8; 32];
-        let key = if key.len() > 64 {
-            let mut hashed_key = Sha256::new();
-            hashed_key.update(key);
-            stack_array = hashed_key.finalize().into();
-            &stack_array
+        let hashed_key;
+        let key = if key.len() > block_size {
+            hashed_key = D::digest(key);
+            hashed_key.as_slice()
         } else {
             key
         };
 
-        let mut padded = [0; 64];
+        let mut padded = [0; 128];
         padded[..key.len()].copy_from_slice(key);
+        let padded = &padded[..block_size];
+
+        let mut ih = D::new();
+        ih.update(&xor(padded, 0x36)[..block_size]);
 
-        let input_xor = xor(padded, 0x36);
-        let output_xor = xor(padded, 0x5C);
+        let mut oh = D::new();
+        oh.update(&xor(padded, 0x5C)[..block_size]);
 
-        let mut ih = Sha256::new();
-        ih.update(&input_xor);
-        Self { ih, output_xor }
+        Self { ih, oh }
     }
 
     pub fn update(&mut self, input: impl AsRef<[u8]>) {
         self.ih.update(input);
     }
 
-    pub fn finalize(self) -> [u8; 32] {
-        let mut oh = Sha256::new();
-        oh.update(&self.output_xor);
+    /// Writes the MAC to the beginning of `output`
+    pub fn finalize_into(self, output: &mut [u8]) {
+        let mut oh = self.oh;
         oh.update(self.ih.finalize());
-        oh.finalize().into()
+        let mac = oh.finalize();
+        output[..mac.len()].copy_from_slice(&mac);
+    }
+}
+
+/// Integrity protection of packets, for one direction
+///
+/// The MAC is computed over the sequence number and the
+/// packet, length field included.
+pub trait Mac {
+    fn size(&self) -> usize;
+
+    /// Encrypt-then-MAC: the length field is sent in clear and the
+    /// MAC covers the encrypted packet (`-etm@openssh.com` variants)
+    fn encrypt_then_mac(&self) -> bool;
+
+    /// Writes the MAC of `packet` to the beginning of `output`
+    fn compute(&self, packet_number: u32, packet: &[u8], output: &mut [u8]);
+}
+
+struct HmacAlgorithm<D> {
+    hmac: Hmac<D>,
+    size: usize,
+    etm: bool,
+}
+
+impl<D: Digest + BlockSizeUser + Clone> Mac for HmacAlgorithm<D> {
+    fn size(&self) -> usize {
+        self.size
     }
-}
\ No newline at end of file
+
+    fn encrypt_then_mac(&self) -> bool {
+        self.etm
+    }
+
+    fn compute(&self, packet_number: u32, packet: &[u8], output: &mut [u8]) {
+        let mut hmac = self.hmac.clone();
+        hmac.update(packet_number.to_be_bytes());
+        hmac.update(packet);
+        hmac.finalize_into(output);
+    }
+}
+
+/// Length of the key of a negotiated MAC, which is also the size of its MACs
+pub fn mac_key_len(algorithm: &str) -> usize {
+    match algorithm.trim_end_matches("-etm@openssh.com") {
+        "hmac-sha2-512" => 64,
+        "hmac-sha1" => 20,
+        _ => 32,
+    }
+}
+
+/// Builds a negotiated MAC; `key` must be [`mac_key_len`] long.
+pub fn mac(algorithm: &str, key: &[u8]) -> Box<dyn Mac + Send> {
+    let etm = algorithm.ends_with("-etm@openssh.com");
+    let size = key.len();
+
+    match algorithm.trim_end_matches("-etm@openssh.com") {
+        "hmac-sha2-512" => Box::new(HmacAlgorithm { hmac: Hmac::<Sha512>::new(key), size, etm }),
+        "hmac-sha1" => Box::new(HmacAlgorithm { hmac: Hmac::<Sha1>::new(key), size, etm }),
+        _ => Box::new(HmacAlgorithm { hmac: Hmac::<Sha256>::new(key), size, etm }),
+    }
+}
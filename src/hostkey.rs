@@ -0,0 +1,148 @@
+use alloc::{format, vec::Vec};
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256, Sha512};
+use super::{ErrorKind, Result, Error, Verifier};
+use super::parsedump::ParseDump;
+use super::messages::UnsignedMpInt;
+use super::certificate::{Certificate, ED25519_CERT};
+use super::rsa::{RSA_MIN_BITS, SHA256_DIGEST_INFO, SHA512_DIGEST_INFO};
+
+/// Server host key algorithms we can verify, by order of preference
+macro_rules! host_key_algorithms {
+    () => ("ssh-ed25519,ecdsa-sha2-nistp256,rsa-sha2-512,rsa-sha2-256")
+}
+
+pub const HOST_KEY_ALGORITHMS: &str = host_key_algorithms!();
+/// Offered when certificate authorities are trusted
+pub const CERT_HOST_KEY_ALGORITHMS: &str = concat!("ssh-ed25519-cert-v01@openssh.com,", host_key_algorithms!());
+
+/// Interprets an mpint which must be positive
+pub(crate) fn positive_mpint(mpint: UnsignedMpInt) -> Result<BigUint> {
+    match mpint.0.first() {
+        Some(byte) if (byte & 0x80) != 0 => Err(Error::new(ErrorKind::InvalidData).context("negative mpint where a positive one was expected")),
+        _ => Ok(BigUint::from_bytes_be(mpint.0)),
+    }
+}
+
+/// Checks that `signature` (an encoded signature blob) is a valid
+/// signature of `data` by `host_key` (an encoded public key blob),
+/// using the negotiated host key `algorithm`.
+pub fn verify_host_signature(algorithm: &str, host_key: &[u8], signature: &[u8], data: &[u8]) -> Result<()> {
+    // signed by the certified key; the certificate is checked by the caller
+    if algorithm == ED25519_CERT {
+        let certificate = Certificate::parse(host_key)?;
+        return verify_host_signature("ssh-ed25519", &certificate.key_blob()?, signature, data);
+    }
+
+    let (key_type, key_fields) = split_blob(host_key)?;
+    let (signature_type, signature_fields) = split_blob(signature)?;
+    let (signature, _) = <&[u8]>::parse(signature_fields)?;
+
+    if signature_type != algorithm {
+        return Err(Error::new(ErrorKind::InvalidData).context(format!("host key signature is {}, expected {}", signature_type, algorithm)));
+    }
+
+    match (algorithm, key_type) {
+        ("ssh-ed25519", "ssh-ed25519") => verify_ed25519(key_fields, signature, data),
+        ("ecdsa-sha2-nistp256", "ecdsa-sha2-nistp256") => verify_ecdsa_p256(key_fields, signature, data),
+        ("rsa-sha2-256", "ssh-rsa") => verify_rsa(key_fields, signature, SHA256_DIGEST_INFO, &Sha256::digest(data)),
+        ("rsa-sha2-512", "ssh-rsa") => verify_rsa(key_fields, signature, SHA512_DIGEST_INFO, &Sha512::digest(data)),
+        _ => Err(Error::new(ErrorKind::InvalidData).context(format!("host key of type {} doesn't match algorithm {}", key_type, algorithm))),
+    }
+}
+
+/// Returns the algorithm name of a blob and the fields which follow it
+fn split_blob(blob: &[u8]) -> Result<(&str, &[u8])> {
+    let (name, i) = <&str>::parse(blob)?;
+    Ok((name, &blob[i..]))
+}
+
+fn verify_ed25519(key_fields: &[u8], signature: &[u8], data: &[u8]) -> Result<()> {
+    let (host_pubkey_bytes, _) = <&[u8]>::parse(key_fields)?;
+
+    if host_pubkey_bytes.len() != 32 {
+        return Err(Error::new(ErrorKind::InvalidData).context(format!("ed25519 host key is {} bytes long, expected 32", host_pubkey_bytes.len())));
+    }
+
+    if signature.len() != 64 {
+        return Err(Error::new(ErrorKind::InvalidData).context(format!("ed25519 signature is {} bytes long, expected 64", signature.len())));
+    }
+
+    let host_pubkey = ed25519_dalek::PublicKey::from_bytes(host_pubkey_bytes).map_err(|e| Error::new(ErrorKind::InvalidData).context(format!("couldn't reconstruct server public key: {}", e)))?;
+
+    let signature = {
+        let mut sig_array = [0; 64];
+        sig_array.copy_from_slice(signature);
+        ed25519_dalek::Signature::from(sig_array)
+    };
+
+    host_pubkey.verify(data, &signature).map_err(|e| Error::new(ErrorKind::InvalidData).context(format!("exchange hash couldn't be verified: {}", e)))
+}
+
+/// ECDSA over NIST P-256 with SHA-256 (RFC 5656, section 3.1)
+fn verify_ecdsa_p256(key_fields: &[u8], signature: &[u8], data: &[u8]) -> Result<()> {
+    use p256::ecdsa::{Signature, VerifyingKey, signature::Verifier};
+
+    let (curve, i) = <&str>::parse(key_fields)?;
+    let (point, _) = <&[u8]>::parse(&key_fields[i..])?;
+
+    if curve != "nistp256" {
+        return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid ECDSA host key curve: {}", curve)));
+    }
+
+    let host_pubkey = VerifyingKey::from_sec1_bytes(point).map_err(|_| Error::new(ErrorKind::InvalidData).context("couldn't reconstruct server public key (ECDSA)"))?;
+
+    // r and s are mpints in the signature blob
+    let (r, i) = UnsignedMpInt::parse(signature)?;
+    let (s, _) = UnsignedMpInt::parse(&signature[i..])?;
+
+    let mut scalars = [0; 64];
+    for (scalar, dst) in [r, s].into_iter().zip(scalars.chunks_mut(32)) {
+        let start = scalar.0.iter().position(|b| *b != 0).unwrap_or(scalar.0.len());
+        let scalar = &scalar.0[start..];
+        if scalar.len() > 32 {
+            return Err(Error::new(ErrorKind::InvalidData).context("invalid ECDSA signature (scalar too big)"));
+        }
+
+        dst[32 - scalar.len()..].copy_from_slice(scalar);
+    }
+
+    let signature = Signature::try_from(scalars.as_slice()).map_err(|_| Error::new(ErrorKind::InvalidData).context("invalid ECDSA signature"))?;
+
+    host_pubkey.verify(data, &signature).map_err(|_| Error::new(ErrorKind::InvalidData).context("exchange hash couldn't be verified (ECDSA)"))
+}
+
+/// RSASSA-PKCS1-v1_5 verification (RFC 8332)
+fn verify_rsa(key_fields: &[u8], signature: &[u8], digest_info: &[u8], digest: &[u8]) -> Result<()> {
+    let (exponent, i) = UnsignedMpInt::parse(key_fields)?;
+    let (modulus, _) = UnsignedMpInt::parse(&key_fields[i..])?;
+    let exponent = positive_mpint(exponent)?;
+    let modulus = positive_mpint(modulus)?;
+
+    let modulus_len = modulus.bits().div_ceil(8) as usize;
+    if modulus.bits() < RSA_MIN_BITS || signature.len() > modulus_len {
+        return Err(Error::new(ErrorKind::InvalidData).context("invalid RSA host key or signature size"));
+    }
+
+    let signature = BigUint::from_bytes_be(signature);
+    if signature >= modulus {
+        return Err(Error::new(ErrorKind::InvalidData).context("RSA signature is out of range"));
+    }
+
+    let encoded = signature.modpow(&exponent, &modulus).to_bytes_be();
+
+    // 0x00 0x01 0xff..0xff 0x00 DigestInfo digest, the
+    // leading zero byte being dropped by to_bytes_be()
+    let padding_len = modulus_len - 3 - digest_info.len() - digest.len();
+    let mut expected = Vec::with_capacity(modulus_len);
+    expected.push(0x01);
+    expected.resize(1 + padding_len, 0xff);
+    expected.push(0x00);
+    expected.extend_from_slice(digest_info);
+    expected.extend_from_slice(digest);
+
+    match encoded == expected {
+        true => Ok(()),
+        false => Err(Error::new(ErrorKind::InvalidData).context("exchange hash couldn't be verified (RSA)")),
+    }
+}
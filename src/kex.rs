@@ -0,0 +1,450 @@
+use num_bigint::BigUint;
+use rand_core::RngCore;
+use zeroize::Zeroizing;
+use super::{ErrorKind, Result, Error, U8, Rng, sha256};
+use super::parsedump::{ParseDump, Buffer, parse_dump_struct};
+use super::messages::{UnsignedMpInt, MessageType};
+use super::hostkey::{verify_host_signature, positive_mpint};
+use super::packets::{PacketReader, PacketWriter};
+use super::stream::{Source, Sink};
+
+/// A kex method message, whose number `N` only makes sense
+/// in the context of the negotiated kex method.
+pub struct KexMessage<const N: u8, M>(pub M);
+
+impl<'b, const N: u8, M: ParseDump<'b>> ParseDump<'b> for KexMessage<N, M> {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        let (number, mut i) = u8::parse(bytes)?;
+        if number != N {
+            return Err(Error::new(ErrorKind::UnknownMessageType(number)).context(format!("expected kex message {} but got {}", N, number)));
+        }
+
+        let (inner, inc) = M::parse(&bytes[i..])?;
+        i += inc;
+
+        Ok((Self(inner), i))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        N.dump(sink)?;
+        self.0.dump(sink)
+    }
+}
+
+/// What every kex method hands over to the key derivation
+pub struct KexOutput {
+    pub exchange_hash: [u8; 32],
+    /// big-endian, unsigned; wiped when dropped
+    pub shared_secret: Zeroizing<Vec<u8>>,
+    /// blob of the server's public host key
+    pub host_key: Vec<u8>,
+}
+
+/// Inputs to the exchange hash which are common to all kex methods
+pub struct KexContext<'a> {
+    pub client_header: &'a [u8],
+    pub server_header: &'a [u8],
+    pub client_kexinit_payload: &'a [u8],
+    pub server_kexinit_payload: &'a [u8],
+    /// negotiated server host key algorithm
+    pub host_key_algorithm: &'a str,
+}
+
+// curve25519-sha256 (RFC 8731), using the ECDH messages of RFC 5656
+
+const KEX_ECDH_INIT: u8 = 30;
+const KEX_ECDH_REPLY: u8 = 31;
+
+parse_dump_struct!(KexEcdhInit<'a> {
+    client_ephemeral_pubkey: &'a [u8],
+});
+
+parse_dump_struct!(KexEcdhReply<'a> {
+    server_public_host_key: &'a [u8],
+    server_ephemeral_pubkey: &'a [u8],
+    exchange_hash_signature: &'a [u8],
+});
+
+parse_dump_struct!(EcdhExchangeHash<'a> {
+    client_header: &'a [u8],
+    server_header: &'a [u8],
+    client_kexinit_payload: &'a [u8],
+    server_kexinit_payload: &'a [u8],
+    server_public_host_key: &'a [u8],
+    client_ephemeral_pubkey: &'a [u8],
+    server_ephemeral_pubkey: &'a [u8],
+    shared_secret: UnsignedMpInt<'a>,
+});
+
+pub async fn curve25519_sha256<R: Source, W: Sink>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    context: &KexContext<'_>,
+) -> Result<KexOutput> {
+    let secret_key = x25519_dalek::EphemeralSecret::new(Rng);
+    let public_key = x25519_dalek::PublicKey::from(&secret_key);
+    let client_ephemeral_pubkey = public_key.as_bytes().as_slice();
+
+    writer.send(&KexMessage::<KEX_ECDH_INIT, _>(KexEcdhInit {
+        client_ephemeral_pubkey,
+    })).await?;
+
+    let KexMessage::<KEX_ECDH_REPLY, _>(KexEcdhReply {
+        server_public_host_key,
+        server_ephemeral_pubkey,
+        exchange_hash_signature,
+    }) = reader.recv().await?;
+
+    if server_ephemeral_pubkey.len() != 32 {
+        return Err(Error::new(ErrorKind::InvalidData).context("invalid Server KexEcdhReply (wrong field length)"));
+    }
+
+    let shared_secret = {
+        let mut sep_array = [0; 32];
+        sep_array.copy_from_slice(server_ephemeral_pubkey);
+        secret_key.diffie_hellman(&sep_array.into())
+    };
+
+    let exchange_hash = sha256(&EcdhExchangeHash {
+        client_header: context.client_header,
+        server_header: context.server_header,
+        client_kexinit_payload: context.client_kexinit_payload,
+        server_kexinit_payload: context.server_kexinit_payload,
+        server_public_host_key,
+        client_ephemeral_pubkey,
+        server_ephemeral_pubkey,
+        shared_secret: UnsignedMpInt(shared_secret.as_bytes()),
+    })?;
+
+    verify_host_signature(context.host_key_algorithm, server_public_host_key, exchange_hash_signature, &exchange_hash)?;
+
+    Ok(KexOutput {
+        exchange_hash,
+        shared_secret: Zeroizing::new(shared_secret.as_bytes().to_vec()),
+        host_key: server_public_host_key.to_vec(),
+    })
+}
+
+// diffie-hellman-group-exchange-sha256 (RFC 4419)
+
+const KEX_DH_GEX_GROUP: u8 = 31;
+const KEX_DH_GEX_INIT: u8 = 32;
+const KEX_DH_GEX_REPLY: u8 = 33;
+const KEX_DH_GEX_REQUEST: u8 = 34;
+
+/// Size of our private exponent; twice the strength
+/// of the strongest negotiable cipher (aes256).
+const DH_EXPONENT_BYTES: usize = 64;
+
+/// Modulus sizes, in bits, requested during DH group exchange
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GexGroupSize {
+    pub min: u32,
+    pub preferred: u32,
+    pub max: u32,
+}
+
+impl Default for GexGroupSize {
+    fn default() -> Self {
+        Self {
+            min: 2048,
+            preferred: 3072,
+            max: 8192,
+        }
+    }
+}
+
+parse_dump_struct!(#[derive(Copy, Clone)] KexDhGexRequest {
+    min: u32,
+    preferred: u32,
+    max: u32,
+});
+
+parse_dump_struct!(KexDhGexGroup<'a> {
+    prime: UnsignedMpInt<'a>,
+    generator: UnsignedMpInt<'a>,
+});
+
+parse_dump_struct!(KexDhGexInit<'a> {
+    client_public: UnsignedMpInt<'a>,
+});
+
+parse_dump_struct!(KexDhGexReply<'a> {
+    server_public_host_key: &'a [u8],
+    server_public: UnsignedMpInt<'a>,
+    exchange_hash_signature: &'a [u8],
+});
+
+// the GEX request and group are hashed between
+// the host key and the DH public values
+parse_dump_struct!(GexExchangeHash<'a> {
+    client_header: &'a [u8],
+    server_header: &'a [u8],
+    client_kexinit_payload: &'a [u8],
+    server_kexinit_payload: &'a [u8],
+    server_public_host_key: &'a [u8],
+    min: u32,
+    preferred: u32,
+    max: u32,
+    prime: UnsignedMpInt<'a>,
+    generator: UnsignedMpInt<'a>,
+    client_public: UnsignedMpInt<'a>,
+    server_public: UnsignedMpInt<'a>,
+    shared_secret: UnsignedMpInt<'a>,
+});
+
+/// Classic Diffie-Hellman over a MODP group
+struct DhGroup {
+    prime: BigUint,
+    generator: BigUint,
+    exponent: BigUint,
+}
+
+impl DhGroup {
+    fn new(prime: BigUint, generator: BigUint) -> Result<Self> {
+        let one = BigUint::from(1u8);
+        if prime.bits() < 1024 || generator <= one || generator >= (&prime - &one) {
+            return Err(Error::new(ErrorKind::InvalidData).context("invalid DH group"));
+        }
+
+        let mut bytes = [0; DH_EXPONENT_BYTES];
+        Rng.fill_bytes(&mut bytes);
+        // 1 < exponent < prime - 1
+        let exponent = BigUint::from_bytes_be(&bytes) | BigUint::from(2u8);
+
+        Ok(Self {
+            prime,
+            generator,
+            exponent,
+        })
+    }
+
+    fn public(&self) -> Vec<u8> {
+        self.generator.modpow(&self.exponent, &self.prime).to_bytes_be()
+    }
+
+    fn shared_secret(&self, peer_public: &BigUint) -> Result<Zeroizing<Vec<u8>>> {
+        // RFC 4253, section 8: 1 < f < p - 1
+        let one = BigUint::from(1u8);
+        if *peer_public <= one || *peer_public >= (&self.prime - &one) {
+            return Err(Error::new(ErrorKind::InvalidData).context("peer DH public value is out of range"));
+        }
+
+        Ok(Zeroizing::new(peer_public.modpow(&self.exponent, &self.prime).to_bytes_be()))
+    }
+}
+
+/// Exchange hash of a group exchange (RFC 4419, section 3),
+/// from the messages which were sent and received
+fn gex_exchange_hash(
+    context: &KexContext,
+    request: &KexDhGexRequest,
+    group: &KexDhGexGroup,
+    init: &KexDhGexInit,
+    reply: &KexDhGexReply,
+    shared_secret: &[u8],
+) -> Result<[u8; 32]> {
+    sha256(&GexExchangeHash {
+        client_header: context.client_header,
+        server_header: context.server_header,
+        client_kexinit_payload: context.client_kexinit_payload,
+        server_kexinit_payload: context.server_kexinit_payload,
+        server_public_host_key: reply.server_public_host_key,
+        min: request.min,
+        preferred: request.preferred,
+        max: request.max,
+        prime: group.prime,
+        generator: group.generator,
+        client_public: init.client_public,
+        server_public: reply.server_public,
+        shared_secret: UnsignedMpInt(shared_secret),
+    })
+}
+
+pub async fn diffie_hellman_group_exchange_sha256<R: Source, W: Sink>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    context: &KexContext<'_>,
+    group_size: GexGroupSize,
+) -> Result<KexOutput> {
+    let GexGroupSize {
+        min,
+        preferred,
+        max,
+    } = group_size;
+
+    let request = KexDhGexRequest {
+        min,
+        preferred,
+        max,
+    };
+    writer.send(&KexMessage::<KEX_DH_GEX_REQUEST, _>(request)).await?;
+
+    let (prime_bytes, generator_bytes, group) = {
+        let KexMessage::<KEX_DH_GEX_GROUP, _>(KexDhGexGroup {
+            prime,
+            generator,
+        }) = reader.recv().await?;
+
+        let group = DhGroup::new(positive_mpint(prime)?, positive_mpint(generator)?)?;
+        (prime.0.to_vec(), generator.0.to_vec(), group)
+    };
+
+    let prime_bits = group.prime.bits();
+    if prime_bits < min as u64 || prime_bits > max as u64 {
+        return Err(Error::new(ErrorKind::InvalidData).context(format!("server sent a {} bits DH group, out of [{}, {}]", prime_bits, min, max)));
+    }
+
+    let client_public = group.public();
+    let init = KexDhGexInit {
+        client_public: UnsignedMpInt(&client_public),
+    };
+    writer.send(&KexMessage::<KEX_DH_GEX_INIT, _>(init)).await?;
+
+    let KexMessage::<KEX_DH_GEX_REPLY, _>(reply) = reader.recv().await?;
+    let KexDhGexReply {
+        server_public_host_key,
+        server_public,
+        exchange_hash_signature,
+    } = reply;
+
+    let shared_secret = group.shared_secret(&positive_mpint(server_public)?)?;
+    let group_message = KexDhGexGroup {
+        prime: UnsignedMpInt(&prime_bytes),
+        generator: UnsignedMpInt(&generator_bytes),
+    };
+    let exchange_hash = gex_exchange_hash(context, &request, &group_message, &init, &reply, &shared_secret)?;
+
+    verify_host_signature(context.host_key_algorithm, server_public_host_key, exchange_hash_signature, &exchange_hash)?;
+
+    Ok(KexOutput {
+        exchange_hash,
+        shared_secret,
+        host_key: server_public_host_key.to_vec(),
+    })
+}
+
+// diffie-hellman-group14-sha256 (RFC 8268)
+
+const KEXDH_INIT: u8 = 30;
+const KEXDH_REPLY: u8 = 31;
+
+/// 2048-bit MODP group (RFC 3526, section 3), generator 2
+const GROUP14_PRIME: &[u8] = &[
+    0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xc9, 0x0f, 0xda, 0xa2, 0x21, 0x68, 0xc2, 0x34,
+    0xc4, 0xc6, 0x62, 0x8b, 0x80, 0xdc, 0x1c, 0xd1, 0x29, 0x02, 0x4e, 0x08, 0x8a, 0x67, 0xcc, 0x74,
+    0x02, 0x0b, 0xbe, 0xa6, 0x3b, 0x13, 0x9b, 0x22, 0x51, 0x4a, 0x08, 0x79, 0x8e, 0x34, 0x04, 0xdd,
+    0xef, 0x95, 0x19, 0xb3, 0xcd, 0x3a, 0x43, 0x1b, 0x30, 0x2b, 0x0a, 0x6d, 0xf2, 0x5f, 0x14, 0x37,
+    0x4f, 0xe1, 0x35, 0x6d, 0x6d, 0x51, 0xc2, 0x45, 0xe4, 0x85, 0xb5, 0x76, 0x62, 0x5e, 0x7e, 0xc6,
+    0xf4, 0x4c, 0x42, 0xe9, 0xa6, 0x37, 0xed, 0x6b, 0x0b, 0xff, 0x5c, 0xb6, 0xf4, 0x06, 0xb7, 0xed,
+    0xee, 0x38, 0x6b, 0xfb, 0x5a, 0x89, 0x9f, 0xa5, 0xae, 0x9f, 0x24, 0x11, 0x7c, 0x4b, 0x1f, 0xe6,
+    0x49, 0x28, 0x66, 0x51, 0xec, 0xe4, 0x5b, 0x3d, 0xc2, 0x00, 0x7c, 0xb8, 0xa1, 0x63, 0xbf, 0x05,
+    0x98, 0xda, 0x48, 0x36, 0x1c, 0x55, 0xd3, 0x9a, 0x69, 0x16, 0x3f, 0xa8, 0xfd, 0x24, 0xcf, 0x5f,
+    0x83, 0x65, 0x5d, 0x23, 0xdc, 0xa3, 0xad, 0x96, 0x1c, 0x62, 0xf3, 0x56, 0x20, 0x85, 0x52, 0xbb,
+    0x9e, 0xd5, 0x29, 0x07, 0x70, 0x96, 0x96, 0x6d, 0x67, 0x0c, 0x35, 0x4e, 0x4a, 0xbc, 0x98, 0x04,
+    0xf1, 0x74, 0x6c, 0x08, 0xca, 0x18, 0x21, 0x7c, 0x32, 0x90, 0x5e, 0x46, 0x2e, 0x36, 0xce, 0x3b,
+    0xe3, 0x9e, 0x77, 0x2c, 0x18, 0x0e, 0x86, 0x03, 0x9b, 0x27, 0x83, 0xa2, 0xec, 0x07, 0xa2, 0x8f,
+    0xb5, 0xc5, 0x5d, 0xf0, 0x6f, 0x4c, 0x52, 0xc9, 0xde, 0x2b, 0xcb, 0xf6, 0x95, 0x58, 0x17, 0x18,
+    0x39, 0x95, 0x49, 0x7c, 0xea, 0x95, 0x6a, 0xe5, 0x15, 0xd2, 0x26, 0x18, 0x98, 0xfa, 0x05, 0x10,
+    0x15, 0x72, 0x8e, 0x5a, 0x8a, 0xac, 0xaa, 0x68, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff,
+];
+
+parse_dump_struct!(KexdhInit<'a> {
+    client_public: UnsignedMpInt<'a>,
+});
+
+parse_dump_struct!(KexdhReply<'a> {
+    server_public_host_key: &'a [u8],
+    server_public: UnsignedMpInt<'a>,
+    exchange_hash_signature: &'a [u8],
+});
+
+parse_dump_struct!(DhExchangeHash<'a> {
+    client_header: &'a [u8],
+    server_header: &'a [u8],
+    client_kexinit_payload: &'a [u8],
+    server_kexinit_payload: &'a [u8],
+    server_public_host_key: &'a [u8],
+    client_public: UnsignedMpInt<'a>,
+    server_public: UnsignedMpInt<'a>,
+    shared_secret: UnsignedMpInt<'a>,
+});
+
+pub async fn diffie_hellman_group14_sha256<R: Source, W: Sink>(
+    reader: &mut PacketReader<R>,
+    writer: &mut PacketWriter<W>,
+    context: &KexContext<'_>,
+) -> Result<KexOutput> {
+    let group = DhGroup::new(BigUint::from_bytes_be(GROUP14_PRIME), BigUint::from(2u8))?;
+
+    let client_public = group.public();
+    writer.send(&KexMessage::<KEXDH_INIT, _>(KexdhInit {
+        client_public: UnsignedMpInt(&client_public),
+    })).await?;
+
+    let KexMessage::<KEXDH_REPLY, _>(KexdhReply {
+        server_public_host_key,
+        server_public,
+        exchange_hash_signature,
+    }) = reader.recv().await?;
+
+    let shared_secret = group.shared_secret(&positive_mpint(server_public)?)?;
+
+    let exchange_hash = sha256(&DhExchangeHash {
+        client_header: context.client_header,
+        server_header: context.server_header,
+        client_kexinit_payload: context.client_kexinit_payload,
+        server_kexinit_payload: context.server_kexinit_payload,
+        server_public_host_key,
+        client_public: UnsignedMpInt(&client_public),
+        server_public,
+        shared_secret: UnsignedMpInt(&shared_secret),
+    })?;
+
+    verify_host_signature(context.host_key_algorithm, server_public_host_key, exchange_hash_signature, &exchange_hash)?;
+
+    Ok(KexOutput {
+        exchange_hash,
+        shared_secret,
+        host_key: server_public_host_key.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Recorded from a real OpenSSH client; see the header of the file
+    const OPENSSH_GEX: &str = include_str!("../tests/fixtures/openssh_gex_sha256.txt");
+
+    fn recorded(name: &str) -> Vec<u8> {
+        let hex = OPENSSH_GEX.lines()
+            .find_map(|line| line.strip_prefix(name)?.strip_prefix(' '))
+            .unwrap_or_else(|| panic!("{} is missing from the fixture", name));
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..][..2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn gex_exchange_hash_matches_openssh() {
+        let (client_header, server_header) = (recorded("V_C"), recorded("V_S"));
+        let (client_kexinit_payload, server_kexinit_payload) = (recorded("I_C"), recorded("I_S"));
+        let context = KexContext {
+            client_header: &client_header,
+            server_header: &server_header,
+            client_kexinit_payload: &client_kexinit_payload,
+            server_kexinit_payload: &server_kexinit_payload,
+            host_key_algorithm: "ssh-ed25519",
+        };
+
+        let (request, group, init, reply) = (recorded("KEX_DH_GEX_REQUEST"), recorded("KEX_DH_GEX_GROUP"), recorded("KEX_DH_GEX_INIT"), recorded("KEX_DH_GEX_REPLY"));
+        let (KexMessage::<KEX_DH_GEX_REQUEST, KexDhGexRequest>(request), _) = ParseDump::parse(&request).unwrap();
+        let (KexMessage::<KEX_DH_GEX_GROUP, KexDhGexGroup>(group), _) = ParseDump::parse(&group).unwrap();
+        let (KexMessage::<KEX_DH_GEX_INIT, KexDhGexInit>(init), _) = ParseDump::parse(&init).unwrap();
+        let (KexMessage::<KEX_DH_GEX_REPLY, KexDhGexReply>(reply), _) = ParseDump::parse(&reply).unwrap();
+        assert_eq!((request.min, request.preferred, request.max), (2048, 8192, 8192));
+
+        let exchange_hash = gex_exchange_hash(&context, &request, &group, &init, &reply, &recorded("K")).unwrap();
+        assert_eq!(exchange_hash.as_slice(), recorded("H"));
+        verify_host_signature(context.host_key_algorithm, reply.server_public_host_key, reply.exchange_hash_signature, &exchange_hash).unwrap();
+    }
+}
@@ -1,47 +1,153 @@
 #![doc = include_str!("../README.md")]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use std::io::{Result as IoResult, Error as IoError, ErrorKind, BufReader, BufWriter, BufRead, Read, Write};
+extern crate alloc;
+
+#[cfg(feature = "std")]
+use std::io::{Result as IoResult, ErrorKind as IoErrorKind, BufReader, BufWriter, BufRead, Read, Write};
+#[cfg(feature = "std")]
 use std::net::TcpStream;
 use core::mem::size_of;
 
+#[cfg(feature = "std")]
 use rand_core::OsRng as Rng;
-use aes::cipher::{KeyIvInit, StreamCipher};
-use hmac::Hmac;
-use ed25519_dalek::{Keypair, Verifier, Signer};
-
-type Cipher = ctr::Ctr64BE<aes::Aes256>;
+use ed25519_dalek::Keypair;
+#[cfg(feature = "std")]
+use ed25519_dalek::{Verifier, Signer};
 
-const VERSION_HEADER: &'static [u8] = b"SSH-2.0-tinyssh+1.0";
+#[cfg(feature = "std")]
+const VERSION_HEADER: &[u8] = b"SSH-2.0-tinyssh+1.0";
 const U32: usize = size_of::<u32>();
+const U64: usize = size_of::<u64>();
 const U8: usize = size_of::<u8>();
 
-mod connection;
+// the wire format and keys, on `core` and `alloc`
+mod error;
 mod parsedump;
+pub mod wire;
 mod userauth;
 mod channelrequest;
 mod messages;
+mod keygen;
+mod md5;
+mod rsa;
+mod ecdsa;
+
+// connections over `std` streams, with their key exchange and ciphers
+#[cfg(feature = "std")]
+mod connection;
+#[cfg(feature = "std")]
 mod packets;
-mod run;
+#[cfg(feature = "std")]
+mod kex;
+#[cfg(feature = "std")]
+mod hostkey;
+#[cfg(feature = "std")]
+mod certificate;
+#[cfg(feature = "std")]
 mod hmac;
-mod keygen;
+#[cfg(feature = "std")]
+mod chachapoly;
+#[cfg(feature = "std")]
+mod transport;
+#[cfg(feature = "std")]
+mod run;
+#[cfg(feature = "std")]
+mod command;
+#[cfg(feature = "std")]
+mod channel;
+#[cfg(feature = "std")]
+mod sftp;
+#[cfg(feature = "std")]
+mod shell;
+#[cfg(feature = "std")]
+mod output;
+#[cfg(feature = "zlib")]
+mod compression;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+mod forward;
+#[cfg(feature = "std")]
+mod tunnel;
+#[cfg(feature = "std")]
+mod proxy;
+#[cfg(feature = "std")]
+mod destination;
+#[cfg(feature = "std")]
+mod reconnect;
+#[cfg(feature = "std")]
+mod pool;
+#[cfg(feature = "std")]
+mod fleet;
+#[cfg(feature = "std")]
+mod observer;
+#[cfg(feature = "tokio")]
+mod asynchronous;
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "ssh-config")]
+mod ssh_config;
+
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 
 #[doc(inline)]
 pub use {
-    connection::{Connection, Auth},
-    run::{Run, RunResult, RunEvent, ExitStatus},
-    messages::MessageType,
-    keygen::{create_ed25519_keypair, dump_ed25519_pk_openssh},
+    error::{Error, ErrorKind, Result},
+    userauth::AuthSigner,
+    messages::{Message, OwnedMessage, MessageType, NegotiatedAlgorithms, DisconnectReasonCode, DebugMessage, ExtendedDataType},
+    rsa::RsaKey,
+    ecdsa::EcdsaP256Key,
+    keygen::{
+        dump_ed25519_pk_openssh, load_openssh_key, load_pkcs8_key,
+        fingerprint_sha256, fingerprint_md5,
+        PublicKey, FingerprintDisplay, OpensshDisplay,
+    },
 };
 
+#[cfg(feature = "std")]
+#[doc(inline)]
+pub use {
+    connection::{Connection, Auth, AuthMethods, ConnectOptions},
+    stream::Stream,
+    forward::{X11Forwarding, LocalStream},
+    tunnel::Tunnel,
+    proxy::Socks5,
+    destination::Destination,
+    reconnect::ReconnectingConnection,
+    pool::{Pool, PooledConnection},
+    fleet::{Fleet, HostOutput},
+    run::{Run, RunResult, RunOptions, RunEvent, ExitStatus, WaitOutput},
+    command::{RemoteCommand, shell_quote},
+    channel::Channel,
+    output::{RunOutput, OutputStream},
+    shell::{Shell, TerminalSize},
+    sftp::{Sftp, SftpFile, SftpStatus, OpenFlags, FileAttributes, DirEntry},
+    observer::{PacketObserver, PacketDirection, ConnectProgress, TransferProgress},
+    keygen::{create_ed25519_keypair, dump_ed25519_sk_openssh},
+};
+
+#[cfg(feature = "tokio")]
+#[doc(inline)]
+pub use asynchronous::{AsyncConnection, AsyncRun};
+
+#[cfg(feature = "websocket")]
+#[doc(inline)]
+pub use websocket::WebSocket;
+
+#[cfg(feature = "ssh-config")]
+#[doc(inline)]
+pub use ssh_config::{SshConfig, HostConfig};
+
+#[cfg(feature = "std")]
 fn sha256<'b, P: parsedump::ParseDump<'b>>(data: &P) -> Result<[u8; 32]> {
     use sha2::{Sha256, Digest};
 
     struct Wrapper(Sha256);
-    impl Write for Wrapper {
-        fn flush(&mut self) -> IoResult<()> { Ok(()) }
-        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
-            self.0.update(buf);
-            Ok(buf.len())
+    impl parsedump::Buffer for Wrapper {
+        fn put(&mut self, bytes: &[u8]) {
+            self.0.update(bytes);
         }
     }
 
@@ -55,28 +161,3 @@ pub(crate) const fn ed25519_blob_len(content_len: u32) -> u32 {
     4 + 11 + 4 + content_len
 }
 
-/// Fatal errors
-#[derive(Copy, Clone, Debug)]
-pub enum Error {
-    /// No data to be read / send buffer is full.
-    Timeout,
-    /// Errors related to the TCP socket
-    TcpError(ErrorKind),
-    /// Invalid data type/encoding/size
-    InvalidData,
-    AuthenticationFailure,
-    InvalidKeypair,
-    ProcessHasExited,
-    UnexpectedMessageType(MessageType),
-    UnknownMessageType(u8),
-    /// This can be raised instead of UnexpectedMessageType, if the peer sends random bytes
-    Unimplemented,
-}
-
-pub type Result<T> = core::result::Result<T, Error>;
-
-impl From<IoError> for Error {
-    fn from(err: IoError) -> Self {
-        Self::TcpError(err.kind())
-    }
-}
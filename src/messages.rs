@@ -1,14 +1,14 @@
-use super::{Result, Error, Write, U8, U32};
-use super::parse_dump_struct;
-use super::parsedump::{ParseDump, too_short, try_u32};
+use alloc::{format, vec::Vec};
+use core::ops::RangeInclusive;
+use super::{ErrorKind, Result, Error, U8, U32};
+use super::parsedump::parse_dump_struct;
+use super::parsedump::{ParseDump, Buffer, NameList, too_short, try_u32};
 pub use super::userauth::UserauthRequest;
 pub use super::channelrequest::ChannelRequest;
 
 // Use with caution: copy-pasting
 // and leaving the wrong variant name
 // can lead to stack overflow
-#[doc(hidden)]
-#[macro_export]
 macro_rules! check_msg_type {
     ($name:ident, $expected:expr, $bytes:ident) => {
         let raw_msg_type = u8::parse($bytes)?.0;
@@ -16,11 +16,14 @@ macro_rules! check_msg_type {
         if msg_type != $expected {
             let (msg, _) = $crate::messages::Message::parse($bytes)?;
             log::error!(concat!("Expected ", stringify!($name), " message but got {:#?}"), msg);
-            return Err(Error::UnexpectedMessageType(msg_type));
+            return Err(ErrorKind::UnexpectedMessageType(msg_type).into());
         }
     }
 }
 
+pub(crate) use check_msg_type;
+
+/// A decoded message, borrowing from its payload
 #[derive(Debug)]
 #[allow(dead_code)]
 pub enum Message<'a> {
@@ -30,13 +33,13 @@ pub enum Message<'a> {
     Debug,
     ServiceRequest(ServiceRequest<'a>),
     ServiceAccept(ServiceAccept<'a>),
+    ExtInfo(ExtInfo<'a>),
     Kexinit(Kexinit<'a>),
     Newkeys(Newkeys),
-    KexdhInit(KexdhInit<'a>),
-    KexdhReply(KexdhReply<'a>),
     UserauthRequest(UserauthRequest<'a>),
     UserauthFailure(UserauthFailure<'a>),
     UserauthSuccess(UserauthSuccess),
+    UserauthBanner(UserauthBanner<'a>),
     UserauthPkOk(UserauthPkOk<'a>),
     GlobalRequest(GlobalRequest<'a>),
     RequestSuccess,
@@ -54,140 +57,337 @@ pub enum Message<'a> {
     ChannelFailure(ChannelFailure),
 }
 
-parse_dump_struct!(Unimplemented {
-    packet_number: u32,
-});
-
-parse_dump_struct!(Kexinit<'a> {
-    cookie: [u8; 16],
-    kex_algorithms: &'a str,
-    server_host_key_algorithms: &'a str,
-    encryption_algorithms_client_to_server: &'a str,
-    encryption_algorithms_server_to_client: &'a str,
-    mac_algorithms_client_to_server: &'a str,
-    mac_algorithms_server_to_client: &'a str,
-    compression_algorithms_client_to_server: &'a str,
-    compression_algorithms_server_to_client: &'a str,
-    languages_client_to_server: &'a str,
-    languages_server_to_client: &'a str,
-    first_kex_packet_follows: bool,
-    nop: u32,
-});
-
-parse_dump_struct!(KexdhInit<'a> {
-    client_ephemeral_pubkey: &'a [u8],
-});
-
-parse_dump_struct!(KexdhReply<'a> {
-    server_public_host_key: Blob<'a>,
-    server_ephemeral_pubkey: &'a [u8],
-    exchange_hash_signature: Blob<'a>,
-});
-
-parse_dump_struct!(Newkeys {});
-
-parse_dump_struct!(ServiceRequest<'a> {
-    service_name: &'a str,
-});
-
-parse_dump_struct!(ServiceAccept<'a> {
-    service_name: &'a str,
-});
-
-parse_dump_struct!(Disconnect<'a> {
-    reason_code: DisconnectReasonCode,
-    description: &'a str,
-    language_tag: &'a str,
-});
-
-parse_dump_struct!(UserauthSuccess {});
-
-parse_dump_struct!(UserauthPkOk<'a> {
-    algorithm: &'a str,
-    blob: Blob<'a>,
-});
-
-parse_dump_struct!(UserauthFailure<'a> {
-    allowed_auth: &'a str,
-    partial_success: bool,
-});
-
-parse_dump_struct!(ChannelOpen<'a> {
-    channel_type: &'a str,
-    client_channel: u32,
-    client_initial_window_size: u32,
-    client_max_packet_size: u32,
-});
-
-parse_dump_struct!(ChannelOpenConfirmation {
-    client_channel: u32,
-    server_channel: u32,
-    server_initial_window_size: u32,
-    server_max_packet_size: u32,
-});
-
-parse_dump_struct!(ChannelOpenFailure<'a> {
-    client_channel: u32,
-    reason_code: u32,
-    description: &'a str,
-    language_tag: &'a str,
-});
-
-parse_dump_struct!(ChannelData<'a> {
-    recipient_channel: u32,
-    data: &'a [u8],
-});
-
-parse_dump_struct!(ChannelExtendedData<'a> {
-    recipient_channel: u32,
-    data_type: u32,
-    data: &'a [u8],
-});
-
-parse_dump_struct!(ChannelEof {
-    recipient_channel: u32,
-});
-
-parse_dump_struct!(ChannelClose {
-    recipient_channel: u32,
-});
-
-parse_dump_struct!(ChannelSuccess {
-    recipient_channel: u32,
-});
-
-parse_dump_struct!(ChannelFailure {
-    recipient_channel: u32,
-});
-
-parse_dump_struct!(GlobalRequest<'a> {
-    request_name: &'a str,
-    want_reply: bool,
-});
-
-parse_dump_struct!(ChannelWindowAdjust {
-    recipient_channel: u32,
-    bytes_to_add: u32,
-});
+parse_dump_struct!(
+    /// `SSH_MSG_UNIMPLEMENTED` (RFC 4253, section 11.4)
+    Unimplemented {
+        packet_number: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_KEXINIT` (RFC 4253, section 7.1)
+    Kexinit<'a> {
+        cookie: [u8; 16],
+        kex_algorithms: NameList<'a>,
+        server_host_key_algorithms: NameList<'a>,
+        encryption_algorithms_client_to_server: NameList<'a>,
+        encryption_algorithms_server_to_client: NameList<'a>,
+        mac_algorithms_client_to_server: NameList<'a>,
+        mac_algorithms_server_to_client: NameList<'a>,
+        compression_algorithms_client_to_server: NameList<'a>,
+        compression_algorithms_server_to_client: NameList<'a>,
+        languages_client_to_server: NameList<'a>,
+        languages_server_to_client: NameList<'a>,
+        first_kex_packet_follows: bool,
+        nop: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_NEWKEYS` (RFC 4253, section 7.3)
+    Newkeys {}
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_SERVICE_REQUEST` (RFC 4253, section 10)
+    ServiceRequest<'a> {
+        service_name: &'a str,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_SERVICE_ACCEPT` (RFC 4253, section 10)
+    ServiceAccept<'a> {
+        service_name: &'a str,
+    }
+);
+
+/// Placed in our KEXINIT to ask the server for an [`ExtInfo`];
+/// it isn't a key exchange method (RFC 8308, section 2.1)
+pub const EXT_INFO_C: &str = "ext-info-c";
+
+/// Protocol extensions advertised by the server (RFC 8308), as names and raw values
+#[derive(Clone, Debug)]
+pub struct ExtInfo<'a> {
+    pub extensions: Vec<(&'a str, &'a [u8])>,
+}
+
+impl<'a> ExtInfo<'a> {
+    /// Value of the extension named `name`, if present
+    pub fn get(&self, name: &str) -> Option<&'a [u8]> {
+        self.extensions.iter().find(|(n, _)| *n == name).map(|(_, value)| *value)
+    }
+}
+
+impl<'a, 'b: 'a> ParseDump<'b> for ExtInfo<'a> {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        check_msg_type!(ExtInfo, MessageType::ExtInfo, bytes);
+        let mut i = U8;
+
+        let (count, inc) = u32::parse(&bytes[i..])?;
+        i += inc;
+
+        // each extension takes at least 8 bytes
+        let mut extensions = Vec::with_capacity((count as usize).min(bytes.len() / 8));
+        for _ in 0..count {
+            let (name, inc) = <&'a str>::parse(&bytes[i..])?;
+            i += inc;
+            let (value, inc) = <&'a [u8]>::parse(&bytes[i..])?;
+            i += inc;
+            extensions.push((name, value));
+        }
+
+        Ok((Self {
+            extensions,
+        }, i))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        (MessageType::ExtInfo as u8).dump(sink)?;
+        (self.extensions.len() as u32).dump(sink)?;
+
+        for (name, value) in &self.extensions {
+            name.dump(sink)?;
+            value.dump(sink)?;
+        }
+
+        Ok(())
+    }
+}
+
+parse_dump_struct!(
+    /// `SSH_MSG_DISCONNECT` (RFC 4253, section 11.1)
+    Disconnect<'a> {
+        reason_code: DisconnectReasonCode,
+        description: &'a str,
+        language_tag: &'a str,
+    }
+);
+
+parse_dump_struct!(
+    /// Payload of an `SSH_MSG_IGNORE` message (RFC 4253, section 11.2)
+    IgnoreData<'a> {
+        data: &'a [u8],
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_DEBUG` (RFC 4253, section 11.3)
+    DebugMessage<'a> {
+        always_display: bool,
+        message: &'a str,
+        language_tag: &'a str,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_USERAUTH_SUCCESS` (RFC 4252, section 5.1)
+    UserauthSuccess {}
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_USERAUTH_BANNER` (RFC 4252, section 5.4)
+    UserauthBanner<'a> {
+        message: &'a str,
+        language_tag: &'a str,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_USERAUTH_PK_OK` (RFC 4252, section 7)
+    UserauthPkOk<'a> {
+        algorithm: &'a str,
+        // the public key blob of the request, whatever its fields
+        blob: &'a [u8],
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_USERAUTH_FAILURE` (RFC 4252, section 5.1)
+    UserauthFailure<'a> {
+        allowed_auth: &'a str,
+        partial_success: bool,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_OPEN` (RFC 4254, section 5.1), without type-specific data
+    ChannelOpen<'a> {
+        channel_type: &'a str,
+        client_channel: u32,
+        client_initial_window_size: u32,
+        client_max_packet_size: u32,
+    }
+);
+
+/// [`ChannelOpen`] of type `direct-tcpip` (RFC 4254, section 7.2)
+#[derive(Copy, Clone, Debug)]
+pub struct DirectTcpipOpen<'a> {
+    pub client_channel: u32,
+    pub client_initial_window_size: u32,
+    pub client_max_packet_size: u32,
+    /// where the server should connect to
+    pub host: &'a str,
+    pub port: u32,
+    pub originator_address: &'a str,
+    pub originator_port: u32,
+}
+
+impl<'a, 'b: 'a> ParseDump<'b> for DirectTcpipOpen<'a> {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        let (open, mut i) = ChannelOpen::parse(bytes)?;
+        if open.channel_type != "direct-tcpip" {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("expected a direct-tcpip ChannelOpen, got {}", open.channel_type)));
+        }
+
+        let (host, inc) = <&'a str>::parse(&bytes[i..])?;
+        i += inc;
+        let (port, inc) = u32::parse(&bytes[i..])?;
+        i += inc;
+        let (originator_address, inc) = <&'a str>::parse(&bytes[i..])?;
+        i += inc;
+        let (originator_port, inc) = u32::parse(&bytes[i..])?;
+        i += inc;
+
+        Ok((Self {
+            client_channel: open.client_channel,
+            client_initial_window_size: open.client_initial_window_size,
+            client_max_packet_size: open.client_max_packet_size,
+            host,
+            port,
+            originator_address,
+            originator_port,
+        }, i))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        ChannelOpen {
+            channel_type: "direct-tcpip",
+            client_channel: self.client_channel,
+            client_initial_window_size: self.client_initial_window_size,
+            client_max_packet_size: self.client_max_packet_size,
+        }.dump(sink)?;
+
+        self.host.dump(sink)?;
+        self.port.dump(sink)?;
+        self.originator_address.dump(sink)?;
+        self.originator_port.dump(sink)
+    }
+}
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_OPEN_CONFIRMATION` (RFC 4254, section 5.1)
+    ChannelOpenConfirmation {
+        client_channel: u32,
+        server_channel: u32,
+        server_initial_window_size: u32,
+        server_max_packet_size: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_OPEN_FAILURE` (RFC 4254, section 5.1)
+    ChannelOpenFailure<'a> {
+        client_channel: u32,
+        reason_code: u32,
+        description: &'a str,
+        language_tag: &'a str,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_DATA` (RFC 4254, section 5.2)
+    ChannelData<'a> {
+        recipient_channel: u32,
+        data: &'a [u8],
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_EXTENDED_DATA` (RFC 4254, section 5.2)
+    ChannelExtendedData<'a> {
+        recipient_channel: u32,
+        data_type: ExtendedDataType,
+        data: &'a [u8],
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_EOF` (RFC 4254, section 5.3)
+    ChannelEof {
+        recipient_channel: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_CLOSE` (RFC 4254, section 5.3)
+    ChannelClose {
+        recipient_channel: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_SUCCESS` (RFC 4254, section 5.4)
+    ChannelSuccess {
+        recipient_channel: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_FAILURE` (RFC 4254, section 5.4)
+    ChannelFailure {
+        recipient_channel: u32,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_GLOBAL_REQUEST` (RFC 4254, section 4), without request-specific data
+    GlobalRequest<'a> {
+        request_name: &'a str,
+        want_reply: bool,
+    }
+);
+
+parse_dump_struct!(
+    /// `SSH_MSG_CHANNEL_WINDOW_ADJUST` (RFC 4254, section 5.2)
+    ChannelWindowAdjust {
+        recipient_channel: u32,
+        bytes_to_add: u32,
+    }
+);
 
 // utils, not messages:
 
-parse_dump_struct!(ExchangeHash<'a> {
-    client_header: &'a [u8],
-    server_header: &'a [u8],
-    client_kexinit_payload: &'a [u8],
-    server_kexinit_payload: &'a [u8],
-    server_public_host_key: Blob<'a>,
-    client_ephemeral_pubkey: &'a [u8],
-    server_ephemeral_pubkey: &'a [u8],
-    shared_secret: UnsignedMpInt<'a>,
-});
-
-parse_dump_struct!(Blob<'a> {
-    blob_len: u32,
-    header: &'a str,
-    content: &'a [u8],
-});
+/// A string containing an algorithm name and a string, e.g. a signature blob
+#[derive(Copy, Clone, Debug)]
+pub struct Blob<'a> {
+    pub header: &'a str,
+    pub content: &'a [u8],
+}
+
+impl<'a, 'b: 'a> ParseDump<'b> for Blob<'a> {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        let (blob, progress) = <&'a [u8]>::parse(bytes)?;
+        let (header, i) = <&'a str>::parse(blob)?;
+        let (content, inc) = <&'a [u8]>::parse(&blob[i..])?;
+
+        if i + inc != blob.len() {
+            return Err(Error::new(ErrorKind::InvalidData).context("trailing bytes in blob"));
+        }
+
+        Ok((Self {
+            header,
+            content,
+        }, progress))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        let blob_len = U32 + self.header.len() + U32 + self.content.len();
+        (blob_len as u32).dump(sink)?;
+        self.header.dump(sink)?;
+        self.content.dump(sink)
+    }
+}
 
 macro_rules! forward_and_wrap {
     ($variant:ident, $rem:ident) => ( $variant::parse($rem).map(|(inner, p)| (Self::$variant(inner), p)) )
@@ -195,20 +395,25 @@ macro_rules! forward_and_wrap {
 
 impl<'a, 'b: 'a> ParseDump<'b> for Message<'a> {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
-        let typ = *bytes.get(0).ok_or_else(|| too_short())?;
+        let typ = *bytes.first().ok_or_else(too_short)?;
         match MessageType::try_from(typ)? {
 
+            // their fields are ignored
+            MessageType::Ignore => Ok((Self::Ignore, bytes.len())),
+            MessageType::Debug => Ok((Self::Debug, bytes.len())),
+            MessageType::RequestSuccess => Ok((Self::RequestSuccess, bytes.len())),
+            MessageType::RequestFailure => Ok((Self::RequestFailure, bytes.len())),
             MessageType::Disconnect => forward_and_wrap!(Disconnect, bytes),
             MessageType::Unimplemented => forward_and_wrap!(Unimplemented, bytes),
             MessageType::ServiceRequest => forward_and_wrap!(ServiceRequest, bytes),
             MessageType::ServiceAccept => forward_and_wrap!(ServiceAccept, bytes),
+            MessageType::ExtInfo => forward_and_wrap!(ExtInfo, bytes),
             MessageType::Kexinit => forward_and_wrap!(Kexinit, bytes),
             MessageType::Newkeys => forward_and_wrap!(Newkeys, bytes),
-            MessageType::KexdhInit => forward_and_wrap!(KexdhInit, bytes),
-            MessageType::KexdhReply => forward_and_wrap!(KexdhReply, bytes),
             MessageType::UserauthRequest => forward_and_wrap!(UserauthRequest, bytes),
             MessageType::UserauthFailure => forward_and_wrap!(UserauthFailure, bytes),
             MessageType::UserauthSuccess => forward_and_wrap!(UserauthSuccess, bytes),
+            MessageType::UserauthBanner => forward_and_wrap!(UserauthBanner, bytes),
             MessageType::UserauthPkOk => forward_and_wrap!(UserauthPkOk, bytes),
             MessageType::ChannelOpen => forward_and_wrap!(ChannelOpen, bytes),
             MessageType::ChannelOpenConfirmation => forward_and_wrap!(ChannelOpenConfirmation, bytes),
@@ -222,28 +427,26 @@ impl<'a, 'b: 'a> ParseDump<'b> for Message<'a> {
             MessageType::ChannelFailure => forward_and_wrap!(ChannelFailure, bytes),
             MessageType::ChannelRequest => forward_and_wrap!(ChannelRequest, bytes),
             MessageType::GlobalRequest => forward_and_wrap!(GlobalRequest, bytes),
-
-            typ => {
-                log::error!("Unimplemented: Message::parse() for {:?}", typ);
-                Err(Error::Unimplemented)
-            },
         }
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        (self.typ() as u8).dump(sink)?;
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
         match self {
+            Self::Ignore => (MessageType::Ignore as u8).dump(sink),
+            Self::Debug => (MessageType::Debug as u8).dump(sink),
+            Self::RequestSuccess => (MessageType::RequestSuccess as u8).dump(sink),
+            Self::RequestFailure => (MessageType::RequestFailure as u8).dump(sink),
             Self::Disconnect(inner) => inner.dump(sink),
             Self::Unimplemented(inner) => inner.dump(sink),
             Self::ServiceRequest(inner) => inner.dump(sink),
             Self::ServiceAccept(inner) => inner.dump(sink),
+            Self::ExtInfo(inner) => inner.dump(sink),
             Self::Kexinit(inner) => inner.dump(sink),
             Self::Newkeys(inner) => inner.dump(sink),
-            Self::KexdhInit(inner) => inner.dump(sink),
-            Self::KexdhReply(inner) => inner.dump(sink),
             Self::UserauthRequest(inner) => inner.dump(sink),
             Self::UserauthFailure(inner) => inner.dump(sink),
             Self::UserauthSuccess(inner) => inner.dump(sink),
+            Self::UserauthBanner(inner) => inner.dump(sink),
             Self::UserauthPkOk(inner) => inner.dump(sink),
             Self::ChannelOpen(inner) => inner.dump(sink),
             Self::ChannelOpenConfirmation(inner) => inner.dump(sink),
@@ -257,15 +460,57 @@ impl<'a, 'b: 'a> ParseDump<'b> for Message<'a> {
             Self::ChannelFailure(inner) => inner.dump(sink),
             Self::ChannelRequest(inner) => inner.dump(sink),
             Self::GlobalRequest(inner) => inner.dump(sink),
-
-            typ => {
-                log::error!("Unimplemented: Message::dump() for {:?}", typ);
-                Err(Error::Unimplemented)
-            },
         }
     }
 }
 
+/// A [`Message`] which owns its payload, so that it can be kept
+/// across receptions (e.g. queued for another channel)
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct OwnedMessage {
+    payload: Vec<u8>,
+}
+
+impl OwnedMessage {
+    /// Takes a payload, checking that it can be parsed
+    pub fn from_payload(payload: Vec<u8>) -> Result<Self> {
+        Message::parse(&payload)?;
+        Ok(Self {
+            payload,
+        })
+    }
+
+    /// Parses the payload again, borrowing from it
+    pub fn message(&self) -> Message<'_> {
+        // checked in constructors
+        Message::parse(&self.payload).map(|(message, _)| message).unwrap()
+    }
+
+    pub fn typ(&self) -> MessageType {
+        self.message().typ()
+    }
+
+    pub fn payload(&self) -> &[u8] {
+        &self.payload
+    }
+
+    pub fn into_payload(self) -> Vec<u8> {
+        self.payload
+    }
+}
+
+impl<'a> Message<'a> {
+    /// Copies the message into an [`OwnedMessage`]
+    ///
+    /// Unknown channel requests and the fields of `Ignore` and `Debug`
+    /// messages aren't kept; [`OwnedMessage::from_payload`] keeps them.
+    pub fn to_owned_message(&self) -> Result<OwnedMessage> {
+        let mut payload = Vec::new();
+        self.dump(&mut payload)?;
+        OwnedMessage::from_payload(payload)
+    }
+}
+
 impl<'a> Message<'a> {
     pub fn typ(&self) -> MessageType {
         match self {
@@ -275,13 +520,13 @@ impl<'a> Message<'a> {
             Self::Debug => MessageType::Debug,
             Self::ServiceRequest(_) => MessageType::ServiceRequest,
             Self::ServiceAccept(_) => MessageType::ServiceAccept,
+            Self::ExtInfo(_) => MessageType::ExtInfo,
             Self::Kexinit(_) => MessageType::Kexinit,
             Self::Newkeys(_) => MessageType::Newkeys,
-            Self::KexdhInit(_) => MessageType::KexdhInit,
-            Self::KexdhReply(_) => MessageType::KexdhReply,
             Self::UserauthRequest(_) => MessageType::UserauthRequest,
             Self::UserauthFailure(_) => MessageType::UserauthFailure,
             Self::UserauthSuccess(_) => MessageType::UserauthSuccess,
+            Self::UserauthBanner(_) => MessageType::UserauthBanner,
             Self::UserauthPkOk(_) => MessageType::UserauthPkOk,
             Self::GlobalRequest(_) => MessageType::GlobalRequest,
             Self::RequestSuccess => MessageType::RequestSuccess,
@@ -301,8 +546,12 @@ impl<'a> Message<'a> {
     }
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+/// SSH message numbers (RFC 4250, section 4.1)
+///
+/// New variants may be added in minor releases.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 #[repr(u8)]
+#[non_exhaustive]
 pub enum MessageType {
     Disconnect = 1,
     Ignore = 2,
@@ -310,10 +559,10 @@ pub enum MessageType {
     Debug = 4,
     ServiceRequest = 5,
     ServiceAccept = 6,
+    ExtInfo = 7,
     Kexinit = 20,
     Newkeys = 21,
-    KexdhInit = 30,
-    KexdhReply = 31,
+    // 30 to 49 are kex method specific, see kex.rs
     UserauthRequest = 50,
     UserauthFailure = 51,
     UserauthSuccess = 52,
@@ -336,7 +585,7 @@ pub enum MessageType {
 }
 
 impl MessageType {
-    const fn from_struct_name(name: &str) -> Option<Self> {
+    pub(crate) const fn from_struct_name(name: &str) -> Option<Self> {
         match name.as_bytes() {
             b"Disconnect" => Some(Self::Disconnect),
             b"Ignore" => Some(Self::Ignore),
@@ -344,10 +593,9 @@ impl MessageType {
             b"Debug" => Some(Self::Debug),
             b"ServiceRequest" => Some(Self::ServiceRequest),
             b"ServiceAccept" => Some(Self::ServiceAccept),
+            b"ExtInfo" => Some(Self::ExtInfo),
             b"Kexinit" => Some(Self::Kexinit),
             b"Newkeys" => Some(Self::Newkeys),
-            b"KexdhInit" => Some(Self::KexdhInit),
-            b"KexdhReply" => Some(Self::KexdhReply),
             b"UserauthRequest" => Some(Self::UserauthRequest),
             b"UserauthFailure" => Some(Self::UserauthFailure),
             b"UserauthSuccess" => Some(Self::UserauthSuccess),
@@ -370,11 +618,23 @@ impl MessageType {
 
             b"ChannelSuccess" => Some(Self::ChannelSuccess),
             b"ChannelFailure" => Some(Self::ChannelFailure),
+
+            // the fields of these aren't kept in `Message`
+            b"IgnoreData" => Some(Self::Ignore),
+            b"DebugMessage" => Some(Self::Debug),
+
             _ => None,
         }
     }
 }
 
+/// Message numbers which are reused by each kex method
+/// with a different meaning (RFC 4250, section 4.1.2).
+///
+/// They are rejected by the global `MessageType` parser;
+/// the kex method in use parses them with `kex::KexMessage`.
+pub const KEX_MESSAGES: RangeInclusive<u8> = 30..=49;
+
 impl TryFrom<u8> for MessageType {
     type Error = Error;
     fn try_from(value: u8) -> Result<Self> {
@@ -385,10 +645,10 @@ impl TryFrom<u8> for MessageType {
             4 => Ok(Self::Debug),
             5 => Ok(Self::ServiceRequest),
             6 => Ok(Self::ServiceAccept),
+            7 => Ok(Self::ExtInfo),
             20 => Ok(Self::Kexinit),
             21 => Ok(Self::Newkeys),
-            30 => Ok(Self::KexdhInit),
-            31 => Ok(Self::KexdhReply),
+            value if KEX_MESSAGES.contains(&value) => Err(Error::new(ErrorKind::UnknownMessageType(value)).context(format!("kex method message ({}) outside of a key exchange", value))),
             50 => Ok(Self::UserauthRequest),
             51 => Ok(Self::UserauthFailure),
             52 => Ok(Self::UserauthSuccess),
@@ -408,32 +668,37 @@ impl TryFrom<u8> for MessageType {
             98 => Ok(Self::ChannelRequest),
             99 => Ok(Self::ChannelSuccess),
             100 => Ok(Self::ChannelFailure),
-            value => Err(Error::UnknownMessageType(value)),
+            value => Err(ErrorKind::UnknownMessageType(value).into()),
         }
     }
 }
 
+/// A non-negative multiple precision integer (`mpint`), as big-endian
+/// bytes; leading zeros are stripped when dumped, and a zero byte is
+/// prepended if the most significant bit is set.
 #[derive(Copy, Clone, Debug)]
 pub struct UnsignedMpInt<'a>(pub &'a [u8]);
 
 impl<'a, 'b: 'a> ParseDump<'b> for UnsignedMpInt<'a> {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
-        let total = U32 + (try_u32(bytes)? as usize);
-        Ok((Self(bytes.get(U32..total).ok_or_else(|| too_short())?), total))
+        let total = U32.checked_add(try_u32(bytes)? as usize).ok_or_else(too_short)?;
+        Ok((Self(bytes.get(U32..total).ok_or_else(too_short)?), total))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        // leading zeros must be stripped (RFC 4251, section 5)
         let has_non_zero = self.0.iter().position(|b| *b != 0);
-        if has_non_zero.is_some() {
-            let prevent_sign = (self.0[0] & 0x80) != 0;
-            let len = self.0.len() + (prevent_sign as usize);
+        if let Some(start) = has_non_zero {
+            let digits = &self.0[start..];
+            let prevent_sign = (digits[0] & 0x80) != 0;
+            let len = digits.len() + (prevent_sign as usize);
 
-            sink.write(&(len as u32).to_be_bytes())?;
+            sink.put(&(len as u32).to_be_bytes());
             if prevent_sign {
-                sink.write(&[0])?;
+                sink.put(&[0]);
             }
 
-            sink.write(self.0)?;
+            sink.put(digits);
             Ok(())
         } else {
             0u32.dump(sink)
@@ -441,8 +706,12 @@ impl<'a, 'b: 'a> ParseDump<'b> for UnsignedMpInt<'a> {
     }
 }
 
-#[derive(Copy, Clone, Debug)]
-#[repr(u8)]
+/// Why a connection is closed (RFC 4250, section 4.2.2)
+///
+/// New variants may be added in minor releases.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u32)]
+#[non_exhaustive]
 pub enum DisconnectReasonCode {
     HostNotAllowedToConnect = 1,
     ProtocolError = 2,
@@ -463,8 +732,8 @@ pub enum DisconnectReasonCode {
 
 impl<'b> ParseDump<'b> for DisconnectReasonCode {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
-        let (byte, progress) = u8::parse(bytes)?;
-        let reason = match byte {
+        let (code, progress) = u32::parse(bytes)?;
+        let reason = match code {
             1 => Ok(Self::HostNotAllowedToConnect),
             2 => Ok(Self::ProtocolError),
             3 => Ok(Self::KeyExchangeFailed),
@@ -480,38 +749,116 @@ impl<'b> ParseDump<'b> for DisconnectReasonCode {
             13 => Ok(Self::AuthCancelledByUser),
             14 => Ok(Self::NoMoreAuthMethodsAvailable),
             15 => Ok(Self::IllegalUserName),
-            c => {
-                log::error!("Invalid disconnect reason code: {}", c);
-                Err(Error::InvalidData)
-            },
+            c => Err(Error::new(ErrorKind::InvalidData).context(format!("invalid disconnect reason code: {}", c))),
         }?;
         Ok((reason, progress))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        (*self as u8).dump(sink)
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        (*self as u32).dump(sink)
     }
 }
 
-impl<'a> Kexinit<'a> {
-    pub fn check_compat(&self, client: &Self) -> Result<()> {
-        fn find(haystack: &str, needle: &str) -> Result<()> {
-            match haystack.split(",").position(|alg| alg == needle) {
-                None => {
-                    log::error!("Couldn't agree with peer on an algorithm set");
-                    Err(Error::Unimplemented)
-                },
-                Some(_) => Ok(()),
-            }
+/// What a [`ChannelExtendedData`] carries (RFC 4254, section 5.2)
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ExtendedDataType {
+    /// `SSH_EXTENDED_DATA_STDERR`
+    Stderr,
+    /// Not defined by RFC 4254
+    Unknown(u32),
+}
+
+impl<'b> ParseDump<'b> for ExtendedDataType {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        let (code, progress) = u32::parse(bytes)?;
+        let data_type = match code {
+            1 => Self::Stderr,
+            code => Self::Unknown(code),
+        };
+
+        Ok((data_type, progress))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        match self {
+            Self::Stderr => 1u32.dump(sink),
+            Self::Unknown(code) => code.dump(sink),
         }
+    }
+}
+
+/// Picks the first algorithm of `client` which `server` also supports
+/// (RFC 4253, section 7.1); `category` is only used in logs.
+pub fn negotiate<'c>(category: &str, client: NameList<'c>, server: NameList) -> Result<&'c str> {
+    let mut common = client.intersection(server).filter(|alg| *alg != EXT_INFO_C);
+    match common.next() {
+        None => Err(Error::new(ErrorKind::Unimplemented).context(format!("couldn't agree with peer on a {} algorithm (ours: {}, theirs: {})", category, client, server))),
+        Some(alg) => Ok(alg),
+    }
+}
+
+/// Algorithms agreed upon with the server during key exchange
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub struct NegotiatedAlgorithms<'c> {
+    pub kex: &'c str,
+    pub host_key: &'c str,
+    pub encryption_client_to_server: &'c str,
+    pub encryption_server_to_client: &'c str,
+    pub mac_client_to_server: &'c str,
+    pub mac_server_to_client: &'c str,
+    pub compression_client_to_server: &'c str,
+    pub compression_server_to_client: &'c str,
+}
+
+impl<'a> Kexinit<'a> {
+    /// Picks, in each category, the first algorithm of
+    /// `client` that this (server) Kexinit also lists
+    pub fn check_compat<'c>(&self, client: &Kexinit<'c>) -> Result<NegotiatedAlgorithms<'c>> {
+        Ok(NegotiatedAlgorithms {
+            kex: negotiate("kex", client.kex_algorithms, self.kex_algorithms)?,
+            host_key: negotiate("host key", client.server_host_key_algorithms, self.server_host_key_algorithms)?,
+            encryption_client_to_server: negotiate(
+                "encryption (client to server)",
+                client.encryption_algorithms_client_to_server,
+                self.encryption_algorithms_client_to_server,
+            )?,
+            encryption_server_to_client: negotiate(
+                "encryption (server to client)",
+                client.encryption_algorithms_server_to_client,
+                self.encryption_algorithms_server_to_client,
+            )?,
+            mac_client_to_server: negotiate(
+                "MAC (client to server)",
+                client.mac_algorithms_client_to_server,
+                self.mac_algorithms_client_to_server,
+            )?,
+            mac_server_to_client: negotiate(
+                "MAC (server to client)",
+                client.mac_algorithms_server_to_client,
+                self.mac_algorithms_server_to_client,
+            )?,
+            compression_client_to_server: negotiate(
+                "compression (client to server)",
+                client.compression_algorithms_client_to_server,
+                self.compression_algorithms_client_to_server,
+            )?,
+            compression_server_to_client: negotiate(
+                "compression (server to client)",
+                client.compression_algorithms_server_to_client,
+                self.compression_algorithms_server_to_client,
+            )?,
+        })
+    }
 
-        find(self.kex_algorithms, client.kex_algorithms)?;
-        find(self.server_host_key_algorithms, client.server_host_key_algorithms)?;
-        find(self.encryption_algorithms_client_to_server, client.encryption_algorithms_client_to_server)?;
-        find(self.encryption_algorithms_server_to_client, client.encryption_algorithms_server_to_client)?;
-        find(self.mac_algorithms_client_to_server, client.mac_algorithms_client_to_server)?;
-        find(self.mac_algorithms_server_to_client, client.mac_algorithms_server_to_client)?;
-        find(self.compression_algorithms_client_to_server, client.compression_algorithms_client_to_server)?;
-        find(self.compression_algorithms_server_to_client, client.compression_algorithms_server_to_client)
+    /// Whether this (server) Kexinit has `first_kex_packet_follows` with a
+    /// wrong guess, i.e. its preferred key exchange or host key algorithm
+    /// isn't the one of `client`; the guessed packet must then be ignored
+    /// (RFC 4253, section 7)
+    pub fn wrong_guess(&self, client: &Kexinit) -> bool {
+        self.first_kex_packet_follows && (
+            self.kex_algorithms.iter().next() != client.kex_algorithms.iter().next()
+            || self.server_host_key_algorithms.iter().next() != client.server_host_key_algorithms.iter().next()
+        )
     }
 }
@@ -0,0 +1,252 @@
+//! Wire-level tracing of the packets exchanged with the server,
+//! and progress of the handshake and of transfers
+
+use std::io::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::parsedump::ParseDump;
+use super::messages::{Message, NegotiatedAlgorithms, DebugMessage};
+
+/// Which way a packet went
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PacketDirection {
+    Sent,
+    Received,
+}
+
+/// Receives the payload of every packet, see [`ConnectOptions::packet_observer`](crate::ConnectOptions::packet_observer)
+///
+/// Payloads are observed in plaintext (decrypted and uncompressed), so
+/// they include passwords and other secrets sent during authentication.
+pub trait PacketObserver: Send {
+    /// `message` is the decoded payload, unless it couldn't be decoded
+    /// (e.g. messages of the key exchange methods)
+    fn observe(&mut self, direction: PacketDirection, payload: &[u8], message: Option<&Message>);
+}
+
+impl<F: FnMut(PacketDirection, &[u8], Option<&Message>) + Send> PacketObserver for F {
+    fn observe(&mut self, direction: PacketDirection, payload: &[u8], message: Option<&Message>) {
+        self(direction, payload, message)
+    }
+}
+
+/// Steps of [`Connection::new_with_options`](crate::Connection::new_with_options),
+/// see [`ConnectOptions::progress`](crate::ConnectOptions::progress)
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ConnectProgress<'a> {
+    /// The server sent its version header, e.g. `SSH-2.0-OpenSSH_9.6`
+    VersionExchanged {
+        server_version: &'a str,
+    },
+    /// The first key exchange completed
+    KexDone {
+        algorithms: NegotiatedAlgorithms<'static>,
+        session_id: &'a [u8],
+    },
+    /// The server rejected an authentication method
+    AuthRejected {
+        method: &'static str,
+        /// methods which can continue (comma-separated)
+        allowed: &'a str,
+    },
+    /// The server accepted an authentication method, but requires
+    /// another one as well (`partial_success`)
+    AuthPartialSuccess {
+        method: &'static str,
+        /// methods which can continue (comma-separated)
+        allowed: &'a str,
+    },
+    /// The server accepted an authentication method
+    AuthAccepted {
+        method: &'static str,
+    },
+}
+
+/// Data transferred over a channel so far, see
+/// [`Run::set_transfer_progress`](crate::Run::set_transfer_progress)
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TransferProgress {
+    /// bytes of standard input sent
+    pub sent: u64,
+    /// bytes of standard output and error received
+    pub received: u64,
+    /// bytes which can be sent before the server enlarges its window
+    pub server_window: u32,
+    /// bytes which the server can send before we enlarge our window
+    pub client_window: u32,
+}
+
+/// An optional callback, shared by clones of its holder
+pub(crate) struct Hook<T: ?Sized>(pub(crate) Option<Arc<Mutex<T>>>);
+
+/// The observer shared by the packet reader and writer, if any
+pub(crate) type Observer = Hook<dyn PacketObserver>;
+
+/// See [`ConnectOptions::progress`](crate::ConnectOptions::progress)
+pub(crate) type Progress = Hook<dyn FnMut(ConnectProgress) + Send>;
+
+/// See [`Run::set_transfer_progress`](crate::Run::set_transfer_progress)
+pub(crate) type TransferHook = Hook<dyn FnMut(TransferProgress) + Send>;
+
+/// See [`ConnectOptions::debug_callback`](crate::ConnectOptions::debug_callback)
+pub(crate) type DebugHook = Hook<dyn FnMut(DebugMessage) + Send>;
+
+/// See [`ConnectOptions::global_request_handler`](crate::ConnectOptions::global_request_handler)
+pub(crate) type GlobalRequestHook = Hook<dyn FnMut(&str, &[u8]) -> bool + Send>;
+
+/// See [`Connection::set_transcript`](crate::Connection::set_transcript)
+pub(crate) type Transcript = Hook<dyn Write + Send>;
+
+/// See [`ConnectOptions::key_log`](crate::ConnectOptions::key_log)
+#[cfg(feature = "keylog")]
+pub(crate) type KeyLog = Hook<dyn Write + Send>;
+
+impl Observer {
+    pub(crate) fn new<O: PacketObserver + 'static>(observer: O) -> Self {
+        Self(Some(Arc::new(Mutex::new(observer))))
+    }
+
+    pub(crate) fn observe(&self, direction: PacketDirection, payload: &[u8]) {
+        if let Some(observer) = &self.0 {
+            let message = Message::parse(payload).ok().map(|(message, _)| message);
+            let mut observer = observer.lock().unwrap_or_else(PoisonError::into_inner);
+            observer.observe(direction, payload, message.as_ref());
+        }
+    }
+}
+
+impl Progress {
+    pub(crate) fn new<F: FnMut(ConnectProgress) + Send + 'static>(callback: F) -> Self {
+        Self(Some(Arc::new(Mutex::new(callback))))
+    }
+
+    pub(crate) fn report(&self, progress: ConnectProgress) {
+        log::info!("{:?}", progress);
+        if let Some(callback) = &self.0 {
+            let mut callback = callback.lock().unwrap_or_else(PoisonError::into_inner);
+            callback(progress);
+        }
+    }
+}
+
+impl TransferHook {
+    pub(crate) fn new<F: FnMut(TransferProgress) + Send + 'static>(callback: F) -> Self {
+        Self(Some(Arc::new(Mutex::new(callback))))
+    }
+
+    pub(crate) fn report(&self, progress: TransferProgress) {
+        if let Some(callback) = &self.0 {
+            let mut callback = callback.lock().unwrap_or_else(PoisonError::into_inner);
+            callback(progress);
+        }
+    }
+}
+
+impl DebugHook {
+    pub(crate) fn new<F: FnMut(DebugMessage) + Send + 'static>(callback: F) -> Self {
+        Self(Some(Arc::new(Mutex::new(callback))))
+    }
+
+    pub(crate) fn report(&self, message: DebugMessage) {
+        log::debug!("Server debug message: {}", message.message);
+        if let Some(callback) = &self.0 {
+            let mut callback = callback.lock().unwrap_or_else(PoisonError::into_inner);
+            callback(message);
+        }
+    }
+}
+
+impl GlobalRequestHook {
+    pub(crate) fn new<F: FnMut(&str, &[u8]) -> bool + Send + 'static>(handler: F) -> Self {
+        Self(Some(Arc::new(Mutex::new(handler))))
+    }
+
+    /// Whether the request succeeded; unknown requests fail
+    pub(crate) fn handle(&self, request_name: &str, data: &[u8]) -> bool {
+        match &self.0 {
+            Some(handler) => {
+                let mut handler = handler.lock().unwrap_or_else(PoisonError::into_inner);
+                handler(request_name, data)
+            },
+            None => false,
+        }
+    }
+}
+
+impl Transcript {
+    pub(crate) fn new<W: Write + Send + 'static>(sink: W) -> Self {
+        Self(Some(Arc::new(Mutex::new(sink))))
+    }
+
+    /// Writes `entry` on its own line, after the current UTC time
+    pub(crate) fn record(&self, entry: core::fmt::Arguments) {
+        if self.0.is_some() {
+            self.write_line(format_args!("{} {}", utc_timestamp(), entry));
+        }
+    }
+
+    /// Writes `line` and flushes; failing sinks are
+    /// logged, not fatal to the connection
+    pub(crate) fn write_line(&self, line: core::fmt::Arguments) {
+        if let Some(sink) = &self.0 {
+            let mut sink = sink.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(error) = writeln!(sink, "{}", line).and_then(|()| sink.flush()) {
+                log::warn!("Couldn't write to the sink: {}", error);
+            }
+        }
+    }
+}
+
+/// RFC 3339 timestamp of the current UTC time, with milliseconds
+fn utc_timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    let (days, secs) = (now.as_secs() / 86400, now.as_secs() % 86400);
+
+    // civil date of a day count (http://howardhinnant.github.io/date_algorithms.html)
+    let z = days + 719468;
+    let (era, doe) = (z / 146097, z % 146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day, secs / 3600, secs / 60 % 60, secs % 60, now.subsec_millis(),
+    )
+}
+
+impl<T: ?Sized> Clone for Hook<T> {
+    fn clone(&self) -> Self {
+        Self(self.0.clone())
+    }
+}
+
+impl<T: ?Sized> Default for Hook<T> {
+    fn default() -> Self {
+        Self(None)
+    }
+}
+
+impl<T: ?Sized> PartialEq for Hook<T> {
+    fn eq(&self, other: &Self) -> bool {
+        match (&self.0, &other.0) {
+            (Some(a), Some(b)) => Arc::ptr_eq(a, b),
+            (a, b) => a.is_none() && b.is_none(),
+        }
+    }
+}
+
+impl<T: ?Sized> Eq for Hook<T> {}
+
+impl<T: ?Sized> core::fmt::Debug for Hook<T> {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self.0 {
+            Some(_) => f.write_str("Some(..)"),
+            None => f.write_str("None"),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use super::{Read, IoResult, TcpStream};
+use super::run::{Run, RunEvent, ExitStatus};
+use super::stream::Stream;
+
+impl<'a, S: Stream> Run<'a, S> {
+    /// Splits the output of the remote process into two readers,
+    /// one for its standard output and one for its standard error
+    pub fn into_output(self) -> RunOutput<'a, S> {
+        RunOutput {
+            state: RefCell::new(OutputState {
+                run: self,
+                stdout: VecDeque::new(),
+                stderr: VecDeque::new(),
+                exit_status: None,
+                stopped: false,
+            }),
+        }
+    }
+}
+
+/// Output of a [`Run`], buffered per stream
+///
+/// Reading from one stream buffers what the other
+/// stream receives in the meantime.
+#[derive(Debug)]
+pub struct RunOutput<'a, S: Stream = TcpStream> {
+    state: RefCell<OutputState<'a, S>>,
+}
+
+#[derive(Debug)]
+struct OutputState<'a, S: Stream> {
+    run: Run<'a, S>,
+    stdout: VecDeque<u8>,
+    stderr: VecDeque<u8>,
+    exit_status: Option<ExitStatus>,
+    stopped: bool,
+}
+
+impl<'a, S: Stream> RunOutput<'a, S> {
+    /// Standard output of the remote process
+    pub fn stdout(&self) -> OutputStream<'_, 'a, S> {
+        OutputStream {
+            output: self,
+            stderr: false,
+        }
+    }
+
+    /// Standard error of the remote process
+    pub fn stderr(&self) -> OutputStream<'_, 'a, S> {
+        OutputStream {
+            output: self,
+            stderr: true,
+        }
+    }
+
+    /// The exit status of the process, once one of the streams reached its end
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.state.borrow().exit_status.clone()
+    }
+}
+
+/// One of the streams of a [`RunOutput`]; reads fail with
+/// [`std::io::ErrorKind::TimedOut`] if nothing was received
+/// before the stream's read timeout expired.
+#[derive(Debug)]
+pub struct OutputStream<'o, 'a, S: Stream = TcpStream> {
+    output: &'o RunOutput<'a, S>,
+    stderr: bool,
+}
+
+impl<'o, 'a, S: Stream> Read for OutputStream<'o, 'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let mut state = self.output.state.borrow_mut();
+        let state = &mut *state;
+
+        loop {
+            let buffer = match self.stderr {
+                true => &state.stderr,
+                false => &state.stdout,
+            };
+
+            if !buffer.is_empty() || state.stopped {
+                break;
+            }
+
+            match state.run.next_event()? {
+                RunEvent::Data(data) => state.stdout.extend(data),
+                RunEvent::ExtDataStderr(data) => state.stderr.extend(data),
+                RunEvent::Stopped(exit_status) => {
+                    state.exit_status = Some(exit_status);
+                    state.stopped = true;
+                },
+                _ => (),
+            }
+        }
+
+        let buffer = match self.stderr {
+            true => &mut state.stderr,
+            false => &mut state.stdout,
+        };
+
+        let len = buffer.len().min(buf.len());
+        for (dst, byte) in buf.iter_mut().zip(buffer.drain(..len)) {
+            *dst = byte;
+        }
+
+        Ok(len)
+    }
+}
@@ -1,210 +1,568 @@
 use core::ops::Range;
-use super::{
-    Result, Error, U8, U32, Write, BufReader,
-    BufWriter, Cipher, Hmac, ErrorKind, Read,
-};
-use super::StreamCipher;
-use super::messages::{MessageType, GlobalRequest};
+use std::collections::VecDeque;
+use super::{ErrorKind, Result, Error, U8, U32, IoErrorKind};
+use super::messages::{MessageType, GlobalRequest, ExtInfo, DebugMessage, Disconnect, ChannelData, KEX_MESSAGES};
 use super::parsedump::{ParseDump, try_u32};
+use super::transport::Transport;
+use super::stream::{Source, Sink};
+use super::observer::{Observer, DebugHook, GlobalRequestHook, PacketDirection, Hook};
+use super::Rng;
+use rand_core::RngCore;
+#[cfg(feature = "zlib")]
+use zeroize::Zeroize;
+use std::io::IoSlice;
+use std::sync::{Arc, Mutex, PoisonError};
+#[cfg(feature = "zlib")]
+use super::compression::{Compressor, Decompressor};
+
+/// Minimum padding length (RFC 4253, section 6)
+const MIN_PADDING: usize = 4;
+
+/// Maximum padding length (RFC 4253, section 6)
+const MAX_PADDING: usize = 255;
+
+/// Initial size of the packet buffers: enough for full `ChannelData`
+/// packets (RFC 4253, section 6.1), so that they aren't grown while
+/// transferring large amounts of data
+const PACKET_CAPACITY: usize = 35000;
+
+/// Default maximum of `packet_length`, like OpenSSH
+pub const MAX_PACKET_LENGTH: u32 = 256 * 1024;
+
+/// Largest `packet_length` of a packet carrying `data` bytes of channel
+/// data: the padding_length byte, the ChannelExtendedData header, the
+/// maximum padding and, as zlib may not shrink the data, its framing
+/// (5 bytes per stored block of up to 65535 bytes, plus the flush).
+pub(crate) fn channel_packet_length(data: u32) -> u64 {
+    let payload = U8 as u64 + 3 * U32 as u64 + U32 as u64 + data as u64;
+    let zlib = (payload / 65535 + 1) * 5 + 8;
+    U8 as u64 + payload + zlib + MAX_PADDING as u64
+}
+
+/// Fills KEXINIT cookies and packet padding, see
+/// [`ConnectOptions::random_source`](crate::ConnectOptions::random_source)
+pub(crate) type RandomSource = Hook<dyn FnMut(&mut [u8]) + Send>;
+
+impl RandomSource {
+    pub(crate) fn new<F: FnMut(&mut [u8]) + Send + 'static>(source: F) -> Self {
+        Hook(Some(Arc::new(Mutex::new(source))))
+    }
+
+    /// Uses the operating system's generator by default
+    pub(crate) fn fill(&self, bytes: &mut [u8]) {
+        match &self.0 {
+            Some(source) => (source.lock().unwrap_or_else(PoisonError::into_inner))(bytes),
+            None => Rng.fill_bytes(bytes),
+        }
+    }
+}
 
-pub struct PacketReader<R: Read> {
-    pub(crate) inner: BufReader<R>,
+/// Negotiated encryption and integrity protection, for one direction
+pub type PacketCipher = Box<dyn Transport + Send>;
+
+pub struct PacketReader<R> {
+    pub(crate) inner: R,
     packet: Vec<u8>,
     packet_number: u32,
-    negociated: Option<(Cipher, Hmac)>,
+    negociated: Option<PacketCipher>,
     block_size: usize,
     mac_size: usize,
+    /// received since the last key exchange
+    pub(crate) bytes: u64,
+    /// payloads received during a key exchange, not processed yet
+    deferred: VecDeque<Vec<u8>>,
+    /// location of the last payload in `packet`
+    payload: Range<usize>,
+    #[cfg(feature = "zlib")]
+    decompressor: Option<Decompressor>,
+    /// reused for each decompressed payload
+    #[cfg(feature = "zlib")]
+    scratch: Vec<u8>,
+    /// allows kex method messages (see `KEX_MESSAGES`) through
+    pub(crate) in_kex: bool,
+    pub(crate) observer: Observer,
+    /// given the Debug messages, which are skipped
+    pub(crate) debug_hook: DebugHook,
+    /// given the GlobalRequests; see `ConnectionInner::answer_global_request`
+    pub(crate) global_requests: GlobalRequestHook,
+    /// `server-sig-algs` of the last ExtInfo message (RFC 8308)
+    pub(crate) server_sig_algs: Option<String>,
+    /// larger packets are refused before being received
+    pub(crate) max_packet_length: u32,
 }
 
-impl<R: Read> PacketReader<R> {
-    pub fn new(inner: BufReader<R>) -> Self {
+impl<R> PacketReader<R> {
+    pub fn new(inner: R) -> Self {
         Self {
             inner,
-            packet: Vec::new(),
+            packet: Vec::with_capacity(PACKET_CAPACITY),
             packet_number: 0,
             negociated: None,
             block_size: 8,
             mac_size: 0,
+            bytes: 0,
+            deferred: VecDeque::new(),
+            payload: 0..0,
+            #[cfg(feature = "zlib")]
+            decompressor: None,
+            #[cfg(feature = "zlib")]
+            scratch: Vec::new(),
+            in_kex: false,
+            observer: Observer::default(),
+            debug_hook: DebugHook::default(),
+            global_requests: GlobalRequestHook::default(),
+            server_sig_algs: None,
+            max_packet_length: MAX_PACKET_LENGTH,
+        }
+    }
+
+    pub fn set_decryptor(&mut self, decryptor: PacketCipher) {
+        self.block_size = decryptor.block_size();
+        self.mac_size = decryptor.mac_size();
+        self.negociated = Some(decryptor);
+        self.bytes = 0;
+    }
+
+    #[cfg(feature = "zlib")]
+    pub fn enable_decompression(&mut self) {
+        // the stream carries on through key exchanges
+        let max_payload = self.max_packet_length as usize;
+        self.decompressor.get_or_insert_with(|| Decompressor::new(max_payload));
+    }
+
+    /// Decompresses the payload at `range` (if compression is enabled),
+    /// appending it to the packet; returns the range of the payload.
+    #[cfg(feature = "zlib")]
+    fn decompress(&mut self, range: Range<usize>) -> Result<Range<usize>> {
+        match &mut self.decompressor {
+            Some(decompressor) => {
+                self.scratch.clear();
+                decompressor.decompress(&self.packet[range], &mut self.scratch)?;
+
+                let start = self.packet.len();
+                self.packet.extend_from_slice(&self.scratch);
+                Ok(start..self.packet.len())
+            },
+            None => Ok(range),
         }
     }
 
-    pub fn set_decryptor(&mut self, decryptor: Cipher, hmac: Hmac, block_size: usize, mac_size: usize) {
-        self.negociated = Some((decryptor, hmac));
-        self.block_size = block_size;
-        self.mac_size = mac_size;
+    #[cfg(not(feature = "zlib"))]
+    fn decompress(&mut self, range: Range<usize>) -> Result<Range<usize>> {
+        Ok(range)
+    }
+
+    /// Keeps a payload for the next call to [`Self::recv_payload`]
+    pub fn defer(&mut self, payload: Vec<u8>) {
+        self.deferred.push_back(payload);
+    }
+
+    /// Makes `payload` the last received payload
+    pub fn replay(&mut self, payload: Vec<u8>) {
+        self.payload = 0..payload.len();
+        self.packet = payload;
+    }
+
+    /// The payload returned by the last call to [`Self::recv_payload`]
+    pub fn last_payload(&self) -> &[u8] {
+        &self.packet[self.payload.clone()]
+    }
+
+    /// Parses [`Self::last_payload`]
+    pub fn last<'a, 'b: 'a, M: ParseDump<'a>>(&'b self) -> Result<M> {
+        M::parse(self.last_payload()).map(|(m, _)| m)
     }
+}
 
-    fn pull(&mut self, to_pull: usize) -> Result<Range<usize>> {
+impl<R: Source> PacketReader<R> {
+    async fn pull(&mut self, to_pull: usize) -> Result<Range<usize>> {
         let old_len = self.packet.len();
         let new_len = old_len + to_pull;
         let range = old_len..new_len;
 
         self.packet.resize(new_len, 0);
-        self.inner.read_exact(&mut self.packet[range.clone()])?;
+        self.inner.read_exact(&mut self.packet[range.clone()]).await?;
 
         Ok(range)
     }
 
-    fn pull_and_decrypt(&mut self, to_pull: usize) -> Result<()> {
-        let range = self.pull(to_pull)?;
+    pub async fn recv_raw(&mut self) -> Result<&[u8]> {
+        let range = loop {
+            let range = self.recv_packet().await?;
+            if self.in_kex && KEX_MESSAGES.contains(&self.packet[range.start]) {
+                break range;
+            }
 
-        if let Some((decryptor, _hmac)) = &mut self.negociated {
-            decryptor.apply_keystream(&mut self.packet[range]);
-        }
+            let msg_type = MessageType::try_from(self.packet[range.start])?;
+            match msg_type {
+                MessageType::Ignore => continue,
+                MessageType::Disconnect => {
+                    let (disconnect, _) = Disconnect::parse(&self.packet[range.clone()])?;
+                    log::info!("The server disconnected ({:?}): {}", disconnect.reason_code, disconnect.description);
+                    let error = Error::new(ErrorKind::Disconnected {
+                        reason: disconnect.reason_code,
+                    });
+
+                    return Err(error.context(disconnect.description.to_string()));
+                },
+                MessageType::Debug => {
+                    let (debug, _) = DebugMessage::parse(&self.packet[range.clone()])?;
+                    self.debug_hook.report(debug);
+                },
+                MessageType::ExtInfo => {
+                    let (ext_info, _) = ExtInfo::parse(&self.packet[range.clone()])?;
+                    if let Some(algorithms) = ext_info.get("server-sig-algs") {
+                        let algorithms = core::str::from_utf8(algorithms).map_err(|_| ErrorKind::InvalidData)?;
+                        self.server_sig_algs = Some(algorithms.into());
+                    }
+                },
+                MessageType::GlobalRequest => {
+                    // THIS FILTERS OUT GLOBAL REQUESTS WITHOUT `want_reply`
+                    let (global_req, len) = GlobalRequest::parse(&self.packet[range.clone()])?;
+                    match global_req.want_reply {
+                        true => break range,
+                        false => {
+                            log::info!("Got global request (type = {})", global_req.request_name);
+                            let data = &self.packet[range.start + len..range.end];
+                            self.global_requests.handle(global_req.request_name, data);
+                        },
+                    }
+                },
+                _ => break range,
+            }
+        };
 
-        Ok(())
+        Ok(&self.packet[range])
     }
 
-    pub fn recv_raw(&mut self) -> Result<&[u8]> {
+    /// Receives, decrypts and decompresses a packet;
+    /// returns the range of its (non-empty) payload
+    async fn recv_packet(&mut self) -> Result<Range<usize>> {
         self.packet.clear();
 
-        log::trace!("---------- PACKET ----------");
-        log::trace!("packet_number = {}", self.packet_number);
-        self.pull_and_decrypt(U32)?;
+        self.pull(U32).await?;
+
+        let packet_length = match &mut self.negociated {
+            Some(decryptor) => {
+                let mut length = [0; U32];
+                length.copy_from_slice(&self.packet);
+                let packet_length = decryptor.packet_length(self.packet_number, &mut length);
+                self.packet.copy_from_slice(&length);
+                packet_length
+            },
+            None => try_u32(&self.packet)?,
+        };
+
+        if packet_length > self.max_packet_length {
+            return Err(Error::new(ErrorKind::PacketTooLarge).context(format!("refusing a packet of {} bytes (maximum: {})", packet_length, self.max_packet_length)));
+        }
 
-        let packet_length = try_u32(&self.packet).unwrap() as usize;
-        log::trace!("packet_length = {}", packet_length);
-        self.pull_and_decrypt(packet_length)?;
-        log::trace!("self.packet.len() = {}", self.packet.len());
+        let packet_length = packet_length as usize;
 
-        if self.mac_size != 0 {
-            log::trace!("self.mac_size = {}", self.mac_size);
-            self.pull(self.mac_size)?;
-            log::trace!("self.packet.len() = {}", self.packet.len());
+        // the length field only counts with ciphers which encrypt it
+        let aligned_length = match &self.negociated {
+            Some(decryptor) if !decryptor.aligns_length() => packet_length,
+            _ => U32 + packet_length,
+        };
+
+        if aligned_length % self.block_size != 0 {
+            let message = format!("packet_length ({}) isn't aligned to the block size ({})", packet_length, self.block_size);
+            return Err(Error::new(ErrorKind::InvalidData).context(message));
         }
 
-        let padding_length = self.packet[U32] as usize;
-        log::trace!("padding_length = {}", padding_length);
-        if let Some(payload_length) = packet_length.checked_sub(padding_length).and_then(|v| v.checked_sub(U8)) {
-            let payload_offset = U32 + U8;
+        self.pull(packet_length).await?;
 
-            if let Some((_decryptor, hmac)) = &self.negociated {
-                let mut hmac = hmac.clone();
-                hmac.update(self.packet_number.to_be_bytes().as_slice());
+        if self.mac_size != 0 {
+            self.pull(self.mac_size).await?;
+        }
 
-                let (packet, packet_hmac) = self.packet.split_at(packet_length + U32);
-                log::trace!("hmac 2nd update: {} bytes", packet.len());
-                hmac.update(packet);
+        let (packet, packet_mac) = self.packet.split_at_mut(U32 + packet_length);
+        if let Some(decryptor) = &mut self.negociated {
+            decryptor.open(self.packet_number, packet, packet_mac)?;
+        }
 
-                if packet_hmac.len() != self.mac_size {
-                    log::error!("Incorrect Packet Mac Size ({})", packet_hmac.len());
-                    return Err(Error::InvalidData);
-                }
+        let payload = depad(&self.packet[U32..U32 + packet_length])?;
 
-                if packet_hmac != &hmac.finalize() {
-                    log::error!("Incorrect Packet Mac");
-                    return Err(Error::InvalidData);
-                }
-            }
+        self.packet_number = self.packet_number.wrapping_add(1);
+        self.bytes += self.packet.len() as u64;
 
-            self.packet_number = self.packet_number.wrapping_add(1);
+        let range = self.decompress((U32 + payload.start)..(U32 + payload.end))?;
+        if range.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData).context("empty payload"));
+        }
 
-            let range = payload_offset..(payload_offset + payload_length);
-            let msg_type = self.packet[payload_offset];
-            let msg_type = MessageType::try_from(msg_type)?;
-            match msg_type {
-                MessageType::Ignore => self.recv_raw(),
-                MessageType::GlobalRequest => {
-                    // THIS FILTERS OUT GLOBAL REQUESTS WITHOUT `want_reply`
-                    let (global_req, _) = GlobalRequest::parse(&self.packet[range.clone()])?;
-                    match global_req.want_reply {
-                        true => Ok(&self.packet[range]),
-                        false => {
-                            log::info!("Ignoring global request (type = {})", global_req.request_name);
-                            self.recv_raw()
-                        },
-                    }
-                },
-                _ => Ok(&self.packet[range]),
+        self.observer.observe(PacketDirection::Received, &self.packet[range.clone()]);
+        self.payload = range.clone();
+        Ok(range)
+    }
+
+    /// Same as [`Self::recv_raw`], but deferred payloads come first
+    /// (once the key exchange is over) and an expired read timeout
+    /// is reported as [`ErrorKind::Timeout`]
+    pub async fn recv_payload(&mut self) -> Result<&[u8]> {
+        if !self.in_kex {
+            if let Some(payload) = self.deferred.pop_front() {
+                self.replay(payload);
+                return Ok(&self.packet);
             }
-        } else {
-            log::error!("Invalid packet_length");
-            Err(Error::InvalidData)
         }
-    }
 
-    pub fn recv<'a, 'b: 'a, M: ParseDump<'a>>(&'b mut self) -> Result<M> {
-        M::parse(match self.recv_raw() {
+        match self.recv_raw().await {
             Ok(bytes) => Ok(bytes),
-            Err(Error::TcpError(ErrorKind::WouldBlock | ErrorKind::TimedOut)) => Err(Error::Timeout),
+            Err(e) if matches!(e.kind(), ErrorKind::TcpError(IoErrorKind::WouldBlock | IoErrorKind::TimedOut)) => Err(ErrorKind::Timeout.into()),
             Err(e) => Err(e),
-        }?).map(|(m, _)| m)
+        }
+    }
+
+    pub async fn recv<'a, 'b: 'a, M: ParseDump<'a>>(&'b mut self) -> Result<M> {
+        M::parse(self.recv_payload().await?).map(|(m, _)| m)
+    }
+}
+
+/// Checks the padding of a decrypted packet (from its `padding_length`
+/// byte to the end of the padding); returns the range of its payload
+pub(crate) fn depad(packet: &[u8]) -> Result<Range<usize>> {
+    let padding_length = *packet.first().ok_or(ErrorKind::InvalidData)? as usize;
+    if !(MIN_PADDING..=MAX_PADDING).contains(&padding_length) {
+        return Err(Error::new(ErrorKind::InvalidData).context("invalid padding_length"));
+    }
+
+    match packet.len().checked_sub(padding_length).and_then(|v| v.checked_sub(U8)) {
+        Some(0) => Err(Error::new(ErrorKind::InvalidData).context("the padding leaves no payload")),
+        Some(payload_length) => Ok(U8..(U8 + payload_length)),
+        None => Err(Error::new(ErrorKind::InvalidData).context("invalid packet_length")),
     }
 }
 
-pub struct PacketWriter<W: Write> {
-    inner: BufWriter<W>,
+pub struct PacketWriter<W> {
+    inner: W,
     packet: Vec<u8>,
     packet_number: u32,
-    negociated: Option<(Cipher, Hmac)>,
+    negociated: Option<PacketCipher>,
     block_size: usize,
+    /// sent since the last key exchange
+    pub(crate) bytes: u64,
+    #[cfg(feature = "zlib")]
+    compressor: Option<Compressor>,
+    /// reused for each compressed payload
+    #[cfg(feature = "zlib")]
+    scratch: Vec<u8>,
+    pub(crate) observer: Observer,
+    pub(crate) random: RandomSource,
 }
 
-impl<W: Write> PacketWriter<W> {
-    pub fn new(inner: BufWriter<W>) -> Self {
+impl<W> PacketWriter<W> {
+    pub fn new(inner: W) -> Self {
         Self {
             inner,
-            packet: Vec::new(),
+            packet: Vec::with_capacity(PACKET_CAPACITY),
             packet_number: 0,
             negociated: None,
             block_size: 8,
+            bytes: 0,
+            #[cfg(feature = "zlib")]
+            compressor: None,
+            #[cfg(feature = "zlib")]
+            scratch: Vec::new(),
+            observer: Observer::default(),
+            random: RandomSource::default(),
         }
     }
 
-    pub fn set_encryptor(&mut self, encryptor: Cipher, hmac: Hmac, block_size: usize) {
-        self.negociated = Some((encryptor, hmac));
-        self.block_size = block_size;
+    pub fn set_encryptor(&mut self, encryptor: PacketCipher) {
+        self.block_size = encryptor.block_size();
+        self.negociated = Some(encryptor);
+        self.bytes = 0;
+    }
+
+    #[cfg(feature = "zlib")]
+    pub fn enable_compression(&mut self) {
+        // the stream carries on through key exchanges
+        self.compressor.get_or_insert_with(Compressor::new);
     }
 
-    fn send_raw<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
+    /// Dumps, compresses and encrypts `message` into `self.packet`
+    ///
+    /// Ciphers work in place, so the payload is copied once (by the dump);
+    /// packets larger than the stream's `BufWriter` then go straight to
+    /// the stream. See [`PacketWriter::send_channel_data`] for the
+    /// unencrypted case.
+    fn seal_packet<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
         self.packet.clear();
         // make room for packet_length & padding_length
         self.packet.resize(U32 + U8, 0);
 
         message.dump(&mut self.packet)?;
+        self.observer.observe(PacketDirection::Sent, &self.packet[U32 + U8..]);
+
+        #[cfg(feature = "zlib")]
+        if let Some(compressor) = &mut self.compressor {
+            self.scratch.clear();
+            compressor.compress(&self.packet[U32 + U8..], &mut self.scratch)?;
+            // the plaintext (e.g. a password) mustn't linger in either buffer
+            self.packet[U32 + U8..].zeroize();
+            self.packet.truncate(U32 + U8);
+            self.packet.extend_from_slice(&self.scratch);
+            self.scratch.zeroize();
+        }
 
-        // todo: compress payload
-
-        let mut packet_length = U8 + self.packet.len() - (U32 + U8);
-        let mut encrypted_length = U32 + packet_length;
-        let padding_length = match encrypted_length % self.block_size {
-            0 => 0,
-            n => self.block_size - n,
+        let aligned_offset = match &self.negociated {
+            Some(encryptor) if !encryptor.aligns_length() => U32,
+            _ => 0,
         };
-        packet_length += padding_length;
-        encrypted_length += padding_length;
-        assert_eq!(encrypted_length % self.block_size, 0);
+
+        let padding_length = self.padding_length(self.packet.len() - aligned_offset);
+        let packet_length = self.packet.len() + padding_length - U32;
 
         // set correct values for packet_length & padding_length
         self.packet[..U32].copy_from_slice(&(packet_length as u32).to_be_bytes());
         self.packet[U32] = padding_length as u8;
 
         // pad
-        self.packet.resize(encrypted_length, 0);
+        let padding_offset = self.packet.len();
+        self.packet.resize(U32 + packet_length, 0);
+        self.random.fill(&mut self.packet[padding_offset..]);
 
-        if let Some((encryptor, hmac)) = &mut self.negociated {
-            let mut hmac = hmac.clone();
-            hmac.update(self.packet_number.to_be_bytes().as_slice());
-            hmac.update(self.packet.as_slice());
+        if let Some(encryptor) = &mut self.negociated {
+            encryptor.seal(self.packet_number, &mut self.packet);
+        }
+
+        self.packet_number = self.packet_number.wrapping_add(1);
+        self.bytes += self.packet.len() as u64;
+
+        Ok(())
+    }
 
-            // encrypt then push hmac
-            encryptor.apply_keystream(&mut self.packet);
-            self.packet.extend_from_slice(&hmac.finalize());
+    /// For packets of `unpadded_length` bytes, counted from the first
+    /// byte which the cipher aligns
+    fn padding_length(&self, unpadded_length: usize) -> usize {
+        let padding_length = self.block_size - (unpadded_length % self.block_size);
+        match padding_length < MIN_PADDING {
+            true => padding_length + self.block_size,
+            false => padding_length,
         }
+    }
+
+    /// Whether packets are sent as dumped: not encrypted, compressed
+    /// or observed
+    fn sends_plaintext(&self) -> bool {
+        #[cfg(feature = "zlib")]
+        let compressing = self.compressor.is_some();
+        #[cfg(not(feature = "zlib"))]
+        let compressing = false;
+
+        self.negociated.is_none() && !compressing && self.observer.0.is_none()
+    }
+}
+
+impl<W: Sink> PacketWriter<W> {
+    pub async fn send<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
+        self.queue(message).await?;
+        self.flush().await
+    }
+
+    /// Same as [`Self::send`], but the packet may stay buffered until
+    /// [`Self::flush`], so that consecutive packets share TCP segments
+    pub async fn queue<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
+        self.seal_packet(message)?;
+        timeout_error(self.inner.write(&self.packet).await)
+    }
+
+    pub async fn flush(&mut self) -> Result<()> {
+        timeout_error(self.inner.flush().await)
+    }
+
+    /// Same as [`Self::send`] with a `ChannelData`; when packets are sent
+    /// in plaintext, `data` isn't copied into the packet: it's written
+    /// between the header and the padding with a vectored write
+    pub async fn send_channel_data(&mut self, recipient_channel: u32, data: &[u8]) -> Result<()> {
+        if !self.sends_plaintext() {
+            return self.send(&ChannelData { recipient_channel, data }).await;
+        }
+
+        self.packet.clear();
+        // packet_length & padding_length
+        self.packet.resize(U32 + U8, 0);
+        (MessageType::ChannelData as u8).dump(&mut self.packet)?;
+        recipient_channel.dump(&mut self.packet)?;
+        (data.len() as u32).dump(&mut self.packet)?;
+
+        let header_length = self.packet.len();
+        let padding_length = self.padding_length(header_length + data.len());
+        let packet_length = header_length + data.len() + padding_length - U32;
+
+        self.packet[..U32].copy_from_slice(&(packet_length as u32).to_be_bytes());
+        self.packet[U32] = padding_length as u8;
+
+        self.packet.resize(header_length + padding_length, 0);
+        self.random.fill(&mut self.packet[header_length..]);
 
         self.packet_number = self.packet_number.wrapping_add(1);
+        self.bytes += (U32 + packet_length) as u64;
 
-        self.inner.write_all(&self.packet)?;
-        self.inner.flush()?;
+        let (header, padding) = self.packet.split_at(header_length);
+        let mut bufs = [IoSlice::new(header), IoSlice::new(data), IoSlice::new(padding)];
+        timeout_error(self.inner.write_vectored(&mut bufs).await)?;
+        self.flush().await
+    }
+}
 
-        Ok(())
+fn timeout_error(result: Result<()>) -> Result<()> {
+    match result {
+        Err(e) if matches!(e.kind(), ErrorKind::TcpError(IoErrorKind::WouldBlock | IoErrorKind::TimedOut)) => Err(ErrorKind::Timeout.into()),
+        result => result,
     }
+}
 
-    pub fn send<'a, M: ParseDump<'a>>(&mut self, message: &M) -> Result<()> {
-        match self.send_raw(message) {
-            Ok(()) => Ok(()),
-            Err(Error::TcpError(ErrorKind::WouldBlock | ErrorKind::TimedOut)) => Err(Error::Timeout),
-            Err(e) => Err(e),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::stream::block_on;
+
+    /// Records what is written, and how
+    #[derive(Default)]
+    struct Recorder {
+        written: Vec<u8>,
+        vectored_writes: usize,
+    }
+
+    impl Sink for Recorder {
+        async fn write(&mut self, buf: &[u8]) -> Result<()> {
+            self.written.extend_from_slice(buf);
+            Ok(())
+        }
+
+        async fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        async fn write_vectored(&mut self, bufs: &mut [IoSlice<'_>]) -> Result<()> {
+            self.vectored_writes += 1;
+            bufs.iter().for_each(|buf| self.written.extend_from_slice(buf));
+            Ok(())
+        }
+    }
+
+    fn writer() -> PacketWriter<Recorder> {
+        let mut writer = PacketWriter::new(Recorder::default());
+        writer.random = RandomSource::new(|bytes: &mut [u8]| bytes.fill(0x5a));
+        writer
+    }
+
+    #[test]
+    fn plaintext_channel_data_is_written_vectored() {
+        for len in [0, 1, 7, 8, 0x8000] {
+            let data = vec![0xa5; len];
+
+            let mut copied = writer();
+            block_on(copied.send(&ChannelData { recipient_channel: 3, data: &data })).unwrap();
+
+            let mut vectored = writer();
+            block_on(vectored.send_channel_data(3, &data)).unwrap();
+
+            assert_eq!(vectored.inner.written, copied.inner.written, "{} bytes", len);
+            assert_eq!(vectored.inner.vectored_writes, 1);
+            assert_eq!((vectored.bytes, vectored.packet_number), (copied.bytes, copied.packet_number));
         }
     }
 }
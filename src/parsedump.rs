@@ -1,30 +1,57 @@
+use alloc::{format, vec::Vec};
 use core::str::from_utf8;
-use super::{Result, Error, ErrorKind, U8, U32, Write};
+use core::fmt;
+use super::{ErrorKind, Result, Error, U8, U32, U64};
 
+#[cfg(feature = "std")]
 pub (crate) fn too_short() -> Error {
-    Error::TcpError(ErrorKind::UnexpectedEof)
+    ErrorKind::TcpError(super::IoErrorKind::UnexpectedEof).into()
 }
 
+#[cfg(not(feature = "std"))]
+pub (crate) fn too_short() -> Error {
+    Error::new(ErrorKind::InvalidData).context("truncated")
+}
+
+/// What [`ParseDump::dump`] appends to; unlike `std::io::Write`,
+/// it only needs `alloc`, and appending can't fail.
+pub trait Buffer {
+    fn put(&mut self, bytes: &[u8]);
+}
+
+impl Buffer for Vec<u8> {
+    fn put(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+}
+
+/// Types with an SSH wire encoding (RFC 4251, section 5); see [`crate::wire`]
 pub trait ParseDump<'b>: Sized {
+    /// Decodes a value at the start of `bytes`, borrowing from them;
+    /// returns it with the number of bytes it took. Truncated input
+    /// fails with `ErrorKind::TcpError(UnexpectedEof)` (with
+    /// [`ErrorKind::InvalidData`] without the `std` feature).
     fn parse(bytes: &'b[u8]) -> Result<(Self, usize)>;
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()>;
+
+    /// Appends the encoded value to `sink`
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()>;
 }
 
-#[doc(hidden)]
-#[macro_export]
 macro_rules! parse_dump_struct_inner {
     ($name:ident { $($field:ident: $field_type:ty,)* }) => {
         fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
             #[allow(unused_mut)]
             let mut i = if let Some(expected) = MessageType::from_struct_name(stringify!($name)) {
-                $crate::check_msg_type!($name, expected, bytes);
+                $crate::messages::check_msg_type!($name, expected, bytes);
                 U8
             } else {
                 0
             };
 
             $(
-                let ($field, inc) = <$field_type>::parse(&bytes[i..])?;
+                let ($field, inc) = <$field_type>::parse(&bytes[i..]).map_err(|e| {
+                    e.context(alloc::format!("{}::{} at byte {}", stringify!($name), stringify!($field), i))
+                })?;
                 i += inc;
             )*
             Ok((Self {
@@ -34,7 +61,7 @@ macro_rules! parse_dump_struct_inner {
             }, i))
         }
 
-        fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
+        fn dump<W: $crate::parsedump::Buffer>(&self, sink: &mut W) -> Result<()> {
             if let Some(msg_type) = MessageType::from_struct_name(stringify!($name)) {
                 (msg_type as u8).dump(sink)?;
             }
@@ -45,10 +72,11 @@ macro_rules! parse_dump_struct_inner {
     }
 }
 
-#[doc(hidden)]
-#[macro_export]
+pub(crate) use parse_dump_struct_inner;
+
 macro_rules! parse_dump_struct {
-    ($name:ident<$lifetime:lifetime> { $($field:ident: $field_type:ty,)* }) => {
+    ($(#[$attr:meta])* $name:ident<$lifetime:lifetime> { $($field:ident: $field_type:ty,)* }) => {
+        $(#[$attr])*
         #[derive(Copy, Clone, Debug)]
         pub struct $name<$lifetime> {
             $(
@@ -57,10 +85,11 @@ macro_rules! parse_dump_struct {
         }
 
         impl<$lifetime, 'b: $lifetime> $crate::parsedump::ParseDump<'b> for $name<$lifetime> {
-            $crate::parse_dump_struct_inner!($name { $($field: $field_type,)* });
+            $crate::parsedump::parse_dump_struct_inner!($name { $($field: $field_type,)* });
         }
     };
-    ($name:ident { $($field:ident: $field_type:ty,)* }) => {
+    ($(#[$attr:meta])* $name:ident { $($field:ident: $field_type:ty,)* }) => {
+        $(#[$attr])*
         #[derive(Debug)]
         pub struct $name {
             $(
@@ -69,28 +98,32 @@ macro_rules! parse_dump_struct {
         }
 
         impl<'b> $crate::parsedump::ParseDump<'b> for $name {
-            $crate::parse_dump_struct_inner!($name { $($field: $field_type,)* });
+            $crate::parsedump::parse_dump_struct_inner!($name { $($field: $field_type,)* });
         }
     };
 }
 
+pub(crate) use parse_dump_struct;
+
 impl<'b> ParseDump<'b> for bool {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
-        Ok((*bytes.get(0).ok_or_else(|| too_short())? != 0, U8))
+        Ok((*bytes.first().ok_or_else(too_short)? != 0, U8))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        Ok(sink.write(&[*self as u8]).map(|_| ())?)
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        sink.put(&[*self as u8]);
+        Ok(())
     }
 }
 
 impl<'b> ParseDump<'b> for u8 {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
-        Ok((*bytes.get(0).ok_or_else(|| too_short())?, U8))
+        Ok((*bytes.first().ok_or_else(too_short)?, U8))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        Ok(sink.write(&[*self]).map(|_| ())?)
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        sink.put(&[*self]);
+        Ok(())
     }
 }
 
@@ -99,8 +132,20 @@ impl<'b> ParseDump<'b> for u32 {
         Ok((try_u32(bytes)?, U32))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        Ok(sink.write(&self.to_be_bytes()).map(|_| ())?)
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        sink.put(&self.to_be_bytes());
+        Ok(())
+    }
+}
+
+impl<'b> ParseDump<'b> for u64 {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        Ok((u64::from_be_bytes(try_get(bytes)?), U64))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        sink.put(&self.to_be_bytes());
+        Ok(())
     }
 }
 
@@ -109,53 +154,110 @@ impl<'b> ParseDump<'b> for [u8; 16] {
         Ok((try_get(bytes)?, 16))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        Ok(sink.write(&*self).map(|_| ())?)
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        sink.put(self);
+        Ok(())
     }
 }
 
 impl<'a, 'b: 'a> ParseDump<'b> for &'a [u8] {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
-        let total = U32 + (try_u32(bytes)? as usize);
-        Ok((bytes.get(U32..total).ok_or_else(|| too_short())?, total))
+        let total = U32.checked_add(try_u32(bytes)? as usize).ok_or_else(too_short)?;
+        Ok((bytes.get(U32..total).ok_or_else(too_short)?, total))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
-        sink.write(&(self.len() as u32).to_be_bytes())?;
-        Ok(sink.write(self).map(|_| ())?)
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        sink.put(&(self.len() as u32).to_be_bytes());
+        sink.put(self);
+        Ok(())
     }
 }
 
 impl<'a, 'b: 'a> ParseDump<'b> for &'a str {
     fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
         let (slice, progress) = <&'a [u8]>::parse(bytes)?;
-        Ok((from_utf8(slice).map_err(|_| Error::InvalidData)?, progress))
+        Ok((from_utf8(slice).map_err(|_| ErrorKind::InvalidData)?, progress))
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
         self.as_bytes().dump(sink)
     }
 }
 
+/// Concatenated without length prefixes, e.g. to hash several
+/// fields at once; it can't be parsed back
 impl<'a, 'b: 'a> ParseDump<'b> for &'a [&'a [u8]] {
     fn parse(_bytes: &'b [u8]) -> Result<(Self, usize)> {
-        panic!("This is only intended for sha256!");
+        // only intended for sha256
+        Err(ErrorKind::Unimplemented.into())
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
         for slice in self.iter() {
-            sink.write(slice).map(|_| ())?;
+            sink.put(slice);
         }
         Ok(())
     }
 }
 
+/// A comma-separated list of names, e.g. of algorithms (RFC 4251, section 5);
+/// each name is non-empty printable ASCII without commas
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct NameList<'a>(pub &'a str);
+
+impl<'a> NameList<'a> {
+    pub fn iter(&self) -> impl Iterator<Item = &'a str> {
+        self.0.split(',').filter(|name| !name.is_empty())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.iter().any(|n| n == name)
+    }
+
+    /// The names which `other` also lists, in the order of this list
+    pub fn intersection<'o>(&self, other: NameList<'o>) -> impl Iterator<Item = &'a str> + 'o where 'a: 'o {
+        self.iter().filter(move |name| other.contains(name))
+    }
+
+    /// The first name of this list (the preferred one) which `other`
+    /// also lists, as in algorithm negotiation (RFC 4253, section 7.1)
+    pub fn first_common(&self, other: NameList) -> Option<&'a str> {
+        self.iter().find(|name| other.contains(name))
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+}
+
+impl fmt::Display for NameList<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.0)
+    }
+}
+
+impl<'a, 'b: 'a> ParseDump<'b> for NameList<'a> {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        let (list, progress) = <&'a str>::parse(bytes)?;
+        let valid = |name: &str| !name.is_empty() && name.bytes().all(|b| b.is_ascii_graphic() && b != b',');
+        if !list.is_empty() && !list.split(',').all(valid) {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid name-list: {:?}", list)));
+        }
+
+        Ok((Self(list), progress))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        self.0.dump(sink)
+    }
+}
+
 pub fn try_get<const N: usize>(src: &[u8]) -> Result<[u8; N]> {
     let mut dst = [0; N];
-    dst.copy_from_slice(src.get(..N).ok_or_else(|| too_short())?);
+    dst.copy_from_slice(src.get(..N).ok_or_else(too_short)?);
     Ok(dst)
 }
 
 pub fn try_u32(src: &[u8]) -> Result<u32> {
-    try_get(src).map(|array| u32::from_be_bytes(array))
+    try_get(src).map(u32::from_be_bytes)
 }
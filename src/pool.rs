@@ -0,0 +1,181 @@
+//! Connections shared by threads running commands across hosts
+
+use std::collections::HashMap;
+use std::sync::{Mutex, MutexGuard, Condvar, PoisonError};
+use core::ops::Deref;
+use super::{Connection, Auth, ConnectOptions, Result};
+
+const DEFAULT_MAX_PER_HOST: usize = 4;
+
+type HealthCheck<'a> = Box<dyn Fn(&Connection) -> Result<()> + Send + Sync + 'a>;
+
+/// Idle connections to a host, and how many are open
+#[derive(Default)]
+struct Host {
+    idle: Vec<Connection>,
+    open: usize,
+}
+
+/// Authenticated connections to one or more hosts, which threads check
+/// out and give back; they're opened on demand, up to a limit per host:
+///
+/// ```no_run
+/// use coolssh::Pool;
+///
+/// let pool = Pool::new(("user", "<hex keypair>").into(), Default::default()).max_per_host(2);
+/// let hosts = ["web1.example.com:22", "web2.example.com:22", "db.example.com:22"];
+///
+/// std::thread::scope(|scope| {
+///     for host in hosts {
+///         let pool = &pool;
+///         scope.spawn(move || {
+///             let conn = pool.checkout(host).unwrap();
+///             println!("{}: {:?}", host, conn.quick_run("uptime").unwrap());
+///         });
+///     }
+/// });
+/// ```
+///
+/// Idle connections go through a health check when they're checked out,
+/// and are replaced by new ones if it fails.
+pub struct Pool<'a> {
+    auth: Auth<'a>,
+    options: ConnectOptions,
+    max_per_host: usize,
+    health_check: HealthCheck<'a>,
+    hosts: Mutex<HashMap<String, Host>>,
+    checked_in: Condvar,
+}
+
+impl<'a> Pool<'a> {
+    /// A pool connecting with `auth` and `options`; no connection is opened yet
+    pub fn new(auth: Auth<'a>, options: ConnectOptions) -> Self {
+        Self {
+            auth,
+            options,
+            max_per_host: DEFAULT_MAX_PER_HOST,
+            health_check: Box::new(|conn| conn.send_ignore(0)),
+            hosts: Mutex::new(HashMap::new()),
+            checked_in: Condvar::new(),
+        }
+    }
+
+    /// How many connections can be open to each host (default: 4);
+    /// [`Self::checkout`] waits when they're all checked out
+    pub fn max_per_host(mut self, max: usize) -> Self {
+        self.max_per_host = max.max(1);
+        self
+    }
+
+    /// Checks idle connections before handing them out; the default
+    /// sends an `Ignore` message, which only detects broken sockets
+    pub fn health_check<F: Fn(&Connection) -> Result<()> + Send + Sync + 'a>(mut self, check: F) -> Self {
+        self.health_check = Box::new(check);
+        self
+    }
+
+    /// Hands out a healthy idle connection to `host` (`host:port`),
+    /// or opens a new one; it returns to the pool when dropped
+    pub fn checkout(&self, host: &str) -> Result<PooledConnection<'_, 'a>> {
+        let mut hosts = self.hosts();
+
+        loop {
+            let slots = hosts.entry(host.into()).or_default();
+
+            if let Some(conn) = slots.idle.pop() {
+                drop(hosts);
+                match (self.health_check)(&conn) {
+                    Ok(()) => return Ok(self.pooled(host, conn)),
+                    Err(error) => log::info!("Dropping a connection to {}: {}", host, error),
+                }
+
+                hosts = self.hosts();
+                self.release(&mut hosts, host);
+            } else if slots.open < self.max_per_host {
+                slots.open += 1;
+                drop(hosts);
+                return match Connection::connect(host, self.auth, self.options.clone()) {
+                    Ok(conn) => Ok(self.pooled(host, conn)),
+                    Err(error) => {
+                        self.release(&mut self.hosts(), host);
+                        Err(error)
+                    },
+                };
+            } else {
+                hosts = self.checked_in.wait(hosts).unwrap_or_else(PoisonError::into_inner);
+            }
+        }
+    }
+
+    /// Number of connections to `host`, idle or checked out
+    pub fn open_connections(&self, host: &str) -> usize {
+        self.hosts().get(host).map_or(0, |slots| slots.open)
+    }
+
+    /// Closes the idle connections to every host
+    pub fn clear(&self) {
+        for slots in self.hosts().values_mut() {
+            slots.open -= slots.idle.len();
+            slots.idle.clear();
+        }
+
+        self.checked_in.notify_all();
+    }
+
+    fn hosts(&self) -> MutexGuard<'_, HashMap<String, Host>> {
+        self.hosts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn pooled(&self, host: &str, conn: Connection) -> PooledConnection<'_, 'a> {
+        PooledConnection {
+            pool: self,
+            host: host.into(),
+            conn: Some(conn),
+        }
+    }
+
+    /// Frees the slot of a connection which was dropped
+    fn release(&self, hosts: &mut HashMap<String, Host>, host: &str) {
+        if let Some(slots) = hosts.get_mut(host) {
+            slots.open -= 1;
+        }
+
+        self.checked_in.notify_one();
+    }
+}
+
+/// A connection checked out of a [`Pool`], which it returns to when dropped
+pub struct PooledConnection<'p, 'a> {
+    pool: &'p Pool<'a>,
+    host: String,
+    conn: Option<Connection>,
+}
+
+impl PooledConnection<'_, '_> {
+    /// Closes the connection instead of returning it to the pool,
+    /// e.g. after an error left it in an unknown state
+    pub fn discard(mut self) {
+        drop(self.conn.take());
+        self.pool.release(&mut self.pool.hosts(), &self.host);
+    }
+}
+
+impl Deref for PooledConnection<'_, '_> {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledConnection<'_, '_> {
+    fn drop(&mut self) {
+        if let Some(conn) = self.conn.take() {
+            if let Some(slots) = self.pool.hosts().get_mut(&self.host) {
+                slots.idle.push(conn);
+            }
+
+            self.pool.checked_in.notify_one();
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! Connections through a SOCKS5 proxy (RFC 1928), with optional
+//! username/password authentication (RFC 1929)
+
+use std::io::{Read, Write, ErrorKind as IoErrorKind};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+use zeroize::Zeroizing;
+use super::{ErrorKind, Result, Error, TcpStream};
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const USERNAME_PASSWORD_VERSION: u8 = 1;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+/// A SOCKS5 proxy, see [`ConnectOptions::proxy`](crate::ConnectOptions::proxy)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Socks5 {
+    /// `host:port` of the proxy
+    pub addr: String,
+    /// Username and password, if the proxy requires them
+    pub auth: Option<(String, String)>,
+}
+
+impl Socks5 {
+    /// Connects to the proxy and asks it to connect to `target`; the
+    /// handshake is bounded by `timeout`, like connection attempts.
+    pub(crate) fn connect(&self, target: SocketAddr, timeout: Option<Duration>) -> Result<TcpStream> {
+        let mut last_error = Error::from(ErrorKind::TcpError(IoErrorKind::InvalidInput));
+        for proxy in self.addr.to_socket_addrs()? {
+            let stream = match timeout {
+                Some(timeout) => TcpStream::connect_timeout(&proxy, timeout),
+                None => TcpStream::connect(proxy),
+            };
+
+            match stream {
+                Ok(stream) => {
+                    stream.set_read_timeout(timeout)?;
+                    stream.set_write_timeout(timeout)?;
+                    return self.handshake(stream, target);
+                },
+                Err(error) => {
+                    log::info!("Couldn't connect to the proxy at {}: {}", proxy, error);
+                    last_error = error.into();
+                },
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn handshake(&self, mut stream: TcpStream, target: SocketAddr) -> Result<TcpStream> {
+        let methods: &[u8] = match self.auth {
+            Some(_) => &[VERSION, 2, NO_AUTHENTICATION, USERNAME_PASSWORD],
+            None => &[VERSION, 1, NO_AUTHENTICATION],
+        };
+        stream.write_all(methods)?;
+
+        let mut choice = [0; 2];
+        stream.read_exact(&mut choice)?;
+        match (choice, &self.auth) {
+            ([VERSION, NO_AUTHENTICATION], _) => (),
+            ([VERSION, USERNAME_PASSWORD], Some((username, password))) => {
+                let (username, password) = (username.as_bytes(), password.as_bytes());
+                let field_len = |field: &[u8]| u8::try_from(field.len()).map_err(|_| ErrorKind::InvalidData);
+
+                let mut request = Zeroizing::new(vec![USERNAME_PASSWORD_VERSION, field_len(username)?]);
+                request.extend_from_slice(username);
+                request.push(field_len(password)?);
+                request.extend_from_slice(password);
+                stream.write_all(&request)?;
+
+                let mut status = [0; 2];
+                stream.read_exact(&mut status)?;
+                if status[1] != 0 {
+                    return Err(Error::new(ErrorKind::AuthenticationFailure).context("the proxy rejected our credentials"));
+                }
+            },
+            ([VERSION, NO_ACCEPTABLE_METHOD], _) => {
+                return Err(Error::new(ErrorKind::AuthenticationFailure).context("the proxy requires an authentication method which we don't support"));
+            },
+            _ => {
+                return Err(Error::new(ErrorKind::InvalidData).context(format!("unexpected reply from the proxy: {:?}", choice)));
+            },
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        match target {
+            SocketAddr::V4(addr) => {
+                request.push(IPV4);
+                request.extend_from_slice(&addr.ip().octets());
+            },
+            SocketAddr::V6(addr) => {
+                request.push(IPV6);
+                request.extend_from_slice(&addr.ip().octets());
+            },
+        }
+        request.extend_from_slice(&target.port().to_be_bytes());
+        stream.write_all(&request)?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply)?;
+        if reply[0] != VERSION {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("unexpected reply from the proxy: {:?}", reply)));
+        }
+
+        if reply[1] != 0 {
+            return Err(Error::new(ErrorKind::TcpError(match reply[1] {
+                2 => IoErrorKind::PermissionDenied,
+                3 | 4 => IoErrorKind::NotFound,
+                5 => IoErrorKind::ConnectionRefused,
+                6 => IoErrorKind::TimedOut,
+                _ => IoErrorKind::Other,
+            })).context(format!("the proxy couldn't connect to {} (reply: {})", target, reply[1])));
+        }
+
+        // the address the proxy bound, which isn't useful here
+        let bound_len = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => {
+                let mut len = [0];
+                stream.read_exact(&mut len)?;
+                len[0] as usize
+            },
+            _ => return Err(ErrorKind::InvalidData.into()),
+        };
+
+        let mut bound = vec![0; bound_len + 2];
+        stream.read_exact(&mut bound)?;
+
+        Ok(stream)
+    }
+}
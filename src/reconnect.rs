@@ -0,0 +1,178 @@
+//! A connection which dials again when the transport is lost
+
+use std::net::ToSocketAddrs;
+use std::time::Duration;
+use super::{Connection, Auth, ConnectOptions, PublicKey, RunResult, ExitStatus, Result, Error, ErrorKind};
+
+/// Delays between the attempts of [`ReconnectingConnection::reconnect`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+    attempts: u32,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            attempts: 10,
+        }
+    }
+}
+
+type Setup<'a> = Box<dyn FnMut(&Connection) -> Result<()> + 'a>;
+
+/// A [`Connection`] which is dialed again, with exponential backoff,
+/// when an operation fails because the transport was lost
+/// ([`ErrorKind::TcpError`] or [`ErrorKind::ConnectionLost`]):
+///
+/// ```no_run
+/// use std::time::Duration;
+/// use coolssh::ReconnectingConnection;
+///
+/// let mut conn = ReconnectingConnection::connect("example.com:22", ("user", "<hex keypair>").into(), Default::default())
+///     .unwrap()
+///     .backoff(Duration::from_millis(500), Duration::from_secs(30), 8);
+///
+/// conn.env("LANG", "C");
+/// conn.on_connect(|conn| {
+///     conn.set_agent_forwarding(true);
+///     conn.set_keepalive(Some(Duration::from_secs(30)));
+///     Ok(())
+/// }).unwrap();
+///
+/// let uptime = conn.quick_run("uptime").unwrap();
+/// ```
+///
+/// New connections must present the host key of the first one,
+/// otherwise reconnecting fails with [`ErrorKind::UntrustedHostKey`].
+pub struct ReconnectingConnection<'a, A: ToSocketAddrs> {
+    addr: A,
+    auth: Auth<'a>,
+    options: ConnectOptions,
+    backoff: Backoff,
+    env: Vec<(String, String)>,
+    setup: Vec<Setup<'a>>,
+    host_key: PublicKey,
+    conn: Connection,
+}
+
+impl<'a, A: ToSocketAddrs> ReconnectingConnection<'a, A> {
+    /// Connects like [`Connection::connect`]; this first attempt isn't retried
+    pub fn connect(addr: A, auth: Auth<'a>, options: ConnectOptions) -> Result<Self> {
+        let conn = Connection::connect(&addr, auth, options.clone())?;
+
+        Ok(Self {
+            addr,
+            auth,
+            options,
+            backoff: Backoff::default(),
+            env: Vec::new(),
+            setup: Vec::new(),
+            host_key: conn.host_key(),
+            conn,
+        })
+    }
+
+    /// Waits `initial` after the first failed attempt, doubling the delay
+    /// up to `max`, and gives up after `attempts` attempts
+    /// (defaults: one second, one minute, 10 attempts)
+    pub fn backoff(mut self, initial: Duration, max: Duration, attempts: u32) -> Self {
+        self.backoff = Backoff {
+            initial,
+            max,
+            attempts,
+        };
+        self
+    }
+
+    /// Sets an environment variable for the commands run with [`Self::quick_run`]
+    pub fn env(&mut self, name: &str, value: &str) {
+        self.env.push((name.into(), value.into()));
+    }
+
+    /// Runs `setup` on the current connection, then on each new one
+    /// (e.g. to enable forwardings or keepalives)
+    pub fn on_connect<F: FnMut(&Connection) -> Result<()> + 'a>(&mut self, mut setup: F) -> Result<()> {
+        setup(&self.conn)?;
+        self.setup.push(Box::new(setup));
+        Ok(())
+    }
+
+    /// The current connection
+    pub fn connection(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Calls `op` with the current connection; if the transport was lost,
+    /// reconnects and calls it once more. `op` may thus run twice, so it
+    /// shouldn't have effects which can't be repeated.
+    pub fn with<T, F: FnMut(&Connection) -> Result<T>>(&mut self, mut op: F) -> Result<T> {
+        match op(&self.conn) {
+            Err(error) if is_connection_loss(&error) => {
+                log::info!("Connection lost ({}), reconnecting", error);
+                self.reconnect()?;
+                op(&self.conn)
+            },
+            result => result,
+        }
+    }
+
+    /// Like [`Connection::quick_run`], with the environment variables
+    /// set with [`Self::env`], reconnecting if needed
+    pub fn quick_run(&mut self, command: &str) -> Result<RunResult<(String, ExitStatus)>> {
+        let env = self.env.clone();
+        let env: Vec<_> = env.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        self.with(|conn| conn.quick_run_with_env(command, &env))
+    }
+
+    /// Replaces the current connection with a new one, then replays
+    /// the callbacks given to [`Self::on_connect`]; fails with the
+    /// last error once all attempts have failed.
+    pub fn reconnect(&mut self) -> Result<()> {
+        let mut delay = self.backoff.initial;
+        let mut last_error = Error::new(ErrorKind::ConnectionLost).context("no reconnection attempt was allowed");
+
+        for attempt in 0..self.backoff.attempts {
+            if attempt > 0 {
+                std::thread::sleep(delay);
+                delay = (delay * 2).min(self.backoff.max);
+            }
+
+            match self.dial() {
+                Ok(conn) => {
+                    self.conn = conn;
+                    return Ok(());
+                },
+                Err(error) if is_connection_loss(&error) => {
+                    log::info!("Reconnection attempt {} failed: {}", attempt + 1, error);
+                    last_error = error;
+                },
+                Err(error) => return Err(error),
+            }
+        }
+
+        Err(last_error)
+    }
+
+    fn dial(&mut self) -> Result<Connection> {
+        let conn = Connection::connect(&self.addr, self.auth, self.options.clone())?;
+
+        if conn.host_key() != self.host_key {
+            let message = format!("the host key changed to {}", conn.host_key_fingerprint());
+            return Err(Error::new(ErrorKind::UntrustedHostKey).context(message));
+        }
+
+        for setup in &mut self.setup {
+            setup(&conn)?;
+        }
+
+        Ok(conn)
+    }
+}
+
+fn is_connection_loss(error: &Error) -> bool {
+    matches!(error.kind(), ErrorKind::TcpError(_) | ErrorKind::ConnectionLost)
+}
@@ -0,0 +1,145 @@
+//! RSA client keys, for `rsa-sha2-256` user authentication (RFC 8332)
+
+use alloc::{format, vec, vec::Vec};
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+use super::{ErrorKind, Result, Error};
+use super::parsedump::ParseDump;
+use super::messages::UnsignedMpInt;
+use super::keygen::{decode_openssh_key, invalid_keypair};
+use super::userauth::AuthSigner;
+
+/// Smallest RSA modulus we accept, in bits
+pub(crate) const RSA_MIN_BITS: u64 = 1024;
+
+// DER-encoded DigestInfo headers (RFC 8017, section 9.2)
+pub(crate) const SHA256_DIGEST_INFO: &[u8] = &[
+    0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+    0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+];
+#[cfg(feature = "std")]
+pub(crate) const SHA512_DIGEST_INFO: &[u8] = &[
+    0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+    0x65, 0x03, 0x04, 0x02, 0x03, 0x05, 0x00, 0x04, 0x40,
+];
+
+/// An RSA private key, for [`Auth::Rsa`](crate::Auth::Rsa):
+///
+/// ```no_run
+/// use coolssh::{Connection, Auth, RsaKey};
+///
+/// let file = std::fs::read("/home/user/.ssh/id_rsa").unwrap();
+/// let key = RsaKey::from_openssh(&file, None).unwrap();
+/// let auth = Auth::Rsa { username: "user", key: &key };
+/// let conn = Connection::connect("example.com:22", auth, Default::default()).unwrap();
+/// ```
+///
+/// Signatures aren't computed in constant time.
+#[derive(Clone)]
+pub struct RsaKey {
+    modulus: BigUint,
+    public_exponent: BigUint,
+    private_exponent: BigUint,
+}
+
+impl RsaKey {
+    /// Loads an `ssh-rsa` key from an OpenSSH private key file
+    /// (e.g. `~/.ssh/id_rsa`); `passphrase` is required for encrypted keys.
+    pub fn from_openssh(file: &[u8], passphrase: Option<&str>) -> Result<Self> {
+        let private_key = decode_openssh_key(file, passphrase)?;
+        Self::parse_private_key(&private_key).map_err(invalid_keypair)
+    }
+
+    fn parse_private_key(private_key: &[u8]) -> Result<Self> {
+        let (algorithm, mut i) = <&str>::parse(private_key)?;
+        if algorithm != "ssh-rsa" {
+            return Err(Error::new(ErrorKind::Unimplemented).context(format!("unsupported private key algorithm: {}", algorithm)));
+        }
+
+        // n, e, d, iqmp, p, q
+        let mut fields = Vec::with_capacity(6);
+        for _ in 0..6 {
+            let (field, inc) = UnsignedMpInt::parse(&private_key[i..])?;
+            i += inc;
+            fields.push(BigUint::from_bytes_be(field.0));
+        }
+
+        let [modulus, public_exponent, private_exponent, _, p, q] = fields.try_into().unwrap();
+        if modulus.bits() < RSA_MIN_BITS {
+            return Err(Error::new(ErrorKind::InvalidData).context("RSA keys must have at least 1024 bits"));
+        }
+
+        if p * q != modulus {
+            return Err(Error::new(ErrorKind::InvalidData).context("corrupted private key"));
+        }
+
+        Ok(Self {
+            modulus,
+            public_exponent,
+            private_exponent,
+        })
+    }
+
+    /// Size of the modulus, in bits
+    pub fn bits(&self) -> u64 {
+        self.modulus.bits()
+    }
+
+    /// The `ssh-rsa` public key blob (RFC 4253, section 6.6)
+    pub fn public_key_blob(&self) -> Vec<u8> {
+        let exponent = self.public_exponent.to_bytes_be();
+        let modulus = self.modulus.to_bytes_be();
+
+        let mut blob = Vec::new();
+        // dumping to a Vec doesn't fail
+        let _ = "ssh-rsa".dump(&mut blob);
+        let _ = UnsignedMpInt(&exponent).dump(&mut blob);
+        let _ = UnsignedMpInt(&modulus).dump(&mut blob);
+        blob
+    }
+
+    /// RSASSA-PKCS1-v1_5 signature with SHA-256, as long as the modulus
+    fn sign_sha256(&self, data: &[u8]) -> Vec<u8> {
+        let digest = Sha256::digest(data);
+        let modulus_len = self.modulus.bits().div_ceil(8) as usize;
+
+        // 0x00 0x01 0xff..0xff 0x00 DigestInfo digest
+        let padding_len = modulus_len - 3 - SHA256_DIGEST_INFO.len() - digest.len();
+        let mut encoded = Vec::with_capacity(modulus_len);
+        encoded.extend_from_slice(&[0x00, 0x01]);
+        encoded.resize(2 + padding_len, 0xff);
+        encoded.push(0x00);
+        encoded.extend_from_slice(SHA256_DIGEST_INFO);
+        encoded.extend_from_slice(&digest);
+
+        let signature = BigUint::from_bytes_be(&encoded).modpow(&self.private_exponent, &self.modulus);
+        let signature = signature.to_bytes_be();
+
+        let mut padded = vec![0; modulus_len - signature.len()];
+        padded.extend_from_slice(&signature);
+        padded
+    }
+}
+
+impl AuthSigner for RsaKey {
+    fn algorithm(&self) -> &str {
+        "rsa-sha2-256"
+    }
+
+    fn public_blob(&self) -> Vec<u8> {
+        self.public_key_blob()
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.sign_sha256(data))
+    }
+}
+
+// doesn't leak secrets in logs
+impl core::fmt::Debug for RsaKey {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("RsaKey")
+            .field("bits", &self.bits())
+            .finish_non_exhaustive()
+    }
+}
@@ -1,77 +1,293 @@
-use super::{Connection, Result, Error};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use super::{ErrorKind, Connection, Result, Error, TcpStream, Read, Write, IoResult};
+use super::connection::ConnectionInner;
+use super::packets::{PacketWriter, channel_packet_length};
+use super::observer::{TransferHook, TransferProgress, Transcript};
+use super::command::RemoteCommand;
+use super::stream::{Stream, Source, Sink, block_on};
+use super::parsedump::ParseDump;
+use super::channelrequest::RawChannelRequest;
 use super::messages::{
-    ChannelOpen, ChannelOpenConfirmation, ChannelRequest, ChannelClose,
-    ChannelData, Message, ChannelExtendedData, ChannelWindowAdjust,
+    ChannelOpen, ChannelOpenConfirmation, ChannelOpenFailure, ChannelRequest, ChannelClose,
+    ChannelData, Message, ChannelExtendedData, ExtendedDataType, ChannelWindowAdjust, ChannelEof, DirectTcpipOpen,
+    ChannelFailure, Unimplemented,
 };
 
-pub type ExitStatus = u32;
+/// How the remote process terminated
+///
+/// New variants may be added in minor releases.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ExitStatus {
+    /// The process exited with this code
+    Code(u32),
+    /// The process was killed by this signal (without the `SIG` prefix, e.g. `KILL`)
+    Signal(String),
+    /// The server didn't report it
+    Unknown,
+}
 
-const CLIENT_INITIAL_WINDOW_SIZE: u32 = u32::MAX;
-const CLIENT_WIN_TELL_TRIGGER: u32 = CLIENT_INITIAL_WINDOW_SIZE / 4;
-const CLIENT_MAX_PACKET_SIZE: u32 = 64 * 0x1000;
+impl ExitStatus {
+    /// Whether the process exited with code zero
+    pub fn success(&self) -> bool {
+        *self == Self::Code(0)
+    }
+}
 
-#[derive(Debug)]
-pub enum RunResult<T: core::fmt::Debug> {
+/// What [`Run::wait_with_timeout`] collected
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WaitOutput {
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// `None` if the timeout expired first (the process may still be running)
+    pub exit_status: Option<ExitStatus>,
+}
+
+pub(crate) type QuickRunOutput = (Option<Vec<u8>>, ExitStatus);
+
+/// Like OpenSSH; see [`Connection::set_channel_window`]
+const CLIENT_INITIAL_WINDOW_SIZE: u32 = 0x200000;
+/// Like OpenSSH; see [`ChannelWindow::check`]
+const CLIENT_MAX_PACKET_SIZE: u32 = 0x8000;
+/// Shortest read timeout used by [`Run::wait_event`]
+const MIN_WAIT: Duration = Duration::from_millis(1);
+
+/// Flow control parameters advertised for the channels we open,
+/// see [`Connection::set_channel_window`]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) struct ChannelWindow {
+    pub(crate) initial_window: u32,
+    pub(crate) max_packet_size: u32,
+}
+
+impl ChannelWindow {
+    /// Fails with [`ErrorKind::PacketTooLarge`] if full-size data packets
+    /// wouldn't fit in `max_packet_length`, so that we don't invite packets
+    /// which we then refuse
+    pub(crate) fn check(&self, max_packet_length: u32) -> Result<()> {
+        let packet_length = channel_packet_length(self.max_packet_size);
+        match packet_length > max_packet_length as u64 {
+            true => {
+                let message = format!("channel packets of {} bytes can take {} bytes, more than the maximum packet length ({})", self.max_packet_size, packet_length, max_packet_length);
+                Err(Error::new(ErrorKind::PacketTooLarge).context(message))
+            },
+            false => Ok(()),
+        }
+    }
+}
+
+impl Default for ChannelWindow {
+    fn default() -> Self {
+        Self {
+            initial_window: CLIENT_INITIAL_WINDOW_SIZE,
+            max_packet_size: CLIENT_MAX_PACKET_SIZE,
+        }
+    }
+}
+
+/// Flow control parameters of one channel, see [`Connection::run_with_options`];
+/// those which aren't set are the ones of [`Connection::set_channel_window`]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct RunOptions {
+    window: Option<u32>,
+    max_packet: Option<u32>,
+}
+
+impl RunOptions {
+    /// Window size advertised for the channel, i.e. how much
+    /// output the server may send ahead of reads
+    pub fn window(mut self, bytes: u32) -> Self {
+        self.window = Some(bytes);
+        self
+    }
+
+    /// Largest data packet which the server may send on the channel; it must
+    /// fit in [`ConnectOptions::max_packet_length`](crate::ConnectOptions::max_packet_length)
+    /// (see [`Connection::set_channel_window`])
+    pub fn max_packet(mut self, bytes: u32) -> Self {
+        self.max_packet = Some(bytes);
+        self
+    }
+
+    fn channel_window(&self, defaults: ChannelWindow) -> ChannelWindow {
+        ChannelWindow {
+            initial_window: self.window.unwrap_or(defaults.initial_window),
+            max_packet_size: self.max_packet.unwrap_or(defaults.max_packet_size),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RunResult<T> {
     Refused,
     Accepted(T),
 }
 
-impl Connection {
-    pub fn run(&mut self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<Run>> {
-        let client_channel = self.next_client_channel;
-        self.next_client_channel += 1;
+/// Sends `open` and awaits the server's answer; returns the server channel
+/// number, window size and maximum packet size, or `None` if the server
+/// refused to open the channel.
+async fn confirm_open<'b, R: Source, W: Sink, M: ParseDump<'b>>(
+    inner: &mut ConnectionInner<R, W>,
+    client_channel: u32,
+    open: &M,
+) -> Result<Option<(u32, u32, u32)>> {
+    inner.send(open).await?;
 
-        self.writer.send(&ChannelOpen {
-            channel_type: "session",
-            client_channel,
-            client_initial_window_size: CLIENT_INITIAL_WINDOW_SIZE,
-            client_max_packet_size: CLIENT_MAX_PACKET_SIZE,
-        })?;
+    loop {
+        inner.recv_channel(client_channel).await?;
+        match inner.reader.last()? {
+            Message::ChannelOpenConfirmation(ChannelOpenConfirmation {
+                client_channel: _,
+                server_channel,
+                server_initial_window_size,
+                server_max_packet_size,
+            }) => break Ok(Some((server_channel, server_initial_window_size, server_max_packet_size))),
+            Message::ChannelOpenFailure(ChannelOpenFailure { description, .. }) => {
+                log::info!("The server refused to open a channel: {}", description);
+                break Ok(None);
+            },
+            msg => skip_benign(msg)?,
+        }
+    }
+}
 
-        let ChannelOpenConfirmation {
-            client_channel: _,
-            server_channel,
-            server_initial_window_size,
-            server_max_packet_size,
-        } = self.reader.recv()?;
+/// Logs messages which don't concern a channel's state, or
+/// fails with [`ErrorKind::UnexpectedMessageType`]
+fn skip_benign(msg: Message) -> Result<()> {
+    match msg {
+        Message::Unimplemented(Unimplemented { packet_number }) => {
+            log::warn!("The server didn't implement our packet #{}", packet_number);
+            Ok(())
+        },
+        msg => {
+            log::error!("Unexpected message: {:#?}", msg);
+            Err(ErrorKind::UnexpectedMessageType(msg.typ()).into())
+        },
+    }
+}
 
-        for (name, value) in env {
-            self.writer.send(&ChannelRequest::EnvironmentVariable {
-                recipient_channel: server_channel,
-                want_reply: false,
-                name,
-                value,
-            })?;
-        }
+/// ChannelOpen of a session channel
+fn session_open(window: ChannelWindow, client_channel: u32) -> ChannelOpen<'static> {
+    ChannelOpen {
+        channel_type: "session",
+        client_channel,
+        client_initial_window_size: window.initial_window,
+        client_max_packet_size: window.max_packet_size,
+    }
+}
+
+impl<S: Stream> Connection<S> {
+    /// Sets the window size and maximum packet size advertised for the
+    /// channels opened afterwards (defaults: 2MiB and 32KiB, like OpenSSH);
+    /// the window bounds how much the server sends ahead of reads. Bulk
+    /// transfers can pass `u32::MAX` to never wait for window adjusts, at
+    /// the cost of buffering whatever isn't read yet.
+    /// [`Self::run_with_options`] overrides them for one channel.
+    ///
+    /// Fails with [`ErrorKind::PacketTooLarge`] if packets of `max_packet_size`
+    /// bytes of data wouldn't fit in [`ConnectOptions::max_packet_length`](crate::ConnectOptions::max_packet_length).
+    /// Servers which overrun them are reported with [`ErrorKind::InvalidData`].
+    pub fn set_channel_window(&self, initial_window: u32, max_packet_size: u32) -> Result<()> {
+        self.inner().set_channel_window(initial_window, max_packet_size)
+    }
+
+    /// Records the commands started afterwards (`exec`, `shell` and
+    /// `subsystem` requests) to `sink`, one line each, then how they
+    /// terminated with the bytes sent and received, after the UTC time;
+    /// e.g. for audits. Environment variables aren't recorded.
+    ///
+    /// ```text
+    /// 2024-01-31T12:00:00.000Z channel 0: exec "uptime"
+    /// 2024-01-31T12:00:00.042Z channel 0: exited with Code(0), sent 0 bytes, received 52 bytes
+    /// ```
+    pub fn set_transcript<T: Write + Send + 'static>(&self, sink: T) {
+        self.inner().transcript = Transcript::new(sink);
+    }
+
+    /// Opens a session channel and executes `command` in it
+    pub fn run(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<Run<'_, S>>> {
+        self.run_with_options(command, env, RunOptions::default())
+    }
+
+    /// Same as [`Self::run`], with the flow control parameters of `options`
+    pub fn run_with_options(&self, command: &str, env: &[(&str, &str)], options: RunOptions) -> Result<RunResult<Run<'_, S>>> {
+        let prelude = |recipient_channel| env.iter().map(|(name, value)| ChannelRequest::EnvironmentVariable {
+            recipient_channel,
+            want_reply: true,
+            name,
+            value,
+        }).collect();
 
-        self.writer.send(&ChannelRequest::Exec {
-            recipient_channel: server_channel,
+        self.start_session(options, prelude, |recipient_channel| ChannelRequest::Exec {
+            recipient_channel,
             want_reply: true,
             command,
-        })?;
+        })
+    }
+
+    /// Same as [`Self::run`], with a command line assembled by `command`
+    pub fn run_command(&self, command: &RemoteCommand) -> Result<RunResult<Run<'_, S>>> {
+        let env: Vec<_> = command.get_env().collect();
+        self.run(&command.to_string(), &env)
+    }
 
-        match self.reader.recv()? {
-            Message::ChannelSuccess(_) => Ok(RunResult::Accepted(Run {
+    /// Opens a session channel and starts the `name` subsystem (e.g. `sftp`) in it
+    pub fn subsystem(&self, name: &str) -> Result<RunResult<Run<'_, S>>> {
+        self.start_session(RunOptions::default(), |_| Vec::new(), |recipient_channel| ChannelRequest::Subsystem {
+            recipient_channel,
+            want_reply: true,
+            subsystem_name: name,
+        })
+    }
+
+    /// See [`Channel::open`]
+    pub(crate) fn start_session<'b, P, F>(&self, options: RunOptions, prelude: P, start: F) -> Result<RunResult<Run<'_, S>>>
+        where P: FnOnce(u32) -> Vec<ChannelRequest<'b>>,
+              F: FnOnce(u32) -> ChannelRequest<'b>
+    {
+        Ok(match block_on(Channel::open(&mut self.inner(), options, prelude, start))? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(channel) => RunResult::Accepted(Run {
                 conn: self,
-                server_channel,
-                client_channel,
-                exit_status: None,
-                closed: false,
-
-                client_window: CLIENT_INITIAL_WINDOW_SIZE as _,
-                server_window: server_initial_window_size as _,
-                server_max_packet_size: server_max_packet_size as _,
-            })),
-            Message::ChannelFailure(_) => Ok(RunResult::Refused),
-            msg => {
-                log::error!("Unexpected message: {:#?}", msg);
-                return Err(Error::UnexpectedMessageType(msg.typ()));
-            },
-        }
+                channel,
+                unread: Vec::new(),
+            }),
+        })
     }
 
-    fn quick_run_internal(&mut self, command: &str, get_output: bool) -> Result<RunResult<(Option<Vec<u8>>, Option<ExitStatus>)>> {
-        match self.run(command, &[])? {
+    /// See [`Channel::open_bare`]
+    pub(crate) fn start_bare_session(&self) -> Result<RunResult<Run<'_, S>>> {
+        Ok(match block_on(Channel::open_bare(&mut self.inner(), RunOptions::default()))? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(channel) => RunResult::Accepted(Run {
+                conn: self,
+                channel,
+                unread: Vec::new(),
+            }),
+        })
+    }
+
+    /// See [`Channel::open_direct_tcpip`]
+    pub(crate) fn start_direct_tcpip(&self, host: &str, port: u32) -> Result<RunResult<Run<'_, S>>> {
+        Ok(match block_on(Channel::open_direct_tcpip(&mut self.inner(), host, port))? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(channel) => RunResult::Accepted(Run {
+                conn: self,
+                channel,
+                unread: Vec::new(),
+            }),
+        })
+    }
+
+    fn quick_run_internal(
+        &self,
+        command: &str,
+        env: &[(&str, &str)],
+        deadline: Option<Instant>,
+        get_output: bool,
+    ) -> Result<RunResult<QuickRunOutput>> {
+        match self.run(command, env)? {
             RunResult::Refused => Ok(RunResult::Refused),
             RunResult::Accepted(mut run) => {
                 let mut output = match get_output {
@@ -80,10 +296,20 @@ impl Connection {
                 };
 
                 loop {
-                    match run.poll()? {
-                        RunEvent::None => std::thread::sleep(std::time::Duration::from_millis(10)),
-                        RunEvent::Data(data) => { output.as_mut().map(|o| o.extend_from_slice(data)); },
-                        RunEvent::ExtDataStderr(data) => { output.as_mut().map(|o| o.extend_from_slice(data)); },
+                    let event = match deadline {
+                        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                            Some(left) if !left.is_zero() => run.wait_event(Some(left))?,
+                            _ => RunEvent::TimedOut,
+                        },
+                        None => run.wait_event(None)?,
+                    };
+
+                    match event {
+                        RunEvent::None => (),
+                        RunEvent::Data(data) => { if let Some(o) = output.as_mut() { o.extend_from_slice(data) } },
+                        RunEvent::ExtDataStderr(data) => { if let Some(o) = output.as_mut() { o.extend_from_slice(data) } },
+                        RunEvent::ExtData { .. } | RunEvent::Eof | RunEvent::Killed { .. } => (),
+                        RunEvent::TimedOut => return Err(ErrorKind::Timeout.into()),
                         RunEvent::Stopped(exit_status) => return Ok(RunResult::Accepted((output, exit_status))),
                     }
                 }
@@ -91,182 +317,1121 @@ impl Connection {
         }
     }
 
-    pub fn quick_run_bytes(&mut self, command: &str) -> Result<RunResult<(Vec<u8>, Option<ExitStatus>)>> {
-        Ok(match self.quick_run_internal(command, true)? {
-            RunResult::Refused => RunResult::Refused,
-            RunResult::Accepted((None, _)) => unreachable!(),
-            RunResult::Accepted((Some(vec), status)) => RunResult::Accepted((vec, status)),
-        })
+    /// Runs `command` to completion and returns its output (stdout and stderr)
+    pub fn quick_run_bytes(&self, command: &str) -> Result<RunResult<(Vec<u8>, ExitStatus)>> {
+        self.quick_run_bytes_with_env(command, &[])
     }
 
-    pub fn quick_run(&mut self, command: &str) -> Result<RunResult<(String, Option<ExitStatus>)>> {
-        Ok(match self.quick_run_internal(command, true)? {
-            RunResult::Refused => RunResult::Refused,
-            RunResult::Accepted((None, _)) => unreachable!(),
-            RunResult::Accepted((Some(bytes), status)) => {
-                RunResult::Accepted((String::from_utf8(bytes).map_err(|_| {
-                    log::error!("Non-UTF-8 bytes in command output");
-                    Error::InvalidData
-                })?, status))
-            },
-        })
+    /// Same as [`Self::quick_run_bytes`], for UTF-8 output
+    pub fn quick_run(&self, command: &str) -> Result<RunResult<(String, ExitStatus)>> {
+        self.quick_run_with_env(command, &[])
     }
 
-    pub fn quick_run_blind(&mut self, command: &str) -> Result<RunResult<Option<ExitStatus>>> {
-        Ok(match self.quick_run_internal(command, false)? {
+    /// Runs `command` to completion, discarding its output
+    pub fn quick_run_blind(&self, command: &str) -> Result<RunResult<ExitStatus>> {
+        self.quick_run_blind_with_env(command, &[])
+    }
+
+    /// Same as [`Self::quick_run_bytes`], setting the environment variables in `env` first
+    pub fn quick_run_bytes_with_env(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<(Vec<u8>, ExitStatus)>> {
+        Ok(bytes_output(self.quick_run_internal(command, env, None, true)?))
+    }
+
+    /// Same as [`Self::quick_run`], setting the environment variables in `env` first
+    pub fn quick_run_with_env(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<(String, ExitStatus)>> {
+        string_output(self.quick_run_internal(command, env, None, true)?)
+    }
+
+    /// Same as [`Self::quick_run_blind`], setting the environment variables in `env` first
+    pub fn quick_run_blind_with_env(&self, command: &str, env: &[(&str, &str)]) -> Result<RunResult<ExitStatus>> {
+        Ok(blind_output(self.quick_run_internal(command, env, None, false)?))
+    }
+
+    /// Runs `command` to completion and returns its standard output, or fails
+    /// with [`ErrorKind::CommandFailed`] if it didn't exit with code zero;
+    /// the message of that error holds the exit status and standard error.
+    pub fn quick_run_checked(&self, command: &str) -> Result<RunResult<String>> {
+        let output = match self.run(command, &[])? {
             RunResult::Refused => RunResult::Refused,
-            RunResult::Accepted((None, status)) => RunResult::Accepted(status),
-            RunResult::Accepted((Some(_), _)) => unreachable!(),
-        })
+            RunResult::Accepted(mut run) => RunResult::Accepted(run.wait_output()?),
+        };
+
+        checked_output(output)
+    }
+
+    /// Same as [`Self::quick_run_with_env`], but fails with [`ErrorKind::Timeout`] if
+    /// the process is still running after `timeout`; see [`Run::wait_event`].
+    ///
+    /// Use [`Run::wait_with_timeout`] to get the output of a process which timed out.
+    pub fn quick_run_with_timeout(&self, command: &str, env: &[(&str, &str)], timeout: Duration) -> Result<RunResult<(String, ExitStatus)>> {
+        string_output(self.quick_run_internal(command, env, Some(Instant::now() + timeout), true)?)
+    }
+}
+
+pub(crate) fn bytes_output(output: RunResult<QuickRunOutput>) -> RunResult<(Vec<u8>, ExitStatus)> {
+    match output {
+        RunResult::Refused => RunResult::Refused,
+        RunResult::Accepted((None, _)) => unreachable!(),
+        RunResult::Accepted((Some(vec), status)) => RunResult::Accepted((vec, status)),
+    }
+}
+
+pub(crate) fn string_output(output: RunResult<QuickRunOutput>) -> Result<RunResult<(String, ExitStatus)>> {
+    Ok(match bytes_output(output) {
+        RunResult::Refused => RunResult::Refused,
+        RunResult::Accepted((bytes, status)) => {
+            RunResult::Accepted((String::from_utf8(bytes).map_err(|_| Error::new(ErrorKind::InvalidData).context("non-UTF-8 bytes in command output"))?, status))
+        },
+    })
+}
+
+/// See [`Connection::quick_run_checked`]
+pub(crate) fn checked_output(output: RunResult<WaitOutput>) -> Result<RunResult<String>> {
+    let WaitOutput { stdout, stderr, exit_status } = match output {
+        RunResult::Refused => return Ok(RunResult::Refused),
+        RunResult::Accepted(output) => output,
+    };
+
+    let exit_status = exit_status.unwrap_or(ExitStatus::Unknown);
+    if !exit_status.success() {
+        let code = match exit_status {
+            ExitStatus::Code(code) => Some(code),
+            _ => None,
+        };
+
+        let error = Error::new(ErrorKind::CommandFailed { code });
+        let stderr = String::from_utf8_lossy(&stderr);
+        return Err(match stderr.trim_end() {
+            "" => error.context(format!("{:?}", exit_status)),
+            stderr => error.context(format!("{:?}: {}", exit_status, stderr)),
+        });
+    }
+
+    match String::from_utf8(stdout) {
+        Ok(stdout) => Ok(RunResult::Accepted(stdout)),
+        Err(_) => Err(Error::new(ErrorKind::InvalidData).context("non-UTF-8 bytes in command output")),
     }
 }
 
+pub(crate) fn blind_output(output: RunResult<QuickRunOutput>) -> RunResult<ExitStatus> {
+    match output {
+        RunResult::Refused => RunResult::Refused,
+        RunResult::Accepted((None, status)) => RunResult::Accepted(status),
+        RunResult::Accepted((Some(_), _)) => unreachable!(),
+    }
+}
+
+#[derive(Debug)]
+pub struct Run<'a, S: Stream = TcpStream> {
+    conn: &'a Connection<S>,
+    channel: Channel,
+    /// standard output received but not read yet, see `impl Read for Run`
+    unread: Vec<u8>,
+}
+
+/// A session channel, whichever way the connection is shared
 #[derive(Debug)]
-pub struct Run<'a> {
-    conn: &'a mut Connection,
-    exit_status: Option<ExitStatus>,
+pub(crate) struct Channel {
+    exit_code: Option<u32>,
+    exit_signal: Option<ExitSignal>,
     closed: bool,
+    eof_sent: bool,
+    eof_received: bool,
+    /// events received by [`Channel::write_stdin`], for [`Channel::poll`]
+    pending: VecDeque<PendingEvent>,
+    /// data of the last event taken from `pending`
+    delivered: Vec<u8>,
+    /// pairs ChannelSuccess and ChannelFailure with our requests
+    replies: ReplyMatcher,
     server_channel: u32,
     server_max_packet_size: usize,
     server_window: usize,
-    client_window: usize,
-
-    // todo: check it in incoming messages
-    #[allow(dead_code)]
+    /// what we advertised when opening the channel
+    window: ChannelWindow,
+    /// bytes which the server may still send
+    client_window: u32,
     client_channel: u32,
+    /// data sent and received, for `transfer_progress`
+    sent: u64,
+    received: u64,
+    pub(crate) transfer_progress: TransferHook,
+    /// the start of the command was recorded, but not its end
+    recorded: bool,
+}
+
+/// Pairs the ChannelSuccess and ChannelFailure messages of a channel
+/// with the requests which wanted a reply, which the server answers
+/// in order (RFC 4254, section 5.4)
+#[derive(Debug, Default)]
+struct ReplyMatcher {
+    /// oldest first
+    awaiting: VecDeque<AwaitedReply>,
+    next_ticket: u64,
+    /// replies to tickets, not taken yet
+    replies: Vec<(u64, bool)>,
+    /// environment variables which the server refused to set
+    refused_env: Vec<String>,
+}
+
+#[derive(Debug)]
+enum AwaitedReply {
+    /// an environment variable, which may be refused
+    Env(String),
+    /// a request whose reply is taken with [`ReplyMatcher::take`]
+    Ticket(u64),
+}
+
+impl ReplyMatcher {
+    fn expect_env(&mut self, name: &str) {
+        self.awaiting.push_back(AwaitedReply::Env(name.into()));
+    }
+
+    /// Returns the ticket of the reply
+    fn expect(&mut self) -> u64 {
+        let ticket = self.next_ticket;
+        self.next_ticket += 1;
+        self.awaiting.push_back(AwaitedReply::Ticket(ticket));
+        ticket
+    }
+
+    fn received(&mut self, success: bool) -> Result<()> {
+        let Some(awaited) = self.awaiting.pop_front() else {
+            return Err(Error::new(ErrorKind::InvalidData).context("got a channel request reply, but no request wanted one"));
+        };
+
+        match (awaited, success) {
+            (AwaitedReply::Ticket(ticket), success) => self.replies.push((ticket, success)),
+            (AwaitedReply::Env(name), false) => {
+                log::warn!("The server refused to set {}", name);
+                self.refused_env.push(name);
+            },
+            (_, true) => (),
+        }
+
+        Ok(())
+    }
+
+    fn take(&mut self, ticket: u64) -> Option<bool> {
+        let i = self.replies.iter().position(|(t, _)| *t == ticket)?;
+        Some(self.replies.remove(i).1)
+    }
+}
+
+/// See [`RunEvent::Killed`]
+#[derive(Debug)]
+struct ExitSignal {
+    signal: String,
+    core_dumped: bool,
+    message: String,
+}
+
+impl ExitSignal {
+    fn event(&self) -> RunEvent<'_> {
+        RunEvent::Killed {
+            signal: &self.signal,
+            core_dumped: self.core_dumped,
+            message: &self.message,
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Debug)]
+enum PendingEvent {
+    Data(Vec<u8>),
+    ExtDataStderr(Vec<u8>),
+    ExtData(ExtendedDataType, Vec<u8>),
+    Eof,
+    Killed,
+    Stopped,
+}
+
+/// Keeps `event` for [`Channel::poll`]; fails once the channel is closed
+fn defer_event(pending: &mut VecDeque<PendingEvent>, event: RunEvent) -> Result<()> {
+    match event {
+        RunEvent::Data(data) => pending.push_back(PendingEvent::Data(data.to_vec())),
+        RunEvent::ExtDataStderr(data) => pending.push_back(PendingEvent::ExtDataStderr(data.to_vec())),
+        RunEvent::ExtData { kind, data } => pending.push_back(PendingEvent::ExtData(kind, data.to_vec())),
+        RunEvent::Eof => pending.push_back(PendingEvent::Eof),
+        RunEvent::Killed { .. } => pending.push_back(PendingEvent::Killed),
+        RunEvent::Stopped(_) => {
+            pending.push_back(PendingEvent::Stopped);
+            return Err(ErrorKind::ProcessHasExited.into());
+        },
+        RunEvent::None | RunEvent::TimedOut => (),
+    }
+
+    Ok(())
+}
+
+/// Something that happened on a [`Run`] channel
+///
+/// New variants may be added in minor releases.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
 pub enum RunEvent<'a> {
+    /// Nothing to report (yet)
     None,
+    /// Standard output of the remote process
     Data(&'a [u8]),
+    /// Standard error of the remote process
     ExtDataStderr(&'a [u8]),
-    Stopped(Option<ExitStatus>),
+    /// Extended data of another type than standard error
+    ExtData {
+        kind: ExtendedDataType,
+        data: &'a [u8],
+    },
+    /// The server won't send more data on the channel; it stays
+    /// open until [`RunEvent::Stopped`], e.g. for the exit status
+    Eof,
+    /// The remote process was killed by a signal (named without
+    /// the `SIG` prefix, e.g. `KILL`); the channel closes afterwards.
+    Killed {
+        signal: &'a str,
+        core_dumped: bool,
+        message: &'a str,
+    },
+    /// The channel was closed
+    Stopped(ExitStatus),
+    /// The deadline given to [`Run::poll_until`] has passed
+    TimedOut,
 }
 
-impl<'a> Run<'a> {
-    pub fn poll(&mut self) -> Result<RunEvent> {
-        let message = match self.conn.reader.recv() {
-            Ok(message) => message,
-            Err(Error::Timeout) => return Ok(RunEvent::None),
-            Err(e) => return Err(e),
+impl<'a, S: Stream> Run<'a, S> {
+    /// Returns the next event, or [`RunEvent::None`] if
+    /// the stream's read timeout expired first
+    pub fn poll(&mut self) -> Result<RunEvent<'_>> {
+        block_on(self.channel.poll(&mut self.conn.inner()))
+    }
+
+    /// Same as [`Self::poll`], but returns [`RunEvent::TimedOut`] once `deadline`
+    /// has passed
+    ///
+    /// The deadline is checked before reading, so the stream's read
+    /// timeout must be set for it to be honored while the process is silent.
+    pub fn poll_until(&mut self, deadline: Instant) -> Result<RunEvent<'_>> {
+        if Instant::now() >= deadline {
+            return Ok(RunEvent::TimedOut);
+        }
+
+        self.poll()
+    }
+
+    /// Blocks until the next event, or until `timeout` expires (never if
+    /// `None`), in which case [`RunEvent::None`] is returned
+    ///
+    /// The stream's read timeout is replaced while waiting, so this doesn't
+    /// spin like a loop over [`Self::poll`]; it's restored afterwards.
+    /// With [`Connection::set_keepalive`], keepalives are sent meanwhile.
+    pub fn wait_event(&mut self, timeout: Option<Duration>) -> Result<RunEvent<'_>> {
+        block_on(self.channel.wait_event(&mut self.conn.inner(), timeout))
+    }
+
+    /// Collects the output of the process until the channel is closed or
+    /// `timeout` expires; see [`Self::poll_until`].
+    ///
+    /// The channel is left open if the process is still running,
+    /// so it can be stopped with [`Self::signal`].
+    pub fn wait_with_timeout(&mut self, timeout: Duration) -> Result<WaitOutput> {
+        let deadline = Instant::now() + timeout;
+        let mut output = WaitOutput::default();
+
+        loop {
+            match self.poll_until(deadline)? {
+                RunEvent::Data(data) => output.stdout.extend_from_slice(data),
+                RunEvent::ExtDataStderr(data) => output.stderr.extend_from_slice(data),
+                RunEvent::Stopped(exit_status) => {
+                    output.exit_status = Some(exit_status);
+                    break Ok(output);
+                },
+                RunEvent::TimedOut => break Ok(output),
+                _ => (),
+            }
+        }
+    }
+
+    /// Collects the output of the process until the channel is closed
+    fn wait_output(&mut self) -> Result<WaitOutput> {
+        let mut output = WaitOutput::default();
+
+        loop {
+            match self.wait_event(None)? {
+                RunEvent::Data(data) => output.stdout.extend_from_slice(data),
+                RunEvent::ExtDataStderr(data) => output.stderr.extend_from_slice(data),
+                RunEvent::Stopped(exit_status) => {
+                    output.exit_status = Some(exit_status);
+                    break Ok(output);
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// Calls `callback` after each packet of data sent or received
+    /// over this channel, e.g. to display a progress bar
+    pub fn set_transfer_progress<F: FnMut(TransferProgress) + Send + 'static>(&mut self, callback: F) {
+        self.channel.transfer_progress = TransferHook::new(callback);
+    }
+
+    /// Streams the standard output of the process to `writer` until the
+    /// channel is closed, e.g. for multi-gigabyte `cat` transfers; returns
+    /// the number of bytes written and the exit status.
+    ///
+    /// Each packet is written before the next one is read, so nothing
+    /// accumulates in memory and the channel window is only enlarged as
+    /// `writer` keeps up (see [`Connection::set_channel_window`]).
+    /// `progress` is given the number of bytes written so far after each
+    /// packet. Standard error is discarded.
+    pub fn read_to<W: Write, F: FnMut(u64)>(&mut self, mut writer: W, mut progress: F) -> Result<(u64, ExitStatus)> {
+        let mut written = 0;
+
+        // left over by `impl Read for Run`
+        if !self.unread.is_empty() {
+            writer.write_all(&self.unread)?;
+            written += self.unread.len() as u64;
+            self.unread.clear();
+            progress(written);
+        }
+
+        if self.channel.output_finished() {
+            writer.flush()?;
+            return Ok((written, self.channel.exit_status()));
+        }
+
+        loop {
+            match self.poll()? {
+                RunEvent::Data(data) => {
+                    writer.write_all(data)?;
+                    written += data.len() as u64;
+                    progress(written);
+                },
+                RunEvent::Stopped(exit_status) => {
+                    writer.flush()?;
+                    break Ok((written, exit_status));
+                },
+                _ => (),
+            }
+        }
+    }
+
+    /// The environment variables given to [`Connection::run`] which the
+    /// server refused to set (e.g. because of its `AcceptEnv` option)
+    pub fn refused_env(&self) -> &[String] {
+        self.channel.refused_env()
+    }
+
+    /// The exit status, once the channel is closed
+    pub(crate) fn closed(&self) -> Option<ExitStatus> {
+        self.channel.closed.then(|| self.channel.exit_status())
+    }
+
+    /// See [`Channel::request`]
+    pub(crate) fn request(&mut self, request_type: &str, want_reply: bool, data: &[u8]) -> Result<bool> {
+        block_on(self.channel.request(&mut self.conn.inner(), request_type, want_reply, data))
+    }
+
+    /// Same as [`Self::poll`], but an expired read timeout
+    /// is reported as [`ErrorKind::Timeout`]
+    pub(crate) fn next_event(&mut self) -> Result<RunEvent<'_>> {
+        block_on(self.channel.next_event(&mut self.conn.inner()))
+    }
+
+    /// Tries to send `data` over the run channel and calls `event_callback`
+    /// if an event occurs during the transmission.
+    ///
+    /// Events are only polled for while waiting for the server to enlarge
+    /// its window; those received otherwise are left for [`Self::poll`].
+    ///
+    /// Use this if the protocol you're using is full-duplex.
+    pub fn write_poll<WPE: From<Error>, F: FnMut(RunEvent) -> core::result::Result<(), WPE>>(
+        &mut self,
+        data: &[u8],
+        event_callback: F,
+    ) -> core::result::Result<(), WPE> {
+        block_on(self.channel.write_poll(&mut self.conn.inner(), data, event_callback))
+    }
+
+    /// Sends `data` to the standard input of the remote process; output
+    /// received in the meantime is returned by the next calls to [`Self::poll`].
+    pub fn write_stdin(&mut self, data: &[u8]) -> Result<()> {
+        block_on(self.channel.write_stdin(&mut self.conn.inner(), data))
+    }
+
+    /// Closes the standard input of the remote process: half-closes the
+    /// channel, whose output can still be read until [`RunEvent::Eof`],
+    /// e.g. in pipelines
+    pub fn close_write(&mut self) -> Result<()> {
+        block_on(self.channel.close_write(&mut self.conn.inner()))
+    }
+
+    /// Closes the standard input of the remote process
+    #[deprecated(note = "renamed to `Run::close_write`")]
+    pub fn eof(&mut self) -> Result<()> {
+        self.close_write()
+    }
+
+    /// Sends the signal `name` (without the `SIG` prefix, e.g. `TERM`
+    /// or `KILL`) to the remote process; servers may ignore it.
+    pub fn signal(&mut self, name: &str) -> Result<()> {
+        self.send_request(|recipient_channel| ChannelRequest::Signal {
+            recipient_channel,
+            signal_name: name,
+        })
+    }
+
+    /// The connection this channel belongs to
+    pub(crate) fn connection(&self) -> &'a Connection<S> {
+        self.conn
+    }
+
+    /// Sends the request returned by `request`, which is
+    /// given the server's number for this channel
+    pub(crate) fn send_request<'b, F>(&mut self, request: F) -> Result<()>
+        where F: FnOnce(u32) -> ChannelRequest<'b>
+    {
+        block_on(self.channel.send_request(&mut self.conn.inner(), request))
+    }
+
+    /// Tries to send `data` over the run channel and returns the `on_event` error
+    /// if an event occurs during the transmission.
+    ///
+    /// Use this if the protocol you're using is half-duplex.
+    pub fn write<WPE: From<Error>>(&mut self, data: &[u8], on_event: WPE) -> core::result::Result<(), WPE> {
+        let mut on_event = Some(on_event);
+        self.write_poll(data, |data| {
+            log::error!("Unexpected RunEvent in Run::write(): {:?}", data);
+            Err(on_event.take().unwrap())
+        })
+    }
+}
+
+/// Reads the standard output of the remote process until the channel
+/// is closed; reads fail with [`std::io::ErrorKind::TimedOut`] if nothing
+/// was received before the stream's read timeout expired.
+///
+/// Standard error is discarded (see [`Run::into_output`] to read both),
+/// and bytes which were received but not read yet aren't returned by [`Run::poll`].
+impl<'a, S: Stream> Read for Run<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        while self.unread.is_empty() && !self.channel.output_finished() {
+            let event = block_on(self.channel.next_event(&mut self.conn.inner()))?;
+            if let RunEvent::Data(data) = event {
+                // straight to `buf`, only the excess is kept
+                let len = buf.len().min(data.len());
+                buf[..len].copy_from_slice(&data[..len]);
+                self.unread.extend_from_slice(&data[len..]);
+                return Ok(len);
+            }
+        }
+
+        let len = buf.len().min(self.unread.len());
+        buf[..len].copy_from_slice(&self.unread[..len]);
+        self.unread.drain(..len);
+        Ok(len)
+    }
+}
+
+/// Writes to the standard input of the remote process, waiting
+/// for the server to adjust its window if needed; see [`Run::write_stdin`].
+///
+/// [`Run::write`] shadows [`Write::write`], which can be called as such.
+impl<'a, S: Stream> Write for Run<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.write_stdin(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl<'a, S: Stream> Drop for Run<'a, S> {
+    fn drop(&mut self) {
+        let _ = block_on(self.channel.close(&mut self.conn.inner()));
+    }
+}
+
+impl Channel {
+    /// Opens a session channel, sends the requests returned by `prelude`
+    /// (e.g. environment variables, whose refusal isn't fatal, or a
+    /// pty, whose refusal is if it wants a reply), then sends the
+    /// request returned by `start`, whose refusal is fatal too
+    pub(crate) async fn open<'b, R: Source, W: Sink, P, F>(
+        inner: &mut ConnectionInner<R, W>,
+        options: RunOptions,
+        prelude: P,
+        start: F,
+    ) -> Result<RunResult<Self>>
+        where P: FnOnce(u32) -> Vec<ChannelRequest<'b>>,
+              F: FnOnce(u32) -> ChannelRequest<'b>
+    {
+        let mut channel = match Self::open_bare(inner, options).await? {
+            RunResult::Refused => return Ok(RunResult::Refused),
+            RunResult::Accepted(channel) => channel,
         };
 
+        match channel.start(inner, prelude, start).await {
+            Ok(true) => Ok(RunResult::Accepted(channel)),
+            Ok(false) => {
+                // best effort, like dropping a Run
+                let _ = channel.close(inner).await;
+                Ok(RunResult::Refused)
+            },
+            Err(error) => {
+                inner.forget_channel(channel.client_channel);
+                Err(error)
+            },
+        }
+    }
+
+    /// Sends the requests of [`Self::open`], in as few TCP segments
+    /// as possible; returns whether the server accepted `start` and
+    /// the prelude requests whose refusal is fatal
+    async fn start<'b, R: Source, W: Sink, P, F>(
+        &mut self,
+        inner: &mut ConnectionInner<R, W>,
+        prelude: P,
+        start: F,
+    ) -> Result<bool>
+        where P: FnOnce(u32) -> Vec<ChannelRequest<'b>>,
+              F: FnOnce(u32) -> ChannelRequest<'b>
+    {
+        let mut required = Vec::new();
+        for request in prelude(self.server_channel) {
+            match request {
+                ChannelRequest::EnvironmentVariable { want_reply: true, name, .. } => self.replies.expect_env(name),
+                request if request.want_reply() => required.push((request.request_type(), self.replies.expect())),
+                _ => (),
+            }
+
+            inner.queue(&request).await?;
+        }
+
+        let start = start(self.server_channel);
+        if let ChannelRequest::Exec { .. } | ChannelRequest::Shell { .. } = start {
+            inner.request_forwarding(self.server_channel).await?;
+        }
+
+        let ticket = self.replies.expect();
+        inner.queue(&start).await?;
+        inner.flush().await?;
+
+        let mut refused = None;
+        for (request_type, ticket) in required {
+            if !self.await_reply(inner, ticket).await? {
+                log::warn!("The server refused a {} request", request_type);
+                refused.get_or_insert(request_type);
+            }
+        }
+
+        let accepted = self.await_reply(inner, ticket).await? && refused.is_none();
+        let command = match (start, refused) {
+            (_, Some(request_type)) => request_type.to_string(),
+            (ChannelRequest::Exec { command, .. }, None) => format!("exec {:?}", command),
+            (ChannelRequest::Subsystem { subsystem_name, .. }, None) => format!("subsystem {:?}", subsystem_name),
+            (request, None) => request.request_type().to_string(),
+        };
+
+        match accepted {
+            true => inner.transcript.record(format_args!("channel {}: {}", self.client_channel, command)),
+            false => inner.transcript.record(format_args!("channel {}: {} refused", self.client_channel, command)),
+        }
+
+        self.recorded = accepted;
+        Ok(accepted)
+    }
+
+    /// Opens a session channel without sending any request
+    pub(crate) async fn open_bare<R: Source, W: Sink>(inner: &mut ConnectionInner<R, W>, options: RunOptions) -> Result<RunResult<Self>> {
+        let window = options.channel_window(inner.channel_window);
+        window.check(inner.reader.max_packet_length)?;
+        let client_channel = inner.open_channel();
+        let open = session_open(window, client_channel);
+        let opened = confirm_open(inner, client_channel, &open).await;
+        Self::opened(inner, client_channel, window, opened)
+    }
+
+    /// Opens a `direct-tcpip` channel, through which the server
+    /// connects to `host`:`port`
+    pub(crate) async fn open_direct_tcpip<R: Source, W: Sink>(
+        inner: &mut ConnectionInner<R, W>,
+        host: &str,
+        port: u32,
+    ) -> Result<RunResult<Self>> {
+        let window = inner.channel_window;
+        window.check(inner.reader.max_packet_length)?;
+        let client_channel = inner.open_channel();
+        let open = DirectTcpipOpen {
+            client_channel,
+            client_initial_window_size: window.initial_window,
+            client_max_packet_size: window.max_packet_size,
+            host,
+            port,
+            originator_address: "127.0.0.1",
+            originator_port: 0,
+        };
+
+        let opened = confirm_open(inner, client_channel, &open).await;
+        Self::opened(inner, client_channel, window, opened)
+    }
+
+    /// Builds the channel once it was opened, or forgets it
+    fn opened<R: Source, W: Sink>(
+        inner: &mut ConnectionInner<R, W>,
+        client_channel: u32,
+        window: ChannelWindow,
+        opened: Result<Option<(u32, u32, u32)>>,
+    ) -> Result<RunResult<Self>> {
+        let (server_channel, server_initial_window_size, server_max_packet_size) = match opened {
+            Ok(Some(opened)) => opened,
+            result => {
+                inner.forget_channel(client_channel);
+                return result.map(|_| RunResult::Refused);
+            },
+        };
+
+        Ok(RunResult::Accepted(Self {
+            server_channel,
+            client_channel,
+            window,
+            exit_code: None,
+            exit_signal: None,
+            closed: false,
+            eof_sent: false,
+            eof_received: false,
+            pending: VecDeque::new(),
+            delivered: Vec::new(),
+            replies: ReplyMatcher::default(),
+
+            client_window: window.initial_window,
+            server_window: server_initial_window_size as _,
+            server_max_packet_size: server_max_packet_size as _,
+            sent: 0,
+            received: 0,
+            transfer_progress: TransferHook::default(),
+            recorded: false,
+        }))
+    }
+
+    /// See [`Run::poll`]
+    pub(crate) async fn poll<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>) -> Result<RunEvent<'_>> {
+        match self.next_event(inner).await {
+            Err(e) if e.kind() == ErrorKind::Timeout => Ok(RunEvent::None),
+            result => result,
+        }
+    }
+
+    /// See [`Run::wait_event`]
+    pub(crate) async fn wait_event<R: Source, W: Sink>(
+        &mut self,
+        inner: &mut ConnectionInner<R, W>,
+        timeout: Option<Duration>,
+    ) -> Result<RunEvent<'_>> {
+        if !self.pending.is_empty() {
+            return self.poll(inner).await;
+        }
+
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let read_timeout = inner.reader.inner.read_timeout()?;
+        let received = loop {
+            // keepalives are sent when a read times out, so
+            // reads mustn't block past the next one
+            let wait = match (deadline, inner.keepalive_due_in()) {
+                (Some(deadline), Some(due)) => Some(deadline.saturating_duration_since(Instant::now()).min(due)),
+                (Some(deadline), None) => Some(deadline.saturating_duration_since(Instant::now())),
+                (None, due) => due,
+            };
+
+            // sockets refuse a zero timeout
+            if let Err(e) = inner.reader.inner.set_read_timeout(wait.map(|t| t.max(MIN_WAIT))) {
+                break Err(e);
+            }
+
+            match inner.recv_channel(self.client_channel).await {
+                Err(e) if e.kind() == ErrorKind::Timeout => match deadline {
+                    Some(deadline) if Instant::now() >= deadline => break Ok(false),
+                    _ => (),
+                },
+                result => break result.map(|()| true),
+            }
+        };
+
+        inner.reader.inner.set_read_timeout(read_timeout)?;
+        match received? {
+            true => self.handle_message(inner).await,
+            false => Ok(RunEvent::None),
+        }
+    }
+
+    /// See [`Run::next_event`]
+    pub(crate) async fn next_event<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>) -> Result<RunEvent<'_>> {
+        if let Some(event) = self.pending.pop_front() {
+            return Ok(match event {
+                PendingEvent::Data(data) => {
+                    self.delivered = data;
+                    RunEvent::Data(&self.delivered)
+                },
+                PendingEvent::ExtDataStderr(data) => {
+                    self.delivered = data;
+                    RunEvent::ExtDataStderr(&self.delivered)
+                },
+                PendingEvent::ExtData(kind, data) => {
+                    self.delivered = data;
+                    RunEvent::ExtData {
+                        kind,
+                        data: &self.delivered,
+                    }
+                },
+                PendingEvent::Eof => RunEvent::Eof,
+                PendingEvent::Killed => match &self.exit_signal {
+                    Some(exit_signal) => exit_signal.event(),
+                    None => unreachable!("queued with the exit signal"),
+                },
+                PendingEvent::Stopped => RunEvent::Stopped(self.exit_status()),
+            });
+        }
+
+        inner.recv_channel(self.client_channel).await?;
+        self.handle_message(inner).await
+    }
+
+    /// Turns the message which [`ConnectionInner::recv_channel`] just
+    /// received into an event
+    async fn handle_message<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>) -> Result<RunEvent<'_>> {
+        let message = inner.reader.last()?;
+
         match message {
             Message::ChannelData(ChannelData {
                 recipient_channel: _,
                 data,
             }) => {
-                self.client_window -= data.len();
-                let cw = self.client_window as u32;
-                if cw < CLIENT_WIN_TELL_TRIGGER {
-                    self.conn.writer.send(&ChannelWindowAdjust {
-                        recipient_channel: self.server_channel,
-                        bytes_to_add: CLIENT_INITIAL_WINDOW_SIZE - cw,
-                    })?;
+                self.consume_client_window(&mut inner.writer, data.len()).await?;
 
-                    self.client_window = CLIENT_INITIAL_WINDOW_SIZE as _;
-                }
-                Ok(RunEvent::Data(data))
+                self.delivered.clear();
+                self.delivered.extend_from_slice(data);
+                Ok(RunEvent::Data(&self.delivered))
             },
             Message::ChannelWindowAdjust(ChannelWindowAdjust {
                 recipient_channel: _,
                 bytes_to_add,
             }) => {
-                self.server_window += bytes_to_add as usize;
+                // the window can't exceed 2^32 - 1 bytes (RFC 4254, section 5.2)
+                match u32::try_from(self.server_window + bytes_to_add as usize) {
+                    Ok(window) => self.server_window = window as usize,
+                    Err(_) => return Err(Error::new(ErrorKind::InvalidData).context("the server's channel window exceeds 2^32 - 1 bytes")),
+                }
+
+                self.report_transfer();
+                Ok(RunEvent::None)
+            },
+            Message::ChannelEof(_) => {
+                self.eof_received = true;
+                Ok(RunEvent::Eof)
+            },
+            Message::ChannelSuccess(_) => {
+                self.replies.received(true)?;
+                Ok(RunEvent::None)
+            },
+            Message::ChannelFailure(_) => {
+                self.replies.received(false)?;
                 Ok(RunEvent::None)
             },
-            Message::ChannelEof(_) => Ok(RunEvent::None),
             Message::ChannelClose(_) => {
-                self.conn.writer.send(&ChannelClose {
+                inner.writer.send(&ChannelClose {
                     recipient_channel: self.server_channel,
-                })?;
+                }).await?;
 
                 self.closed = true;
+                self.record_end(&inner.transcript);
 
-                Ok(RunEvent::Stopped(self.exit_status))
+                Ok(RunEvent::Stopped(self.exit_status()))
             },
             Message::ChannelRequest(ChannelRequest::ExitStatus {
                 recipient_channel: _,
                 exit_status,
             }) => {
-                self.exit_status = Some(exit_status);
+                self.exit_code = Some(exit_status);
+                Ok(RunEvent::None)
+            },
+            Message::ChannelRequest(ChannelRequest::ExitSignal {
+                recipient_channel: _,
+                signal_name,
+                core_dumped,
+                error_message,
+                language_tag: _,
+            }) => {
+                let exit_signal = self.exit_signal.insert(ExitSignal {
+                    signal: signal_name.into(),
+                    core_dumped,
+                    message: error_message.into(),
+                });
+
+                Ok(exit_signal.event())
+            },
+            Message::ChannelRequest(request) => {
+                // e.g. `keepalive@openssh.com`, which OpenSSH servers send when idle
+                log::info!("Refusing channel request (type = {})", request.request_type());
+                if request.want_reply() {
+                    inner.writer.send(&ChannelFailure {
+                        recipient_channel: self.server_channel,
+                    }).await?;
+                }
+
                 Ok(RunEvent::None)
             },
             Message::ChannelExtendedData(ChannelExtendedData {
                 recipient_channel: _,
-                data_type: 1,
+                data_type,
                 data,
-            }) => Ok(RunEvent::ExtDataStderr(data)),
+            }) => {
+                self.consume_client_window(&mut inner.writer, data.len()).await?;
+                self.delivered.clear();
+                self.delivered.extend_from_slice(data);
+                Ok(match data_type {
+                    ExtendedDataType::Stderr => RunEvent::ExtDataStderr(&self.delivered),
+                    kind => RunEvent::ExtData {
+                        kind,
+                        data: &self.delivered,
+                    },
+                })
+            },
             msg => {
-                log::error!("Unexpected message: {:#?}", msg);
-                return Err(Error::UnexpectedMessageType(msg.typ()));
+                skip_benign(msg)?;
+                Ok(RunEvent::None)
             },
         }
     }
 
-    /// Tries to send `data` over the run channel and calls `event_callback`
-    /// if an event occurs during the transmission.
-    ///
-    /// Use this if the protocol you're using is full-duplex.
-    pub fn write_poll<WPE: From<Error>, F: FnMut(RunEvent) -> core::result::Result<(), WPE>>(
+    /// Accounts for `len` bytes of data received from the server,
+    /// enlarging the window once three quarters of it were used
+    async fn consume_client_window<W: Sink>(&mut self, writer: &mut PacketWriter<W>, len: usize) -> Result<()> {
+        let max_packet_size = self.window.max_packet_size as usize;
+        let remaining = (self.client_window as usize).checked_sub(len).filter(|_| len <= max_packet_size);
+        let Some(remaining) = remaining else {
+            let message = format!("the server sent {} bytes, exceeding the channel window ({}) or maximum packet size ({})", len, self.client_window, max_packet_size);
+            return Err(Error::new(ErrorKind::InvalidData).context(message));
+        };
+
+        self.client_window = remaining as u32;
+        if self.client_window < self.window.initial_window / 4 {
+            writer.send(&ChannelWindowAdjust {
+                recipient_channel: self.server_channel,
+                bytes_to_add: self.window.initial_window - self.client_window,
+            }).await?;
+
+            self.client_window = self.window.initial_window;
+        }
+
+        self.received += len as u64;
+        self.report_transfer();
+        Ok(())
+    }
+
+    /// Accounts for `len` bytes of data sent to the server
+    fn consume_server_window(&mut self, len: usize) {
+        self.server_window -= len;
+        self.sent += len as u64;
+        self.report_transfer();
+    }
+
+    fn report_transfer(&self) {
+        self.transfer_progress.report(TransferProgress {
+            sent: self.sent,
+            received: self.received,
+            server_window: self.server_window as u32,
+            client_window: self.client_window,
+        });
+    }
+
+    /// See [`Run::write_poll`]
+    pub(crate) async fn write_poll<R, W, WPE, F>(
         &mut self,
+        inner: &mut ConnectionInner<R, W>,
         mut data: &[u8],
         mut event_callback: F,
-    ) -> core::result::Result<(), WPE> {
+    ) -> core::result::Result<(), WPE>
+        where R: Source,
+              W: Sink,
+              WPE: From<Error>,
+              F: FnMut(RunEvent) -> core::result::Result<(), WPE>,
+    {
         if self.closed {
-            return Err(Error::ProcessHasExited.into());
+            return Err(Error::from(ErrorKind::ProcessHasExited).into());
+        }
+
+        if self.eof_sent {
+            return Err(Error::new(ErrorKind::InvalidData).context("can't send data after Run::close_write()").into());
         }
 
         loop {
             let step = self.server_max_packet_size.min(self.server_window);
             if step >= data.len() {
-                self.conn.writer.send(&ChannelData {
-                    recipient_channel: self.server_channel,
-                    data,
-                })?;
+                inner.send_channel_data(self.server_channel, data).await?;
 
-                self.server_window -= data.len();
+                self.consume_server_window(data.len());
 
                 break Ok(())
             } else if step > 0 {
                 let (sendable, next) = data.split_at(step);
 
-                self.conn.writer.send(&ChannelData {
-                    recipient_channel: self.server_channel,
-                    data: sendable,
-                })?;
+                inner.send_channel_data(self.server_channel, sendable).await?;
 
-                self.server_window -= step;
+                self.consume_server_window(step);
                 data = next;
+            } else {
+                // wait for the server to adjust its window
+                match self.poll(inner).await? {
+                    RunEvent::None => (),
+                    e => event_callback(e)?,
+                }
             }
+        }
+    }
 
-            match self.poll()? {
-                RunEvent::None => (),
-                e => event_callback(e)?,
-            }
+    /// See [`Run::write_stdin`]
+    pub(crate) async fn write_stdin<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>, data: &[u8]) -> Result<()> {
+        let mut pending = core::mem::take(&mut self.pending);
+
+        let result = self.write_poll(inner, data, |event| defer_event(&mut pending, event)).await;
+
+        self.pending = pending;
+        result
+    }
+
+    /// Sends a request of any type; returns whether the server accepted it,
+    /// or true if `want_reply` is false. Events received meanwhile are
+    /// kept for [`Channel::poll`].
+    pub(crate) async fn request<R: Source, W: Sink>(
+        &mut self,
+        inner: &mut ConnectionInner<R, W>,
+        request_type: &str,
+        want_reply: bool,
+        data: &[u8],
+    ) -> Result<bool> {
+        if self.closed {
+            return Err(ErrorKind::ProcessHasExited.into());
+        }
+
+        inner.send(&RawChannelRequest {
+            recipient_channel: self.server_channel,
+            request_type,
+            want_reply,
+            data,
+        }).await?;
+
+        match want_reply {
+            true => {
+                let ticket = self.replies.expect();
+                self.await_reply(inner, ticket).await
+            },
+            false => Ok(true),
         }
     }
 
-    /// Tries to send `data` over the run channel and returns the `on_event` error
-    /// if an event occurs during the transmission.
-    ///
-    /// Use this if the protocol you're using is half-duplex.
-    pub fn write<WPE: From<Error>>(&mut self, data: &[u8], on_event: WPE) -> core::result::Result<(), WPE> {
-        let mut on_event = Some(on_event);
-        self.write_poll(data, |data| {
-            log::error!("Unexpected RunEvent in Run::write(): {:?}", data);
-            Err(on_event.take().unwrap())
-        })
+    /// Receives messages until the reply to the request of `ticket`
+    /// arrives; events received meanwhile are kept for [`Channel::poll`].
+    async fn await_reply<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>, ticket: u64) -> Result<bool> {
+        let mut pending = core::mem::take(&mut self.pending);
+        let result = loop {
+            if let Some(reply) = self.replies.take(ticket) {
+                break Ok(reply);
+            }
+
+            let event = match self.poll(inner).await {
+                Ok(event) => event,
+                Err(error) => break Err(error),
+            };
+
+            if let Err(error) = defer_event(&mut pending, event) {
+                break Err(error);
+            }
+        };
+
+        self.pending = pending;
+        result
     }
-}
 
-impl<'a> Drop for Run<'a> {
-    fn drop(&mut self) {
-        if !self.closed {
-            let _ = self.conn.writer.send(&ChannelClose {
+    /// See [`Run::close_write`]
+    pub(crate) async fn close_write<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>) -> Result<()> {
+        if self.closed {
+            return Err(ErrorKind::ProcessHasExited.into());
+        }
+
+        if !self.eof_sent {
+            inner.send(&ChannelEof {
                 recipient_channel: self.server_channel,
-            });
+            }).await?;
+
+            self.eof_sent = true;
         }
+
+        Ok(())
+    }
+
+    /// See [`Run::send_request`]
+    pub(crate) async fn send_request<'b, R: Source, W: Sink, F>(&mut self, inner: &mut ConnectionInner<R, W>, request: F) -> Result<()>
+        where F: FnOnce(u32) -> ChannelRequest<'b>
+    {
+        if self.closed {
+            return Err(ErrorKind::ProcessHasExited.into());
+        }
+
+        inner.send(&request(self.server_channel)).await
+    }
+
+    /// Sends ChannelClose (unless the server closed the channel)
+    /// and drops the messages which are queued for it
+    pub(crate) async fn close<R: Source, W: Sink>(&mut self, inner: &mut ConnectionInner<R, W>) -> Result<()> {
+        inner.forget_channel(self.client_channel);
+        match self.closing() {
+            Some(recipient_channel) => {
+                self.closed = true;
+                self.record_end(&inner.transcript);
+                inner.writer.send(&ChannelClose {
+                    recipient_channel,
+                }).await
+            },
+            None => Ok(()),
+        }
+    }
+
+    /// Records how the command terminated, see [`Connection::set_transcript`]
+    fn record_end(&mut self, transcript: &Transcript) {
+        if core::mem::take(&mut self.recorded) {
+            let (channel, sent, received) = (self.client_channel, self.sent, self.received);
+            match (self.exit_code, &self.exit_signal) {
+                (None, None) => transcript.record(format_args!("channel {}: closed, sent {} bytes, received {} bytes", channel, sent, received)),
+                _ => transcript.record(format_args!("channel {}: exited with {:?}, sent {} bytes, received {} bytes", channel, self.exit_status(), sent, received)),
+            }
+        }
+    }
+
+    fn exit_status(&self) -> ExitStatus {
+        match (self.exit_code, &self.exit_signal) {
+            (Some(code), _) => ExitStatus::Code(code),
+            (None, Some(exit_signal)) => ExitStatus::Signal(exit_signal.signal.clone()),
+            (None, None) => ExitStatus::Unknown,
+        }
+    }
+
+    /// See [`Run::refused_env`]
+    pub(crate) fn refused_env(&self) -> &[String] {
+        &self.replies.refused_env
+    }
+
+    /// Whether the server won't send more data on the channel (it sent
+    /// ChannelEof or closed it) and all of it was taken from `pending`
+    fn output_finished(&self) -> bool {
+        self.pending.is_empty() && (self.closed || self.eof_received)
+    }
+
+    /// The server channel which must be closed, if any
+    pub(crate) fn closing(&self) -> Option<u32> {
+        (!self.closed).then_some(self.server_channel)
+    }
+
+    #[cfg(feature = "tokio")]
+    pub(crate) fn client_channel(&self) -> u32 {
+        self.client_channel
     }
 }
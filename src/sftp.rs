@@ -0,0 +1,536 @@
+//! SFTP version 3 client (draft-ietf-secsh-filexfer-02)
+
+use core::ops::BitOr;
+use super::{ErrorKind, Connection, Result, Error, U32, Write, Read, TcpStream};
+use super::stream::Stream;
+use super::parsedump::{ParseDump, Buffer, try_u32};
+use super::run::{Run, RunEvent, RunResult};
+use super::observer::TransferProgress;
+
+const SFTP_VERSION: u32 = 3;
+
+/// Size of READ/WRITE requests; all servers accept 32 KiB
+const CHUNK_SIZE: usize = 0x8000;
+
+/// Incoming packets larger than this are rejected
+const MAX_PACKET_SIZE: usize = 0x40000;
+
+const FXP_INIT: u8 = 1;
+const FXP_VERSION: u8 = 2;
+const FXP_OPEN: u8 = 3;
+const FXP_CLOSE: u8 = 4;
+const FXP_READ: u8 = 5;
+const FXP_WRITE: u8 = 6;
+const FXP_OPENDIR: u8 = 11;
+const FXP_READDIR: u8 = 12;
+const FXP_STAT: u8 = 17;
+const FXP_STATUS: u8 = 101;
+const FXP_HANDLE: u8 = 102;
+const FXP_DATA: u8 = 103;
+const FXP_NAME: u8 = 104;
+const FXP_ATTRS: u8 = 105;
+
+const FX_OK: u32 = 0;
+const FX_EOF: u32 = 1;
+
+const ATTR_SIZE: u32 = 0x1;
+const ATTR_UIDGID: u32 = 0x2;
+const ATTR_PERMISSIONS: u32 = 0x4;
+const ATTR_ACMODTIME: u32 = 0x8;
+const ATTR_EXTENDED: u32 = 0x80000000;
+
+/// Error codes sent by SFTP servers
+///
+/// New variants may be added in minor releases.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SftpStatus {
+    NoSuchFile,
+    PermissionDenied,
+    Failure,
+    BadMessage,
+    NoConnection,
+    ConnectionLost,
+    OpUnsupported,
+    Other(u32),
+}
+
+impl From<u32> for SftpStatus {
+    fn from(code: u32) -> Self {
+        match code {
+            2 => Self::NoSuchFile,
+            3 => Self::PermissionDenied,
+            4 => Self::Failure,
+            5 => Self::BadMessage,
+            6 => Self::NoConnection,
+            7 => Self::ConnectionLost,
+            8 => Self::OpUnsupported,
+            code => Self::Other(code),
+        }
+    }
+}
+
+/// Flags for [`Sftp::open`], combined with `|`
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct OpenFlags(u32);
+
+impl OpenFlags {
+    pub const READ: Self = Self(0x01);
+    pub const WRITE: Self = Self(0x02);
+    pub const APPEND: Self = Self(0x04);
+    pub const CREATE: Self = Self(0x08);
+    pub const TRUNCATE: Self = Self(0x10);
+    pub const EXCLUSIVE: Self = Self(0x20);
+}
+
+impl BitOr for OpenFlags {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// File metadata; fields the server didn't send are `None`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FileAttributes {
+    pub size: Option<u64>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub permissions: Option<u32>,
+    pub atime: Option<u32>,
+    pub mtime: Option<u32>,
+}
+
+impl<'b> ParseDump<'b> for FileAttributes {
+    fn parse(bytes: &'b [u8]) -> Result<(Self, usize)> {
+        let mut attributes = Self::default();
+        let (flags, mut i) = u32::parse(bytes)?;
+
+        if (flags & ATTR_SIZE) != 0 {
+            let (size, inc) = u64::parse(&bytes[i..])?;
+            attributes.size = Some(size);
+            i += inc;
+        }
+
+        if (flags & ATTR_UIDGID) != 0 {
+            let (uid, inc) = u32::parse(&bytes[i..])?;
+            i += inc;
+            let (gid, inc) = u32::parse(&bytes[i..])?;
+            i += inc;
+            attributes.uid = Some(uid);
+            attributes.gid = Some(gid);
+        }
+
+        if (flags & ATTR_PERMISSIONS) != 0 {
+            let (permissions, inc) = u32::parse(&bytes[i..])?;
+            attributes.permissions = Some(permissions);
+            i += inc;
+        }
+
+        if (flags & ATTR_ACMODTIME) != 0 {
+            let (atime, inc) = u32::parse(&bytes[i..])?;
+            i += inc;
+            let (mtime, inc) = u32::parse(&bytes[i..])?;
+            i += inc;
+            attributes.atime = Some(atime);
+            attributes.mtime = Some(mtime);
+        }
+
+        if (flags & ATTR_EXTENDED) != 0 {
+            let (count, inc) = u32::parse(&bytes[i..])?;
+            i += inc;
+
+            // extended attributes are skipped
+            for _ in 0..count {
+                let (_type, inc) = <&[u8]>::parse(&bytes[i..])?;
+                i += inc;
+                let (_data, inc) = <&[u8]>::parse(&bytes[i..])?;
+                i += inc;
+            }
+        }
+
+        Ok((attributes, i))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        let mut flags = 0;
+        let mut set = |present: bool, flag| if present { flags |= flag };
+        set(self.size.is_some(), ATTR_SIZE);
+        set(self.uid.is_some() && self.gid.is_some(), ATTR_UIDGID);
+        set(self.permissions.is_some(), ATTR_PERMISSIONS);
+        set(self.atime.is_some() && self.mtime.is_some(), ATTR_ACMODTIME);
+        flags.dump(sink)?;
+
+        if let Some(size) = self.size {
+            size.dump(sink)?;
+        }
+
+        if let (Some(uid), Some(gid)) = (self.uid, self.gid) {
+            uid.dump(sink)?;
+            gid.dump(sink)?;
+        }
+
+        if let Some(permissions) = self.permissions {
+            permissions.dump(sink)?;
+        }
+
+        if let (Some(atime), Some(mtime)) = (self.atime, self.mtime) {
+            atime.dump(sink)?;
+            mtime.dump(sink)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// An entry returned by [`Sftp::read_dir`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    pub filename: String,
+    /// `ls -l`-like description of the entry
+    pub longname: String,
+    pub attributes: FileAttributes,
+}
+
+/// An open remote file; must be closed with [`Sftp::close`]
+#[derive(Debug, PartialEq, Eq)]
+pub struct SftpFile {
+    handle: Vec<u8>,
+}
+
+enum Response<'p> {
+    /// Status code (neither OK nor EOF)
+    Failure(u32),
+    Ok,
+    Eof,
+    Handle(&'p [u8]),
+    Data(&'p [u8]),
+    Name(u32, &'p [u8]),
+    Attributes(FileAttributes),
+}
+
+/// An SFTP session, running in its own channel
+#[derive(Debug)]
+pub struct Sftp<'a, S: Stream = TcpStream> {
+    run: Run<'a, S>,
+    next_id: u32,
+    /// bytes received from the channel, not yet split in packets
+    input: Vec<u8>,
+    /// the last received packet, without its length
+    packet: Vec<u8>,
+    /// the packet being sent
+    output: Vec<u8>,
+}
+
+impl<'a, S: Stream> Sftp<'a, S> {
+    /// Starts the `sftp` subsystem and negotiates the protocol version
+    pub fn new(conn: &'a Connection<S>) -> Result<RunResult<Self>> {
+        let run = match conn.subsystem("sftp")? {
+            RunResult::Accepted(run) => run,
+            RunResult::Refused => return Ok(RunResult::Refused),
+        };
+
+        let mut sftp = Self {
+            run,
+            next_id: 0,
+            input: Vec::new(),
+            packet: Vec::new(),
+            output: Vec::new(),
+        };
+
+        // INIT and VERSION have no request id
+        sftp.output.clear();
+        sftp.output.resize(U32, 0);
+        FXP_INIT.dump(&mut sftp.output)?;
+        SFTP_VERSION.dump(&mut sftp.output)?;
+        sftp.send_output()?;
+
+        sftp.recv_packet()?;
+        let (typ, i) = u8::parse(&sftp.packet)?;
+        let (version, _) = u32::parse(&sftp.packet[i..])?;
+
+        if typ != FXP_VERSION || version < SFTP_VERSION {
+            return Err(Error::new(ErrorKind::Unimplemented).context(format!("unsupported SFTP server (type = {}, version = {})", typ, version)));
+        }
+
+        Ok(RunResult::Accepted(sftp))
+    }
+
+    /// Opens a remote file
+    pub fn open(&mut self, path: &str, flags: OpenFlags) -> Result<SftpFile> {
+        let id = self.request(FXP_OPEN, |out| {
+            path.dump(out)?;
+            flags.0.dump(out)?;
+            FileAttributes::default().dump(out)
+        })?;
+
+        self.expect_handle(id)
+    }
+
+    /// Reads up to `buf.len()` bytes at `offset`; returns
+    /// the number of bytes read, zero meaning end of file.
+    pub fn read(&mut self, file: &SftpFile, offset: u64, buf: &mut [u8]) -> Result<usize> {
+        let len = buf.len().min(CHUNK_SIZE) as u32;
+        let id = self.request(FXP_READ, |out| {
+            file.handle.as_slice().dump(out)?;
+            offset.dump(out)?;
+            len.dump(out)
+        })?;
+
+        match self.response(id)? {
+            Response::Data(data) if data.len() <= buf.len() => {
+                buf[..data.len()].copy_from_slice(data);
+                Ok(data.len())
+            },
+            Response::Eof => Ok(0),
+            response => Self::unexpected(response),
+        }
+    }
+
+    /// Writes `data` at `offset`
+    pub fn write(&mut self, file: &SftpFile, mut offset: u64, data: &[u8]) -> Result<()> {
+        for chunk in data.chunks(CHUNK_SIZE) {
+            let id = self.request(FXP_WRITE, |out| {
+                file.handle.as_slice().dump(out)?;
+                offset.dump(out)?;
+                chunk.dump(out)
+            })?;
+
+            self.expect_ok(id)?;
+            offset += chunk.len() as u64;
+        }
+
+        Ok(())
+    }
+
+    /// Closes a remote file
+    pub fn close(&mut self, file: SftpFile) -> Result<()> {
+        self.close_handle(&file.handle)
+    }
+
+    /// Retrieves the attributes of a remote file, following symbolic links
+    pub fn stat(&mut self, path: &str) -> Result<FileAttributes> {
+        let id = self.request(FXP_STAT, |out| path.dump(out))?;
+
+        match self.response(id)? {
+            Response::Attributes(attributes) => Ok(attributes),
+            response => Self::unexpected(response),
+        }
+    }
+
+    /// Lists the content of a remote directory
+    pub fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>> {
+        let id = self.request(FXP_OPENDIR, |out| path.dump(out))?;
+        let dir = self.expect_handle(id)?;
+        let mut entries = Vec::new();
+
+        let result = loop {
+            let id = self.request(FXP_READDIR, |out| dir.handle.as_slice().dump(out))?;
+
+            let (count, mut names) = match self.response(id)? {
+                Response::Name(count, names) => (count, names),
+                Response::Eof => break Ok(()),
+                response => break Self::unexpected(response),
+            };
+
+            for _ in 0..count {
+                let (filename, i) = <&[u8]>::parse(names)?;
+                names = &names[i..];
+                let (longname, i) = <&[u8]>::parse(names)?;
+                names = &names[i..];
+                let (attributes, i) = FileAttributes::parse(names)?;
+                names = &names[i..];
+
+                entries.push(DirEntry {
+                    filename: String::from_utf8_lossy(filename).into_owned(),
+                    longname: String::from_utf8_lossy(longname).into_owned(),
+                    attributes,
+                });
+            }
+        };
+
+        self.close_handle(&dir.handle)?;
+        result.map(|()| entries)
+    }
+
+    /// Copies everything `source` yields to `remote_path`,
+    /// creating or truncating it; returns the number of bytes copied.
+    pub fn upload<R: Read>(&mut self, mut source: R, remote_path: &str) -> Result<u64> {
+        let flags = OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE;
+        let file = self.open(remote_path, flags)?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut offset = 0;
+
+        let result = loop {
+            let len = match source.read(&mut buffer) {
+                Ok(0) => break Ok(offset),
+                Ok(len) => len,
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(e) => break Err(e.into()),
+            };
+
+            if let Err(e) = self.write(&file, offset, &buffer[..len]) {
+                break Err(e);
+            }
+
+            offset += len as u64;
+        };
+
+        self.close(file)?;
+        result
+    }
+
+    /// Calls `callback` after each packet sent or received, see
+    /// [`Run::set_transfer_progress`]; the counts include the
+    /// headers of SFTP packets.
+    pub fn set_transfer_progress<F: FnMut(TransferProgress) + Send + 'static>(&mut self, callback: F) {
+        self.run.set_transfer_progress(callback);
+    }
+
+    /// Copies the content of `remote_path` to `sink`;
+    /// returns the number of bytes copied.
+    pub fn download<W: Write>(&mut self, remote_path: &str, mut sink: W) -> Result<u64> {
+        let file = self.open(remote_path, OpenFlags::READ)?;
+        let mut buffer = vec![0; CHUNK_SIZE];
+        let mut offset = 0;
+
+        let result = loop {
+            let len = match self.read(&file, offset, &mut buffer) {
+                Ok(0) => break Ok(offset),
+                Ok(len) => len,
+                Err(e) => break Err(e),
+            };
+
+            if let Err(e) = sink.write_all(&buffer[..len]) {
+                break Err(e.into());
+            }
+
+            offset += len as u64;
+        };
+
+        self.close(file)?;
+        result
+    }
+
+    fn close_handle(&mut self, handle: &[u8]) -> Result<()> {
+        let id = self.request(FXP_CLOSE, |out| handle.dump(out))?;
+        self.expect_ok(id)
+    }
+
+    fn expect_ok(&mut self, id: u32) -> Result<()> {
+        match self.response(id)? {
+            Response::Ok => Ok(()),
+            response => Self::unexpected(response),
+        }
+    }
+
+    fn expect_handle(&mut self, id: u32) -> Result<SftpFile> {
+        match self.response(id)? {
+            Response::Handle(handle) => Ok(SftpFile {
+                handle: handle.to_vec(),
+            }),
+            response => Self::unexpected(response),
+        }
+    }
+
+    fn unexpected<T>(response: Response) -> Result<T> {
+        match response {
+            Response::Failure(code) => Err(ErrorKind::Sftp(code.into()).into()),
+            Response::Eof => Err(ErrorKind::Sftp(SftpStatus::Failure).into()),
+            _ => Err(Error::new(ErrorKind::InvalidData).context("unexpected SFTP response")),
+        }
+    }
+
+    /// Sends a request of type `typ` whose fields are written by `fields`
+    fn request<F>(&mut self, typ: u8, fields: F) -> Result<u32>
+        where F: FnOnce(&mut Vec<u8>) -> Result<()>
+    {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        self.output.clear();
+        self.output.resize(U32, 0);
+        typ.dump(&mut self.output)?;
+        id.dump(&mut self.output)?;
+        fields(&mut self.output)?;
+        self.send_output()?;
+
+        Ok(id)
+    }
+
+    fn send_output(&mut self) -> Result<()> {
+        let len = (self.output.len() - U32) as u32;
+        self.output[..U32].copy_from_slice(&len.to_be_bytes());
+
+        let input = &mut self.input;
+        self.run.write_poll(&self.output, |event| {
+            match event {
+                RunEvent::Data(data) => input.extend_from_slice(data),
+                RunEvent::Stopped(_) => return Err(ErrorKind::ProcessHasExited.into()),
+                _ => (),
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Waits for the response to request `id`
+    fn response(&mut self, id: u32) -> Result<Response<'_>> {
+        self.recv_packet()?;
+
+        let (typ, mut i) = u8::parse(&self.packet)?;
+        let (response_id, inc) = u32::parse(&self.packet[i..])?;
+        i += inc;
+
+        if response_id != id {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("SFTP response to request {} while waiting for {}", response_id, id)));
+        }
+
+        let body = &self.packet[i..];
+        Ok(match typ {
+            FXP_STATUS => match u32::parse(body)?.0 {
+                FX_OK => Response::Ok,
+                FX_EOF => Response::Eof,
+                code => Response::Failure(code),
+            },
+            FXP_HANDLE => Response::Handle(<&[u8]>::parse(body)?.0),
+            FXP_DATA => Response::Data(<&[u8]>::parse(body)?.0),
+            FXP_NAME => {
+                let (count, i) = u32::parse(body)?;
+                Response::Name(count, &body[i..])
+            },
+            FXP_ATTRS => Response::Attributes(FileAttributes::parse(body)?.0),
+            typ => {
+                return Err(Error::new(ErrorKind::InvalidData).context(format!("unknown SFTP packet type: {}", typ)));
+            },
+        })
+    }
+
+    /// Moves the next complete packet from `input` to `packet`
+    fn recv_packet(&mut self) -> Result<()> {
+        loop {
+            if self.input.len() >= U32 {
+                let len = try_u32(&self.input)? as usize;
+                if len == 0 || len > MAX_PACKET_SIZE {
+                    return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid SFTP packet length: {}", len)));
+                }
+
+                let end = U32 + len;
+                if self.input.len() >= end {
+                    self.packet.clear();
+                    self.packet.extend_from_slice(&self.input[U32..end]);
+                    self.input.drain(..end);
+                    return Ok(());
+                }
+            }
+
+            match self.run.poll()? {
+                RunEvent::Data(data) => self.input.extend_from_slice(data),
+                RunEvent::ExtDataStderr(data) => log::warn!("SFTP stderr: {}", String::from_utf8_lossy(data)),
+                RunEvent::Stopped(_) => return Err(ErrorKind::ProcessHasExited.into()),
+                _ => (),
+            }
+        }
+    }
+}
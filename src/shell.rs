@@ -0,0 +1,241 @@
+use std::time::{Duration, Instant};
+use super::{ErrorKind, Connection, Result, Error, TcpStream};
+use super::stream::Stream;
+use super::run::{Run, RunResult, RunOptions, RunEvent, ExitStatus};
+use super::parsedump::ParseDump;
+use super::messages::ChannelRequest;
+
+/// Ends the encoded terminal modes
+const TTY_OP_END: u8 = 0;
+
+/// Writes up to this size are paced by [`Shell::obscure_keystroke_timing`];
+/// larger ones (e.g. pastes) are sent right away
+const KEYSTROKE_MAX_LEN: usize = 16;
+/// How long chaff is sent after the last keystroke
+const CHAFF_DURATION: Duration = Duration::from_secs(1);
+/// Ignore data making chaff as large as the ChannelData of one keystroke
+const CHAFF_LEN: usize = 5;
+
+/// See [`Shell::obscure_keystroke_timing`]
+#[derive(Debug)]
+struct KeystrokeTiming {
+    interval: Duration,
+    /// packets are sent at multiples of `interval` from then
+    epoch: Instant,
+    /// tick of the last packet sent
+    last_tick: Option<Instant>,
+    /// end of the chaff which follows the last keystroke
+    chaff_until: Instant,
+}
+
+impl KeystrokeTiming {
+    /// The first tick after `now` at which nothing was sent yet
+    fn next_tick(&self, now: Instant) -> Instant {
+        let interval = self.interval.as_nanos();
+        let elapsed = now.saturating_duration_since(self.epoch).as_nanos();
+        let tick = self.epoch + Duration::from_nanos(((elapsed / interval + 1) * interval) as u64);
+
+        match self.last_tick {
+            Some(last_tick) if last_tick >= tick => last_tick + self.interval,
+            _ => tick,
+        }
+    }
+
+    /// When to send chaff next, if it's still needed
+    fn next_chaff(&self) -> Option<Instant> {
+        let tick = self.next_tick(Instant::now());
+        (tick <= self.chaff_until).then_some(tick)
+    }
+}
+
+/// Dimensions of a pseudo-terminal
+///
+/// Pixel dimensions are informative and can be left to zero.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct TerminalSize {
+    pub columns: u32,
+    pub rows: u32,
+    pub width_pixels: u32,
+    pub height_pixels: u32,
+}
+
+impl TerminalSize {
+    /// A terminal of `columns` x `rows` characters
+    pub fn new(columns: u32, rows: u32) -> Self {
+        Self {
+            columns,
+            rows,
+            width_pixels: 0,
+            height_pixels: 0,
+        }
+    }
+}
+
+impl<S: Stream> Connection<S> {
+    /// Opens a session channel, allocates a pseudo-terminal and starts
+    /// the user's default shell in it.
+    ///
+    /// `term` is the value of `TERM` (e.g. `xterm-256color`);
+    /// `modes` are (opcode, argument) pairs, as defined by
+    /// RFC 4254, section 8 (e.g. `(53, 0)` disables echo).
+    ///
+    /// Returns [`RunResult::Refused`] if the server refuses
+    /// the pseudo-terminal or the shell.
+    pub fn shell(&self, term: &str, size: TerminalSize, modes: &[(u8, u32)]) -> Result<RunResult<Shell<'_, S>>> {
+        let mut encoded_modes = Vec::new();
+        for (opcode, argument) in modes {
+            if *opcode == TTY_OP_END || *opcode >= 160 {
+                return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid terminal mode opcode: {}", opcode)));
+            }
+
+            opcode.dump(&mut encoded_modes)?;
+            argument.dump(&mut encoded_modes)?;
+        }
+        TTY_OP_END.dump(&mut encoded_modes)?;
+
+        // a shell without its pty is of no use: refusing it refuses the session
+        let prelude = |recipient_channel| vec![ChannelRequest::PtyReq {
+            recipient_channel,
+            want_reply: true,
+            term,
+            width_chars: size.columns,
+            height_rows: size.rows,
+            width_pixels: size.width_pixels,
+            height_pixels: size.height_pixels,
+            modes: &encoded_modes,
+        }];
+
+        let start = |recipient_channel| ChannelRequest::Shell {
+            recipient_channel,
+            want_reply: true,
+        };
+
+        Ok(match self.start_session(RunOptions::default(), prelude, start)? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(run) => RunResult::Accepted(Shell {
+                run,
+                pending: Vec::new(),
+                exit_status: None,
+                stopped: false,
+                keystroke_timing: None,
+            }),
+        })
+    }
+}
+
+/// An interactive shell session, running in a pseudo-terminal
+///
+/// With a pty, the server merges the standard error
+/// of the shell into its standard output.
+#[derive(Debug)]
+pub struct Shell<'a, S: Stream = TcpStream> {
+    run: Run<'a, S>,
+    /// output received while we were writing
+    pending: Vec<u8>,
+    exit_status: Option<ExitStatus>,
+    stopped: bool,
+    keystroke_timing: Option<KeystrokeTiming>,
+}
+
+impl<'a, S: Stream> Shell<'a, S> {
+    /// Reads output of the shell into `buf`; returns the number
+    /// of bytes read, zero meaning the shell has exited.
+    ///
+    /// Fails with [`ErrorKind::Timeout`] if nothing was received before
+    /// the stream's read timeout expired.
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        while self.pending.is_empty() && !self.stopped {
+            let chaff = self.keystroke_timing.as_ref().and_then(KeystrokeTiming::next_chaff);
+            let event = match chaff {
+                Some(chaff) => self.run.wait_event(Some(chaff.saturating_duration_since(Instant::now())))?,
+                None => self.run.next_event()?,
+            };
+
+            match event {
+                RunEvent::Data(data) | RunEvent::ExtDataStderr(data) => self.pending.extend_from_slice(data),
+                RunEvent::Stopped(exit_status) => {
+                    self.exit_status = Some(exit_status);
+                    self.stopped = true;
+                },
+                _ => (),
+            }
+
+            if let (Some(chaff), Some(timing)) = (chaff, &mut self.keystroke_timing) {
+                if Instant::now() >= chaff && !self.stopped {
+                    self.run.connection().send_ignore(CHAFF_LEN)?;
+                    timing.last_tick = Some(chaff);
+                }
+            }
+        }
+
+        let len = self.pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+
+    /// Sends `data` (typically keystrokes) to the shell
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        if let Some(timing) = self.keystroke_timing.as_mut().filter(|_| data.len() <= KEYSTROKE_MAX_LEN) {
+            let now = Instant::now();
+            let tick = timing.next_tick(now);
+            std::thread::sleep(tick - now);
+            timing.last_tick = Some(tick);
+            timing.chaff_until = tick + CHAFF_DURATION;
+        }
+
+        let pending = &mut self.pending;
+        let exit_status = &mut self.exit_status;
+        let stopped = &mut self.stopped;
+
+        self.run.write_poll(data, |event| {
+            match event {
+                RunEvent::Data(data) | RunEvent::ExtDataStderr(data) => pending.extend_from_slice(data),
+                RunEvent::Stopped(status) => {
+                    *exit_status = Some(status);
+                    *stopped = true;
+                    return Err(ErrorKind::ProcessHasExited.into());
+                },
+                _ => (),
+            }
+
+            Ok(())
+        })
+    }
+
+    /// Tells the server that the terminal was resized
+    pub fn resize(&mut self, size: TerminalSize) -> Result<()> {
+        self.run.send_request(|recipient_channel| ChannelRequest::WindowChange {
+            recipient_channel,
+            width_chars: size.columns,
+            height_rows: size.rows,
+            width_pixels: size.width_pixels,
+            height_pixels: size.height_pixels,
+        })
+    }
+
+    /// Paces small writes (keystrokes) to multiples of `interval`, and keeps
+    /// sending `Ignore` messages of the same size at this pace for a second
+    /// after the last keystroke, like OpenSSH's `ObscureKeystrokeTiming`
+    /// (which uses 20ms); `None` disables it, which is the default.
+    ///
+    /// This hides the timing of keystrokes from observers of the network,
+    /// which could otherwise guess what was typed (e.g. passwords). Chaff
+    /// is sent while [`Self::read`] waits, so it should be called meanwhile.
+    pub fn obscure_keystroke_timing(&mut self, interval: Option<Duration>) {
+        self.keystroke_timing = interval.filter(|interval| !interval.is_zero()).map(|interval| {
+            let epoch = Instant::now();
+            KeystrokeTiming {
+                interval,
+                epoch,
+                last_tick: None,
+                chaff_until: epoch,
+            }
+        });
+    }
+
+    /// The exit status of the shell, once [`Self::read`] returned zero
+    pub fn exit_status(&self) -> Option<ExitStatus> {
+        self.exit_status.clone()
+    }
+}
@@ -0,0 +1,258 @@
+//! OpenSSH client configuration files (`~/.ssh/config`, see
+//! `ssh_config(5)`), so that hosts behave like they do with `ssh`
+
+use std::path::PathBuf;
+use std::time::Duration;
+use super::{Result, Error, ErrorKind, Auth, ConnectOptions, Destination, load_openssh_key};
+
+const DEFAULT_PORT: u16 = 22;
+const DEFAULT_IDENTITY_FILE: &str = "~/.ssh/id_ed25519";
+
+/// A parsed configuration file:
+///
+/// ```no_run
+/// use coolssh::{Connection, SshConfig, Auth};
+///
+/// let host = SshConfig::load().unwrap().resolve("myserver");
+/// let keypairs = host.load_identities(None);
+/// let methods = host.auth_methods(&keypairs);
+/// let conn = Connection::connect(&host.destination(), Auth::Any(&methods), host.options()).unwrap();
+/// ```
+///
+/// Supported keywords are `Host`, `HostName`, `User`, `Port`,
+/// `IdentityFile`, `ProxyJump` and `ConnectTimeout`; the others are
+/// ignored, as are `Match` blocks and `Include` directives.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Block {
+    /// `Host` patterns, `None` for `Match` blocks which never apply
+    patterns: Option<Vec<String>>,
+    /// lowercase keyword and value
+    options: Vec<(String, String)>,
+}
+
+/// The settings of one host, see [`SshConfig::resolve`]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HostConfig {
+    /// `HostName`, or the alias given to [`SshConfig::resolve`]
+    pub host_name: String,
+    /// `User`, or the local user (`$USER`) if unset
+    pub user: String,
+    pub port: u16,
+    /// `IdentityFile`s, or `~/.ssh/id_ed25519` if there are none
+    pub identity_files: Vec<PathBuf>,
+    /// `ProxyJump` hosts, in order; see [`Connection::jump`](crate::Connection::jump)
+    pub proxy_jump: Vec<Destination>,
+    pub connect_timeout: Option<Duration>,
+}
+
+impl SshConfig {
+    /// Loads `~/.ssh/config`; a missing file is an empty configuration.
+    pub fn load() -> Result<Self> {
+        let path = expand_home("~/.ssh/config");
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text),
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(error) => Err(error.into()),
+        }
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        // options before the first Host line apply to all hosts
+        let mut blocks = vec![Block {
+            patterns: Some(vec!["*".into()]),
+            options: Vec::new(),
+        }];
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |message: &str| Error::new(ErrorKind::InvalidData).context(format!("line {}: {}", number + 1, message));
+
+            let (keyword, value) = line
+                .split_once(|c: char| c.is_whitespace() || c == '=')
+                .ok_or_else(|| invalid("missing value"))?;
+            let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim_end();
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+            let keyword = keyword.to_ascii_lowercase();
+
+            match keyword.as_str() {
+                "host" => blocks.push(Block {
+                    patterns: Some(value.split_whitespace().map(String::from).collect()),
+                    options: Vec::new(),
+                }),
+                "match" => blocks.push(Block {
+                    patterns: None,
+                    options: Vec::new(),
+                }),
+                "port" | "connecttimeout" if value.parse::<u64>().is_err() => return Err(invalid("not a number")),
+                _ => blocks.last_mut().unwrap().options.push((keyword, value.into())),
+            }
+        }
+
+        Ok(Self {
+            blocks,
+        })
+    }
+
+    /// Gathers the settings of the blocks matching `alias`; as with
+    /// `ssh`, the first value of each setting wins.
+    pub fn resolve(&self, alias: &str) -> HostConfig {
+        let mut options: Vec<(&str, &str)> = Vec::new();
+        for block in &self.blocks {
+            if block.patterns.as_ref().is_some_and(|patterns| host_matches(patterns, alias)) {
+                options.extend(block.options.iter().map(|(keyword, value)| (keyword.as_str(), value.as_str())));
+            }
+        }
+
+        let first = |keyword: &str| options.iter().find(|(k, _)| *k == keyword).map(|(_, value)| *value);
+
+        let host_name = first("hostname").map(|name| name.replace("%h", alias)).unwrap_or_else(|| alias.into());
+        let user = first("user").map(String::from).unwrap_or_else(|| std::env::var("USER").unwrap_or_default());
+        let port = first("port").and_then(|port| port.parse().ok()).unwrap_or(DEFAULT_PORT);
+
+        let mut identity_files: Vec<_> = options.iter()
+            .filter(|(keyword, _)| *keyword == "identityfile")
+            .map(|(_, path)| expand_tokens(path, alias, &host_name, &user))
+            .collect();
+
+        if identity_files.is_empty() {
+            identity_files.push(expand_home(DEFAULT_IDENTITY_FILE));
+        }
+
+        let proxy_jump = match first("proxyjump") {
+            None | Some("none") => Vec::new(),
+            Some(hops) => hops.split(',').filter_map(|hop| match Destination::parse(hop.trim()) {
+                Ok(hop) => Some(hop),
+                Err(error) => {
+                    log::warn!("Ignoring ProxyJump host: {}", error);
+                    None
+                },
+            }).collect(),
+        };
+
+        let connect_timeout = first("connecttimeout")
+            .and_then(|seconds| seconds.parse().ok())
+            .map(Duration::from_secs);
+
+        HostConfig {
+            host_name,
+            user,
+            port,
+            identity_files,
+            proxy_jump,
+            connect_timeout,
+        }
+    }
+}
+
+impl HostConfig {
+    /// Where to connect, for [`Connection::connect`](crate::Connection::connect)
+    pub fn destination(&self) -> Destination {
+        Destination {
+            username: Some(self.user.clone()),
+            host: self.host_name.clone(),
+            port: self.port,
+        }
+    }
+
+    pub fn options(&self) -> ConnectOptions {
+        ConnectOptions::default().connect_timeout(self.connect_timeout)
+    }
+
+    /// Loads the `IdentityFile`s which exist and hold an ed25519 key,
+    /// hex-encoded like [`load_openssh_key`] does; the others are skipped.
+    pub fn load_identities(&self, passphrase: Option<&str>) -> Vec<String> {
+        let mut keypairs = Vec::new();
+        for path in &self.identity_files {
+            let loaded = std::fs::read(path)
+                .map_err(Error::from)
+                .and_then(|file| load_openssh_key(&file, passphrase));
+
+            match loaded {
+                Ok(keypair) => keypairs.push(keypair),
+                Err(error) => log::info!("Skipping identity file {:?}: {}", path, error),
+            }
+        }
+
+        keypairs
+    }
+
+    /// One [`Auth::Ed25519`] per keypair, to be tried in order with [`Auth::Any`]
+    pub fn auth_methods<'a>(&'a self, keypairs: &'a [String]) -> Vec<Auth<'a>> {
+        keypairs.iter().map(|hex_keypair| Auth::Ed25519 {
+            username: &self.user,
+            hex_keypair,
+        }).collect()
+    }
+}
+
+/// True if one of `patterns` matches `host` and none of the negated ones does
+fn host_matches(patterns: &[String], host: &str) -> bool {
+    let mut matched = false;
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) if wildcard_match(negated.as_bytes(), host.as_bytes()) => return false,
+            Some(_) => (),
+            None => matched |= wildcard_match(pattern.as_bytes(), host.as_bytes()),
+        }
+    }
+
+    matched
+}
+
+/// `*` matches any sequence, `?` any single character
+fn wildcard_match(pattern: &[u8], text: &[u8]) -> bool {
+    match (pattern.first(), text.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => wildcard_match(&pattern[1..], text) || (!text.is_empty() && wildcard_match(pattern, &text[1..])),
+        (Some(b'?'), Some(_)) => wildcard_match(&pattern[1..], &text[1..]),
+        (Some(p), Some(t)) if p.eq_ignore_ascii_case(t) => wildcard_match(&pattern[1..], &text[1..]),
+        _ => false,
+    }
+}
+
+/// Expands `~`, `%d` (home), `%h` (host name), `%n` (alias), `%r` (user) and `%%`
+fn expand_tokens(path: &str, alias: &str, host_name: &str, user: &str) -> PathBuf {
+    let mut expanded = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('d') => expanded.push_str(&home()),
+            Some('h') => expanded.push_str(host_name),
+            Some('n') => expanded.push_str(alias),
+            Some('r') => expanded.push_str(user),
+            Some('%') => expanded.push('%'),
+            Some(other) => {
+                expanded.push('%');
+                expanded.push(other);
+            },
+            None => expanded.push('%'),
+        }
+    }
+
+    expand_home(&expanded)
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match path.strip_prefix("~/") {
+        Some(rest) => PathBuf::from(home()).join(rest),
+        None => PathBuf::from(path),
+    }
+}
+
+fn home() -> String {
+    std::env::var("HOME").unwrap_or_default()
+}
@@ -0,0 +1,196 @@
+use core::future::Future;
+use core::pin::pin;
+use core::task::{Context, Poll, Waker};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+use std::io::IoSlice;
+use super::{Result, ErrorKind, TcpStream, BufReader, BufWriter, BufRead, Read, Write, IoResult, IoErrorKind};
+
+/// A byte stream which a [`Connection`](crate::Connection) can run over
+///
+/// For streams without read timeouts, the provided methods are enough:
+///
+/// ```
+/// # struct Pipe;
+/// # impl std::io::Read for Pipe { fn read(&mut self, _: &mut [u8]) -> std::io::Result<usize> { Ok(0) } }
+/// # impl std::io::Write for Pipe {
+/// #     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { Ok(buf.len()) }
+/// #     fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+/// # }
+/// impl coolssh::Stream for Pipe {}
+/// ```
+pub trait Stream: Read + Write {
+    /// The current read timeout; key exchanges suspend it
+    fn read_timeout(&self) -> IoResult<Option<Duration>> {
+        Ok(None)
+    }
+
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl Stream for TcpStream {
+    fn read_timeout(&self) -> IoResult<Option<Duration>> {
+        TcpStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+#[cfg(unix)]
+impl Stream for std::os::unix::net::UnixStream {
+    fn read_timeout(&self) -> IoResult<Option<Duration>> {
+        std::os::unix::net::UnixStream::read_timeout(self)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        std::os::unix::net::UnixStream::set_read_timeout(self, timeout)
+    }
+}
+
+/// Gives the packet reader and the packet writer access to the same
+/// stream; they never use it at the same time.
+pub(crate) struct Shared<S>(Arc<Mutex<S>>);
+
+impl<S> Shared<S> {
+    pub(crate) fn new(stream: S) -> (Self, Self) {
+        let stream = Arc::new(Mutex::new(stream));
+        (Self(stream.clone()), Self(stream))
+    }
+
+    pub(crate) fn lock(&self) -> MutexGuard<'_, S> {
+        self.0.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<S: Read> Read for Shared<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        self.lock().read(buf)
+    }
+}
+
+impl<S: Write> Write for Shared<S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.lock().write(buf)
+    }
+
+    fn write_vectored(&mut self, bufs: &[IoSlice<'_>]) -> IoResult<usize> {
+        self.lock().write_vectored(bufs)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.lock().flush()
+    }
+}
+
+/// Where packets are read from
+///
+/// The protocol is written once, as `async` code; blocking streams
+/// implement this with futures which are ready when first polled,
+/// and are driven by [`block_on`].
+pub(crate) trait Source {
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+
+    /// Appends a line (newline included) to `line`, stopping
+    /// after `max` bytes if the newline wasn't found yet
+    async fn read_line(&mut self, line: &mut Vec<u8>, max: usize) -> Result<usize>;
+
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(None)
+    }
+
+    fn set_read_timeout(&mut self, _timeout: Option<Duration>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Where packets are written to; see [`Source`]
+pub(crate) trait Sink {
+    /// Writes all of `buf`, which may stay buffered until [`Self::flush`]
+    async fn write(&mut self, buf: &[u8]) -> Result<()>;
+
+    async fn flush(&mut self) -> Result<()>;
+
+    /// Writes all of `buf` and flushes
+    async fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.write(buf).await?;
+        self.flush().await
+    }
+
+    /// Writes all of `bufs`, in order, like [`Self::write`]
+    async fn write_vectored(&mut self, bufs: &mut [IoSlice<'_>]) -> Result<()> {
+        for buf in bufs.iter() {
+            self.write(buf).await?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: Stream> Source for BufReader<Shared<S>> {
+    async fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        Ok(Read::read_exact(self, buf)?)
+    }
+
+    async fn read_line(&mut self, line: &mut Vec<u8>, max: usize) -> Result<usize> {
+        Ok(Read::take(self, max as u64).read_until(b'\n', line)?)
+    }
+
+    fn read_timeout(&self) -> Result<Option<Duration>> {
+        Ok(self.get_ref().lock().read_timeout()?)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        Ok(self.get_ref().lock().set_read_timeout(timeout)?)
+    }
+}
+
+impl<S: Stream> Sink for BufWriter<Shared<S>> {
+    async fn write(&mut self, buf: &[u8]) -> Result<()> {
+        Ok(Write::write_all(self, buf)?)
+    }
+
+    async fn flush(&mut self) -> Result<()> {
+        Ok(Write::flush(self)?)
+    }
+
+    async fn write_vectored(&mut self, mut bufs: &mut [IoSlice<'_>]) -> Result<()> {
+        let len = bufs.iter().map(|buf| buf.len()).sum::<usize>();
+        if len < self.capacity() {
+            for buf in bufs.iter() {
+                Write::write_all(self, buf)?;
+            }
+
+            return Ok(());
+        }
+
+        // too large for the buffer: after what it holds,
+        // the slices go straight to the stream, in one call
+        Write::flush(self)?;
+        let stream = self.get_mut();
+
+        IoSlice::advance_slices(&mut bufs, 0);
+        while !bufs.is_empty() {
+            match Write::write_vectored(stream, bufs) {
+                Ok(0) => return Err(ErrorKind::TcpError(IoErrorKind::WriteZero).into()),
+                Ok(written) => IoSlice::advance_slices(&mut bufs, written),
+                Err(e) if e.kind() == IoErrorKind::Interrupted => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a future over blocking streams to completion
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let future = pin!(future);
+    match future.poll(&mut Context::from_waker(Waker::noop())) {
+        Poll::Ready(output) => output,
+        Poll::Pending => unreachable!("blocking streams are always ready"),
+    }
+}
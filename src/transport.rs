@@ -0,0 +1,271 @@
+use aes::cipher::{KeyIvInit, StreamCipher};
+use aes_gcm::{Aes128Gcm, Aes256Gcm, AeadInPlace, KeyInit, Nonce, Tag};
+use zeroize::Zeroizing;
+use super::{ErrorKind, Result, Error, U32};
+use super::hmac::{Mac, MAX_MAC_SIZE, mac, mac_key_len};
+use super::chachapoly::{ChaCha20Poly1305, CHACHA20_POLY1305};
+use super::packets::PacketCipher;
+use subtle::ConstantTimeEq;
+
+pub const AES128_CTR: &str = "aes128-ctr";
+pub const AES192_CTR: &str = "aes192-ctr";
+pub const AES256_CTR: &str = "aes256-ctr";
+pub const AES128_GCM: &str = "aes128-gcm@openssh.com";
+pub const AES256_GCM: &str = "aes256-gcm@openssh.com";
+
+const GCM_TAG_SIZE: usize = 16;
+
+/// Encryption and integrity protection of packets, for one direction
+///
+/// `packet_number` is the sequence number of the packet.
+pub trait Transport {
+    /// Alignment of packets (if [`Self::aligns_length`], of the whole packet;
+    /// otherwise, of what follows the length field)
+    fn block_size(&self) -> usize;
+
+    /// Size of the MAC (or of the authentication tag) following each packet
+    fn mac_size(&self) -> usize;
+
+    /// AEADs authenticate the length field instead of
+    /// counting it in the packet alignment
+    fn aligns_length(&self) -> bool {
+        true
+    }
+
+    /// Returns the length of an incoming packet; `length` (the first
+    /// four bytes of the packet) may be decrypted in place.
+    fn packet_length(&mut self, packet_number: u32, length: &mut [u8; U32]) -> u32;
+
+    /// Checks `mac` and decrypts `packet`, which starts with the
+    /// length field, as left by [`Self::packet_length`].
+    fn open(&mut self, packet_number: u32, packet: &mut [u8], mac: &[u8]) -> Result<()>;
+
+    /// Encrypts `packet` (length field included) and appends its MAC
+    fn seal(&mut self, packet_number: u32, packet: &mut Vec<u8>);
+}
+
+/// IV, encryption key and integrity key of one direction, as derived
+/// from a key exchange (RFC 4253, section 7.2); wiped when dropped
+pub struct DirectionKeys {
+    pub iv: Zeroizing<Vec<u8>>,
+    pub key: Zeroizing<Vec<u8>>,
+    pub mac_key: Zeroizing<Vec<u8>>,
+}
+
+impl DirectionKeys {
+    /// Lengths of the IV, key and MAC key which `cipher` and
+    /// `mac` take; AEADs ignore the MAC algorithm
+    pub fn lengths(cipher: &str, mac: &str) -> (usize, usize, usize) {
+        match cipher {
+            CHACHA20_POLY1305 => (0, 64, 0),
+            AES256_GCM => (12, 32, 0),
+            AES128_GCM => (12, 16, 0),
+            AES256_CTR => (16, 32, mac_key_len(mac)),
+            AES192_CTR => (16, 24, mac_key_len(mac)),
+            AES128_CTR => (16, 16, mac_key_len(mac)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Builds the transport of this direction, see [`Self::lengths`]
+    pub fn transport(&self, cipher: &str, mac_algorithm: &str) -> PacketCipher {
+        match cipher {
+            CHACHA20_POLY1305 => Box::new(ChaCha20Poly1305::new(&self.key)),
+            AES256_GCM | AES128_GCM => Box::new(AesGcm::new(&self.key, &self.iv)),
+            _ => Box::new(AesCtr::new(CtrCipher::new(&self.key, &self.iv), mac(mac_algorithm, &self.mac_key))),
+        }
+    }
+}
+
+/// Compares MACs in constant time, so that the time it takes
+/// doesn't tell how much of a forged one is correct
+pub fn tags_match(received: &[u8], expected: &[u8]) -> bool {
+    received.ct_eq(expected).into()
+}
+
+/// AES in counter mode, with one of the key sizes
+pub enum CtrCipher {
+    Aes128(ctr::Ctr64BE<aes::Aes128>),
+    Aes192(ctr::Ctr64BE<aes::Aes192>),
+    Aes256(ctr::Ctr64BE<aes::Aes256>),
+}
+
+impl CtrCipher {
+    /// `key` must be 16 bytes long for aes128-ctr, 24 for aes192-ctr,
+    /// 32 for aes256-ctr; `iv` must be 16 bytes long
+    pub fn new(key: &[u8], iv: &[u8]) -> Self {
+        match key.len() {
+            16 => Self::Aes128(KeyIvInit::new(key.into(), iv.into())),
+            24 => Self::Aes192(KeyIvInit::new(key.into(), iv.into())),
+            _ => Self::Aes256(KeyIvInit::new(key.into(), iv.into())),
+        }
+    }
+
+    pub fn apply_keystream(&mut self, data: &mut [u8]) {
+        match self {
+            Self::Aes128(cipher) => cipher.apply_keystream(data),
+            Self::Aes192(cipher) => cipher.apply_keystream(data),
+            Self::Aes256(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+/// aes128-ctr, aes192-ctr and aes256-ctr with one of the HMACs
+pub struct AesCtr {
+    cipher: CtrCipher,
+    mac: Box<dyn Mac + Send>,
+}
+
+impl AesCtr {
+    pub fn new(cipher: CtrCipher, mac: Box<dyn Mac + Send>) -> Self {
+        Self {
+            cipher,
+            mac,
+        }
+    }
+
+    fn check_mac(&self, packet_number: u32, packet: &[u8], mac: &[u8]) -> Result<()> {
+        let mut expected = [0; MAX_MAC_SIZE];
+        self.mac.compute(packet_number, packet, &mut expected);
+
+        match tags_match(mac, &expected[..self.mac.size()]) {
+            true => Ok(()),
+            false => Err(ErrorKind::MacMismatch.into()),
+        }
+    }
+}
+
+impl Transport for AesCtr {
+    fn block_size(&self) -> usize {
+        16
+    }
+
+    fn mac_size(&self) -> usize {
+        self.mac.size()
+    }
+
+    fn aligns_length(&self) -> bool {
+        !self.mac.encrypt_then_mac()
+    }
+
+    fn packet_length(&mut self, _packet_number: u32, length: &mut [u8; U32]) -> u32 {
+        if !self.mac.encrypt_then_mac() {
+            self.cipher.apply_keystream(length);
+        }
+
+        u32::from_be_bytes(*length)
+    }
+
+    fn open(&mut self, packet_number: u32, packet: &mut [u8], mac: &[u8]) -> Result<()> {
+        if self.mac.encrypt_then_mac() {
+            self.check_mac(packet_number, packet, mac)?;
+            self.cipher.apply_keystream(&mut packet[U32..]);
+            Ok(())
+        } else {
+            self.cipher.apply_keystream(&mut packet[U32..]);
+            self.check_mac(packet_number, packet, mac)
+        }
+    }
+
+    fn seal(&mut self, packet_number: u32, packet: &mut Vec<u8>) {
+        let len = packet.len();
+        packet.resize(len + self.mac.size(), 0);
+        let (packet, mac) = packet.split_at_mut(len);
+
+        if self.mac.encrypt_then_mac() {
+            self.cipher.apply_keystream(&mut packet[U32..]);
+            self.mac.compute(packet_number, packet, mac);
+        } else {
+            self.mac.compute(packet_number, packet, mac);
+            self.cipher.apply_keystream(packet);
+        }
+    }
+}
+
+// one of these per direction, boxed with the rest
+#[allow(clippy::large_enum_variant)]
+enum GcmCipher {
+    Aes128(Aes128Gcm),
+    Aes256(Aes256Gcm),
+}
+
+/// aes128-gcm@openssh.com and aes256-gcm@openssh.com (RFC 5647)
+///
+/// The length field is sent in clear, as additional authenticated data.
+pub struct AesGcm {
+    cipher: GcmCipher,
+    /// 4-byte fixed field, then the 8-byte invocation counter
+    nonce: [u8; 12],
+}
+
+impl AesGcm {
+    /// `key` must be 16 bytes long for aes128-gcm, 32 for aes256-gcm;
+    /// `iv` must be 12 bytes long
+    pub fn new(key: &[u8], iv: &[u8]) -> Self {
+        let cipher = match key.len() {
+            16 => GcmCipher::Aes128(Aes128Gcm::new(key.into())),
+            _ => GcmCipher::Aes256(Aes256Gcm::new(key.into())),
+        };
+
+        Self {
+            cipher,
+            nonce: iv.try_into().unwrap(),
+        }
+    }
+
+    fn next_nonce(&mut self) -> Nonce<aes_gcm::aes::cipher::consts::U12> {
+        let current = self.nonce;
+
+        let mut counter = [0; 8];
+        counter.copy_from_slice(&self.nonce[4..]);
+        let counter = u64::from_be_bytes(counter).wrapping_add(1);
+        self.nonce[4..].copy_from_slice(&counter.to_be_bytes());
+
+        current.into()
+    }
+}
+
+impl Transport for AesGcm {
+    fn block_size(&self) -> usize {
+        16
+    }
+
+    fn mac_size(&self) -> usize {
+        GCM_TAG_SIZE
+    }
+
+    fn aligns_length(&self) -> bool {
+        false
+    }
+
+    fn packet_length(&mut self, _packet_number: u32, length: &mut [u8; U32]) -> u32 {
+        u32::from_be_bytes(*length)
+    }
+
+    fn open(&mut self, _packet_number: u32, packet: &mut [u8], mac: &[u8]) -> Result<()> {
+        let nonce = self.next_nonce();
+        let (length, payload) = packet.split_at_mut(U32);
+        let tag = Tag::from_slice(mac);
+
+        let result = match &self.cipher {
+            GcmCipher::Aes128(cipher) => cipher.decrypt_in_place_detached(&nonce, length, payload, tag),
+            GcmCipher::Aes256(cipher) => cipher.decrypt_in_place_detached(&nonce, length, payload, tag),
+        };
+
+        result.map_err(|_| Error::new(ErrorKind::MacMismatch).context("GCM"))
+    }
+
+    fn seal(&mut self, _packet_number: u32, packet: &mut Vec<u8>) {
+        let nonce = self.next_nonce();
+        let (length, payload) = packet.split_at_mut(U32);
+
+        let result = match &self.cipher {
+            GcmCipher::Aes128(cipher) => cipher.encrypt_in_place_detached(&nonce, length, payload),
+            GcmCipher::Aes256(cipher) => cipher.encrypt_in_place_detached(&nonce, length, payload),
+        };
+
+        // only fails for payloads bigger than 64GiB
+        let tag = result.unwrap();
+        packet.extend_from_slice(&tag);
+    }
+}
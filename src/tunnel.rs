@@ -0,0 +1,95 @@
+//! Connections which the server makes on our behalf (`direct-tcpip`
+//! channels, RFC 4254, section 7.2), e.g. to reach another SSH server
+
+use std::io::{Read, Write, Result as IoResult, ErrorKind as IoErrorKind};
+use std::time::{Duration, Instant};
+use super::{ErrorKind, Connection, Auth, ConnectOptions, Result, TcpStream};
+use super::stream::Stream;
+use super::run::{Run, RunEvent, RunResult};
+
+impl<S: Stream> Connection<S> {
+    /// Asks the server to connect to `host`:`port` and returns the
+    /// resulting stream, or [`RunResult::Refused`] if it couldn't
+    pub fn direct_tcpip(&self, host: &str, port: u16) -> Result<RunResult<Tunnel<'_, S>>> {
+        Ok(match self.start_direct_tcpip(host, port as u32)? {
+            RunResult::Refused => RunResult::Refused,
+            RunResult::Accepted(run) => RunResult::Accepted(Tunnel {
+                run,
+                pending: Vec::new(),
+                closed: false,
+                read_timeout: None,
+            }),
+        })
+    }
+
+    /// Connects to the SSH server at `host`:`port` through this
+    /// connection, like OpenSSH's `ProxyJump`
+    ///
+    /// Fails with [`std::io::ErrorKind::ConnectionRefused`] if this
+    /// server couldn't connect to the other one.
+    pub fn jump(&self, host: &str, port: u16, auth: Auth, options: ConnectOptions) -> Result<Connection<Tunnel<'_, S>>> {
+        match self.direct_tcpip(host, port)? {
+            RunResult::Accepted(tunnel) => Connection::new_with_options(tunnel, auth, options),
+            RunResult::Refused => Err(ErrorKind::TcpError(IoErrorKind::ConnectionRefused).into()),
+        }
+    }
+}
+
+/// A connection made by the server, see [`Connection::direct_tcpip`]
+///
+/// Reads wait for the underlying connection, whose stream's read
+/// timeout must be set for [`Stream::set_read_timeout`] to be honored.
+#[derive(Debug)]
+pub struct Tunnel<'a, S: Stream = TcpStream> {
+    run: Run<'a, S>,
+    /// received but not read yet
+    pending: Vec<u8>,
+    closed: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl<'a, S: Stream> Read for Tunnel<'a, S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let deadline = self.read_timeout.map(|timeout| Instant::now() + timeout);
+
+        while self.pending.is_empty() && !self.closed {
+            match self.run.next_event() {
+                Ok(RunEvent::Data(data)) => self.pending.extend_from_slice(data),
+                Ok(RunEvent::Eof | RunEvent::Stopped(_)) => self.closed = true,
+                Ok(_) => (),
+                Err(e) if e.kind() == ErrorKind::Timeout => match deadline {
+                    Some(deadline) if Instant::now() >= deadline => return Err(IoErrorKind::TimedOut.into()),
+                    _ => (),
+                },
+                Err(error) => return Err(error.into()),
+            }
+        }
+
+        let len = buf.len().min(self.pending.len());
+        buf[..len].copy_from_slice(&self.pending[..len]);
+        self.pending.drain(..len);
+        Ok(len)
+    }
+}
+
+impl<'a, S: Stream> Write for Tunnel<'a, S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.run.write_stdin(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl<'a, S: Stream> Stream for Tunnel<'a, S> {
+    fn read_timeout(&self) -> IoResult<Option<Duration>> {
+        Ok(self.read_timeout)
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.read_timeout = timeout;
+        Ok(())
+    }
+}
@@ -1,15 +1,83 @@
-use super::{Result, Error, U8, Write, Keypair, Signer};
-use super::parsedump::ParseDump;
-use super::messages::{MessageType, Blob};
-use super::check_msg_type;
+use alloc::{format, vec::Vec};
+use super::{ErrorKind, Result, Error, U8};
+#[cfg(feature = "std")]
+use super::{Keypair, Signer, ed25519_blob_len};
+#[cfg(feature = "std")]
+use super::certificate::ED25519_CERT;
+use super::parsedump::{ParseDump, Buffer};
+use super::messages::{MessageType, Blob, check_msg_type};
 
+/// A private key which signs `publickey` authentication requests
+///
+/// Implement it to log in with keys which can't be read, e.g. in a
+/// PKCS#11 token, a TPM or a cloud KMS; see [`Auth::Signer`](crate::Auth::Signer).
+pub trait AuthSigner: Sync {
+    /// The public key algorithm, e.g. `rsa-sha2-256` or `ssh-ed25519-cert-v01@openssh.com`
+    fn algorithm(&self) -> &str;
+
+    /// The name of the signature format, if it differs from
+    /// [`algorithm`](Self::algorithm) as with certificates
+    fn signature_algorithm(&self) -> &str {
+        self.algorithm()
+    }
+
+    /// The public key or certificate blob
+    fn public_blob(&self) -> Vec<u8>;
+
+    /// Signs `data`; returns the signature without its format name
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// A decoded [`Auth::Ed25519`](crate::Auth::Ed25519) or
+/// [`Auth::Ed25519Certificate`](crate::Auth::Ed25519Certificate) keypair
+#[cfg(feature = "std")]
+pub struct Ed25519Signer<'a> {
+    pub keypair: Keypair,
+    /// the certificate, if any
+    pub certificate: Option<&'a [u8]>,
+}
+
+#[cfg(feature = "std")]
+impl<'a> AuthSigner for Ed25519Signer<'a> {
+    fn algorithm(&self) -> &str {
+        match self.certificate {
+            Some(_) => ED25519_CERT,
+            None => "ssh-ed25519",
+        }
+    }
+
+    // certificates are signed with the certified key
+    fn signature_algorithm(&self) -> &str {
+        "ssh-ed25519"
+    }
+
+    fn public_blob(&self) -> Vec<u8> {
+        match self.certificate {
+            Some(certificate) => certificate.to_vec(),
+            None => {
+                let mut blob = Vec::with_capacity(ed25519_blob_len(32) as _);
+                // dumping to a Vec doesn't fail
+                let _ = "ssh-ed25519".dump(&mut blob);
+                let _ = self.keypair.public.as_bytes().as_slice().dump(&mut blob);
+                blob
+            },
+        }
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(self.keypair.sign(data).to_bytes().to_vec())
+    }
+}
+
+#[cfg(feature = "std")]
 pub fn sign_userauth(
-    keypair: &Keypair,
+    signer: &dyn AuthSigner,
     session_id: &[u8],
     username: &str,
     service_name: &str,
-    ed25519_pub: &Blob,
-) -> Result<[u8; 64]> {
+    algorithm: &str,
+    public_key: &[u8],
+) -> Result<Vec<u8>> {
     let mut dumped = Vec::new();
 
     session_id.dump(&mut dumped)?;
@@ -18,19 +86,25 @@ pub fn sign_userauth(
     service_name.dump(&mut dumped)?;
     "publickey".dump(&mut dumped)?;
     true.dump(&mut dumped)?;
-    "ssh-ed25519".dump(&mut dumped)?;
-    ed25519_pub.dump(&mut dumped)?;
+    algorithm.dump(&mut dumped)?;
+    public_key.dump(&mut dumped)?;
 
-    Ok(keypair.sign(&dumped).to_bytes())
+    signer.sign(&dumped)
 }
 
+/// `SSH_MSG_USERAUTH_REQUEST` (RFC 4252, section 5), by method
 #[derive(Debug)]
 pub enum UserauthRequest<'a> {
+    None {
+        username: &'a str,
+        service_name: &'a str,
+    },
     PublicKey {
         username: &'a str,
         service_name: &'a str,
         algorithm: &'a str,
-        blob: Blob<'a>,
+        /// the public key or certificate
+        blob: &'a [u8],
         signature: Option<Blob<'a>>,
     },
     Password {
@@ -38,7 +112,14 @@ pub enum UserauthRequest<'a> {
         service_name: &'a str,
         password: &'a str,
         new_password: Option<&'a str>
-    }
+    },
+    /// RFC 4256, section 3.1
+    KeyboardInteractive {
+        username: &'a str,
+        service_name: &'a str,
+        /// comma-separated hints, usually empty
+        submethods: &'a str,
+    },
 }
 
 impl<'a, 'b: 'a> ParseDump<'b> for UserauthRequest<'a> {
@@ -52,6 +133,27 @@ impl<'a, 'b: 'a> ParseDump<'b> for UserauthRequest<'a> {
         i += inc;
         let (method_name, inc) = <&'a str>::parse(&bytes[i..])?;
         i += inc;
+
+        if method_name == "none" {
+            return Ok((Self::None {
+                username,
+                service_name,
+            }, i));
+        }
+
+        if method_name == "keyboard-interactive" {
+            let (_language, inc) = <&'a str>::parse(&bytes[i..])?;
+            i += inc;
+            let (submethods, inc) = <&'a str>::parse(&bytes[i..])?;
+            i += inc;
+
+            return Ok((Self::KeyboardInteractive {
+                username,
+                service_name,
+                submethods,
+            }, i));
+        }
+
         let (has_option, inc) = <bool>::parse(&bytes[i..])?;
         i += inc;
 
@@ -59,7 +161,7 @@ impl<'a, 'b: 'a> ParseDump<'b> for UserauthRequest<'a> {
             "publickey" => {
                 let (algorithm, inc) = <&'a str>::parse(&bytes[i..])?;
                 i += inc;
-                let (blob, inc) = Blob::parse(&bytes[i..])?;
+                let (blob, inc) = <&'a [u8]>::parse(&bytes[i..])?;
                 i += inc;
 
                 let (signature, inc) = match has_option {
@@ -93,17 +195,22 @@ impl<'a, 'b: 'a> ParseDump<'b> for UserauthRequest<'a> {
                     new_password,
                 }, i))
             },
-            _ => {
-                log::error!("UserauthRequest: variant {} isn't supported yet", method_name);
-                Err(Error::Unimplemented)
-            },
+            _ => Err(Error::new(ErrorKind::Unimplemented).context(format!("UserauthRequest: variant {} isn't supported yet", method_name))),
         }
     }
 
-    fn dump<W: Write>(&self, sink: &mut W) -> Result<()> {
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
         (MessageType::UserauthRequest as u8).dump(sink)?;
 
         match self {
+            Self::None {
+                username,
+                service_name,
+            } => {
+                username.dump(sink)?;
+                service_name.dump(sink)?;
+                "none".dump(sink)?;
+            },
             Self::PublicKey {
                 username,
                 service_name,
@@ -138,6 +245,120 @@ impl<'a, 'b: 'a> ParseDump<'b> for UserauthRequest<'a> {
                     new_password.dump(sink)?;
                 }
             },
+            Self::KeyboardInteractive {
+                username,
+                service_name,
+                submethods,
+            } => {
+                username.dump(sink)?;
+                service_name.dump(sink)?;
+                "keyboard-interactive".dump(sink)?;
+                // language tag, deprecated
+                "".dump(sink)?;
+                submethods.dump(sink)?;
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// Message numbers of the `keyboard-interactive` method (RFC 4256,
+/// section 5); 60 is also `UserauthPkOk`, so they only make sense
+/// after a keyboard-interactive `UserauthRequest`
+pub(crate) const USERAUTH_INFO_REQUEST: u8 = 60;
+pub(crate) const USERAUTH_INFO_RESPONSE: u8 = 61;
+
+/// `SSH_MSG_USERAUTH_INFO_REQUEST` (RFC 4256, section 3.2)
+#[derive(Debug)]
+pub struct UserauthInfoRequest<'a> {
+    pub name: &'a str,
+    pub instruction: &'a str,
+    /// each prompt, with whether the response may be echoed
+    pub prompts: Vec<(&'a str, bool)>,
+}
+
+impl<'a, 'b: 'a> ParseDump<'b> for UserauthInfoRequest<'a> {
+    fn parse(bytes: &'b[u8]) -> Result<(Self, usize)> {
+        let (number, mut i) = u8::parse(bytes)?;
+        if number != USERAUTH_INFO_REQUEST {
+            return Err(Error::new(ErrorKind::UnknownMessageType(number)).context(format!("expected UserauthInfoRequest but got {}", number)));
+        }
+
+        let (name, inc) = <&'a str>::parse(&bytes[i..])?;
+        i += inc;
+        let (instruction, inc) = <&'a str>::parse(&bytes[i..])?;
+        i += inc;
+        let (_language, inc) = <&'a str>::parse(&bytes[i..])?;
+        i += inc;
+        let (num_prompts, inc) = u32::parse(&bytes[i..])?;
+        i += inc;
+
+        let mut prompts = Vec::new();
+        for _ in 0..num_prompts {
+            let (prompt, inc) = <&'a str>::parse(&bytes[i..])?;
+            i += inc;
+            let (echo, inc) = bool::parse(&bytes[i..])?;
+            i += inc;
+            prompts.push((prompt, echo));
+        }
+
+        Ok((Self {
+            name,
+            instruction,
+            prompts,
+        }, i))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        USERAUTH_INFO_REQUEST.dump(sink)?;
+        self.name.dump(sink)?;
+        self.instruction.dump(sink)?;
+        "".dump(sink)?;
+        (self.prompts.len() as u32).dump(sink)?;
+
+        for (prompt, echo) in &self.prompts {
+            prompt.dump(sink)?;
+            echo.dump(sink)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// `SSH_MSG_USERAUTH_INFO_RESPONSE` (RFC 4256, section 3.4)
+#[derive(Debug)]
+pub struct UserauthInfoResponse<'a> {
+    /// one per prompt, in order
+    pub responses: Vec<&'a str>,
+}
+
+impl<'a, 'b: 'a> ParseDump<'b> for UserauthInfoResponse<'a> {
+    fn parse(bytes: &'b[u8]) -> Result<(Self, usize)> {
+        let (number, mut i) = u8::parse(bytes)?;
+        if number != USERAUTH_INFO_RESPONSE {
+            return Err(Error::new(ErrorKind::UnknownMessageType(number)).context(format!("expected UserauthInfoResponse but got {}", number)));
+        }
+
+        let (num_responses, inc) = u32::parse(&bytes[i..])?;
+        i += inc;
+
+        let mut responses = Vec::new();
+        for _ in 0..num_responses {
+            let (response, inc) = <&'a str>::parse(&bytes[i..])?;
+            i += inc;
+            responses.push(response);
+        }
+
+        Ok((Self { responses }, i))
+    }
+
+    fn dump<W: Buffer>(&self, sink: &mut W) -> Result<()> {
+        USERAUTH_INFO_RESPONSE.dump(sink)?;
+        (self.responses.len() as u32).dump(sink)?;
+
+        for response in &self.responses {
+            response.dump(sink)?;
         }
 
         Ok(())
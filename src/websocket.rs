@@ -0,0 +1,238 @@
+//! SSH over WebSocket (RFC 6455), for gateways which only expose
+//! SSH this way: the byte stream is carried by binary messages
+
+use std::io::{Read, Write, Result as IoResult, Error as IoError, ErrorKind as IoErrorKind};
+use std::time::Duration;
+use base64::{Engine, engine::general_purpose::STANDARD};
+use rand_core::RngCore;
+use sha1::{Sha1, Digest};
+use super::{ErrorKind, Result, Error, TcpStream, Rng};
+use super::stream::Stream;
+
+/// Appended to the key of the handshake before hashing it
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC11B85";
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xa;
+
+const FIN: u8 = 0x80;
+const MASKED: u8 = 0x80;
+
+/// A WebSocket connection, which a [`Connection`](crate::Connection)
+/// can run over:
+///
+/// ```no_run
+/// use std::net::TcpStream;
+/// use coolssh::{Connection, WebSocket};
+///
+/// let stream = TcpStream::connect("gateway.example:80").unwrap();
+/// let websocket = WebSocket::handshake(stream, "gateway.example", "/ssh").unwrap();
+/// let conn = Connection::new(websocket, ("user", "<hex keypair>").into()).unwrap();
+/// ```
+///
+/// For `wss://` URLs, `stream` must be a TLS stream.
+#[derive(Debug)]
+pub struct WebSocket<S: Stream = TcpStream> {
+    inner: S,
+    /// payload of the current binary frame, not read yet
+    remaining: u64,
+    /// of the current frame, if the server masked it
+    mask: Option<([u8; 4], usize)>,
+    closed: bool,
+}
+
+impl<S: Stream> WebSocket<S> {
+    /// Performs the opening handshake, asking `host` to upgrade the
+    /// connection to `path`
+    pub fn handshake(mut inner: S, host: &str, path: &str) -> Result<Self> {
+        let mut nonce = [0; 16];
+        Rng.fill_bytes(&mut nonce);
+        let key = STANDARD.encode(nonce);
+
+        let request = format!(concat!(
+            "GET {} HTTP/1.1\r\n",
+            "Host: {}\r\n",
+            "Upgrade: websocket\r\n",
+            "Connection: Upgrade\r\n",
+            "Sec-WebSocket-Key: {}\r\n",
+            "Sec-WebSocket-Version: 13\r\n",
+            "\r\n",
+        ), path, host, key);
+
+        inner.write_all(request.as_bytes())?;
+        inner.flush()?;
+
+        // byte by byte, so that nothing after the headers is consumed
+        let mut response = Vec::new();
+        while !response.ends_with(b"\r\n\r\n") {
+            let mut byte = [0];
+            inner.read_exact(&mut byte)?;
+            response.push(byte[0]);
+        }
+
+        let response = core::str::from_utf8(&response).map_err(|_| ErrorKind::InvalidData)?;
+        let mut lines = response.split("\r\n");
+        let status = lines.next().unwrap_or("");
+        if !status.starts_with("HTTP/1.1 101") {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("the WebSocket handshake failed: {}", status)));
+        }
+
+        let mut hasher = Sha1::new();
+        hasher.update(key.as_bytes());
+        hasher.update(ACCEPT_GUID.as_bytes());
+        let expected = STANDARD.encode(hasher.finalize());
+
+        let accept = lines.filter_map(|line| line.split_once(':')).find_map(|(name, value)| {
+            name.eq_ignore_ascii_case("Sec-WebSocket-Accept").then_some(value.trim())
+        });
+
+        if accept != Some(&expected) {
+            return Err(Error::new(ErrorKind::InvalidData).context(format!("invalid Sec-WebSocket-Accept header: {:?}", accept)));
+        }
+
+        Ok(Self {
+            inner,
+            remaining: 0,
+            mask: None,
+            closed: false,
+        })
+    }
+
+    /// The underlying stream
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    /// Sends a frame made of `payload`, masked as clients must
+    fn send_frame(&mut self, opcode: u8, payload: &[u8]) -> IoResult<()> {
+        let mut frame = vec![FIN | opcode];
+
+        match payload.len() {
+            len @ 0..=125 => frame.push(MASKED | len as u8),
+            len @ 126..=0xffff => {
+                frame.push(MASKED | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            },
+            len => {
+                frame.push(MASKED | 127);
+                frame.extend_from_slice(&(len as u64).to_be_bytes());
+            },
+        }
+
+        let mut mask = [0; 4];
+        Rng.fill_bytes(&mut mask);
+        frame.extend_from_slice(&mask);
+
+        let offset = frame.len();
+        frame.extend_from_slice(payload);
+        frame[offset..].iter_mut().zip(mask.iter().cycle()).for_each(|(byte, mask)| *byte ^= mask);
+
+        self.inner.write_all(&frame)?;
+        self.inner.flush()
+    }
+
+    /// Reads frame headers until a binary frame with a payload comes,
+    /// answering pings; returns false once the server closed the connection.
+    fn next_frame(&mut self) -> IoResult<bool> {
+        while self.remaining == 0 {
+            if self.closed {
+                return Ok(false);
+            }
+
+            let mut header = [0; 2];
+            self.inner.read_exact(&mut header)?;
+            let opcode = header[0] & 0x0f;
+
+            let mut length = (header[1] & 0x7f) as u64;
+            if length == 126 {
+                let mut extended = [0; 2];
+                self.inner.read_exact(&mut extended)?;
+                length = u16::from_be_bytes(extended) as u64;
+            } else if length == 127 {
+                let mut extended = [0; 8];
+                self.inner.read_exact(&mut extended)?;
+                length = u64::from_be_bytes(extended);
+            }
+
+            self.mask = None;
+            if header[1] & MASKED != 0 {
+                let mut mask = [0; 4];
+                self.inner.read_exact(&mut mask)?;
+                self.mask = Some((mask, 0));
+            }
+
+            match opcode {
+                OPCODE_BINARY | OPCODE_CONTINUATION => self.remaining = length,
+                OPCODE_PING | OPCODE_PONG | OPCODE_CLOSE => {
+                    // control frames are at most 125 bytes long
+                    let mut payload = vec![0; length.min(125) as usize];
+                    self.inner.read_exact(&mut payload)?;
+                    if let Some((mask, _)) = self.mask {
+                        payload.iter_mut().zip(mask.iter().cycle()).for_each(|(byte, mask)| *byte ^= mask);
+                    }
+
+                    match opcode {
+                        OPCODE_PING => self.send_frame(OPCODE_PONG, &payload)?,
+                        OPCODE_CLOSE => {
+                            let _ = self.send_frame(OPCODE_CLOSE, &payload);
+                            self.closed = true;
+                        },
+                        _ => (),
+                    }
+                },
+                _ => {
+                    log::error!("Unexpected WebSocket opcode: {}", opcode);
+                    return Err(IoError::new(IoErrorKind::InvalidData, "unexpected WebSocket frame"));
+                },
+            }
+        }
+
+        Ok(true)
+    }
+}
+
+/// Reads the payload of binary messages
+impl<S: Stream> Read for WebSocket<S> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if !self.next_frame()? {
+            return Ok(0);
+        }
+
+        let len = (buf.len() as u64).min(self.remaining) as usize;
+        let len = self.inner.read(&mut buf[..len])?;
+        if let Some((mask, offset)) = &mut self.mask {
+            for byte in &mut buf[..len] {
+                *byte ^= mask[*offset % 4];
+                *offset += 1;
+            }
+        }
+
+        self.remaining -= len as u64;
+        Ok(len)
+    }
+}
+
+/// Each write is sent as one binary message
+impl<S: Stream> Write for WebSocket<S> {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.send_frame(OPCODE_BINARY, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl<S: Stream> Stream for WebSocket<S> {
+    fn read_timeout(&self) -> IoResult<Option<Duration>> {
+        self.inner.read_timeout()
+    }
+
+    fn set_read_timeout(&mut self, timeout: Option<Duration>) -> IoResult<()> {
+        self.inner.set_read_timeout(timeout)
+    }
+}
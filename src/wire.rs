@@ -0,0 +1,50 @@
+//! The SSH encoding layer (RFC 4251, section 5), for other protocols
+//! built on it, e.g. SSH agents or SFTP extensions:
+//!
+//! ```
+//! use coolssh::wire::{ParseDump, NameList, UnsignedMpInt};
+//!
+//! let mut buffer = Vec::new();
+//! "ssh-ed25519".dump(&mut buffer).unwrap();
+//! NameList("zlib,none").dump(&mut buffer).unwrap();
+//! UnsignedMpInt(&[0, 0x80]).dump(&mut buffer).unwrap();
+//!
+//! let (algorithm, i) = <&str>::parse(&buffer).unwrap();
+//! let (compression, inc) = NameList::parse(&buffer[i..]).unwrap();
+//! let (mpint, _) = UnsignedMpInt::parse(&buffer[i + inc..]).unwrap();
+//!
+//! assert_eq!(algorithm, "ssh-ed25519");
+//! assert!(compression.contains("none"));
+//! assert_eq!(mpint.0, [0, 0x80]);
+//! ```
+//!
+//! | SSH type    | Rust type           |
+//! |-------------|---------------------|
+//! | `byte`      | `u8`                |
+//! | `boolean`   | `bool`              |
+//! | `uint32`    | `u32`               |
+//! | `uint64`    | `u64`               |
+//! | `string`    | `&[u8]`, `&str`     |
+//! | `mpint`     | [`UnsignedMpInt`]   |
+//! | `name-list` | [`NameList`]        |
+//!
+//! [`Blob`] is a string holding an algorithm name and a string (e.g.
+//! a signature), and the messages of the transport, authentication and
+//! connection protocols are structs named after them.
+
+#[doc(inline)]
+pub use super::parsedump::{ParseDump, Buffer, NameList};
+
+#[doc(inline)]
+pub use super::messages::{
+    Message, MessageType, OwnedMessage, UnsignedMpInt, Blob, DisconnectReasonCode,
+    Disconnect, IgnoreData, Unimplemented, DebugMessage, ServiceRequest, ServiceAccept, ExtInfo,
+    Kexinit, Newkeys,
+    UserauthRequest, UserauthFailure, UserauthSuccess, UserauthBanner, UserauthPkOk,
+    GlobalRequest, ChannelOpen, DirectTcpipOpen, ChannelOpenConfirmation, ChannelOpenFailure,
+    ChannelWindowAdjust, ChannelData, ChannelExtendedData, ExtendedDataType, ChannelEof, ChannelClose,
+    ChannelRequest, ChannelSuccess, ChannelFailure,
+};
+
+#[doc(inline)]
+pub use super::userauth::{UserauthInfoRequest, UserauthInfoResponse};
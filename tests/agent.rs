@@ -0,0 +1,80 @@
+#![cfg(unix)]
+
+mod common;
+
+use std::io::{Read, Write};
+use std::os::unix::net::UnixListener;
+use std::time::Duration;
+use coolssh::{RunResult, RunEvent};
+use common::*;
+
+const AGENT_CHANNEL: u32 = 3;
+
+/// SSH_AGENTC_REQUEST_IDENTITIES
+const REQUEST: &[u8] = &[0, 0, 0, 1, 11];
+/// SSH_AGENT_IDENTITIES_ANSWER, without keys
+const ANSWER: &[u8] = &[0, 0, 0, 5, 12, 0, 0, 0, 0];
+
+#[test]
+fn relay_agent_connection() {
+    let path = std::env::temp_dir().join(format!("coolssh-agent-{}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    std::env::set_var("SSH_AUTH_SOCK", &path);
+
+    let agent = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = [0; REQUEST.len()];
+        stream.read_exact(&mut request).unwrap();
+        stream.write_all(ANSWER).unwrap();
+        request
+    });
+
+    scripted(|stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let conn = connect(stream);
+        conn.set_agent_forwarding(true);
+
+        let RunResult::Accepted(mut run) = conn.run("git fetch", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("auth-agent-req@openssh.com"), b"");
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        let mut open = vec![90];
+        open.extend(string(b"auth-agent@openssh.com"));
+        open.extend_from_slice(&AGENT_CHANNEL.to_be_bytes());
+        open.extend_from_slice(&0x10000u32.to_be_bytes());
+        open.extend_from_slice(&0x8000u32.to_be_bytes());
+        server.send(&open);
+
+        let confirmation = server.expect(91);
+        let mut i = 1;
+        assert_eq!(take_u32(&confirmation, &mut i), AGENT_CHANNEL);
+        let agent_channel = take_u32(&confirmation, &mut i);
+
+        server.send_data(agent_channel, REQUEST);
+        let message = server.expect(94);
+        let mut i = 1;
+        assert_eq!(take_u32(&message, &mut i), AGENT_CHANNEL);
+        assert_eq!(take_string(&message, &mut i), ANSWER);
+
+        // the agent closed the connection
+        server.expect(96);
+        server.expect(97);
+        server.send_close(agent_channel);
+
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(agent.join().unwrap(), REQUEST);
+    let _ = std::fs::remove_file(&path);
+}
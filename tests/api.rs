@@ -0,0 +1,114 @@
+//! Pins the usability guarantees of the public API: if one of
+//! these stops compiling, the change is a breaking one.
+
+use coolssh::{
+    Auth, ConnectOptions, Connection, Error, ErrorKind, ExitStatus, MessageType,
+    PublicKey, Run, RunEvent, RunResult,
+};
+
+fn is_send<T: Send>() {}
+fn is_sync<T: Sync>() {}
+fn is_error_friendly<T: std::error::Error + PartialEq + Eq + Send + Sync + 'static>() {}
+fn is_comparable<T: Copy + Clone + core::fmt::Debug + PartialEq + Eq + Send + Sync + 'static>() {}
+
+#[test]
+fn auto_traits() {
+    is_send::<Connection>();
+    is_send::<Run<'static>>();
+    is_send::<PublicKey>();
+    is_sync::<PublicKey>();
+    is_send::<ConnectOptions>();
+    is_error_friendly::<Error>();
+    is_comparable::<ErrorKind>();
+    is_comparable::<MessageType>();
+}
+
+#[test]
+fn errors_can_be_compared() {
+    let result: Result<(), Error> = Err(ErrorKind::Timeout.into());
+    assert_eq!(result, Err(ErrorKind::Timeout.into()));
+    assert_ne!(Error::from(ErrorKind::UnknownMessageType(30)), ErrorKind::UnknownMessageType(31).into());
+    // the source isn't compared
+    assert_eq!(Error::from(std::io::Error::from(std::io::ErrorKind::TimedOut)), ErrorKind::TcpError(std::io::ErrorKind::TimedOut).into());
+
+    // but the message is
+    let error = Error::new(ErrorKind::InvalidData).context("bad field");
+    assert_eq!(error, Error::new(ErrorKind::InvalidData).context("bad field"));
+    assert_ne!(error, ErrorKind::InvalidData.into());
+    assert_ne!(error, Error::new(ErrorKind::InvalidKeypair).context("bad field"));
+}
+
+#[test]
+fn errors_carry_context() {
+    let error = Error::new(ErrorKind::InvalidData).context("bad field").context("parsing a message");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert_eq!(error.message(), Some("parsing a message: bad field"));
+    assert_eq!(error.to_string(), "invalid data: parsing a message: bad field");
+
+    let io_error = std::io::Error::from(std::io::ErrorKind::ConnectionReset);
+    let error = Error::from(io_error);
+    assert!(std::error::Error::source(&error).is_some());
+    assert_eq!(std::io::Error::from(error).kind(), std::io::ErrorKind::ConnectionReset);
+}
+
+#[test]
+fn run_events_can_be_compared() {
+    let data = b"hello";
+    let event = RunEvent::Data(data);
+    assert_eq!(event, RunEvent::Data(b"hello"));
+    assert_ne!(event, RunEvent::ExtDataStderr(b"hello"));
+
+    let status = ExitStatus::Code(0);
+    assert!(status.success());
+    assert_eq!(RunEvent::Stopped(status), RunEvent::Stopped(ExitStatus::Code(0)));
+    assert_ne!(RunEvent::Stopped(ExitStatus::Signal("KILL".into())), RunEvent::Stopped(ExitStatus::Unknown));
+}
+
+#[test]
+fn run_results_can_be_compared() {
+    let accepted: RunResult<(String, ExitStatus)> = RunResult::Accepted(("out".into(), ExitStatus::Unknown));
+    assert_eq!(accepted.clone(), accepted);
+    assert_ne!(RunResult::Refused, accepted);
+}
+
+// growable enums must be matched with a wildcard arm
+#[allow(unreachable_patterns)]
+fn describe(event: RunEvent) -> &'static str {
+    match event {
+        RunEvent::None => "none",
+        RunEvent::Data(_) => "stdout",
+        RunEvent::ExtDataStderr(_) => "stderr",
+        RunEvent::Stopped(_) => "stopped",
+        _ => "other",
+    }
+}
+
+#[test]
+fn growable_enums() {
+    assert_eq!(describe(RunEvent::None), "none");
+
+    // Auth is Copy, so it can be reused after a failed attempt
+    let auth: Auth = ("git", "hex").into();
+    let first_attempt = auth;
+    assert!(matches!(first_attempt, Auth::Ed25519 { username: "git", .. }));
+    assert!(matches!(auth, Auth::Ed25519 { hex_keypair: "hex", .. }));
+}
+
+#[test]
+fn auth_debug_hides_secrets() {
+    let auth = Auth::Password {
+        username: "alice",
+        password: "hunter2",
+    };
+
+    let debug = format!("{:?}", auth);
+    assert!(debug.contains("alice"));
+    assert!(!debug.contains("hunter2"));
+}
+
+#[test]
+fn options_are_plain_values() {
+    let options = ConnectOptions::default().gex_group_size(2048, 4096, 8192);
+    assert_eq!(options.clone(), options);
+    assert_ne!(options, ConnectOptions::default());
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use std::io::Read;
+use coolssh::{RunResult, RunEvent, ExitStatus};
+use common::*;
+
+#[test]
+fn custom_requests() {
+    let (accepted, refused, output, exit_status) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut channel) = conn.open_session().unwrap() else {
+            panic!("session refused");
+        };
+
+        let refused = channel.send_request("custom@example.com", true, &[1, 2, 3]).unwrap();
+        let accepted = channel.send_request("exec", true, &string(b"cat")).unwrap();
+        channel.write(b"hello").unwrap();
+        channel.eof().unwrap();
+
+        let mut output = Vec::new();
+        while let RunEvent::Data(data) = channel.poll().unwrap() {
+            output.extend_from_slice(data);
+        }
+
+        let exit_status = channel.wait_close().unwrap();
+        (accepted, refused, output, exit_status)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+
+        assert_eq!(server.expect_channel_request("custom@example.com"), [1, 2, 3]);
+        // data received before the reply is kept
+        server.send_data(channel, b"early ");
+        server.channel_failure(channel);
+
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat"));
+        server.channel_success(channel);
+        assert_eq!(server.recv_data(), b"hello");
+        server.expect(96);
+
+        server.send_data(channel, b"hello");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert!(accepted);
+    assert!(!refused);
+    assert_eq!(output, b"early hello");
+    assert_eq!(exit_status, ExitStatus::Code(0));
+}
+
+#[test]
+fn into_run() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut channel) = conn.open_session().unwrap() else {
+            panic!("session refused");
+        };
+
+        assert!(channel.send_request("subsystem", true, &string(b"hello")).unwrap());
+        let mut output = String::new();
+        channel.into_run().read_to_string(&mut output).unwrap();
+        output
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("subsystem"), string(b"hello"));
+        server.channel_success(channel);
+
+        server.send_data(channel, b"world");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, "world");
+}
+
+#[test]
+fn benign_messages_during_setup() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        conn.quick_run("hostname").unwrap()
+    }, |mut server| {
+        server.accept_session();
+
+        // ahead of the confirmation
+        server.send(&[3, 0, 0, 0, 7]);
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+
+        // ahead of the reply
+        server.send(&[2, 0, 0, 0, 0]);
+        server.send_window_adjust(channel, 1000);
+        let mut keepalive = vec![98];
+        keepalive.extend_from_slice(&channel.to_be_bytes());
+        keepalive.extend(string(b"keepalive@openssh.com"));
+        keepalive.push(1);
+        server.send(&keepalive);
+        server.channel_success(channel);
+
+        // refused, as OpenSSH clients do
+        server.expect(100);
+
+        server.send_data(channel, b"box\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, RunResult::Accepted(("box\n".into(), ExitStatus::Code(0))));
+}
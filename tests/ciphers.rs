@@ -0,0 +1,146 @@
+mod common;
+
+use coolssh::{RunResult, RunEvent, ErrorKind};
+use common::*;
+
+/// Runs a command over a connection using `cipher` and `mac`,
+/// with enough output to span several packets
+fn run_with(cipher: &'static str, mac: &'static str) {
+    let output: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    let expected = output.clone();
+
+    let (received, algorithms) = scripted(|stream| {
+        let conn = connect(stream);
+        let algorithms = conn.algorithms();
+        let RunResult::Accepted((received, _)) = conn.quick_run_bytes("cat data").unwrap() else {
+            panic!("exec refused");
+        };
+        (received, algorithms)
+    }, move |mut server| {
+        server.cipher = cipher;
+        server.mac = mac;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat data"));
+        server.channel_success(channel);
+
+        for chunk in output.chunks(0x4000) {
+            server.send_data(channel, chunk);
+        }
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(algorithms.encryption_client_to_server, cipher);
+    assert_eq!(algorithms.encryption_server_to_client, cipher);
+    assert_eq!(algorithms.mac_client_to_server, mac);
+    assert_eq!(algorithms.mac_server_to_client, mac);
+    assert!(received == expected);
+}
+
+#[test]
+fn aes256_ctr() {
+    run_with("aes256-ctr", "hmac-sha2-256");
+}
+
+#[test]
+fn aes192_ctr() {
+    run_with("aes192-ctr", "hmac-sha2-256");
+}
+
+#[test]
+fn aes128_ctr() {
+    run_with("aes128-ctr", "hmac-sha2-256");
+}
+
+#[test]
+fn chacha20_poly1305() {
+    run_with("chacha20-poly1305@openssh.com", "hmac-sha2-256");
+}
+
+#[test]
+fn aes256_gcm() {
+    run_with("aes256-gcm@openssh.com", "hmac-sha2-256");
+}
+
+#[test]
+fn aes128_gcm() {
+    run_with("aes128-gcm@openssh.com", "hmac-sha2-256");
+}
+
+#[test]
+fn aes256_ctr_hmac_sha2_512() {
+    run_with("aes256-ctr", "hmac-sha2-512");
+}
+
+#[test]
+fn aes256_ctr_hmac_sha1() {
+    run_with("aes256-ctr", "hmac-sha1");
+}
+
+#[test]
+fn aes256_ctr_hmac_sha2_256_etm() {
+    run_with("aes256-ctr", "hmac-sha2-256-etm@openssh.com");
+}
+
+#[test]
+fn aes256_ctr_hmac_sha2_512_etm() {
+    run_with("aes256-ctr", "hmac-sha2-512-etm@openssh.com");
+}
+
+#[test]
+fn aes256_ctr_hmac_sha1_etm() {
+    run_with("aes256-ctr", "hmac-sha1-etm@openssh.com");
+}
+
+/// Sends one packet with a corrupted MAC, which must be rejected
+fn forged_mac_with(cipher: &'static str, mac: &'static str) {
+    let result = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("cat data", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        loop {
+            match run.poll() {
+                Ok(RunEvent::Stopped(_)) => break Ok(()),
+                Ok(_) => (),
+                Err(error) => break Err(error),
+            }
+        }
+    }, move |mut server| {
+        server.cipher = cipher;
+        server.mac = mac;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.corrupt_next_mac = true;
+        server.send_data(channel, b"forged");
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::MacMismatch));
+}
+
+#[test]
+fn forged_hmac_is_rejected() {
+    forged_mac_with("aes256-ctr", "hmac-sha2-256");
+}
+
+#[test]
+fn forged_hmac_etm_is_rejected() {
+    forged_mac_with("aes256-ctr", "hmac-sha2-256-etm@openssh.com");
+}
+
+#[test]
+fn forged_poly1305_is_rejected() {
+    forged_mac_with("chacha20-poly1305@openssh.com", "hmac-sha2-256");
+}
+
+#[test]
+fn forged_gcm_tag_is_rejected() {
+    forged_mac_with("aes256-gcm@openssh.com", "hmac-sha2-256");
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use coolssh::{RunResult, RunEvent, RemoteCommand, shell_quote};
+use common::*;
+
+#[test]
+fn quoting() {
+    assert_eq!(shell_quote("plain-word_1.txt"), "plain-word_1.txt");
+    assert_eq!(shell_quote(""), "''");
+    assert_eq!(shell_quote("two words"), "'two words'");
+    assert_eq!(shell_quote("$(reboot)"), "'$(reboot)'");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    assert_eq!(shell_quote("line\nbreak"), "'line\nbreak'");
+
+    let command = RemoteCommand::new("grep").args(["-r", "; rm -rf /", "~/My Files"]);
+    assert_eq!(command.to_string(), "grep -r '; rm -rf /' '~/My Files'");
+}
+
+#[test]
+fn run_command() {
+    scripted(|stream| {
+        let conn = connect(stream);
+        let command = RemoteCommand::new("ls").arg("My Documents").env("LANG", "C");
+        let RunResult::Accepted(mut run) = conn.run_command(&command).unwrap() else {
+            panic!("exec refused");
+        };
+
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+
+        let mut expected = string(b"LANG");
+        expected.extend(string(b"C"));
+        assert_eq!(server.expect_channel_request("env"), expected);
+        server.channel_success(channel);
+        assert_eq!(server.expect_channel_request("exec"), string(b"ls 'My Documents'"));
+        server.channel_success(channel);
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+}
@@ -0,0 +1,963 @@
+//! A scripted, single-connection SSH server, just smart enough
+//! to drive the client through specific protocol paths.
+
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use std::thread::JoinHandle;
+use aes::cipher::{KeyIvInit, StreamCipher};
+use sha2::{Digest, Sha256};
+use num_bigint::BigUint;
+
+type Cipher = Box<dyn StreamCipher + Send>;
+
+pub const SERVER_VERSION: &[u8] = b"SSH-2.0-ScriptedServer";
+
+/// RFC 3526, 2048-bit MODP group (group14)
+pub const GROUP14_PRIME: &str = concat!(
+    "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74",
+    "020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F1437",
+    "4FE1356D6D51C245E485B576625E7EC6F44C42E9A637ED6B0BFF5CB6F406B7ED",
+    "EE386BFB5A899FA5AE9F24117C4B1FE649286651ECE45B3DC2007CB8A163BF05",
+    "98DA48361C55D39A69163FA8FD24CF5F83655D23DCA3AD961C62F356208552BB",
+    "9ED529077096966D670C354E4ABC9804F1746C08CA18217C32905E462E36CE3B",
+    "E39E772C180E86039B2783A2EC07A28FB5C55DF06F4C52C9DE2BCBF695581718",
+    "3995497CEA956AE515D2261898FA051015728E5A8AACAA68FFFFFFFFFFFFFFFF",
+);
+
+pub const CLIENT_HEX_KEYPAIR: &str = concat!(
+    "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+    "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+);
+
+/// Channel id the server uses for the first channel it confirms
+pub const SERVER_CHANNEL: u32 = 1000;
+
+/// Runs `client` against a fresh connection to `server`
+pub fn scripted<T, C, S>(client: C, server: S) -> T
+where
+    T: Send + 'static,
+    C: FnOnce(TcpStream) -> T + Send + 'static,
+    S: FnOnce(Server),
+{
+    scripted_at(move |addr| client(TcpStream::connect(addr).unwrap()), server)
+}
+
+/// Same as [`scripted`], but `client` connects by itself
+pub fn scripted_at<T, C, S>(client: C, server: S) -> T
+where
+    T: Send + 'static,
+    C: FnOnce(SocketAddr) -> T + Send + 'static,
+    S: FnOnce(Server),
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client: JoinHandle<T> = std::thread::spawn(move || client(addr));
+    let (stream, _) = listener.accept().unwrap();
+    server(Server::new(stream));
+    client.join().unwrap()
+}
+
+/// Same as [`scripted_at`], but `server` accepts the connections
+/// by itself, with [`Server::accept`]
+pub fn scripted_listener<T, C, S>(client: C, server: S) -> T
+where
+    T: Send + 'static,
+    C: FnOnce(SocketAddr) -> T + Send + 'static,
+    S: FnOnce(TcpListener),
+{
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client: JoinHandle<T> = std::thread::spawn(move || client(addr));
+    server(listener);
+    client.join().unwrap()
+}
+
+/// Connects with the default options and the test keypair
+pub fn connect(stream: TcpStream) -> coolssh::Connection {
+    coolssh::Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).unwrap()
+}
+
+#[allow(clippy::large_enum_variant)]
+enum Direction {
+    /// aes128-ctr, aes192-ctr and aes256-ctr + one of the HMACs
+    AesCtr {
+        cipher: Cipher,
+        mac: &'static str,
+        mac_key: [u8; 64],
+    },
+    /// chacha20-poly1305@openssh.com
+    ChaChaPoly {
+        main_key: [u8; 32],
+        header_key: [u8; 32],
+    },
+    /// aes128-gcm@openssh.com and aes256-gcm@openssh.com;
+    /// a 16-byte key selects AES-128
+    AesGcm {
+        key: Vec<u8>,
+        iv: [u8; 12],
+    },
+}
+
+/// Encrypts (or decrypts) the packet following its cleartext length,
+/// authenticating that length; then increments the invocation counter.
+fn aes_gcm(key: &[u8], iv: &mut [u8; 12], packet: &mut [u8], tag: Option<&[u8]>) -> [u8; 16] {
+    use aes_gcm::{AeadInPlace, Aes128Gcm, Aes256Gcm, KeyInit, Tag};
+
+    let nonce = (*iv).into();
+    let (length, payload) = packet.split_at_mut(4);
+    let tag = match (key.len(), tag) {
+        (16, None) => Aes128Gcm::new(key.into()).encrypt_in_place_detached(&nonce, length, payload).unwrap(),
+        (_, None) => Aes256Gcm::new(key.into()).encrypt_in_place_detached(&nonce, length, payload).unwrap(),
+        (16, Some(tag)) => {
+            let tag = Tag::clone_from_slice(tag);
+            Aes128Gcm::new(key.into()).decrypt_in_place_detached(&nonce, length, payload, &tag).expect("bad client MAC");
+            tag
+        },
+        (_, Some(tag)) => {
+            let tag = Tag::clone_from_slice(tag);
+            Aes256Gcm::new(key.into()).decrypt_in_place_detached(&nonce, length, payload, &tag).expect("bad client MAC");
+            tag
+        },
+    };
+
+    let counter = u64::from_be_bytes(iv[4..].try_into().unwrap()).wrapping_add(1);
+    iv[4..].copy_from_slice(&counter.to_be_bytes());
+    tag.into()
+}
+
+fn chacha(key: &[u8; 32], seq: u32, block: u64, data: &mut [u8]) {
+    use chacha20::cipher::{KeyIvInit, StreamCipher, StreamCipherSeek};
+    let mut cipher = chacha20::ChaCha20Legacy::new(key.into(), &(seq as u64).to_be_bytes().into());
+    cipher.seek(block * 64);
+    cipher.apply_keystream(data);
+}
+
+fn poly1305_tag(main_key: &[u8; 32], seq: u32, data: &[u8]) -> [u8; 16] {
+    use poly1305::universal_hash::KeyInit;
+    let mut poly_key = [0; 32];
+    chacha(main_key, seq, 0, &mut poly_key);
+    poly1305::Poly1305::new(&poly_key.into()).compute_unpadded(data).into()
+}
+
+pub struct Server {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    pub client_version: Vec<u8>,
+    recv_seq: u32,
+    send_seq: u32,
+    decrypt: Option<Direction>,
+    encrypt: Option<Direction>,
+    next_channel: u32,
+    /// encryption algorithm advertised and used after kex
+    pub cipher: &'static str,
+    /// MAC algorithm advertised and used after kex
+    pub mac: &'static str,
+    /// compression algorithm advertised and used after kex (`zlib`)
+    /// or authentication (`zlib@openssh.com`)
+    pub compression: &'static str,
+    compress: Option<flate2::Compress>,
+    decompress: Option<flate2::Decompress>,
+    /// exchange hash of the first key exchange
+    session_id: Option<Vec<u8>>,
+    /// window given to the client in `accept_channel`
+    pub initial_window: u32,
+    /// window and maximum packet size of the last channel accepted
+    pub client_window: (u32, u32),
+    /// flips a bit of the MAC of the next packet sent
+    pub corrupt_next_mac: bool,
+}
+
+impl Server {
+    pub fn accept(listener: &TcpListener) -> Self {
+        Self::new(listener.accept().unwrap().0)
+    }
+
+    fn new(stream: TcpStream) -> Self {
+        Self::greeting(stream, &[SERVER_VERSION, b"\r\n"].concat())
+    }
+
+    /// Same as [`Self::accept`], sending `greeting` instead of the version
+    /// line; it must end with the version, terminated by CR LF or LF
+    pub fn accept_greeting(listener: &TcpListener, greeting: &[u8]) -> Self {
+        Self::greeting(listener.accept().unwrap().0, greeting)
+    }
+
+    fn greeting(stream: TcpStream, greeting: &[u8]) -> Self {
+        let mut writer = stream.try_clone().unwrap();
+        writer.write_all(greeting).unwrap();
+
+        let mut reader = BufReader::new(stream);
+        let mut line = Vec::new();
+        reader.read_until(b'\n', &mut line).unwrap();
+        assert!(line.ends_with(b"\r\n"));
+        line.truncate(line.len() - 2);
+
+        Self {
+            reader,
+            writer,
+            client_version: line,
+            recv_seq: 0,
+            send_seq: 0,
+            decrypt: None,
+            encrypt: None,
+            next_channel: SERVER_CHANNEL,
+            cipher: "aes256-ctr",
+            mac: "hmac-sha2-256",
+            compression: "none",
+            compress: None,
+            decompress: None,
+            session_id: None,
+            initial_window: 0x200000,
+            client_window: (0, 0),
+            corrupt_next_mac: false,
+        }
+    }
+
+    pub fn stream(&self) -> &TcpStream {
+        &self.writer
+    }
+
+    pub fn send(&mut self, payload: &[u8]) {
+        let compressed;
+        let payload = match &mut self.compress {
+            Some(compress) => {
+                compressed = zlib(payload, |input, output| {
+                    compress.compress_vec(input, output, flate2::FlushCompress::Sync).unwrap();
+                });
+                &compressed
+            },
+            None => payload,
+        };
+
+        let (block_size, aligned) = match self.encrypt {
+            None => (8, 5 + payload.len()),
+            Some(Direction::AesCtr { mac, .. }) if is_etm(mac) => (16, 1 + payload.len()),
+            Some(Direction::AesCtr { .. }) => (16, 5 + payload.len()),
+            Some(Direction::ChaChaPoly { .. }) => (8, 1 + payload.len()),
+            Some(Direction::AesGcm { .. }) => (16, 1 + payload.len()),
+        };
+
+        let mut padding = block_size - aligned % block_size;
+        if padding < 4 {
+            padding += block_size;
+        }
+
+        let mut packet = Vec::new();
+        packet.extend_from_slice(&((1 + payload.len() + padding) as u32).to_be_bytes());
+        packet.push(padding as u8);
+        packet.extend_from_slice(payload);
+        packet.resize(packet.len() + padding, 0);
+
+        match &mut self.encrypt {
+            Some(Direction::AesCtr { cipher, mac, mac_key }) => {
+                let etm = is_etm(mac);
+                if etm {
+                    cipher.apply_keystream(&mut packet[4..]);
+                }
+
+                let tag = hmac(mac, mac_key, &[&self.send_seq.to_be_bytes(), &packet]);
+                if !etm {
+                    cipher.apply_keystream(&mut packet);
+                }
+
+                packet.extend_from_slice(&tag);
+            },
+            Some(Direction::ChaChaPoly { main_key, header_key }) => {
+                chacha(header_key, self.send_seq, 0, &mut packet[..4]);
+                chacha(main_key, self.send_seq, 1, &mut packet[4..]);
+                let tag = poly1305_tag(main_key, self.send_seq, &packet);
+                packet.extend_from_slice(&tag);
+            },
+            Some(Direction::AesGcm { key, iv }) => {
+                let tag = aes_gcm(key, iv, &mut packet, None);
+                packet.extend_from_slice(&tag);
+            },
+            None => (),
+        }
+
+        if core::mem::take(&mut self.corrupt_next_mac) {
+            *packet.last_mut().unwrap() ^= 1;
+        }
+
+        self.send_seq = self.send_seq.wrapping_add(1);
+        self.writer.write_all(&packet).unwrap();
+    }
+
+    /// Receives the next packet, checking its MAC and padding
+    pub fn recv(&mut self) -> Vec<u8> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length).unwrap();
+        let mut clear_length = length;
+        match &mut self.decrypt {
+            Some(Direction::AesCtr { mac, .. }) if is_etm(mac) => (),
+            Some(Direction::AesCtr { cipher, .. }) => cipher.apply_keystream(&mut clear_length),
+            Some(Direction::ChaChaPoly { header_key, .. }) => chacha(header_key, self.recv_seq, 0, &mut clear_length),
+            Some(Direction::AesGcm { .. }) | None => (),
+        }
+
+        let mut packet = length.to_vec();
+        packet.resize(4 + u32::from_be_bytes(clear_length) as usize, 0);
+        self.reader.read_exact(&mut packet[4..]).unwrap();
+
+        match &mut self.decrypt {
+            Some(Direction::AesCtr { cipher, mac, mac_key }) => {
+                let etm = is_etm(mac);
+                let mut tag = vec![0; hmac(mac, mac_key, &[]).len()];
+                self.reader.read_exact(&mut tag).unwrap();
+                if etm {
+                    assert_eq!(tag, hmac(mac, mac_key, &[&self.recv_seq.to_be_bytes(), &packet]), "bad client MAC");
+                }
+
+                packet[..4].copy_from_slice(&clear_length);
+                cipher.apply_keystream(&mut packet[4..]);
+                if !etm {
+                    assert_eq!(tag, hmac(mac, mac_key, &[&self.recv_seq.to_be_bytes(), &packet]), "bad client MAC");
+                }
+
+                let aligned = if etm { &packet[4..] } else { &packet[..] };
+                assert_eq!(aligned.len() % 16, 0, "misaligned packet");
+            },
+            Some(Direction::ChaChaPoly { main_key, .. }) => {
+                let mut tag = [0; 16];
+                self.reader.read_exact(&mut tag).unwrap();
+                assert_eq!(tag, poly1305_tag(main_key, self.recv_seq, &packet), "bad client MAC");
+                packet[..4].copy_from_slice(&clear_length);
+                chacha(main_key, self.recv_seq, 1, &mut packet[4..]);
+                assert_eq!((packet.len() - 4) % 8, 0, "misaligned packet");
+            },
+            Some(Direction::AesGcm { key, iv }) => {
+                let mut tag = [0; 16];
+                self.reader.read_exact(&mut tag).unwrap();
+                aes_gcm(key, iv, &mut packet, Some(&tag));
+                assert_eq!((packet.len() - 4) % 16, 0, "misaligned packet");
+            },
+            None => assert_eq!(packet.len() % 8, 0, "misaligned packet"),
+        }
+
+        self.recv_seq = self.recv_seq.wrapping_add(1);
+        let padding = packet[4] as usize;
+        assert!(padding >= 4, "padding is too short");
+        let payload = &packet[5..packet.len() - padding];
+        match &mut self.decompress {
+            Some(decompress) => zlib(payload, |input, output| {
+                decompress.decompress_vec(input, output, flate2::FlushDecompress::Sync).unwrap();
+            }),
+            None => payload.to_vec(),
+        }
+    }
+
+    /// The streams carry on through key exchanges
+    fn enable_compression(&mut self) {
+        self.compress.get_or_insert_with(|| flate2::Compress::new(flate2::Compression::best(), true));
+        self.decompress.get_or_insert_with(|| flate2::Decompress::new(true));
+    }
+
+    /// Receives the next packet and checks its message number
+    pub fn expect(&mut self, number: u8) -> Vec<u8> {
+        let payload = self.recv();
+        assert_eq!(payload[0], number, "unexpected message: {:?}", payload);
+        payload
+    }
+
+    /// Switches to `self.cipher` in both directions
+    pub fn enable_encryption(&mut self, shared_secret: &[u8], exchange_hash: &[u8], session_id: &[u8]) {
+        // RFC 4253, section 7.2
+        let derive = |magic| {
+            let mut hasher = Sha256::new();
+            hasher.update(mpint(shared_secret));
+            hasher.update(exchange_hash);
+            hasher.update([magic]);
+            hasher.update(session_id);
+            let first: [u8; 32] = hasher.finalize().into();
+
+            let mut hasher = Sha256::new();
+            hasher.update(mpint(shared_secret));
+            hasher.update(exchange_hash);
+            hasher.update(first);
+            let second: [u8; 32] = hasher.finalize().into();
+
+            let mut key = [0; 64];
+            key[..32].copy_from_slice(&first);
+            key[32..].copy_from_slice(&second);
+            key
+        };
+
+        let (cipher, mac_algorithm) = (self.cipher, self.mac);
+        let direction = |iv: u8, key: u8, mac: u8| {
+            let key = derive(key);
+            let half = |range: core::ops::Range<usize>| -> [u8; 32] { key[range].try_into().unwrap() };
+            match cipher {
+                "chacha20-poly1305@openssh.com" => Direction::ChaChaPoly {
+                    main_key: half(0..32),
+                    header_key: half(32..64),
+                },
+                "aes128-gcm@openssh.com" | "aes256-gcm@openssh.com" => Direction::AesGcm {
+                    key: key[..if cipher.starts_with("aes128") { 16 } else { 32 }].to_vec(),
+                    iv: derive(iv)[..12].try_into().unwrap(),
+                },
+                _ => {
+                    let iv: [u8; 16] = derive(iv)[..16].try_into().unwrap();
+                    let cipher: Cipher = match cipher {
+                        "aes128-ctr" => Box::new(ctr::Ctr64BE::<aes::Aes128>::new(key[..16].into(), &iv.into())),
+                        "aes192-ctr" => Box::new(ctr::Ctr64BE::<aes::Aes192>::new(key[..24].into(), &iv.into())),
+                        _ => Box::new(ctr::Ctr64BE::<aes::Aes256>::new(&half(0..32).into(), &iv.into())),
+                    };
+                    Direction::AesCtr {
+                        cipher,
+                        mac: mac_algorithm,
+                        mac_key: derive(mac),
+                    }
+                },
+            }
+        };
+
+        self.decrypt = Some(direction(b'A', b'C', b'E'));
+        self.encrypt = Some(direction(b'B', b'D', b'F'));
+
+        if self.compression == "zlib" {
+            self.enable_compression();
+        }
+    }
+
+    /// Runs a curve25519-sha256 key exchange and enables encryption;
+    /// returns the exchange hash.
+    pub fn key_exchange(&mut self, host_key: &HostKey) -> Vec<u8> {
+        let algorithms = [self.cipher, self.mac, self.compression];
+        let server_kexinit = kexinit_with("curve25519-sha256", host_key.algorithm(), algorithms);
+        self.key_exchange_advertising(host_key, server_kexinit)
+    }
+
+    /// Same as [`Self::key_exchange`], but the server sends its KEXINIT first
+    pub fn initiate_key_exchange(&mut self, host_key: &HostKey) -> Vec<u8> {
+        let algorithms = [self.cipher, self.mac, self.compression];
+        let server_kexinit = kexinit_with("curve25519-sha256", host_key.algorithm(), algorithms);
+        self.send(&server_kexinit);
+        let client_kexinit = self.expect(20);
+        self.finish_key_exchange(host_key, client_kexinit, server_kexinit)
+    }
+
+    /// Same as [`Self::key_exchange`], sending `server_kexinit`,
+    /// which must lead to curve25519-sha256 with `host_key`
+    pub fn key_exchange_advertising(&mut self, host_key: &HostKey, server_kexinit: Vec<u8>) -> Vec<u8> {
+        let client_kexinit = self.expect(20);
+        self.send(&server_kexinit);
+        self.finish_key_exchange(host_key, client_kexinit, server_kexinit)
+    }
+
+    /// Same as [`Self::key_exchange_advertising`], sending `guessed` right
+    /// after `server_kexinit`, which sets `first_kex_packet_follows`
+    pub fn key_exchange_guessing(&mut self, host_key: &HostKey, server_kexinit: Vec<u8>, guessed: &[u8]) -> Vec<u8> {
+        let client_kexinit = self.expect(20);
+        self.send(&server_kexinit);
+        self.send(guessed);
+        self.finish_key_exchange(host_key, client_kexinit, server_kexinit)
+    }
+
+    /// Runs a key exchange up to the server's reply, for clients which
+    /// should reject `host_key`
+    pub fn offer_host_key(&mut self, host_key: &HostKey) {
+        let algorithms = [self.cipher, self.mac, self.compression];
+        let server_kexinit = kexinit_with("curve25519-sha256", host_key.algorithm(), algorithms);
+        let client_kexinit = self.expect(20);
+        self.send(&server_kexinit);
+        self.send_kex_reply(host_key, client_kexinit, server_kexinit);
+    }
+
+    fn finish_key_exchange(&mut self, host_key: &HostKey, client_kexinit: Vec<u8>, server_kexinit: Vec<u8>) -> Vec<u8> {
+        let (shared_secret, exchange_hash) = self.send_kex_reply(host_key, client_kexinit, server_kexinit);
+
+        self.expect(21);
+        self.send(&[21]);
+        let session_id = self.session_id.get_or_insert_with(|| exchange_hash.clone()).clone();
+        self.enable_encryption(shared_secret.as_bytes(), &exchange_hash, &session_id);
+
+        exchange_hash
+    }
+
+    /// Answers the client's KEX_ECDH_INIT; returns the shared secret and the exchange hash
+    fn send_kex_reply(&mut self, host_key: &HostKey, client_kexinit: Vec<u8>, server_kexinit: Vec<u8>) -> (x25519_dalek::SharedSecret, Vec<u8>) {
+
+        let init = self.expect(30);
+        let client_public: [u8; 32] = take_string(&init, &mut 1).try_into().unwrap();
+
+        let secret = x25519_dalek::EphemeralSecret::new(rand_core::OsRng);
+        let server_public = x25519_dalek::PublicKey::from(&secret);
+        let shared_secret = secret.diffie_hellman(&client_public.into());
+
+        let mut hashed = Vec::new();
+        hashed.extend(string(&self.client_version));
+        hashed.extend(string(SERVER_VERSION));
+        hashed.extend(string(&client_kexinit));
+        hashed.extend(string(&server_kexinit));
+        hashed.extend(string(&host_key.blob()));
+        hashed.extend(string(&client_public));
+        hashed.extend(string(server_public.as_bytes()));
+        hashed.extend(mpint(shared_secret.as_bytes()));
+        let exchange_hash = Sha256::digest(&hashed).to_vec();
+
+        let mut reply = vec![31];
+        reply.extend(string(&host_key.blob()));
+        reply.extend(string(server_public.as_bytes()));
+        reply.extend(string(&host_key.sign(&exchange_hash)));
+        self.send(&reply);
+
+        (shared_secret, exchange_hash)
+    }
+
+    /// Accepts the `ssh-userauth` service
+    pub fn accept_service(&mut self) {
+        let request = self.expect(5);
+        assert_eq!(take_string(&request, &mut 1), b"ssh-userauth");
+        let mut accept = vec![6];
+        accept.extend(string(b"ssh-userauth"));
+        self.send(&accept);
+    }
+
+    /// Accepts the `ssh-userauth` service and any credentials
+    pub fn accept_auth(&mut self) {
+        self.accept_service();
+        self.accept_auth_requests();
+    }
+
+    /// Accepts any credentials
+    pub fn accept_auth_requests(&mut self) {
+        loop {
+            let request = self.expect(50);
+            let mut i = 1;
+            let _username = take_string(&request, &mut i);
+            let _service = take_string(&request, &mut i);
+            let method = take_string(&request, &mut i).to_vec();
+            let has_signature = request[i] != 0;
+            i += 1;
+
+            if method == b"publickey" && !has_signature {
+                let algorithm = take_string(&request, &mut i);
+                let blob = take_string(&request, &mut i);
+                let mut pk_ok = vec![60];
+                pk_ok.extend(string(algorithm));
+                pk_ok.extend(string(blob));
+                self.send(&pk_ok);
+            } else {
+                self.send(&[52]);
+                if self.compression == "zlib@openssh.com" {
+                    self.enable_compression();
+                }
+                break;
+            }
+        }
+    }
+
+    /// Rejects the next authentication request; returns its method name
+    pub fn reject_auth(&mut self, allowed_auth: &str) -> Vec<u8> {
+        self.fail_auth(allowed_auth, false)
+    }
+
+    /// Like `reject_auth`, but with `partial_success` set: the method
+    /// succeeded and one of `allowed_auth` is required as well
+    pub fn fail_auth(&mut self, allowed_auth: &str, partial_success: bool) -> Vec<u8> {
+        let request = self.expect(50);
+        let mut i = 1;
+        let _username = take_string(&request, &mut i);
+        let _service = take_string(&request, &mut i);
+        let method = take_string(&request, &mut i).to_vec();
+
+        let mut failure = vec![51];
+        failure.extend(string(allowed_auth.as_bytes()));
+        failure.push(partial_success as u8);
+        self.send(&failure);
+        method
+    }
+
+    /// Key exchange + authentication
+    pub fn accept_session(&mut self) {
+        self.key_exchange(&HostKey::new());
+        self.accept_auth();
+    }
+
+    /// Accepts a ChannelOpen; returns (client channel, server channel)
+    pub fn accept_channel(&mut self) -> (u32, u32) {
+        let open = self.expect(90);
+        let mut i = 1;
+        assert_eq!(take_string(&open, &mut i), b"session");
+        let client_channel = take_u32(&open, &mut i);
+        self.client_window = (take_u32(&open, &mut i), take_u32(&open, &mut i));
+
+        let server_channel = self.next_channel;
+        self.next_channel += 1;
+
+        let mut confirmation = vec![91];
+        confirmation.extend_from_slice(&client_channel.to_be_bytes());
+        confirmation.extend_from_slice(&server_channel.to_be_bytes());
+        confirmation.extend_from_slice(&self.initial_window.to_be_bytes());
+        confirmation.extend_from_slice(&0x8000u32.to_be_bytes());
+        self.send(&confirmation);
+
+        (client_channel, server_channel)
+    }
+
+    /// Receives a ChannelRequest of type `name`; returns the
+    /// request-specific data
+    pub fn expect_channel_request(&mut self, name: &str) -> Vec<u8> {
+        let request = self.expect(98);
+        let mut i = 1;
+        let _recipient = take_u32(&request, &mut i);
+        assert_eq!(take_string(&request, &mut i), name.as_bytes());
+        // want_reply
+        i += 1;
+        request[i..].to_vec()
+    }
+
+    pub fn channel_success(&mut self, client_channel: u32) {
+        let mut success = vec![99];
+        success.extend_from_slice(&client_channel.to_be_bytes());
+        self.send(&success);
+    }
+
+    pub fn channel_failure(&mut self, client_channel: u32) {
+        let mut failure = vec![100];
+        failure.extend_from_slice(&client_channel.to_be_bytes());
+        self.send(&failure);
+    }
+
+    pub fn send_data(&mut self, client_channel: u32, data: &[u8]) {
+        let mut message = vec![94];
+        message.extend_from_slice(&client_channel.to_be_bytes());
+        message.extend(string(data));
+        self.send(&message);
+    }
+
+    pub fn send_stderr(&mut self, client_channel: u32, data: &[u8]) {
+        self.send_ext_data(client_channel, 1, data);
+    }
+
+    /// Sends ChannelExtendedData of any type
+    pub fn send_ext_data(&mut self, client_channel: u32, data_type: u32, data: &[u8]) {
+        let mut message = vec![95];
+        message.extend_from_slice(&client_channel.to_be_bytes());
+        message.extend_from_slice(&data_type.to_be_bytes());
+        message.extend(string(data));
+        self.send(&message);
+    }
+
+    /// Receives ChannelData; returns its content
+    pub fn recv_data(&mut self) -> Vec<u8> {
+        let message = self.expect(94);
+        let mut i = 1;
+        let _recipient = take_u32(&message, &mut i);
+        take_string(&message, &mut i).to_vec()
+    }
+
+    pub fn send_exit_status(&mut self, client_channel: u32, status: u32) {
+        let mut message = vec![98];
+        message.extend_from_slice(&client_channel.to_be_bytes());
+        message.extend(string(b"exit-status"));
+        message.push(0);
+        message.extend_from_slice(&status.to_be_bytes());
+        self.send(&message);
+    }
+
+    pub fn send_exit_signal(&mut self, client_channel: u32, signal: &str, core_dumped: bool, message: &str) {
+        let mut request = vec![98];
+        request.extend_from_slice(&client_channel.to_be_bytes());
+        request.extend(string(b"exit-signal"));
+        request.push(0);
+        request.extend(string(signal.as_bytes()));
+        request.push(core_dumped as u8);
+        request.extend(string(message.as_bytes()));
+        request.extend(string(b""));
+        self.send(&request);
+    }
+
+    pub fn send_window_adjust(&mut self, client_channel: u32, bytes: u32) {
+        let mut message = vec![93];
+        message.extend_from_slice(&client_channel.to_be_bytes());
+        message.extend_from_slice(&bytes.to_be_bytes());
+        self.send(&message);
+    }
+
+    pub fn send_eof(&mut self, client_channel: u32) {
+        let mut message = vec![96];
+        message.extend_from_slice(&client_channel.to_be_bytes());
+        self.send(&message);
+    }
+
+    pub fn send_close(&mut self, client_channel: u32) {
+        let mut message = vec![97];
+        message.extend_from_slice(&client_channel.to_be_bytes());
+        self.send(&message);
+    }
+}
+
+/// Runs a zlib stream over `input`, growing the output as needed
+fn zlib(input: &[u8], mut step: impl FnMut(&[u8], &mut Vec<u8>)) -> Vec<u8> {
+    let mut output = Vec::with_capacity(input.len() * 4 + 64);
+    step(input, &mut output);
+    assert!(output.len() < output.capacity(), "zlib output is too big");
+    output
+}
+
+fn is_etm(mac: &str) -> bool {
+    mac.ends_with("-etm@openssh.com")
+}
+
+/// Computes the MAC of the concatenated `parts` with the `mac` algorithm
+pub fn hmac(mac: &str, key: &[u8; 64], parts: &[&[u8]]) -> Vec<u8> {
+    use hmac::{Hmac, Mac};
+
+    fn compute<M: Mac + hmac::digest::KeyInit>(key: &[u8], parts: &[&[u8]]) -> Vec<u8> {
+        let mut hmac = <M as hmac::digest::KeyInit>::new_from_slice(key).unwrap();
+        for part in parts {
+            hmac.update(part);
+        }
+        hmac.finalize().into_bytes().to_vec()
+    }
+
+    match mac.trim_end_matches("-etm@openssh.com") {
+        "hmac-sha2-256" => compute::<Hmac<Sha256>>(&key[..32], parts),
+        "hmac-sha2-512" => compute::<Hmac<sha2::Sha512>>(key, parts),
+        "hmac-sha1" => compute::<Hmac<sha1::Sha1>>(&key[..20], parts),
+        other => panic!("unsupported MAC: {}", other),
+    }
+}
+
+pub fn string(bytes: &[u8]) -> Vec<u8> {
+    let mut out = (bytes.len() as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(bytes);
+    out
+}
+
+pub fn mpint(unsigned: &[u8]) -> Vec<u8> {
+    let start = unsigned.iter().position(|b| *b != 0).unwrap_or(unsigned.len());
+    let mut bytes = unsigned[start..].to_vec();
+    if bytes.first().map(|b| b & 0x80 != 0).unwrap_or(false) {
+        bytes.insert(0, 0);
+    }
+    string(&bytes)
+}
+
+/// Reads an SSH string at `*offset`
+pub fn take_string<'a>(payload: &'a [u8], offset: &mut usize) -> &'a [u8] {
+    let len = u32::from_be_bytes(payload[*offset..][..4].try_into().unwrap()) as usize;
+    let string = &payload[*offset + 4..][..len];
+    *offset += 4 + len;
+    string
+}
+
+pub fn take_u32(payload: &[u8], offset: &mut usize) -> u32 {
+    let value = u32::from_be_bytes(payload[*offset..][..4].try_into().unwrap());
+    *offset += 4;
+    value
+}
+
+pub fn take_u64(payload: &[u8], offset: &mut usize) -> u64 {
+    let value = u64::from_be_bytes(payload[*offset..][..8].try_into().unwrap());
+    *offset += 8;
+    value
+}
+
+pub fn kexinit(kex: &str, host_key: &str) -> Vec<u8> {
+    kexinit_with(kex, host_key, ["aes256-ctr", "hmac-sha2-256", "none"])
+}
+
+/// `algorithms` are the cipher, MAC and compression
+/// lists, used for both directions
+pub fn kexinit_with(kex: &str, host_key: &str, algorithms: [&str; 3]) -> Vec<u8> {
+    let [cipher, mac, compression] = algorithms;
+    let mut payload = vec![20];
+    payload.extend_from_slice(&[0x42; 16]);
+    for list in [kex, host_key, cipher, cipher, mac, mac, compression, compression, "", ""] {
+        payload.extend(string(list.as_bytes()));
+    }
+    payload.push(0);
+    payload.extend_from_slice(&[0; 4]);
+    payload
+}
+
+/// 2048-bit RSA test key
+const RSA_MODULUS: &str = concat!(
+    "89c58462e3e935c55a2436a628aa994ee1c0544326522432e3d3bacf92ed4a72",
+    "4b027d8fe5d38afbdfc4fe5004e91720b1c25a8e4435ab11ea708dd60606035d",
+    "b942fb1b5dc0af04c82061f265dab0a67577ff7dd0c53045b571302ca8a0c3ce",
+    "e0c6c3114025ebd276388a739b86eb68c40d42e9766eb5190c353fe7b1ecf0f4",
+    "e81a0b351f953a2b7ba76e44e48a7bda7309637404958ef2117687746f81ac25",
+    "96a11f9de74cd46e563583a9187ec4bc8ebeb8fa1a057e42526a82839ec27db4",
+    "1ceef0f917bda6f5088f8c982c6289f3efd9942a2a7593ce1f6982c07d632f85",
+    "4dc5e5e72a3fd4dcbff0e3763321f862096fcb59bfa187940ef3fc5861178b73",
+);
+
+const RSA_PRIVATE_EXPONENT: &str = concat!(
+    "9c035d0105aff84dd0dfcb02f80a3891ce79e424f359de3dc2c14e7e5aca289d",
+    "7820a6a966e60dca2c83017a35aef2682d4a9d0e4ac9486fe7bf5a7882f34c42",
+    "efd536b9e0576c40e45fc757d16621a4c52e37d7f545be1798e07ac6e494c2c1",
+    "9cc911e9c3d460b87427c63af13124ca653905a65b2a695040cfa5db3a87c9ed",
+    "7cb02735061f0227f989ffcac57ecbba53892a4d9a7b7bec637cee2d3357b1c5",
+    "5b850e9224eaef8fe24982922f8b4481ff4503b61bb41ef16f430b73bc7f6ad3",
+    "b89437560a0c1819d5b969c7f21ae825362879db01648538c8c329d3c1c4feba",
+    "c748e4bbc0a4c185780093348f51275994144d2258cbf85730f8586dc9e9d1",
+);
+
+pub enum HostKey {
+    Ed25519(ed25519_dalek::Keypair),
+    EcdsaP256(p256::ecdsa::SigningKey),
+    /// signs with rsa-sha2-256 or rsa-sha2-512
+    Rsa {
+        algorithm: &'static str,
+        modulus: BigUint,
+        private_exponent: BigUint,
+    },
+    /// ed25519 key with an OpenSSH certificate, see [`HostKey::certificate`]
+    Certificate {
+        keypair: ed25519_dalek::Keypair,
+        blob: Vec<u8>,
+    },
+}
+
+impl HostKey {
+    pub fn new() -> Self {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[7; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+        Self::Ed25519(ed25519_dalek::Keypair { secret, public })
+    }
+
+    pub fn ecdsa_p256() -> Self {
+        Self::EcdsaP256(p256::ecdsa::SigningKey::from_bytes(&[0x11; 32]).unwrap())
+    }
+
+    pub fn rsa(algorithm: &'static str) -> Self {
+        Self::Rsa {
+            algorithm,
+            modulus: BigUint::parse_bytes(RSA_MODULUS.as_bytes(), 16).unwrap(),
+            private_exponent: BigUint::parse_bytes(RSA_PRIVATE_EXPONENT.as_bytes(), 16).unwrap(),
+        }
+    }
+
+    /// Certifies an ed25519 key with `authority`; `cert_type`
+    /// is 1 for users and 2 for hosts
+    pub fn certificate(authority: &HostKey, cert_type: u32, principals: &[&str], validity: core::ops::Range<u64>) -> Self {
+        let secret = ed25519_dalek::SecretKey::from_bytes(&[9; 32]).unwrap();
+        let public = ed25519_dalek::PublicKey::from(&secret);
+
+        let mut packed_principals = Vec::new();
+        for principal in principals {
+            packed_principals.extend(string(principal.as_bytes()));
+        }
+
+        let mut blob = string(b"ssh-ed25519-cert-v01@openssh.com");
+        blob.extend(string(&[0x33; 32]));
+        blob.extend(string(public.as_bytes()));
+        blob.extend_from_slice(&1u64.to_be_bytes());
+        blob.extend_from_slice(&cert_type.to_be_bytes());
+        blob.extend(string(b"test certificate"));
+        blob.extend(string(&packed_principals));
+        blob.extend_from_slice(&validity.start.to_be_bytes());
+        blob.extend_from_slice(&validity.end.to_be_bytes());
+        blob.extend(string(b""));
+        blob.extend(string(b""));
+        blob.extend(string(b""));
+        blob.extend(string(&authority.blob()));
+        let signature = authority.sign(&blob);
+        blob.extend(string(&signature));
+
+        Self::Certificate {
+            keypair: ed25519_dalek::Keypair { secret, public },
+            blob,
+        }
+    }
+
+    /// The 128-character hex-encoded keypair of an ed25519 key or certificate
+    pub fn hex_keypair(&self) -> String {
+        match self {
+            Self::Ed25519(keypair) | Self::Certificate { keypair, .. } => {
+                keypair.to_bytes().iter().map(|byte| format!("{:02x}", byte)).collect()
+            },
+            _ => panic!("not an ed25519 key"),
+        }
+    }
+
+    /// The host key algorithm to advertise
+    pub fn algorithm(&self) -> &'static str {
+        match self {
+            Self::Ed25519(_) => "ssh-ed25519",
+            Self::Certificate { .. } => "ssh-ed25519-cert-v01@openssh.com",
+            Self::EcdsaP256(_) => "ecdsa-sha2-nistp256",
+            Self::Rsa { algorithm, .. } => algorithm,
+        }
+    }
+
+    pub fn blob(&self) -> Vec<u8> {
+        match self {
+            Self::Ed25519(keypair) => {
+                let mut blob = string(b"ssh-ed25519");
+                blob.extend(string(keypair.public.as_bytes()));
+                blob
+            },
+            Self::EcdsaP256(key) => {
+                let mut blob = string(b"ecdsa-sha2-nistp256");
+                blob.extend(string(b"nistp256"));
+                blob.extend(string(key.verifying_key().to_encoded_point(false).as_bytes()));
+                blob
+            },
+            Self::Rsa { modulus, .. } => {
+                let mut blob = string(b"ssh-rsa");
+                blob.extend(mpint(&[1, 0, 1]));
+                blob.extend(mpint(&modulus.to_bytes_be()));
+                blob
+            },
+            Self::Certificate { blob, .. } => blob.clone(),
+        }
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Vec<u8> {
+        let algorithm = match self {
+            Self::Certificate { .. } => "ssh-ed25519",
+            _ => self.algorithm(),
+        };
+
+        let mut blob = string(algorithm.as_bytes());
+        match self {
+            Self::Ed25519(keypair) | Self::Certificate { keypair, .. } => {
+                use ed25519_dalek::Signer;
+                blob.extend(string(&keypair.sign(data).to_bytes()));
+            },
+            Self::EcdsaP256(key) => {
+                use p256::ecdsa::signature::Signer;
+                let signature: p256::ecdsa::Signature = key.sign(data);
+                let (r, s) = signature.split_bytes();
+                let mut scalars = mpint(&r);
+                scalars.extend(mpint(&s));
+                blob.extend(string(&scalars));
+            },
+            Self::Rsa { algorithm, modulus, private_exponent } => {
+                // EMSA-PKCS1-v1_5 (RFC 8017, section 9.2)
+                let (digest_info, digest): (&[u8], Vec<u8>) = match *algorithm {
+                    "rsa-sha2-256" => (&[
+                        0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+                        0x65, 0x03, 0x04, 0x02, 0x01, 0x05, 0x00, 0x04, 0x20,
+                    ], Sha256::digest(data).to_vec()),
+                    _ => (&[
+                        0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01,
+                        0x65, 0x03, 0x04, 0x02, 0x03, 0x05, 0x00, 0x04, 0x40,
+                    ], sha2::Sha512::digest(data).to_vec()),
+                };
+
+                let len = modulus.to_bytes_be().len();
+                let mut encoded = vec![0, 1];
+                encoded.resize(len - digest_info.len() - digest.len() - 1, 0xff);
+                encoded.push(0);
+                encoded.extend_from_slice(digest_info);
+                encoded.extend_from_slice(&digest);
+
+                let signature = BigUint::from_bytes_be(&encoded).modpow(private_exponent, modulus);
+                let mut signature = signature.to_bytes_be();
+                while signature.len() < len {
+                    signature.insert(0, 0);
+                }
+
+                blob.extend(string(&signature));
+            },
+        }
+        blob
+    }
+}
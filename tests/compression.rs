@@ -0,0 +1,79 @@
+#![cfg(feature = "zlib")]
+
+mod common;
+
+use coolssh::{Connection, ConnectOptions, RunResult};
+use common::*;
+
+/// Runs a command over a connection compressed with `compression`
+fn run_with(compression: &'static str) {
+    let output: Vec<u8> = b"compressible ".repeat(4000);
+    let expected = output.clone();
+
+    let (received, algorithms) = scripted(|stream| {
+        let options = ConnectOptions::default().compression(true);
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        let algorithms = conn.algorithms();
+        let RunResult::Accepted((received, _)) = conn.quick_run_bytes("cat data").unwrap() else {
+            panic!("exec refused");
+        };
+        (received, algorithms)
+    }, move |mut server| {
+        server.compression = compression;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat data"));
+        server.channel_success(channel);
+
+        for chunk in output.chunks(0x4000) {
+            server.send_data(channel, chunk);
+        }
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(algorithms.compression_client_to_server, compression);
+    assert_eq!(algorithms.compression_server_to_client, compression);
+    assert!(received == expected);
+}
+
+#[test]
+fn zlib() {
+    run_with("zlib");
+}
+
+#[test]
+fn delayed_zlib() {
+    run_with("zlib@openssh.com");
+}
+
+#[test]
+fn full_size_packets_are_decompressed() {
+    let received = scripted(|stream| {
+        let options = ConnectOptions::default().compression(true).max_packet_length(1024 * 1024);
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        conn.set_channel_window(u32::MAX, 512 * 1024).unwrap();
+        let RunResult::Accepted((received, _)) = conn.quick_run_bytes("cat data").unwrap() else {
+            panic!("exec refused");
+        };
+        received
+    }, |mut server| {
+        server.compression = "zlib";
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        // larger than the default maximum packet length once decompressed
+        let max_packet_size = server.client_window.1 as usize;
+        server.send_data(channel, &vec![b'0'; max_packet_size]);
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(received.len(), 512 * 1024);
+}
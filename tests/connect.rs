@@ -0,0 +1,67 @@
+mod common;
+
+use std::net::TcpListener;
+use std::time::Duration;
+use coolssh::{Connection, ConnectOptions, ErrorKind, RunResult, RunEvent};
+use common::*;
+
+#[test]
+fn connect_configures_socket() {
+    let (nodelay, timeout) = scripted_at(|addr| {
+        let options = ConnectOptions::default()
+            .connect_timeout(Some(Duration::from_secs(5)))
+            .read_timeout(Some(Duration::from_millis(200)));
+
+        let mut conn = Connection::connect(("localhost", addr.port()), ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        let settings = std::cell::Cell::new(None);
+        conn.mutate_stream(|stream| settings.set(Some((stream.nodelay().unwrap(), stream.read_timeout().unwrap()))));
+        settings.get().unwrap()
+    }, |mut server| {
+        server.accept_session();
+    });
+
+    assert!(nodelay);
+    assert_eq!(timeout, Some(Duration::from_millis(200)));
+}
+
+#[test]
+fn socket_settings_after_connecting() {
+    let (settings, event) = scripted(|stream| {
+        let conn = connect(stream.try_clone().unwrap());
+        conn.set_nodelay(true).unwrap();
+        conn.set_write_timeout(Some(Duration::from_secs(1))).unwrap();
+        conn.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let settings = (
+            stream.nodelay().unwrap(),
+            stream.write_timeout().unwrap(),
+            conn.read_timeout().unwrap(),
+        );
+
+        let RunResult::Accepted(mut run) = conn.run("sleep 100", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let event = run.poll().map(|event| event == RunEvent::None).map_err(|e| e.kind());
+        (settings, event)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(settings, (true, Some(Duration::from_secs(1)), Some(Duration::from_millis(20))));
+    assert_eq!(event, Ok(true));
+}
+
+#[test]
+fn connect_refused() {
+    let addr = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    };
+
+    let result = Connection::connect(addr, ("user", CLIENT_HEX_KEYPAIR).into(), ConnectOptions::default());
+    assert!(matches!(result.map_err(|e| e.kind()), Err(ErrorKind::TcpError(_))));
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use coolssh::{Connection, ConnectOptions, RunResult, ExitStatus};
+use common::*;
+
+#[test]
+fn send_ignore_and_debug() {
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    scripted(|stream| {
+        let conn = connect(stream);
+        conn.send_ignore(12).unwrap();
+        conn.send_debug("hello").unwrap();
+    }, move |mut server| {
+        server.accept_session();
+
+        let ignore = server.expect(2);
+        let mut i = 1;
+        let ignored = take_string(&ignore, &mut i).len();
+
+        let debug = server.expect(4);
+        let mut i = 2;
+        let message = String::from_utf8(take_string(&debug, &mut i).to_vec()).unwrap();
+        sender.send((ignored, (debug[1], message))).unwrap();
+    });
+
+    let (ignored, debug) = receiver.recv().unwrap();
+    assert_eq!(ignored, 12);
+    assert_eq!(debug, (0, "hello".into()));
+}
+
+#[test]
+fn debug_messages_are_reported() {
+    let messages = Arc::new(Mutex::new(Vec::new()));
+    let list = messages.clone();
+
+    let output = scripted(move |stream| {
+        let options = ConnectOptions::default().debug_callback(move |debug| {
+            list.lock().unwrap().push((debug.always_display, debug.message.to_string()));
+        });
+
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        conn.quick_run("echo done").unwrap()
+    }, |mut server| {
+        let debug = |always_display: bool, message: &str| {
+            let mut debug = vec![4, always_display as u8];
+            debug.extend(string(message.as_bytes()));
+            debug.extend(string(b""));
+            debug
+        };
+
+        server.send(&debug(true, "during authentication"));
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send(&debug(false, "during a run"));
+        server.send_data(channel, b"done\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, RunResult::Accepted(("done\n".into(), ExitStatus::Code(0))));
+    assert_eq!(*messages.lock().unwrap(), [
+        (true, "during authentication".to_string()),
+        (false, "during a run".to_string()),
+    ]);
+}
@@ -0,0 +1,64 @@
+mod common;
+
+use coolssh::{Connection, ConnectOptions, Destination, ErrorKind};
+use common::*;
+
+fn destination(username: Option<&str>, host: &str, port: u16) -> Destination {
+    Destination {
+        username: username.map(String::from),
+        host: host.into(),
+        port,
+    }
+}
+
+#[test]
+fn user_host_port() {
+    assert_eq!(Destination::parse("example.com").unwrap(), destination(None, "example.com", 22));
+    assert_eq!(Destination::parse("git@example.com").unwrap(), destination(Some("git"), "example.com", 22));
+    assert_eq!(Destination::parse("git@example.com:2222").unwrap(), destination(Some("git"), "example.com", 2222));
+    assert_eq!(Destination::parse("me@corp.example@bastion:2222").unwrap(), destination(Some("me@corp.example"), "bastion", 2222));
+}
+
+#[test]
+fn ipv6_literals() {
+    assert_eq!(Destination::parse("[2001:db8::1]:2222").unwrap(), destination(None, "2001:db8::1", 2222));
+    assert_eq!(Destination::parse("root@[::1]").unwrap(), destination(Some("root"), "::1", 22));
+    assert_eq!(Destination::parse("root@fe80::1%eth0").unwrap(), destination(Some("root"), "fe80::1%eth0", 22));
+}
+
+#[test]
+fn urls() {
+    assert_eq!(Destination::parse("ssh://example.com").unwrap(), destination(None, "example.com", 22));
+    assert_eq!(Destination::parse("ssh://git@example.com:2222/").unwrap(), destination(Some("git"), "example.com", 2222));
+    assert_eq!(Destination::parse("ssh://a%40b@[::1]:2222").unwrap(), destination(Some("a@b"), "::1", 2222));
+    assert_eq!(Destination::parse("ssh://git;fingerprint=ssh-ed25519-abc@example.com").unwrap(), destination(Some("git"), "example.com", 22));
+}
+
+#[test]
+fn invalid_destinations() {
+    for invalid in ["", "@example.com", "example.com:", "example.com:ssh", "example.com:65536", "[::1", "[::1]2222", "ssh://example.com/path", "ssh://a%4@example.com"] {
+        let error = Destination::parse(invalid).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", invalid);
+    }
+}
+
+#[test]
+fn display_round_trips() {
+    for text in ["example.com", "git@example.com:2222", "root@[::1]:2222", "[2001:db8::1]"] {
+        assert_eq!(Destination::parse(text).unwrap().to_string(), text);
+    }
+}
+
+#[test]
+fn connect_to_destination() {
+    let username = scripted_at(|addr| {
+        let destination = Destination::parse(&format!("user@{}", addr)).unwrap();
+        let username = destination.username.as_deref().unwrap();
+        Connection::connect(&destination, (username, CLIENT_HEX_KEYPAIR).into(), ConnectOptions::default()).map(drop).unwrap();
+        destination.username
+    }, |mut server| {
+        server.accept_session();
+    });
+
+    assert_eq!(username.as_deref(), Some("user"));
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use coolssh::{Connection, DisconnectReasonCode, ErrorKind};
+use common::*;
+
+fn expect_disconnect(server: &mut Server) -> (u32, Vec<u8>, Vec<u8>) {
+    let message = server.expect(1);
+    let mut i = 1;
+    let reason_code = take_u32(&message, &mut i);
+    let description = take_string(&message, &mut i).to_vec();
+    let language_tag = take_string(&message, &mut i).to_vec();
+    (reason_code, description, language_tag)
+}
+
+fn send_disconnect(server: &mut Server, reason_code: u32, description: &str) {
+    let mut message = vec![1];
+    message.extend_from_slice(&reason_code.to_be_bytes());
+    message.extend(string(description.as_bytes()));
+    message.extend(string(b"en"));
+    server.send(&message);
+}
+
+#[test]
+fn disconnect_on_drop() {
+    scripted(|stream| {
+        drop(connect(stream));
+    }, |mut server| {
+        server.accept_session();
+        assert_eq!(expect_disconnect(&mut server), (11, Vec::new(), Vec::new()));
+    });
+}
+
+#[test]
+fn disconnect_with_reason() {
+    scripted(|stream| {
+        let conn = connect(stream);
+        conn.disconnect(DisconnectReasonCode::AuthCancelledByUser, "bye").unwrap();
+    }, |mut server| {
+        server.accept_session();
+        assert_eq!(expect_disconnect(&mut server), (13, b"bye".to_vec(), Vec::new()));
+        // only once
+        let mut buf = [0];
+        assert_eq!(std::io::Read::read(&mut server.stream(), &mut buf).unwrap(), 0);
+    });
+}
+
+#[test]
+fn localized_disconnect() {
+    scripted(|stream| {
+        let conn = connect(stream);
+        conn.disconnect_localized(DisconnectReasonCode::ByApplication, "au revoir", "fr-FR").unwrap();
+    }, |mut server| {
+        server.accept_session();
+        assert_eq!(expect_disconnect(&mut server), (11, b"au revoir".to_vec(), b"fr-FR".to_vec()));
+    });
+}
+
+#[test]
+fn disconnected_by_server() {
+    let error = scripted(|stream| {
+        let conn = connect(stream);
+        conn.quick_run("reboot").unwrap_err()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        send_disconnect(&mut server, 11, "going down for maintenance");
+    });
+
+    assert_eq!(error.kind(), ErrorKind::Disconnected {
+        reason: DisconnectReasonCode::ByApplication,
+    });
+    assert_eq!(error.message(), Some("going down for maintenance"));
+}
+
+#[test]
+fn disconnected_during_authentication() {
+    let result = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).map(drop), |mut server| {
+        server.key_exchange(&HostKey::new());
+        server.expect(5);
+        send_disconnect(&mut server, 12, "too many users");
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::Disconnected {
+        reason: DisconnectReasonCode::TooManyConnections,
+    }));
+}
@@ -0,0 +1,95 @@
+mod common;
+
+use coolssh::{RunResult, RunEvent, ExitStatus};
+use common::*;
+
+#[test]
+fn quick_run_with_env() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        conn.quick_run_with_env("echo $LANG", &[("LANG", "C"), ("TZ", "UTC")]).unwrap()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+
+        let mut expected = string(b"LANG");
+        expected.extend(string(b"C"));
+        assert_eq!(server.expect_channel_request("env"), expected);
+        server.channel_success(channel);
+
+        let mut expected = string(b"TZ");
+        expected.extend(string(b"UTC"));
+        assert_eq!(server.expect_channel_request("env"), expected);
+        server.channel_success(channel);
+
+        assert_eq!(server.expect_channel_request("exec"), string(b"echo $LANG"));
+        server.channel_success(channel);
+
+        server.send_data(channel, b"C\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, RunResult::Accepted(("C\n".into(), ExitStatus::Code(0))));
+}
+
+#[test]
+fn refused_env() {
+    let (refused, output) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("echo $TZ", &[("LANG", "C"), ("TZ", "UTC")]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let refused = run.refused_env().to_vec();
+        let mut output = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => output.extend_from_slice(data),
+                RunEvent::Stopped(_) => break,
+                _ => (),
+            }
+        }
+
+        (refused, output)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("env");
+        server.expect_channel_request("env");
+        server.expect_channel_request("exec");
+
+        // OpenSSH only accepts what AcceptEnv lists
+        server.channel_success(channel);
+        server.channel_failure(channel);
+        // data sent right after the reply
+        server.channel_success(channel);
+        server.send_data(channel, b"\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(refused, ["TZ"]);
+    assert_eq!(output, b"\n");
+}
+
+#[test]
+fn refused_exec_closes_the_channel() {
+    let refused = scripted(|stream| {
+        let conn = connect(stream);
+        let refused = matches!(conn.run("forbidden", &[("LANG", "C")]).unwrap(), RunResult::Refused);
+        refused
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("env");
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.channel_failure(channel);
+        server.expect(97);
+    });
+
+    assert!(refused);
+}
@@ -0,0 +1,202 @@
+mod common;
+
+use std::time::Duration;
+use coolssh::{RunResult, RunEvent, ExitStatus, ExtendedDataType, ErrorKind};
+use common::*;
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Data(Vec<u8>),
+    Stderr(Vec<u8>),
+    ExtData(ExtendedDataType, Vec<u8>),
+    Killed(String, bool, String),
+    Stopped(ExitStatus),
+}
+
+#[test]
+fn killed_by_signal() {
+    let events = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("sleep 100", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut events = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => events.push(Event::Data(data.to_vec())),
+                RunEvent::Killed { signal, core_dumped, message } => {
+                    events.push(Event::Killed(signal.into(), core_dumped, message.into()));
+                },
+                RunEvent::Stopped(status) => {
+                    events.push(Event::Stopped(status));
+                    break events;
+                },
+                _ => (),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send_data(channel, b"zzz");
+        server.send_exit_signal(channel, "KILL", true, "killed by the OOM killer");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(events, [
+        Event::Data(b"zzz".to_vec()),
+        Event::Killed("KILL".into(), true, "killed by the OOM killer".into()),
+        Event::Stopped(ExitStatus::Signal("KILL".into())),
+    ]);
+}
+
+#[test]
+fn unknown_extended_data() {
+    let (events, output) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("vendor-tool", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut events = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => events.push(Event::Data(data.to_vec())),
+                RunEvent::ExtDataStderr(data) => events.push(Event::Stderr(data.to_vec())),
+                RunEvent::ExtData { kind, data } => events.push(Event::ExtData(kind, data.to_vec())),
+                RunEvent::Stopped(status) => {
+                    events.push(Event::Stopped(status));
+                    break;
+                },
+                _ => (),
+            }
+        }
+
+        // quick_run keeps stdout and stderr only
+        let output = conn.quick_run("vendor-tool").unwrap();
+        (events, output)
+    }, |mut server| {
+        server.accept_session();
+
+        for _ in 0..2 {
+            let (channel, _) = server.accept_channel();
+            server.expect_channel_request("exec");
+            server.channel_success(channel);
+
+            server.send_data(channel, b"out");
+            server.send_ext_data(channel, 7, b"side");
+            server.send_stderr(channel, b"err");
+            server.send_exit_status(channel, 0);
+            server.send_close(channel);
+            server.expect(97);
+        }
+    });
+
+    assert_eq!(events, [
+        Event::Data(b"out".to_vec()),
+        Event::ExtData(ExtendedDataType::Unknown(7), b"side".to_vec()),
+        Event::Stderr(b"err".to_vec()),
+        Event::Stopped(ExitStatus::Code(0)),
+    ]);
+
+    assert_eq!(output, RunResult::Accepted(("outerr".into(), ExitStatus::Code(0))));
+}
+
+#[test]
+fn quick_run_exit_status() {
+    let statuses = scripted(|stream| {
+        let conn = connect(stream);
+        ["kill -9 $$", "exit 3", "true"].map(|command| match conn.quick_run_blind(command).unwrap() {
+            RunResult::Accepted(status) => status,
+            RunResult::Refused => panic!("exec refused"),
+        })
+    }, |mut server| {
+        server.accept_session();
+        for i in 0..3 {
+            let (channel, _) = server.accept_channel();
+            server.expect_channel_request("exec");
+            server.channel_success(channel);
+            match i {
+                0 => server.send_exit_signal(channel, "KILL", false, ""),
+                1 => server.send_exit_status(channel, 3),
+                _ => (),
+            }
+            server.send_close(channel);
+            server.expect(97);
+        }
+    });
+
+    assert_eq!(statuses, [
+        ExitStatus::Signal("KILL".into()),
+        ExitStatus::Code(3),
+        ExitStatus::Unknown,
+    ]);
+}
+
+#[test]
+fn quick_run_checked() {
+    let results = scripted(|stream| {
+        let conn = connect(stream);
+        ["echo ok", "ls missing", "kill -9 $$"].map(|command| conn.quick_run_checked(command).map_err(|e| {
+            (e.kind(), e.message().map(str::to_string))
+        }))
+    }, |mut server| {
+        server.accept_session();
+        for i in 0..3 {
+            let (channel, _) = server.accept_channel();
+            server.expect_channel_request("exec");
+            server.channel_success(channel);
+            match i {
+                0 => {
+                    server.send_data(channel, b"ok\n");
+                    server.send_exit_status(channel, 0);
+                },
+                1 => {
+                    server.send_data(channel, b"partial\n");
+                    server.send_stderr(channel, b"ls: missing: No such file or directory\n");
+                    server.send_exit_status(channel, 2);
+                },
+                _ => server.send_exit_signal(channel, "KILL", false, ""),
+            }
+            server.send_close(channel);
+            server.expect(97);
+        }
+    });
+
+    assert_eq!(results, [
+        Ok(RunResult::Accepted("ok\n".into())),
+        Err((ErrorKind::CommandFailed { code: Some(2) }, Some("Code(2): ls: missing: No such file or directory".into()))),
+        Err((ErrorKind::CommandFailed { code: None }, Some("Signal(\"KILL\")".into()))),
+    ]);
+}
+
+#[test]
+fn send_signal() {
+    let output = scripted(|stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("sleep 100", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        assert_eq!(run.wait_with_timeout(Duration::from_millis(100)).unwrap().exit_status, None);
+        run.signal("TERM").unwrap();
+        run.wait_with_timeout(Duration::from_secs(10)).unwrap()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        assert_eq!(server.expect_channel_request("signal"), string(b"TERM"));
+        server.send_exit_signal(channel, "TERM", false, "");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output.exit_status, Some(ExitStatus::Signal("TERM".into())));
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::net::TcpListener;
+use coolssh::{Fleet, ErrorKind, RunResult};
+use common::*;
+
+fn echo(server: &mut Server, output: &[u8]) {
+    server.accept_session();
+    let (channel, _) = server.accept_channel();
+    server.expect_channel_request("exec");
+    server.channel_success(channel);
+    server.send_data(channel, output);
+    server.send_close(channel);
+    server.expect(97);
+}
+
+#[test]
+fn run_on_each_host() {
+    let outputs = scripted_listener(|addr| {
+        let refused = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            listener.local_addr().unwrap()
+        };
+
+        let fleet = Fleet::new(("user", CLIENT_HEX_KEYPAIR).into(), Default::default()).concurrency(1);
+        let hosts = [addr, refused, addr];
+
+        fleet.run_all(&hosts, "hostname").into_iter().map(|output| {
+            let result = match output.result {
+                Ok(RunResult::Accepted((stdout, _))) => Ok(stdout),
+                Ok(RunResult::Refused) => panic!("exec refused"),
+                Err(error) => Err(error.kind()),
+            };
+
+            (output.index, output.host, result)
+        }).collect::<Vec<_>>()
+    }, |listener| {
+        echo(&mut Server::accept(&listener), b"first\n");
+        echo(&mut Server::accept(&listener), b"third\n");
+    });
+
+    assert_eq!(outputs.len(), 3);
+    assert_eq!(outputs[0].2, Ok("first\n".into()));
+    assert!(matches!(outputs[1].2, Err(ErrorKind::TcpError(_))));
+    assert_eq!(outputs[2].2, Ok("third\n".into()));
+    assert_eq!(outputs.iter().map(|output| output.0).collect::<Vec<_>>(), [0, 1, 2]);
+    assert_eq!(outputs[0].1, outputs[2].1);
+}
+
+#[test]
+fn callback_sees_every_host() {
+    let count = scripted_listener(|addr| {
+        let fleet = Fleet::new(("user", CLIENT_HEX_KEYPAIR).into(), Default::default()).concurrency(2);
+        let mut count = 0;
+        fleet.run(&[addr, addr], "true", |output| {
+            assert!(output.result.is_ok());
+            count += 1;
+        });
+
+        count
+    }, |listener| {
+        echo(&mut Server::accept(&listener), b"");
+        echo(&mut Server::accept(&listener), b"");
+    });
+
+    assert_eq!(count, 2);
+}
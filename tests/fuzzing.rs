@@ -0,0 +1,36 @@
+#![cfg(feature = "fuzzing")]
+
+use coolssh::fuzzing::{parse_message, depad, parse_certificate, verify_host_signature};
+use coolssh::MessageType;
+
+#[test]
+fn depad_checks_lengths() {
+    assert_eq!(depad(&[4, b'x', 0, 0, 0, 0]).unwrap(), 1..2);
+    assert!(depad(&[]).is_err());
+    assert!(depad(&[3, b'x', 0, 0, 0]).is_err());
+    assert!(depad(&[255, b'x', 0, 0, 0, 0]).is_err());
+}
+
+#[test]
+fn truncated_messages_are_errors() {
+    // ChannelRequest: recipient channel, "exec", want_reply, command
+    let mut payload = vec![98, 0, 0, 0, 1, 0, 0, 0, 4];
+    payload.extend(b"exec");
+    payload.push(0);
+    payload.extend([0, 0, 0, 2]);
+    payload.extend(b"ls");
+
+    assert_eq!(parse_message(&payload).unwrap().typ(), MessageType::ChannelRequest);
+    for len in 0..payload.len() {
+        assert!(parse_message(&payload[..len]).is_err(), "{} bytes", len);
+    }
+}
+
+#[test]
+fn huge_lengths_are_errors() {
+    assert!(parse_message(&[2, 0xff, 0xff, 0xff, 0xff]).is_ok());
+    assert!(parse_message(&[94, 0, 0, 0, 1, 0xff, 0xff, 0xff, 0xff, 0]).is_err());
+    assert!(parse_message(&[7, 0xff, 0xff, 0xff, 0xff]).is_err());
+    assert!(parse_certificate(&[0xff, 0xff, 0xff, 0xff]).is_err());
+    assert!(verify_host_signature("ssh-ed25519", &[0, 0, 0, 11], &[0xff; 4], b"data").is_err());
+}
@@ -0,0 +1,67 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use coolssh::{Connection, ConnectOptions, RunResult, ExitStatus};
+use common::*;
+
+fn global_request(name: &str, want_reply: bool, data: &[u8]) -> Vec<u8> {
+    let mut request = vec![80];
+    request.extend(string(name.as_bytes()));
+    request.push(want_reply as u8);
+    request.extend_from_slice(data);
+    request
+}
+
+/// Runs `echo done` while the server sends global requests
+/// and returns the replies the server got
+fn run_with(options: ConnectOptions) -> Vec<u8> {
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    let output = scripted(move |stream| {
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        conn.quick_run("echo done").unwrap()
+    }, move |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send(&global_request("hostkeys-00@openssh.com", false, &string(b"key")));
+        server.send(&global_request("keepalive@openssh.com", true, b""));
+        let first = server.recv()[0];
+        server.send(&global_request("hostkeys-prove-00@openssh.com", true, &string(b"key")));
+        let second = server.recv()[0];
+
+        server.send_data(channel, b"done\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+        sender.send(vec![first, second]).unwrap();
+    });
+
+    assert_eq!(output, RunResult::Accepted(("done\n".into(), ExitStatus::Code(0))));
+    receiver.recv().unwrap()
+}
+
+#[test]
+fn unknown_requests_fail() {
+    assert_eq!(run_with(ConnectOptions::default()), [82, 82]);
+}
+
+#[test]
+fn handler_answers_requests() {
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let list = requests.clone();
+
+    let options = ConnectOptions::default().global_request_handler(move |name, data| {
+        list.lock().unwrap().push((name.to_string(), data.to_vec()));
+        name == "hostkeys-prove-00@openssh.com"
+    });
+
+    assert_eq!(run_with(options), [82, 81]);
+    assert_eq!(*requests.lock().unwrap(), [
+        ("hostkeys-00@openssh.com".to_string(), string(b"key")),
+        ("keepalive@openssh.com".to_string(), vec![]),
+        ("hostkeys-prove-00@openssh.com".to_string(), string(b"key")),
+    ]);
+}
@@ -0,0 +1,155 @@
+mod common;
+
+use coolssh::{Connection, ConnectOptions, Error, ErrorKind, PublicKey};
+use common::*;
+
+fn connect_with(host_key: HostKey) -> Result<Connection, Error> {
+    scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), move |mut server| {
+        server.key_exchange(&host_key);
+        server.accept_auth();
+    })
+}
+
+#[test]
+fn ed25519_host_key() {
+    assert!(connect_with(HostKey::new()).is_ok());
+}
+
+#[test]
+fn ecdsa_p256_host_key() {
+    assert!(connect_with(HostKey::ecdsa_p256()).is_ok());
+}
+
+#[test]
+fn rsa_sha2_256_host_key() {
+    assert!(connect_with(HostKey::rsa("rsa-sha2-256")).is_ok());
+}
+
+#[test]
+fn rsa_sha2_512_host_key() {
+    assert!(connect_with(HostKey::rsa("rsa-sha2-512")).is_ok());
+}
+
+#[test]
+fn forged_rsa_signature_is_rejected() {
+    let result = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), |mut server| {
+        let host_key = HostKey::rsa("rsa-sha2-256");
+        server.expect(20);
+        server.send(&kexinit("curve25519-sha256", host_key.algorithm()));
+
+        let _init = server.expect(30);
+        let mut reply = vec![31];
+        reply.extend(string(&host_key.blob()));
+        reply.extend(string(&[9; 32]));
+        // signs something else than the exchange hash
+        reply.extend(string(&host_key.sign(b"not the exchange hash")));
+        server.send(&reply);
+    });
+
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn connection_metadata() {
+    let host_key = HostKey::ecdsa_p256();
+    let blob = host_key.blob();
+    let mut exchange_hash = Vec::new();
+    let conn = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).unwrap(), |mut server| {
+        exchange_hash = server.key_exchange(&host_key);
+        server.accept_auth();
+    });
+
+    assert_eq!(conn.server_version(), "SSH-2.0-ScriptedServer");
+    assert_eq!(conn.session_id(), exchange_hash);
+    assert_eq!(conn.host_key().blob(), blob);
+    assert_eq!(conn.host_key().algorithm(), "ecdsa-sha2-nistp256");
+    assert!(conn.host_key().fingerprint_display().to_string().starts_with("SHA256:"));
+    assert_eq!(conn.host_key_fingerprint(), conn.host_key().fingerprint_display().to_string());
+}
+
+fn trusting(authority: &HostKey) -> ConnectOptions {
+    let authorities = vec![PublicKey::from_blob(&authority.blob()).unwrap()];
+    ConnectOptions::default().host_cert_authorities("server.example", authorities)
+}
+
+fn connect_with_authority(host_key: HostKey, authority: &HostKey) -> Result<Connection, Error> {
+    let options = trusting(authority);
+    scripted(|stream| Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options), move |mut server| {
+        server.key_exchange(&host_key);
+        server.accept_auth();
+    })
+}
+
+fn offer_to_authority(host_key: HostKey, authority: &HostKey) -> Error {
+    let options = trusting(authority);
+    let result = scripted(|stream| Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options), move |mut server| {
+        server.offer_host_key(&host_key);
+    });
+
+    result.unwrap_err()
+}
+
+#[test]
+fn host_certificate() {
+    let authority = HostKey::ecdsa_p256();
+    let certificate = HostKey::certificate(&authority, 2, &["server.example"], 0..u64::MAX);
+    let conn = connect_with_authority(certificate, &authority).unwrap();
+    assert_eq!(conn.host_key().algorithm(), "ssh-ed25519-cert-v01@openssh.com");
+}
+
+#[test]
+fn host_certificate_without_principals() {
+    let authority = HostKey::new();
+    let certificate = HostKey::certificate(&authority, 2, &[], 0..u64::MAX);
+    assert!(connect_with_authority(certificate, &authority).is_ok());
+}
+
+#[test]
+fn host_certificate_from_unknown_authority_is_rejected() {
+    let certificate = HostKey::certificate(&HostKey::new(), 2, &["server.example"], 0..u64::MAX);
+    assert_eq!(offer_to_authority(certificate, &HostKey::ecdsa_p256()).kind(), ErrorKind::UntrustedHostKey);
+}
+
+#[test]
+fn host_certificate_for_another_host_is_rejected() {
+    let authority = HostKey::new();
+    let certificate = HostKey::certificate(&authority, 2, &["other.example"], 0..u64::MAX);
+    assert_eq!(offer_to_authority(certificate, &authority).kind(), ErrorKind::UntrustedHostKey);
+}
+
+#[test]
+fn expired_host_certificate_is_rejected() {
+    let authority = HostKey::new();
+    let certificate = HostKey::certificate(&authority, 2, &["server.example"], 0..1_000_000_000);
+    assert_eq!(offer_to_authority(certificate, &authority).kind(), ErrorKind::UntrustedHostKey);
+}
+
+#[test]
+fn user_certificate_as_host_key_is_rejected() {
+    let authority = HostKey::new();
+    let certificate = HostKey::certificate(&authority, 1, &["server.example"], 0..u64::MAX);
+    assert_eq!(offer_to_authority(certificate, &authority).kind(), ErrorKind::UntrustedHostKey);
+}
+
+#[test]
+fn uncertified_host_key_is_rejected() {
+    assert_eq!(offer_to_authority(HostKey::new(), &HostKey::new()).kind(), ErrorKind::UntrustedHostKey);
+}
+
+#[test]
+fn fetch_host_key() {
+    let host_key = HostKey::ecdsa_p256();
+    let blob = host_key.blob();
+
+    let (algorithm, fetched, fingerprint) = scripted_at(|addr| {
+        Connection::fetch_host_key(addr, ConnectOptions::default()).unwrap()
+    }, move |mut server| {
+        server.key_exchange(&host_key);
+        // no authentication
+        let disconnect = server.expect(1);
+        assert_eq!(disconnect[1..5], 11u32.to_be_bytes());
+    });
+
+    assert_eq!((algorithm.as_str(), &fetched), ("ecdsa-sha2-nistp256", &blob));
+    assert_eq!(fingerprint, PublicKey::from_blob(&blob).unwrap().fingerprint_display().to_string());
+}
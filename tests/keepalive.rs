@@ -0,0 +1,110 @@
+mod common;
+
+use std::time::Duration;
+use coolssh::{RunResult, RunEvent, ErrorKind};
+use common::*;
+
+fn expect_keepalive(server: &mut Server) {
+    let request = server.expect(80);
+    let mut i = 1;
+    assert_eq!(take_string(&request, &mut i), b"keepalive@openssh.com");
+    assert_eq!(request[i], 1);
+}
+
+#[test]
+fn keepalive_while_idle() {
+    let output = scripted(|stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let conn = connect(stream);
+        conn.set_keepalive(Some(Duration::from_millis(50)));
+
+        let RunResult::Accepted(mut run) = conn.run("sleep 1; echo done", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut output = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => output.extend_from_slice(data),
+                RunEvent::Stopped(_) => break output,
+                _ => (),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        for _ in 0..4 {
+            expect_keepalive(&mut server);
+            // OpenSSH servers don't know this request
+            server.send(&[82]);
+        }
+
+        server.send_data(channel, b"done\n");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"done\n");
+}
+
+#[test]
+fn unanswered_keepalives() {
+    let error = scripted(|stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let conn = connect(stream);
+        conn.set_keepalive(Some(Duration::from_millis(50)));
+
+        let RunResult::Accepted(mut run) = conn.run("true", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        loop {
+            if let Err(error) = run.poll() {
+                break error;
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        for _ in 0..3 {
+            expect_keepalive(&mut server);
+        }
+
+        // sent when the Run is dropped
+        server.expect(97);
+    });
+
+    assert_eq!(error.kind(), ErrorKind::ConnectionLost);
+}
+
+#[test]
+fn quick_run_on_a_silent_server() {
+    let error = scripted(|stream| {
+        // no read timeout: quick_run waits for the keepalives by itself
+        let conn = connect(stream);
+        conn.set_keepalive(Some(Duration::from_millis(50)));
+        conn.quick_run("sleep 3600").map(drop).unwrap_err()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        // the first one is answered, then the server goes silent
+        expect_keepalive(&mut server);
+        server.send(&[82]);
+        for _ in 0..3 {
+            expect_keepalive(&mut server);
+        }
+
+        server.expect(97);
+    });
+
+    assert_eq!(error.kind(), ErrorKind::ConnectionLost);
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use coolssh::{Connection, ConnectOptions, Error, ErrorKind};
+use common::*;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+const GEX: &str = "diffie-hellman-group-exchange-sha256";
+
+fn connect(options: ConnectOptions) -> impl FnOnce(std::net::TcpStream) -> Result<Connection, Error> {
+    move |stream| Connection::new_with_options(stream, ("git", CLIENT_HEX_KEYPAIR).into(), options)
+}
+
+/// Negotiates DH-GEX and sends group14 as the group;
+/// returns both kexinit payloads.
+fn serve_gex_group(server: &mut Server, expected_sizes: (u32, u32, u32)) -> (Vec<u8>, Vec<u8>) {
+    let client_kexinit = server.recv();
+    assert_eq!(client_kexinit[0], 20);
+    let server_kexinit = kexinit(GEX, "ssh-ed25519");
+    server.send(&server_kexinit);
+
+    let request = server.recv();
+    assert_eq!(request[0], 34);
+    let mut i = 1;
+    let sizes = (take_u32(&request, &mut i), take_u32(&request, &mut i), take_u32(&request, &mut i));
+    assert_eq!(sizes, expected_sizes);
+
+    let prime = BigUint::parse_bytes(GROUP14_PRIME.as_bytes(), 16).unwrap();
+    let mut group = vec![31];
+    group.extend(mpint(&prime.to_bytes_be()));
+    group.extend(mpint(&[2]));
+    server.send(&group);
+
+    (client_kexinit, server_kexinit)
+}
+
+/// Runs the server side of a group exchange, with group14 as
+/// the group, and returns (shared secret, exchange hash).
+fn serve_gex(server: &mut Server, sizes: (u32, u32, u32)) -> (Vec<u8>, Vec<u8>) {
+    let host_key = HostKey::new();
+    let (client_kexinit, server_kexinit) = serve_gex_group(server, sizes);
+    let prime = BigUint::parse_bytes(GROUP14_PRIME.as_bytes(), 16).unwrap();
+    let generator = BigUint::from(2u8);
+
+    let init = server.recv();
+    assert_eq!(init[0], 32);
+    let client_public = BigUint::from_bytes_be(take_string(&init, &mut 1));
+
+    let exponent = BigUint::from_bytes_be(&[0x5a; 40]);
+    let server_public = generator.modpow(&exponent, &prime);
+    let shared_secret = client_public.modpow(&exponent, &prime).to_bytes_be();
+
+    // RFC 4419, section 3
+    let mut hashed = Vec::new();
+    hashed.extend(string(&server.client_version));
+    hashed.extend(string(SERVER_VERSION));
+    hashed.extend(string(&client_kexinit));
+    hashed.extend(string(&server_kexinit));
+    hashed.extend(string(&host_key.blob()));
+    hashed.extend_from_slice(&sizes.0.to_be_bytes());
+    hashed.extend_from_slice(&sizes.1.to_be_bytes());
+    hashed.extend_from_slice(&sizes.2.to_be_bytes());
+    hashed.extend(mpint(&prime.to_bytes_be()));
+    hashed.extend(mpint(&generator.to_bytes_be()));
+    hashed.extend(mpint(&client_public.to_bytes_be()));
+    hashed.extend(mpint(&server_public.to_bytes_be()));
+    hashed.extend(mpint(&shared_secret));
+    let exchange_hash = Sha256::digest(&hashed).to_vec();
+
+    let mut reply = vec![33];
+    reply.extend(string(&host_key.blob()));
+    reply.extend(mpint(&server_public.to_bytes_be()));
+    reply.extend(string(&host_key.sign(&exchange_hash)));
+    server.send(&reply);
+
+    (shared_secret, exchange_hash)
+}
+
+#[test]
+fn group_exchange_reaches_encrypted_transport() {
+    let result = scripted(connect(ConnectOptions::default()), |mut server| {
+        let (shared_secret, exchange_hash) = serve_gex(&mut server, (2048, 3072, 8192));
+
+        assert_eq!(server.recv(), [21]);
+        server.send(&[21]);
+
+        // the client's first encrypted packet is only readable
+        // if both sides derived the same keys
+        server.enable_encryption(&shared_secret, &exchange_hash, &exchange_hash);
+        let service_request = server.recv();
+        assert_eq!(service_request[0], 5);
+        assert_eq!(take_string(&service_request, &mut 1), b"ssh-userauth");
+    });
+
+    assert!(matches!(result.map_err(|e| e.kind()), Err(ErrorKind::TcpError(_))));
+}
+
+#[test]
+fn configured_group_sizes_are_requested_and_enforced() {
+    let options = ConnectOptions::default().gex_group_size(3072, 4096, 8192);
+    let result = scripted(connect(options), |mut server| {
+        serve_gex_group(&mut server, (3072, 4096, 8192));
+    });
+
+    // group14 is smaller than the requested minimum
+    assert!(matches!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData)));
+}
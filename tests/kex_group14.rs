@@ -0,0 +1,52 @@
+mod common;
+
+use coolssh::Connection;
+use common::*;
+use num_bigint::BigUint;
+use sha2::{Digest, Sha256};
+
+const GROUP14: &str = "diffie-hellman-group14-sha256";
+
+#[test]
+fn group14_fallback() {
+    let result = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), |mut server| {
+        let host_key = HostKey::new();
+        let client_kexinit = server.expect(20);
+        // the server doesn't support curve25519 nor GEX
+        let server_kexinit = kexinit(GROUP14, "ssh-ed25519");
+        server.send(&server_kexinit);
+
+        let init = server.expect(30);
+        let client_public = BigUint::from_bytes_be(take_string(&init, &mut 1));
+
+        let prime = BigUint::parse_bytes(GROUP14_PRIME.as_bytes(), 16).unwrap();
+        let exponent = BigUint::from_bytes_be(&[0x3c; 40]);
+        let server_public = BigUint::from(2u8).modpow(&exponent, &prime);
+        let shared_secret = client_public.modpow(&exponent, &prime).to_bytes_be();
+
+        // RFC 4253, section 8
+        let mut hashed = Vec::new();
+        hashed.extend(string(&server.client_version));
+        hashed.extend(string(SERVER_VERSION));
+        hashed.extend(string(&client_kexinit));
+        hashed.extend(string(&server_kexinit));
+        hashed.extend(string(&host_key.blob()));
+        hashed.extend(mpint(&client_public.to_bytes_be()));
+        hashed.extend(mpint(&server_public.to_bytes_be()));
+        hashed.extend(mpint(&shared_secret));
+        let exchange_hash = Sha256::digest(&hashed).to_vec();
+
+        let mut reply = vec![31];
+        reply.extend(string(&host_key.blob()));
+        reply.extend(mpint(&server_public.to_bytes_be()));
+        reply.extend(string(&host_key.sign(&exchange_hash)));
+        server.send(&reply);
+
+        server.expect(21);
+        server.send(&[21]);
+        server.enable_encryption(&shared_secret, &exchange_hash, &exchange_hash);
+        server.accept_auth();
+    });
+
+    assert!(result.is_ok());
+}
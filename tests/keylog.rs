@@ -0,0 +1,80 @@
+#![cfg(feature = "keylog")]
+
+mod common;
+
+use std::io::{Result as IoResult, Write};
+use std::sync::{Arc, Mutex};
+use sha2::{Digest, Sha256};
+use coolssh::{Connection, ConnectOptions, Message, PacketDirection};
+use common::*;
+
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+fn decode(hex: &str) -> Vec<u8> {
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..][..2], 16).unwrap()).collect()
+}
+
+fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[test]
+fn session_keys_are_logged() {
+    let sink = Sink::default();
+    let key_log = sink.clone();
+
+    let cookie = Arc::new(Mutex::new(String::new()));
+    let sent_cookie = cookie.clone();
+    let observer = move |direction, _: &[u8], message: Option<&Message>| {
+        if let (PacketDirection::Sent, Some(Message::Kexinit(kexinit))) = (direction, message) {
+            *sent_cookie.lock().unwrap() = encode(&kexinit.cookie);
+        }
+    };
+
+    let mut exchange_hash = Vec::new();
+    scripted(|stream| {
+        let options = ConnectOptions::default().packet_observer(observer).key_log(sink);
+        Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+    }, |mut server| {
+        exchange_hash = server.key_exchange(&HostKey::new());
+        server.accept_auth();
+    });
+
+    let key_log = String::from_utf8(key_log.0.lock().unwrap().clone()).unwrap();
+    let lines: Vec<Vec<&str>> = key_log.lines().map(|line| line.split(' ').collect()).collect();
+    let labels: Vec<&str> = lines.iter().map(|fields| fields[1]).collect();
+    assert_eq!(labels, [
+        "SHARED_SECRET",
+        "CLIENT_TO_SERVER_IV",
+        "CLIENT_TO_SERVER_KEY",
+        "CLIENT_TO_SERVER_MAC_KEY",
+        "SERVER_TO_CLIENT_IV",
+        "SERVER_TO_CLIENT_KEY",
+        "SERVER_TO_CLIENT_MAC_KEY",
+    ]);
+    assert!(lines.iter().all(|fields| fields[0] == *cookie.lock().unwrap()));
+
+    // aes256-ctr and hmac-sha2-256, derived from the shared secret (RFC 4253, section 7.2)
+    let shared_secret = decode(lines[0][2]);
+    let lengths = [16, 32, 32, 16, 32, 32];
+    for ((magic, len), fields) in b"ACEBDF".iter().zip(lengths).zip(&lines[1..]) {
+        let mut hasher = Sha256::new();
+        hasher.update(mpint(&shared_secret));
+        hasher.update(&exchange_hash);
+        hasher.update([*magic]);
+        hasher.update(&exchange_hash);
+        let expected = hasher.finalize();
+        assert_eq!(fields[2], encode(&expected[..len]));
+    }
+}
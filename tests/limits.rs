@@ -0,0 +1,132 @@
+mod common;
+
+use std::io::Write;
+use coolssh::{Connection, ConnectOptions, Error, ErrorKind, RunResult, RunOptions};
+use common::*;
+
+fn connect_with(options: ConnectOptions) -> impl FnOnce(std::net::TcpStream) -> Result<(), Error> {
+    move |stream| Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).map(drop)
+}
+
+#[test]
+fn huge_packet_length_is_refused() {
+    let result = scripted(connect_with(ConnectOptions::default()), |server| {
+        // would be a 4GiB allocation
+        server.stream().write_all(&[0xff; 4]).unwrap();
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::PacketTooLarge));
+}
+
+#[test]
+fn configured_maximum_is_honored() {
+    let result = scripted(connect_with(ConnectOptions::default().max_packet_length(64)), |mut server| {
+        server.expect(20);
+        server.send(&kexinit("curve25519-sha256", "ssh-ed25519"));
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::PacketTooLarge));
+}
+
+#[test]
+fn encrypted_packet_over_maximum_is_refused() {
+    let result = scripted(connect_with(ConnectOptions::default().max_packet_length(4096)), |mut server| {
+        server.key_exchange(&HostKey::new());
+        let mut ignore = vec![2];
+        ignore.extend(string(&[0; 8192]));
+        server.send(&ignore);
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::PacketTooLarge));
+}
+
+#[test]
+fn short_padding_is_refused() {
+    let result = scripted(connect_with(ConnectOptions::default()), |server| {
+        // packet_length = 8, padding_length = 2, Ignore payload
+        let packet = [0, 0, 0, 8, 2, 2, 0, 0, 0, 0, 0, 0];
+        server.stream().write_all(&packet).unwrap();
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn misaligned_packet_length_is_refused() {
+    let result = scripted(connect_with(ConnectOptions::default()), |server| {
+        // packet_length = 9, which isn't a multiple of 8 once counted
+        let packet = [0, 0, 0, 9, 4, 2, 0, 0, 0, 0, 0, 0, 0];
+        server.stream().write_all(&packet).unwrap();
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn padding_without_payload_is_refused() {
+    let result = scripted(connect_with(ConnectOptions::default()), |server| {
+        let mut packet = vec![0, 0, 0, 12, 11];
+        packet.resize(16, 0);
+        server.stream().write_all(&packet).unwrap();
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn full_size_channel_data_is_accepted() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted((output, _)) = conn.quick_run_bytes("cat file").unwrap() else {
+            panic!("exec refused");
+        };
+        output
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        let max_packet_size = server.client_window.1 as usize;
+        server.send_data(channel, &vec![b'0'; max_packet_size]);
+        server.send_stderr(channel, &vec![b'1'; max_packet_size]);
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output.len(), 2 * 0x8000);
+}
+
+#[test]
+fn channel_packets_must_fit_in_the_maximum() {
+    let (global, per_run) = scripted(|stream| {
+        let options = ConnectOptions::default().max_packet_length(16 * 1024);
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+
+        let global = conn.set_channel_window(u32::MAX, 16 * 1024).map_err(|e| e.kind());
+        assert!(conn.set_channel_window(u32::MAX, 8 * 1024).is_ok());
+
+        let options = RunOptions::default().max_packet(16 * 1024);
+        let per_run = conn.run_with_options("true", &[], options).map(drop).map_err(|e| e.kind());
+        (global, per_run)
+    }, |mut server| {
+        server.accept_session();
+    });
+
+    assert_eq!(global, Err(ErrorKind::PacketTooLarge));
+    assert_eq!(per_run, Err(ErrorKind::PacketTooLarge));
+}
+
+#[test]
+fn max_packet_size_of_the_maximum_length_is_refused() {
+    let result = scripted(|stream| {
+        let conn = connect(stream);
+        conn.set_channel_window(u32::MAX, 256 * 1024)
+    }, |mut server| {
+        server.accept_session();
+    });
+
+    // the ChannelData header and padding don't fit
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::PacketTooLarge));
+}
@@ -0,0 +1,35 @@
+use coolssh::{Message, OwnedMessage, MessageType, ErrorKind};
+
+#[test]
+fn kex_method_numbers_are_contextual() {
+    for number in 30..=49 {
+        assert!(matches!(MessageType::try_from(number).map_err(|e| e.kind()), Err(ErrorKind::UnknownMessageType(n)) if n == number));
+    }
+
+    assert_eq!(MessageType::try_from(21).unwrap(), MessageType::Newkeys);
+    assert_eq!(MessageType::try_from(50).unwrap(), MessageType::UserauthRequest);
+}
+
+#[test]
+fn owned_message_outlives_its_buffer() {
+    let owned = {
+        let payload = vec![94, 0, 0, 0, 1, 0, 0, 0, 2, b'h', b'i'];
+        OwnedMessage::from_payload(payload).unwrap()
+    };
+
+    assert_eq!(owned.typ(), MessageType::ChannelData);
+    let Message::ChannelData(data) = owned.message() else {
+        panic!("not ChannelData");
+    };
+    assert_eq!((data.recipient_channel, data.data), (1, b"hi".as_slice()));
+
+    let copy = owned.message().to_owned_message().unwrap();
+    assert_eq!(copy, owned);
+}
+
+#[test]
+fn owned_message_is_checked() {
+    assert!(OwnedMessage::from_payload(vec![94, 0, 0]).is_err());
+    assert!(OwnedMessage::from_payload(vec![]).is_err());
+    assert_eq!(OwnedMessage::from_payload(vec![81]).unwrap().typ(), MessageType::RequestSuccess);
+}
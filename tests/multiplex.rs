@@ -0,0 +1,123 @@
+mod common;
+
+use coolssh::{RunResult, RunEvent, Run, ExitStatus, ErrorKind};
+use common::*;
+
+fn collect(run: &mut Run) -> (Vec<u8>, ExitStatus) {
+    let mut output = Vec::new();
+    loop {
+        match run.poll().unwrap() {
+            RunEvent::Data(data) => output.extend_from_slice(data),
+            RunEvent::Stopped(exit_status) => break (output, exit_status),
+            _ => (),
+        }
+    }
+}
+
+#[test]
+fn concurrent_runs() {
+    let (first, second) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut first) = conn.run("echo first", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+        let RunResult::Accepted(mut second) = conn.run("echo second", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // the second one is polled first, while the server
+        // interleaves the output of both
+        let second = collect(&mut second);
+        let first = collect(&mut first);
+        (first, second)
+    }, |mut server| {
+        server.accept_session();
+
+        let (first, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"echo first"));
+        server.channel_success(first);
+
+        let (second, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"echo second"));
+        server.channel_success(second);
+        assert_ne!(first, second);
+
+        server.send_data(first, b"fir");
+        server.send_data(second, b"sec");
+        server.send_data(first, b"st\n");
+        server.send_exit_status(first, 1);
+        server.send_close(first);
+        server.send_data(second, b"ond\n");
+        server.send_exit_status(second, 2);
+        server.send_close(second);
+
+        server.expect(97);
+        server.expect(97);
+    });
+
+    assert_eq!(first, (b"first\n".to_vec(), ExitStatus::Code(1)));
+    assert_eq!(second, (b"second\n".to_vec(), ExitStatus::Code(2)));
+}
+
+#[test]
+fn unknown_channel_is_rejected() {
+    let result = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("echo", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        loop {
+            match run.poll() {
+                Ok(RunEvent::Stopped(_)) => break Ok(()),
+                Ok(_) => (),
+                Err(error) => break Err(error),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(42, b"not yours\n");
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::UnknownChannel(42)));
+}
+
+#[test]
+fn closed_channel_messages_are_ignored() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(first) = conn.run("sleep 10", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+        drop(first);
+
+        let RunResult::Accepted(mut second) = conn.run("echo second", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+        collect(&mut second)
+    }, |mut server| {
+        server.accept_session();
+        let (first, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(first);
+        server.expect(97);
+
+        let (second, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(second);
+
+        // sent before the ChannelClose of the client was received
+        server.send_data(first, b"late\n");
+        server.send_close(first);
+
+        server.send_data(second, b"second\n");
+        server.send_exit_status(second, 0);
+        server.send_close(second);
+        server.expect(97);
+    });
+
+    assert_eq!(output, (b"second\n".to_vec(), ExitStatus::Code(0)));
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use coolssh::{Connection, ConnectOptions, ErrorKind, Message, PacketDirection};
+use common::*;
+
+fn kexinit_with(lists: [&str; 10]) -> Vec<u8> {
+    let mut payload = vec![20];
+    payload.extend_from_slice(&[0x42; 16]);
+    for list in lists {
+        payload.extend(string(list.as_bytes()));
+    }
+    payload.push(0);
+    payload.extend_from_slice(&[0; 4]);
+    payload
+}
+
+#[test]
+fn client_preference_wins() {
+    let conn = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), |mut server| {
+        // the server prefers other algorithms, and lists unknown ones
+        let server_kexinit = kexinit_with([
+            "sntrup761x25519-sha512,diffie-hellman-group14-sha256,curve25519-sha256",
+            "rsa-sha2-256,ssh-ed25519",
+            "3des-cbc,aes256-ctr",
+            "aes128-ctr,aes256-ctr",
+            "umac-64@openssh.com,hmac-sha2-256",
+            "hmac-sha2-256",
+            "zlib@openssh.com,none",
+            "none",
+            "",
+            "",
+        ]);
+
+        server.key_exchange_advertising(&HostKey::new(), server_kexinit);
+        server.accept_auth();
+    }).unwrap();
+
+    let algorithms = conn.algorithms();
+    assert_eq!(algorithms.kex, "curve25519-sha256");
+    assert_eq!(algorithms.host_key, "ssh-ed25519");
+    assert_eq!(algorithms.encryption_client_to_server, "aes256-ctr");
+    assert_eq!(algorithms.mac_server_to_client, "hmac-sha2-256");
+    assert_eq!(algorithms.compression_client_to_server, "none");
+}
+
+#[test]
+fn no_common_cipher() {
+    let result = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), |mut server| {
+        server.expect(20);
+        server.send(&kexinit_with([
+            "curve25519-sha256",
+            "ssh-ed25519",
+            "3des-cbc",
+            "aes128-ctr",
+            "hmac-sha2-256",
+            "hmac-sha2-256",
+            "none",
+            "none",
+            "",
+            "",
+        ]));
+    });
+
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::Unimplemented);
+}
+
+/// `kexinit_with`, with `first_kex_packet_follows`
+fn guessing_kexinit(kex: &str, host_key: &str) -> Vec<u8> {
+    let mut payload = kexinit_with([kex, host_key, "aes256-ctr", "aes256-ctr", "hmac-sha2-256", "hmac-sha2-256", "none", "none", "", ""]);
+    let flag = payload.len() - 5;
+    payload[flag] = 1;
+    payload
+}
+
+#[test]
+fn wrong_guess_is_ignored() {
+    let conn = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), |mut server| {
+        // a group14 KEXDH_INIT, which the client must skip
+        let server_kexinit = guessing_kexinit("diffie-hellman-group14-sha256,curve25519-sha256", "ssh-ed25519");
+        let mut guessed = vec![30];
+        guessed.extend(mpint(&[0x5a; 256]));
+
+        server.key_exchange_guessing(&HostKey::new(), server_kexinit, &guessed);
+        server.accept_auth();
+    }).unwrap();
+
+    assert_eq!(conn.algorithms().kex, "curve25519-sha256");
+}
+
+#[test]
+fn right_guess_is_kept() {
+    // nothing to guess for the server in curve25519-sha256, but
+    // the client mustn't drop the packet which follows
+    let conn = scripted(|stream| Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()), |mut server| {
+        let server_kexinit = guessing_kexinit("curve25519-sha256", "ssh-ed25519");
+        server.key_exchange_advertising(&HostKey::new(), server_kexinit);
+        server.accept_auth();
+    }).unwrap();
+
+    assert_eq!(conn.algorithms().kex, "curve25519-sha256");
+}
+
+#[test]
+fn ext_info_is_requested_and_parsed() {
+    let kex_algorithms = Arc::new(Mutex::new(Vec::new()));
+    let list = kex_algorithms.clone();
+    let observer = move |direction, _: &[u8], message: Option<&Message>| {
+        if let (PacketDirection::Sent, Some(Message::Kexinit(kexinit))) = (direction, message) {
+            list.lock().unwrap().push(kexinit.kex_algorithms.to_string());
+        }
+    };
+
+    let conn = scripted(|stream| {
+        let options = ConnectOptions::default().packet_observer(observer);
+        Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options)
+    }, |mut server| {
+        // the server lists the indicator, which mustn't be negotiated
+        let server_kexinit = kexinit_with([
+            "ext-info-c,curve25519-sha256",
+            "ssh-ed25519",
+            "aes256-ctr",
+            "aes256-ctr",
+            "hmac-sha2-256",
+            "hmac-sha2-256",
+            "none",
+            "none",
+            "",
+            "",
+        ]);
+        server.key_exchange_advertising(&HostKey::new(), server_kexinit);
+
+        let mut ext_info = vec![7];
+        ext_info.extend_from_slice(&2u32.to_be_bytes());
+        ext_info.extend(string(b"server-sig-algs"));
+        ext_info.extend(string(b"ssh-ed25519,rsa-sha2-512"));
+        ext_info.extend(string(b"no-flow-control"));
+        ext_info.extend(string(b"p"));
+        server.send(&ext_info);
+
+        server.accept_auth();
+    }).unwrap();
+
+    assert_eq!(conn.algorithms().kex, "curve25519-sha256");
+    assert_eq!(conn.server_sig_algs().as_deref(), Some("ssh-ed25519,rsa-sha2-512"));
+    assert!(kex_algorithms.lock().unwrap()[0].ends_with(",ext-info-c"));
+}
+
+#[test]
+fn no_ext_info() {
+    let conn = scripted(connect, |mut server| server.accept_session());
+    assert_eq!(conn.server_sig_algs(), None);
+}
+
+#[test]
+fn libssh_curve25519_alias() {
+    let kex_algorithms = Arc::new(Mutex::new(String::new()));
+    let list = kex_algorithms.clone();
+    let observer = move |direction, _: &[u8], message: Option<&Message>| {
+        if let (PacketDirection::Sent, Some(Message::Kexinit(kexinit))) = (direction, message) {
+            *list.lock().unwrap() = kexinit.kex_algorithms.to_string();
+        }
+    };
+
+    let conn = scripted(|stream| {
+        let options = ConnectOptions::default().packet_observer(observer);
+        Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options)
+    }, |mut server| {
+        // an older server, which only knows the name of libssh
+        let server_kexinit = kexinit("curve25519-sha256@libssh.org", "ssh-ed25519");
+        server.key_exchange_advertising(&HostKey::new(), server_kexinit);
+        server.accept_auth();
+    }).unwrap();
+
+    assert_eq!(conn.algorithms().kex, "curve25519-sha256@libssh.org");
+    assert!(kex_algorithms.lock().unwrap().starts_with("curve25519-sha256,curve25519-sha256@libssh.org,"));
+}
@@ -0,0 +1,94 @@
+mod common;
+
+use std::sync::{Arc, Mutex};
+use coolssh::{Connection, ConnectOptions, ConnectProgress, Auth, Message, MessageType, PacketDirection};
+use common::*;
+
+#[test]
+fn observe_packets() {
+    let observed = Arc::new(Mutex::new(Vec::new()));
+    let list = observed.clone();
+    let observer = move |direction, payload: &[u8], message: Option<&Message>| {
+        let data = match message {
+            Some(Message::ChannelData(data)) => Some(data.data.to_vec()),
+            _ => None,
+        };
+
+        list.lock().unwrap().push((direction, payload[0], message.map(Message::typ), data));
+    };
+
+    scripted(|stream| {
+        let options = ConnectOptions::default().packet_observer(observer);
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        conn.quick_run("echo hello").unwrap()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"hello\n");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    let observed = observed.lock().unwrap();
+    let (direction, number, typ, _) = observed[0];
+    assert_eq!((direction, number, typ), (PacketDirection::Sent, 20, Some(MessageType::Kexinit)));
+
+    // the ECDH messages can't be decoded out of context
+    assert!(observed.iter().any(|&(_, number, typ, _)| number == 31 && typ.is_none()));
+
+    let received_data: Vec<_> = observed.iter()
+        .filter(|(direction, ..)| *direction == PacketDirection::Received)
+        .filter_map(|(.., data)| data.clone())
+        .collect();
+    assert_eq!(received_data, [b"hello\n".to_vec()]);
+
+    // sent when the connection is dropped
+    let (direction, _, typ, _) = observed.last().unwrap();
+    assert_eq!((*direction, *typ), (PacketDirection::Sent, Some(MessageType::Disconnect)));
+}
+
+#[test]
+fn report_progress() {
+    let steps = Arc::new(Mutex::new(Vec::new()));
+    let list = steps.clone();
+    let callback = move |progress: ConnectProgress| {
+        let step = match progress {
+            ConnectProgress::VersionExchanged { server_version } => server_version.to_string(),
+            ConnectProgress::KexDone { algorithms, session_id } => format!("{} {}", algorithms.kex, session_id.len()),
+            ConnectProgress::AuthRejected { method, allowed } => format!("{} rejected, {} allowed", method, allowed),
+            ConnectProgress::AuthAccepted { method } => format!("{} accepted", method),
+            _ => unreachable!(),
+        };
+        list.lock().unwrap().push(step);
+    };
+
+    const METHODS: &[Auth] = &[
+        Auth::Ed25519 {
+            username: "user",
+            hex_keypair: CLIENT_HEX_KEYPAIR,
+        },
+        Auth::Password {
+            username: "user",
+            password: "hunter2",
+        },
+    ];
+
+    scripted(|stream| {
+        let options = ConnectOptions::default().progress(callback);
+        Connection::new_with_options(stream, Auth::Any(METHODS), options).map(drop).unwrap()
+    }, |mut server| {
+        server.key_exchange(&HostKey::new());
+        server.accept_service();
+        server.reject_auth("password");
+        server.accept_auth_requests();
+    });
+
+    assert_eq!(*steps.lock().unwrap(), [
+        "SSH-2.0-ScriptedServer",
+        "curve25519-sha256 32",
+        "publickey rejected, password allowed",
+        "password accepted",
+    ]);
+}
@@ -0,0 +1,177 @@
+//! Interoperability tests against a real OpenSSH server, run when
+//! `COOLSSH_SSHD` holds the path of an `sshd` binary, e.g.:
+//!
+//! ```text
+//! COOLSSH_SSHD=/usr/sbin/sshd cargo test --test openssh
+//! ```
+//!
+//! The server runs as the current user, on a free port of the
+//! loopback interface, with a throwaway configuration and host key.
+
+mod common;
+
+use std::fs;
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use coolssh::{Connection, ConnectOptions, ExitStatus, RunResult, create_ed25519_keypair, dump_ed25519_pk_openssh, dump_ed25519_sk_openssh};
+use common::*;
+
+const TRANSFER_SIZE: usize = 8 << 20;
+
+struct Sshd {
+    child: Child,
+    dir: PathBuf,
+    port: u16,
+}
+
+impl Sshd {
+    /// `None` (after saying so) when `COOLSSH_SSHD` isn't set
+    fn spawn(test: &str) -> Option<Self> {
+        let Some(sshd) = std::env::var_os("COOLSSH_SSHD") else {
+            eprintln!("skipping {}: COOLSSH_SSHD isn't set", test);
+            return None;
+        };
+
+        let dir = std::env::temp_dir().join(format!("coolssh-{}-{}", std::process::id(), test));
+        fs::create_dir_all(&dir).unwrap();
+
+        let host_key = dump_ed25519_sk_openssh(&create_ed25519_keypair(), "host", None).unwrap();
+        write_private(&dir.join("host_key"), &host_key);
+        write_private(&dir.join("authorized_keys"), &dump_ed25519_pk_openssh(CLIENT_HEX_KEYPAIR, "client"));
+
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let config = format!(concat!(
+            "ListenAddress 127.0.0.1:{port}\n",
+            "HostKey {dir}/host_key\n",
+            "AuthorizedKeysFile {dir}/authorized_keys\n",
+            "PidFile {dir}/sshd.pid\n",
+            "StrictModes no\n",
+            "PasswordAuthentication no\n",
+            "KbdInteractiveAuthentication no\n",
+        ), port = port, dir = dir.display());
+        fs::write(dir.join("sshd_config"), config).unwrap();
+
+        // sshd wants to be started with an absolute path
+        let child = Command::new(fs::canonicalize(sshd).unwrap())
+            .arg("-D")
+            .arg("-e")
+            .arg("-f")
+            .arg(dir.join("sshd_config"))
+            .stdin(Stdio::null())
+            .spawn()
+            .unwrap();
+
+        let sshd = Self {
+            child,
+            dir,
+            port,
+        };
+
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(Instant::now() < deadline, "sshd didn't start listening");
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        Some(sshd)
+    }
+
+    fn connect(&self) -> Connection {
+        let username = std::env::var("USER").unwrap_or_else(|_| "root".into());
+        let auth = (username.as_str(), CLIENT_HEX_KEYPAIR).into();
+        Connection::connect(("127.0.0.1", self.port), auth, ConnectOptions::default()).unwrap()
+    }
+}
+
+impl Drop for Sshd {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+fn write_private(path: &std::path::Path, contents: &str) {
+    fs::write(path, contents).unwrap();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600)).unwrap();
+    }
+}
+
+#[test]
+fn openssh_run() {
+    let Some(sshd) = Sshd::spawn("run") else {
+        return;
+    };
+
+    let conn = sshd.connect();
+    assert!(conn.server_version().starts_with("SSH-2.0-OpenSSH"));
+
+    let RunResult::Accepted((output, status)) = conn.quick_run("echo hello; exit 3").unwrap() else {
+        panic!("exec refused");
+    };
+
+    assert_eq!((output.as_str(), status), ("hello\n", ExitStatus::Code(3)));
+}
+
+#[test]
+fn openssh_sessions_in_sequence() {
+    let Some(sshd) = Sshd::spawn("sequence") else {
+        return;
+    };
+
+    let conn = sshd.connect();
+    for i in 0..10 {
+        let RunResult::Accepted((output, _)) = conn.quick_run(&format!("echo {}", i)).unwrap() else {
+            panic!("exec refused");
+        };
+
+        assert_eq!(output, format!("{}\n", i));
+    }
+}
+
+#[test]
+fn openssh_large_download() {
+    let Some(sshd) = Sshd::spawn("download") else {
+        return;
+    };
+
+    let conn = sshd.connect();
+    let command = format!("head -c {} /dev/zero", TRANSFER_SIZE);
+    let RunResult::Accepted((output, status)) = conn.quick_run_bytes(&command).unwrap() else {
+        panic!("exec refused");
+    };
+
+    assert_eq!(status, ExitStatus::Code(0));
+    assert_eq!(output.len(), TRANSFER_SIZE);
+    assert!(output.iter().all(|&byte| byte == 0));
+}
+
+#[test]
+fn openssh_large_upload() {
+    let Some(sshd) = Sshd::spawn("upload") else {
+        return;
+    };
+
+    let conn = sshd.connect();
+    let RunResult::Accepted(mut run) = conn.run("wc -c", &[]).unwrap() else {
+        panic!("exec refused");
+    };
+
+    let chunk = [0x5a; 32 * 1024];
+    for _ in 0..TRANSFER_SIZE / chunk.len() {
+        run.write_stdin(&chunk).unwrap();
+    }
+
+    run.close_write().unwrap();
+    let mut output = Vec::new();
+    let (_, status) = run.read_to(&mut output, |_| ()).unwrap();
+
+    assert_eq!(status, ExitStatus::Code(0));
+    assert_eq!(String::from_utf8(output).unwrap().trim(), TRANSFER_SIZE.to_string());
+}
@@ -0,0 +1,77 @@
+mod common;
+
+use std::io::Read;
+use coolssh::{RunResult, ExitStatus};
+use common::*;
+
+#[test]
+fn separate_streams() {
+    let (stdout, stderr, exit_status) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(run) = conn.run("make", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let output = run.into_output();
+        let mut stdout = String::new();
+        output.stdout().read_to_string(&mut stdout).unwrap();
+        let mut stderr = String::new();
+        output.stderr().read_to_string(&mut stderr).unwrap();
+
+        (stdout, stderr, output.exit_status())
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"make"));
+        server.channel_success(channel);
+
+        server.send_data(channel, b"building");
+        server.send_stderr(channel, b"warning: ");
+        server.send_data(channel, b"...\n");
+        server.send_stderr(channel, b"unused variable\n");
+
+        server.send_exit_status(channel, 2);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(stdout, "building...\n");
+    assert_eq!(stderr, "warning: unused variable\n");
+    assert_eq!(exit_status, Some(ExitStatus::Code(2)));
+}
+
+#[test]
+fn read_to_writer() {
+    let (output, written, exit_status, progress) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("cat big.iso", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut first = [0; 3];
+        run.read_exact(&mut first).unwrap();
+
+        let mut output = first.to_vec();
+        let mut progress = Vec::new();
+        let (written, exit_status) = run.read_to(&mut output, |bytes| progress.push(bytes)).unwrap();
+        (output, written, exit_status, progress)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send_data(channel, b"01234567");
+        server.send_stderr(channel, b"ignored");
+        server.send_data(channel, b"89");
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"0123456789");
+    assert_eq!(written, 7);
+    assert_eq!(exit_status, ExitStatus::Code(0));
+    assert_eq!(progress, [5, 7]);
+}
@@ -0,0 +1,84 @@
+mod common;
+
+use coolssh::{Pool, ErrorKind, RunResult};
+use common::*;
+
+fn echo(server: &mut Server, output: &[u8]) {
+    let (channel, _) = server.accept_channel();
+    server.expect_channel_request("exec");
+    server.channel_success(channel);
+    server.send_data(channel, output);
+    server.send_close(channel);
+    server.expect(97);
+}
+
+fn output(conn: &coolssh::Connection) -> String {
+    let RunResult::Accepted((output, _)) = conn.quick_run("echo").unwrap() else {
+        panic!("exec refused");
+    };
+
+    output
+}
+
+#[test]
+fn pool_reuses_connections() {
+    let (outputs, open) = scripted_listener(|addr| {
+        let pool = Pool::new(("user", CLIENT_HEX_KEYPAIR).into(), Default::default()).max_per_host(1);
+        let host = addr.to_string();
+
+        let first = output(&pool.checkout(&host).unwrap());
+        let second = output(&pool.checkout(&host).unwrap());
+        ((first, second), pool.open_connections(&host))
+    }, |listener| {
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        echo(&mut server, b"first\n");
+        server.expect(2);
+        echo(&mut server, b"second\n");
+    });
+
+    assert_eq!(outputs, ("first\n".into(), "second\n".into()));
+    assert_eq!(open, 1);
+}
+
+#[test]
+fn pool_replaces_unhealthy_connections() {
+    let (outputs, open) = scripted_listener(|addr| {
+        let pool = Pool::new(("user", CLIENT_HEX_KEYPAIR).into(), Default::default())
+            .health_check(|_| Err(ErrorKind::ConnectionLost.into()));
+        let host = addr.to_string();
+
+        let first = output(&pool.checkout(&host).unwrap());
+        let second = output(&pool.checkout(&host).unwrap());
+        ((first, second), pool.open_connections(&host))
+    }, |listener| {
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        echo(&mut server, b"first\n");
+        server.expect(1);
+
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        echo(&mut server, b"second\n");
+    });
+
+    assert_eq!(outputs, ("first\n".into(), "second\n".into()));
+    assert_eq!(open, 1);
+}
+
+#[test]
+fn discarded_connections_free_their_slot() {
+    let open = scripted_listener(|addr| {
+        let pool = Pool::new(("user", CLIENT_HEX_KEYPAIR).into(), Default::default()).max_per_host(1);
+        let host = addr.to_string();
+
+        pool.checkout(&host).unwrap().discard();
+        pool.open_connections(&host)
+    }, |listener| {
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        server.expect(1);
+    });
+
+    assert_eq!(open, 0);
+}
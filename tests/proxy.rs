@@ -0,0 +1,122 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, SocketAddr, Ipv4Addr};
+use coolssh::{Connection, ConnectOptions, Socks5, RunResult, ErrorKind};
+use common::*;
+
+/// Reply code of a SOCKS5 proxy to a CONNECT request
+const SUCCEEDED: u8 = 0;
+const CONNECTION_REFUSED: u8 = 5;
+
+/// Serves one client like a SOCKS5 proxy, which requires `credentials`
+/// if set and answers CONNECT requests with `reply`; returns its address.
+fn socks5_proxy(credentials: Option<(&'static str, &'static str)>, reply: u8) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (mut client, _) = listener.accept().unwrap();
+
+        let mut greeting = [0; 2];
+        client.read_exact(&mut greeting).unwrap();
+        assert_eq!(greeting[0], 5);
+        let mut methods = vec![0; greeting[1] as usize];
+        client.read_exact(&mut methods).unwrap();
+
+        if let Some((username, password)) = credentials {
+            assert!(methods.contains(&2));
+            client.write_all(&[5, 2]).unwrap();
+
+            let mut header = [0; 2];
+            client.read_exact(&mut header).unwrap();
+            let mut received_username = vec![0; header[1] as usize];
+            client.read_exact(&mut received_username).unwrap();
+            let mut len = [0];
+            client.read_exact(&mut len).unwrap();
+            let mut received_password = vec![0; len[0] as usize];
+            client.read_exact(&mut received_password).unwrap();
+
+            let valid = (&received_username[..], &received_password[..]) == (username.as_bytes(), password.as_bytes());
+            client.write_all(&[1, !valid as u8]).unwrap();
+            if !valid {
+                return;
+            }
+        } else {
+            client.write_all(&[5, 0]).unwrap();
+        }
+
+        let mut request = [0; 10];
+        client.read_exact(&mut request).unwrap();
+        assert_eq!(request[..4], [5, 1, 0, 1]);
+        let ip = Ipv4Addr::new(request[4], request[5], request[6], request[7]);
+        let port = u16::from_be_bytes([request[8], request[9]]);
+
+        client.write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0, 0]).unwrap();
+        if reply != SUCCEEDED {
+            return;
+        }
+
+        let server = TcpStream::connect((ip, port)).unwrap();
+        let (mut client_reader, mut server_writer) = (client.try_clone().unwrap(), server.try_clone().unwrap());
+        std::thread::spawn(move || std::io::copy(&mut client_reader, &mut server_writer));
+        let (mut server_reader, mut client_writer) = (server, client);
+        let _ = std::io::copy(&mut server_reader, &mut client_writer);
+    });
+
+    addr
+}
+
+fn through(proxy: SocketAddr, auth: Option<(&str, &str)>) -> ConnectOptions {
+    ConnectOptions::default().proxy(Socks5 {
+        addr: proxy.to_string(),
+        auth: auth.map(|(username, password)| (username.into(), password.into())),
+    })
+}
+
+#[test]
+fn connect_through_proxy() {
+    let output = scripted_at(|addr| {
+        let options = through(socks5_proxy(None, SUCCEEDED), None);
+        let conn = Connection::connect(addr, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        conn.quick_run("true").unwrap()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert!(matches!(output, RunResult::Accepted((_, status)) if status.success()));
+}
+
+#[test]
+fn proxy_credentials() {
+    let result = scripted_at(|addr| {
+        let proxy = socks5_proxy(Some(("alice", "secret")), SUCCEEDED);
+        let options = through(proxy, Some(("alice", "secret")));
+        Connection::connect(addr, ("user", CLIENT_HEX_KEYPAIR).into(), options).map(drop)
+    }, |mut server| {
+        server.accept_session();
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Ok(()));
+}
+
+#[test]
+fn wrong_proxy_credentials() {
+    let proxy = socks5_proxy(Some(("alice", "secret")), SUCCEEDED);
+    let target = SocketAddr::from(([127, 0, 0, 1], 22));
+    let result = Connection::connect(target, ("user", CLIENT_HEX_KEYPAIR).into(), through(proxy, Some(("alice", "guess"))));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::AuthenticationFailure);
+}
+
+#[test]
+fn proxy_refuses_to_connect() {
+    let proxy = socks5_proxy(None, CONNECTION_REFUSED);
+    let target = SocketAddr::from(([127, 0, 0, 1], 22));
+    let result = Connection::connect(target, ("user", CLIENT_HEX_KEYPAIR).into(), through(proxy, None));
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::TcpError(std::io::ErrorKind::ConnectionRefused));
+}
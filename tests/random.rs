@@ -0,0 +1,55 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use coolssh::{Connection, ConnectOptions};
+
+const CLIENT_HEX_KEYPAIR: &str = concat!(
+    "0000000000000000000000000000000000000000000000000000000000000000",
+    "3b6a27bcceb6a42d62a3a8d02a6f0d73653215771de243a63ac048a18b59da29",
+);
+
+/// Returns the cookie and the padding of the client's KEXINIT
+fn kexinit_randomness(options: ConnectOptions) -> (Vec<u8>, Vec<u8>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let client = std::thread::spawn(move || {
+        let stream = TcpStream::connect(addr).unwrap();
+        // the server hangs up after the KEXINIT
+        let _ = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options);
+    });
+
+    let (mut stream, _) = listener.accept().unwrap();
+    stream.write_all(b"SSH-2.0-ScriptedServer\r\n").unwrap();
+    let mut reader = BufReader::new(stream);
+    let mut version = String::new();
+    reader.read_line(&mut version).unwrap();
+
+    let mut length = [0; 4];
+    reader.read_exact(&mut length).unwrap();
+    let mut packet = vec![0; u32::from_be_bytes(length) as usize];
+    reader.read_exact(&mut packet).unwrap();
+    drop(reader);
+    client.join().unwrap();
+
+    let padding_length = packet[0] as usize;
+    assert_eq!(packet[1], 20);
+    let cookie = packet[2..18].to_vec();
+    let padding = packet[packet.len() - padding_length..].to_vec();
+    (cookie, padding)
+}
+
+#[test]
+fn random_cookie_and_padding() {
+    let (first_cookie, first_padding) = kexinit_randomness(ConnectOptions::default());
+    let (second_cookie, second_padding) = kexinit_randomness(ConnectOptions::default());
+    assert_ne!(first_cookie, second_cookie);
+    assert_ne!(first_padding, second_padding);
+}
+
+#[test]
+fn deterministic_random_source() {
+    let options = ConnectOptions::default().random_source(|bytes| bytes.fill(0x5a));
+    let (cookie, padding) = kexinit_randomness(options);
+    assert_eq!(cookie, [0x5a; 16]);
+    assert!(padding.len() >= 4);
+    assert!(padding.iter().all(|&byte| byte == 0x5a));
+}
@@ -0,0 +1,86 @@
+mod common;
+
+use std::cell::Cell;
+use std::time::Duration;
+use coolssh::{ReconnectingConnection, ErrorKind, RunResult};
+use common::*;
+
+fn reconnecting(addr: std::net::SocketAddr) -> ReconnectingConnection<'static, std::net::SocketAddr> {
+    ReconnectingConnection::connect(addr, ("user", CLIENT_HEX_KEYPAIR).into(), Default::default())
+        .unwrap()
+        .backoff(Duration::from_millis(1), Duration::from_millis(10), 3)
+}
+
+#[test]
+fn reconnect_and_replay_setup() {
+    let (output, setups) = scripted_listener(|addr| {
+        let setups = Cell::new(0);
+        let mut conn = reconnecting(addr);
+        conn.env("LANG", "C");
+        conn.on_connect(|_| {
+            setups.set(setups.get() + 1);
+            Ok(())
+        }).unwrap();
+
+        let RunResult::Accepted((output, _)) = conn.quick_run("echo hello").unwrap() else {
+            panic!("exec refused");
+        };
+
+        drop(conn);
+        (output, setups.get())
+    }, |listener| {
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        server.expect(90);
+        drop(server);
+
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("env");
+        server.channel_success(channel);
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"hello\n");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, "hello\n");
+    assert_eq!(setups, 2);
+}
+
+#[test]
+fn reconnect_rejects_other_host_key() {
+    let result = scripted_listener(|addr| {
+        let mut conn = reconnecting(addr);
+        conn.quick_run("true").map(|_| ()).map_err(|e| e.kind())
+    }, |listener| {
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        server.expect(90);
+        drop(server);
+
+        let mut server = Server::accept(&listener);
+        server.key_exchange(&HostKey::ecdsa_p256());
+        server.accept_auth();
+    });
+
+    assert_eq!(result, Err(ErrorKind::UntrustedHostKey));
+}
+
+#[test]
+fn reconnect_gives_up() {
+    let result = scripted_listener(|addr| {
+        let mut conn = reconnecting(addr);
+        conn.quick_run("true").map(|_| ()).map_err(|e| e.kind())
+    }, |listener| {
+        let mut server = Server::accept(&listener);
+        server.accept_session();
+        server.expect(90);
+        drop(server);
+        drop(listener);
+    });
+
+    assert!(matches!(result, Err(ErrorKind::TcpError(_))));
+}
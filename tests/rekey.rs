@@ -0,0 +1,76 @@
+mod common;
+
+use std::time::Duration;
+use coolssh::{Connection, ConnectOptions, RunResult};
+use common::*;
+
+const CHUNK: usize = 0x4000;
+
+#[test]
+fn rekey_after_byte_limit() {
+    let output: Vec<u8> = (0..4 * CHUNK as u32).map(|i| (i % 251) as u8).collect();
+    let expected = output.clone();
+
+    let received = scripted(|stream| {
+        // reached after the second chunk
+        let options = ConnectOptions::default().rekey_limits(2 * CHUNK as u64, Duration::from_secs(3600));
+        let conn = Connection::new_with_options(stream, ("user", CLIENT_HEX_KEYPAIR).into(), options).unwrap();
+        let RunResult::Accepted((received, _)) = conn.quick_run_bytes("cat data").unwrap() else {
+            panic!("exec refused");
+        };
+        received
+    }, move |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat data"));
+        server.channel_success(channel);
+
+        let mut chunks = output.chunks(CHUNK);
+        for chunk in chunks.by_ref().take(3) {
+            server.send_data(channel, chunk);
+        }
+
+        // the client must keep the third chunk, which
+        // it receives after sending its KEXINIT
+        server.key_exchange(&HostKey::new());
+
+        for chunk in chunks {
+            server.send_data(channel, chunk);
+        }
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert!(received == expected);
+}
+
+#[test]
+fn rekey_started_by_server() {
+    let output: Vec<u8> = (0..2 * CHUNK as u32).map(|i| (i % 251) as u8).collect();
+    let expected = output.clone();
+
+    let received = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted((received, _)) = conn.quick_run_bytes("cat data").unwrap() else {
+            panic!("exec refused");
+        };
+        received
+    }, move |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat data"));
+        server.channel_success(channel);
+
+        server.send_data(channel, &output[..CHUNK]);
+        server.initiate_key_exchange(&HostKey::new());
+        server.send_data(channel, &output[CHUNK..]);
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert!(received == expected);
+}
@@ -0,0 +1,242 @@
+//! Property tests: random messages must parse, then dump
+//! back to the exact same bytes
+
+use coolssh::{Message, OwnedMessage, MessageType};
+
+const CASES: usize = 256;
+
+/// xorshift64, seeded per test so that failures can be replayed
+struct Gen(u64);
+
+impl Gen {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn u32(&mut self) -> u32 {
+        match self.next() % 4 {
+            0 => 0,
+            1 => u32::MAX,
+            _ => self.next() as u32,
+        }
+    }
+
+    fn bool(&mut self) -> bool {
+        self.next() & 1 == 0
+    }
+
+    fn bytes(&mut self) -> Vec<u8> {
+        let len = self.next() % 64;
+        (0..len).map(|_| self.next() as u8).collect()
+    }
+
+    fn text(&mut self) -> String {
+        const CHARS: [char; 8] = ['a', 'Z', '0', '-', ',', ' ', 'é', '✓'];
+        let len = self.next() % 24;
+        (0..len).map(|_| CHARS[(self.next() % 8) as usize]).collect()
+    }
+
+    /// Comma-separated, non-empty names of printable ASCII
+    fn name_list(&mut self) -> String {
+        let names: Vec<String> = (0..self.next() % 4).map(|_| {
+            let len = 1 + self.next() % 12;
+            (0..len).map(|_| (b'!' + (self.next() % 94) as u8) as char).filter(|c| *c != ',').chain(['x']).collect()
+        }).collect();
+
+        names.join(",")
+    }
+
+    fn one_of<'a>(&mut self, choices: &[&'a str]) -> &'a str {
+        choices[(self.next() % choices.len() as u64) as usize]
+    }
+}
+
+/// Appends fields in their wire encoding
+#[derive(Default)]
+struct Payload(Vec<u8>);
+
+impl Payload {
+    fn new(typ: MessageType) -> Self {
+        Self(vec![typ as u8])
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend(value.to_be_bytes());
+        self
+    }
+
+    fn bool(mut self, value: bool) -> Self {
+        self.0.push(value as u8);
+        self
+    }
+
+    fn string(self, value: &[u8]) -> Self {
+        let mut this = self.u32(value.len() as u32);
+        this.0.extend(value);
+        this
+    }
+
+    fn text(self, value: &str) -> Self {
+        self.string(value.as_bytes())
+    }
+
+    /// A string holding an algorithm name and a string
+    fn blob(self, header: &str, content: &[u8]) -> Self {
+        self.string(&Payload::default().text(header).string(content).0)
+    }
+}
+
+/// Parses `payload`, dumps the message and checks that nothing changed
+fn round_trip(payload: Payload) -> OwnedMessage {
+    let payload = payload.0;
+    let owned = OwnedMessage::from_payload(payload.clone()).unwrap_or_else(|e| panic!("{:?}: {}", payload, e));
+    let dumped = owned.message().to_owned_message().unwrap();
+    assert_eq!(dumped.payload(), payload, "{:?}", owned.message());
+    owned
+}
+
+fn check<F: FnMut(&mut Gen) -> Payload>(seed: u64, mut generate: F) {
+    let mut gen = Gen(seed);
+    for _ in 0..CASES {
+        round_trip(generate(&mut gen));
+    }
+}
+
+#[test]
+fn transport_messages() {
+    check(1, |gen| Payload::new(MessageType::Disconnect).u32(1 + gen.u32() % 15).text(&gen.text()).text(&gen.text()));
+    check(2, |gen| Payload::new(MessageType::Unimplemented).u32(gen.u32()));
+    check(3, |gen| Payload::new(MessageType::ServiceRequest).text(&gen.text()));
+    check(4, |gen| Payload::new(MessageType::ServiceAccept).text(&gen.text()));
+    check(5, |_| Payload::new(MessageType::Newkeys));
+
+    check(6, |gen| {
+        let mut payload = Payload::new(MessageType::ExtInfo);
+        let count = gen.next() % 4;
+        payload = payload.u32(count as u32);
+        for _ in 0..count {
+            payload = payload.text(&gen.text()).string(&gen.bytes());
+        }
+
+        payload
+    });
+
+    check(7, |gen| {
+        let mut payload = Payload::new(MessageType::Kexinit);
+        payload.0.extend((0..16).map(|_| gen.next() as u8));
+        for _ in 0..10 {
+            payload = payload.text(&gen.name_list());
+        }
+
+        payload.bool(gen.bool()).u32(gen.u32())
+    });
+}
+
+#[test]
+fn userauth_messages() {
+    check(10, |gen| {
+        let payload = Payload::new(MessageType::UserauthRequest).text(&gen.text()).text(&gen.text());
+        match gen.next() % 4 {
+            0 => payload.text("none"),
+            1 => {
+                let signed = gen.bool();
+                let payload = payload.text("publickey").bool(signed).text(&gen.text()).string(&gen.bytes());
+                match signed {
+                    true => payload.blob(&gen.text(), &gen.bytes()),
+                    false => payload,
+                }
+            },
+            // the language tag is deprecated, and dumped empty
+            2 => payload.text("keyboard-interactive").text("").text(&gen.text()),
+            _ => {
+                let change = gen.bool();
+                let payload = payload.text("password").bool(change).text(&gen.text());
+                match change {
+                    true => payload.text(&gen.text()),
+                    false => payload,
+                }
+            },
+        }
+    });
+
+    check(11, |gen| Payload::new(MessageType::UserauthFailure).text(&gen.text()).bool(gen.bool()));
+    check(12, |_| Payload::new(MessageType::UserauthSuccess));
+    check(13, |gen| Payload::new(MessageType::UserauthBanner).text(&gen.text()).text(&gen.text()));
+
+    // public key blobs have a variable number of fields (e.g. three for RSA)
+    check(14, |gen| {
+        let mut blob = Payload::default().text(&gen.text());
+        for _ in 0..gen.next() % 4 {
+            blob = blob.string(&gen.bytes());
+        }
+
+        Payload::new(MessageType::UserauthPkOk).text(&gen.text()).string(&blob.0)
+    });
+}
+
+#[test]
+fn connection_messages() {
+    check(20, |gen| Payload::new(MessageType::GlobalRequest).text(&gen.text()).bool(gen.bool()));
+    check(21, |gen| Payload::new(MessageType::ChannelOpen).text(&gen.text()).u32(gen.u32()).u32(gen.u32()).u32(gen.u32()));
+    check(22, |gen| Payload::new(MessageType::ChannelOpenConfirmation).u32(gen.u32()).u32(gen.u32()).u32(gen.u32()).u32(gen.u32()));
+    check(23, |gen| Payload::new(MessageType::ChannelOpenFailure).u32(gen.u32()).u32(gen.u32()).text(&gen.text()).text(&gen.text()));
+    check(24, |gen| Payload::new(MessageType::ChannelWindowAdjust).u32(gen.u32()).u32(gen.u32()));
+    check(25, |gen| Payload::new(MessageType::ChannelData).u32(gen.u32()).string(&gen.bytes()));
+    check(26, |gen| Payload::new(MessageType::ChannelExtendedData).u32(gen.u32()).u32(gen.u32()).string(&gen.bytes()));
+    check(27, |gen| Payload::new(MessageType::ChannelEof).u32(gen.u32()));
+    check(28, |gen| Payload::new(MessageType::ChannelClose).u32(gen.u32()));
+    check(29, |gen| Payload::new(MessageType::ChannelSuccess).u32(gen.u32()));
+    check(30, |gen| Payload::new(MessageType::ChannelFailure).u32(gen.u32()));
+}
+
+#[test]
+fn channel_requests() {
+    check(40, |gen| {
+        let request_type = gen.one_of(&[
+            "exec", "env", "subsystem", "pty-req", "shell", "window-change", "x11-req",
+            "auth-agent-req@openssh.com", "signal", "exit-status", "exit-signal",
+        ]);
+
+        // these can't ask for a reply
+        let want_reply = match request_type {
+            "window-change" | "signal" | "exit-status" | "exit-signal" => false,
+            _ => gen.bool(),
+        };
+
+        let payload = Payload::new(MessageType::ChannelRequest).u32(gen.u32()).text(request_type).bool(want_reply);
+        match request_type {
+            "exec" | "subsystem" | "signal" => payload.text(&gen.text()),
+            "env" => payload.text(&gen.text()).text(&gen.text()),
+            "pty-req" => payload.text(&gen.text()).u32(gen.u32()).u32(gen.u32()).u32(gen.u32()).u32(gen.u32()).string(&gen.bytes()),
+            "window-change" => payload.u32(gen.u32()).u32(gen.u32()).u32(gen.u32()).u32(gen.u32()),
+            "x11-req" => payload.bool(gen.bool()).text(&gen.text()).text(&gen.text()).u32(gen.u32()),
+            "exit-status" => payload.u32(gen.u32()),
+            "exit-signal" => payload.text(&gen.text()).bool(gen.bool()).text(&gen.text()).text(&gen.text()),
+            _ => payload,
+        }
+    });
+}
+
+#[test]
+fn signature_blobs_must_be_exact() {
+    let request = |blob: Payload| Payload::new(MessageType::UserauthRequest)
+        .text("user")
+        .text("ssh-connection")
+        .text("publickey")
+        .bool(true)
+        .text("ssh-ed25519")
+        .string(b"key")
+        .string(&blob.0);
+
+    let valid = Payload::default().text("ssh-ed25519").string(&[7; 64]);
+    assert!(matches!(round_trip(request(valid)).message(), Message::UserauthRequest(_)));
+
+    let trailing = Payload::default().text("ssh-ed25519").string(&[7; 64]).u32(0);
+    assert!(OwnedMessage::from_payload(request(trailing).0).is_err());
+
+    let truncated = Payload::default().text("ssh-ed25519");
+    assert!(OwnedMessage::from_payload(request(truncated).0).is_err());
+}
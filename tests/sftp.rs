@@ -0,0 +1,195 @@
+mod common;
+
+use std::collections::HashMap;
+use coolssh::{ErrorKind, OpenFlags, RunResult, Sftp, SftpStatus};
+use common::*;
+
+/// An in-memory SFTP v3 server, one file per path
+fn serve_sftp(server: &mut Server, channel: u32) {
+    let mut files: HashMap<Vec<u8>, Vec<u8>> = HashMap::new();
+    let mut input = Vec::new();
+    let mut dir_listed = false;
+
+    loop {
+        let message = server.recv();
+        match message[0] {
+            94 => input.extend_from_slice(take_string(&message, &mut 5)),
+            97 => return,
+            _ => continue,
+        }
+
+        while input.len() >= 4 && input.len() >= 4 + take_u32(&input, &mut 0) as usize {
+            let len = take_u32(&input, &mut 0) as usize;
+            let packet: Vec<u8> = input.drain(..4 + len).skip(4).collect();
+
+            if packet[0] == 1 {
+                assert_eq!(take_u32(&packet, &mut 1), 3);
+                server.send_data(channel, &sftp_packet(2, &3u32.to_be_bytes()));
+                continue;
+            }
+
+            let mut i = 1;
+            let id = take_u32(&packet, &mut i);
+            let status = |code: u32| {
+                let mut body = id.to_be_bytes().to_vec();
+                body.extend_from_slice(&code.to_be_bytes());
+                body.extend(string(b""));
+                body.extend(string(b""));
+                sftp_packet(101, &body)
+            };
+            let with_id = |typ, fields: &[u8]| {
+                let mut body = id.to_be_bytes().to_vec();
+                body.extend_from_slice(fields);
+                sftp_packet(typ, &body)
+            };
+
+            let response = match packet[0] {
+                // OPEN: the handle is the path
+                3 => {
+                    let path = take_string(&packet, &mut i).to_vec();
+                    let flags = take_u32(&packet, &mut i);
+                    if flags & 0x08 != 0 {
+                        files.insert(path.clone(), Vec::new());
+                    }
+
+                    match files.contains_key(&path) {
+                        true => with_id(102, &string(&path)),
+                        false => status(2),
+                    }
+                },
+                // CLOSE
+                4 => status(0),
+                // READ
+                5 => {
+                    let content = &files[take_string(&packet, &mut i)];
+                    let offset = take_u64(&packet, &mut i) as usize;
+                    let len = take_u32(&packet, &mut i) as usize;
+                    match offset < content.len() {
+                        true => with_id(103, &string(&content[offset..(offset + len).min(content.len())])),
+                        false => status(1),
+                    }
+                },
+                // WRITE
+                6 => {
+                    let path = take_string(&packet, &mut i).to_vec();
+                    let offset = take_u64(&packet, &mut i) as usize;
+                    let data = take_string(&packet, &mut i);
+                    let content = files.get_mut(&path).unwrap();
+                    assert_eq!(offset, content.len());
+                    content.extend_from_slice(data);
+                    status(0)
+                },
+                // OPENDIR
+                11 => with_id(102, &string(take_string(&packet, &mut i))),
+                // READDIR
+                12 if !dir_listed => {
+                    dir_listed = true;
+                    let mut names = 1u32.to_be_bytes().to_vec();
+                    names.extend(string(b"notes.txt"));
+                    names.extend(string(b"-rw-r--r-- 1 user user 5 notes.txt"));
+                    names.extend_from_slice(&0x5u32.to_be_bytes());
+                    names.extend_from_slice(&5u64.to_be_bytes());
+                    names.extend_from_slice(&0o100644u32.to_be_bytes());
+                    with_id(104, &names)
+                },
+                12 => status(1),
+                // STAT
+                17 => match files.get(take_string(&packet, &mut i)) {
+                    Some(content) => {
+                        let mut attrs = 0x1u32.to_be_bytes().to_vec();
+                        attrs.extend_from_slice(&(content.len() as u64).to_be_bytes());
+                        with_id(105, &attrs)
+                    },
+                    None => status(2),
+                },
+                typ => panic!("unexpected SFTP request {}", typ),
+            };
+
+            // within the client's maximum packet size
+            for chunk in response.chunks(server.client_window.1 as usize) {
+                server.send_data(channel, chunk);
+            }
+        }
+    }
+}
+
+fn sftp_packet(typ: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = (1 + body.len() as u32).to_be_bytes().to_vec();
+    packet.push(typ);
+    packet.extend_from_slice(body);
+    packet
+}
+
+fn start_sftp(server: &mut Server) -> u32 {
+    server.accept_session();
+    let (channel, _) = server.accept_channel();
+    assert_eq!(server.expect_channel_request("subsystem"), string(b"sftp"));
+    server.channel_success(channel);
+    channel
+}
+
+#[test]
+fn upload_stat_download() {
+    // bigger than one SFTP chunk and one channel packet
+    let content: Vec<u8> = (0..100_000u32).map(|i| i as u8).collect();
+    let expected = content.clone();
+
+    let (uploaded, size, downloaded) = scripted(move |stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut sftp) = Sftp::new(&conn).unwrap() else {
+            panic!("sftp refused");
+        };
+
+        let uploaded = sftp.upload(content.as_slice(), "/tmp/data").unwrap();
+        let size = sftp.stat("/tmp/data").unwrap().size;
+        let mut downloaded = Vec::new();
+        sftp.download("/tmp/data", &mut downloaded).unwrap();
+        (uploaded, size, downloaded)
+    }, |mut server| {
+        let channel = start_sftp(&mut server);
+        serve_sftp(&mut server, channel);
+    });
+
+    assert_eq!(uploaded, expected.len() as u64);
+    assert_eq!(size, Some(expected.len() as u64));
+    assert!(downloaded == expected);
+}
+
+#[test]
+fn read_dir_and_missing_file() {
+    let (entries, missing) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut sftp) = Sftp::new(&conn).unwrap() else {
+            panic!("sftp refused");
+        };
+
+        let entries = sftp.read_dir("/home/user").unwrap();
+        let missing = sftp.open("/nope", OpenFlags::READ);
+        (entries, missing)
+    }, |mut server| {
+        let channel = start_sftp(&mut server);
+        serve_sftp(&mut server, channel);
+    });
+
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0].filename, "notes.txt");
+    assert_eq!(entries[0].attributes.size, Some(5));
+    assert_eq!(entries[0].attributes.permissions, Some(0o100644));
+    assert_eq!(missing.map_err(|e| e.kind()), Err(ErrorKind::Sftp(SftpStatus::NoSuchFile)));
+}
+
+#[test]
+fn refused_subsystem() {
+    let refused = scripted(|stream| {
+        let conn = connect(stream);
+        let refused = matches!(Sftp::new(&conn), Ok(RunResult::Refused));
+        refused
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("subsystem");
+        server.channel_failure(channel);
+    });
+
+    assert!(refused);
+}
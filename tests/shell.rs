@@ -0,0 +1,149 @@
+mod common;
+
+use std::time::{Duration, Instant};
+use coolssh::{RunResult, TerminalSize, ExitStatus};
+use common::*;
+
+#[test]
+fn interactive_session() {
+    let (output, exit_status) = scripted(|stream| {
+        let conn = connect(stream);
+        let size = TerminalSize::new(80, 24);
+        let RunResult::Accepted(mut shell) = conn.shell("xterm", size, &[(53, 0)]).unwrap() else {
+            panic!("shell refused");
+        };
+
+        shell.write(b"echo hi\n").unwrap();
+        shell.resize(TerminalSize::new(120, 40)).unwrap();
+
+        let mut output = Vec::new();
+        let mut buf = [0; 4];
+        loop {
+            match shell.read(&mut buf).unwrap() {
+                0 => break,
+                len => output.extend_from_slice(&buf[..len]),
+            }
+        }
+
+        (output, shell.exit_status())
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+
+        let pty_req = server.expect_channel_request("pty-req");
+        let mut i = 0;
+        assert_eq!(take_string(&pty_req, &mut i), b"xterm");
+        assert_eq!(take_u32(&pty_req, &mut i), 80);
+        assert_eq!(take_u32(&pty_req, &mut i), 24);
+        assert_eq!(take_u32(&pty_req, &mut i), 0);
+        assert_eq!(take_u32(&pty_req, &mut i), 0);
+        assert_eq!(take_string(&pty_req, &mut i), [53, 0, 0, 0, 0, 0]);
+        server.channel_success(channel);
+
+        assert!(server.expect_channel_request("shell").is_empty());
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"echo hi\n");
+
+        let window_change = server.expect_channel_request("window-change");
+        assert_eq!(window_change[..8], [0, 0, 0, 120, 0, 0, 0, 40]);
+
+        server.send_data(channel, b"hi\r\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"hi\r\n");
+    assert_eq!(exit_status, Some(ExitStatus::Code(0)));
+}
+
+#[test]
+fn refused_pty() {
+    let refused = scripted(|stream| {
+        let conn = connect(stream);
+        let refused = matches!(conn.shell("xterm", TerminalSize::new(80, 24), &[]).unwrap(), RunResult::Refused);
+
+        // the connection is still usable
+        conn.send_ignore(0).unwrap();
+        refused
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+
+        let pty_req = server.expect(98);
+        let mut i = 5;
+        assert_eq!(take_string(&pty_req, &mut i), b"pty-req");
+        assert_eq!(pty_req[i], 1, "pty-req must want a reply");
+        server.channel_failure(channel);
+
+        // a server may still start the shell, without a pty
+        server.expect_channel_request("shell");
+        server.channel_success(channel);
+
+        server.expect(97);
+        server.send_close(channel);
+        server.expect(2);
+    });
+
+    assert!(refused);
+}
+
+#[test]
+fn obscured_keystroke_timing() {
+    const INTERVAL: Duration = Duration::from_millis(40);
+
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut shell) = conn.shell("xterm", TerminalSize::new(80, 24), &[]).unwrap() else {
+            panic!("shell refused");
+        };
+
+        shell.obscure_keystroke_timing(Some(INTERVAL));
+        for keystroke in [b"l", b"s", b"\n"] {
+            shell.write(keystroke).unwrap();
+        }
+
+        let mut output = Vec::new();
+        let mut buf = [0; 16];
+        loop {
+            match shell.read(&mut buf).unwrap() {
+                0 => break output,
+                len => output.extend_from_slice(&buf[..len]),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("pty-req");
+        server.channel_success(channel);
+        server.expect_channel_request("shell");
+        server.channel_success(channel);
+
+        // (arrival, message number, payload length)
+        let mut packets = Vec::new();
+        while packets.iter().filter(|(_, number, _)| *number == 2).count() < 5 {
+            let payload = server.recv();
+            packets.push((Instant::now(), payload[0], payload.len()));
+        }
+
+        let numbers: Vec<u8> = packets.iter().map(|(_, number, _)| *number).collect();
+        assert_eq!(numbers, [94, 94, 94, 2, 2, 2, 2, 2]);
+        // chaff looks like keystrokes
+        assert!(packets.iter().all(|(_, _, len)| *len == packets[0].2));
+        for pair in packets.windows(2) {
+            assert!(pair[1].0 - pair[0].0 >= INTERVAL / 2, "{:?}", pair[1].0 - pair[0].0);
+        }
+
+        server.send_data(channel, b"Cargo.toml\r\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        loop {
+            if server.recv()[0] == 97 {
+                break;
+            }
+        }
+    });
+
+    assert_eq!(output, b"Cargo.toml\r\n");
+}
@@ -0,0 +1,377 @@
+mod common;
+
+use std::io::Read;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use coolssh::{RunResult, RunEvent, ErrorKind, ExitStatus, TransferProgress};
+use common::*;
+
+#[test]
+fn write_stdin_then_eof() {
+    let exit_status = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("cat > file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        run.write_stdin(b"first line\n").unwrap();
+        run.write_stdin(b"second line\n").unwrap();
+        run.close_write().unwrap();
+        assert_eq!(run.write_stdin(b"too late").map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+
+        loop {
+            if let RunEvent::Stopped(exit_status) = run.poll().unwrap() {
+                break exit_status;
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat > file"));
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"first line\n");
+        assert_eq!(server.recv_data(), b"second line\n");
+        server.expect(96);
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(exit_status, ExitStatus::Code(0));
+}
+
+#[test]
+fn output_received_while_writing_is_kept() {
+    let events = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("tee file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // only half of it fits in the window
+        run.write_stdin(b"0123456789abcdef").unwrap();
+        run.close_write().unwrap();
+
+        let mut events = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => events.push(data.to_vec()),
+                RunEvent::Stopped(_) => break events,
+                _ => (),
+            }
+        }
+    }, |mut server| {
+        server.initial_window = 8;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"tee file"));
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"01234567");
+        server.send_data(channel, b"01234567");
+        server.send_window_adjust(channel, 8);
+
+        assert_eq!(server.recv_data(), b"89abcdef");
+        server.expect(96);
+        server.send_data(channel, b"89abcdef");
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(events, [b"01234567", b"89abcdef"]);
+}
+
+#[test]
+fn run_as_io_stream() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("tr a-z A-Z", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // only half of it fits in the window
+        std::io::copy(&mut &b"0123456789abcdef"[..], &mut run).unwrap();
+        run.close_write().unwrap();
+
+        let mut output = [0; 3];
+        run.read_exact(&mut output).unwrap();
+        let mut rest = Vec::new();
+        run.read_to_end(&mut rest).unwrap();
+        (output, rest)
+    }, |mut server| {
+        server.initial_window = 8;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"01234567");
+        server.send_data(channel, b"01234567");
+        server.send_window_adjust(channel, 8);
+
+        assert_eq!(server.recv_data(), b"89abcdef");
+        server.expect(96);
+        server.send_stderr(channel, b"ignored");
+        server.send_data(channel, b"89ABCDEF");
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(&output.0, b"012");
+    assert_eq!(output.1, b"3456789ABCDEF");
+}
+
+#[test]
+fn transfer_progress() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let list = reports.clone();
+
+    scripted(move |stream| {
+        let conn = connect(stream);
+        conn.set_channel_window(32, 16).unwrap();
+        let RunResult::Accepted(mut run) = conn.run("tee file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        run.set_transfer_progress(move |progress| list.lock().unwrap().push(progress));
+        run.write_stdin(b"0123456789abcdef").unwrap();
+        run.close_write().unwrap();
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+    }, |mut server| {
+        server.initial_window = 8;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.recv_data();
+        server.send_data(channel, b"0123");
+        server.send_window_adjust(channel, 8);
+        server.recv_data();
+        server.expect(96);
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    let progress = |sent, received, server_window, client_window| TransferProgress { sent, received, server_window, client_window };
+    assert_eq!(*reports.lock().unwrap(), [
+        progress(8, 0, 0, 32),
+        progress(8, 4, 0, 28),
+        progress(8, 4, 8, 28),
+        progress(16, 4, 0, 28),
+    ]);
+}
+
+#[derive(Debug, PartialEq)]
+enum Event {
+    Data(Vec<u8>),
+    Eof,
+    Stopped(ExitStatus),
+}
+
+#[test]
+fn half_close() {
+    let events = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("sort", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        run.write_stdin(b"b\na\n").unwrap();
+        run.close_write().unwrap();
+
+        let mut events = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => events.push(Event::Data(data.to_vec())),
+                RunEvent::Eof => events.push(Event::Eof),
+                RunEvent::Stopped(exit_status) => {
+                    events.push(Event::Stopped(exit_status));
+                    break events;
+                },
+                _ => (),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"b\na\n");
+        server.expect(96);
+
+        server.send_data(channel, b"a\nb\n");
+        server.send_eof(channel);
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(events, [
+        Event::Data(b"a\nb\n".to_vec()),
+        Event::Eof,
+        Event::Stopped(ExitStatus::Code(0)),
+    ]);
+}
+
+#[test]
+fn reads_end_at_eof() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("cat file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut output = Vec::new();
+        run.read_to_end(&mut output).unwrap();
+
+        // the channel is still open
+        run.signal("TERM").unwrap();
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+        output
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send_data(channel, b"contents");
+        server.send_eof(channel);
+
+        server.expect_channel_request("signal");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"contents");
+}
+
+#[test]
+fn write_poll_only_polls_while_the_window_is_exhausted() {
+    let (callback_events, polled) = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("cat > file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // let the server's output arrive first; the timeout keeps
+        // extra polls from blocking
+        std::thread::sleep(Duration::from_millis(100));
+        conn.set_read_timeout(Some(Duration::from_millis(200))).unwrap();
+
+        // three channel packets, which fit in the window
+        let mut callback_events = 0;
+        run.write_poll(&[b'x'; 0x8000 * 2 + 1], |_| {
+            callback_events += 1;
+            Ok::<_, coolssh::Error>(())
+        }).unwrap();
+        run.close_write().unwrap();
+
+        let mut polled = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) => polled.extend_from_slice(data),
+                RunEvent::Stopped(_) => break (callback_events, polled),
+                _ => (),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"early output");
+
+        assert_eq!(server.recv_data().len(), 0x8000);
+        assert_eq!(server.recv_data().len(), 0x8000);
+        assert_eq!(server.recv_data().len(), 1);
+        server.expect(96);
+
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(callback_events, 0);
+    assert_eq!(polled, b"early output");
+}
+
+#[test]
+fn output_and_eof_received_while_writing_are_read() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("head -c 6", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // only half of it fits in the window
+        run.write_stdin(b"0123456789abcdef").unwrap();
+        run.close_write().unwrap();
+
+        let mut output = Vec::new();
+        run.read_to_end(&mut output).unwrap();
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+        output
+    }, |mut server| {
+        server.initial_window = 8;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"01234567");
+        server.send_data(channel, b"012345");
+        server.send_eof(channel);
+        server.send_window_adjust(channel, 8);
+
+        assert_eq!(server.recv_data(), b"89abcdef");
+        server.expect(96);
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"012345");
+}
+
+#[test]
+fn output_received_before_close_is_read() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("head -c 6", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        // the process exits before the server adjusts the window
+        let error = run.write_stdin(b"0123456789abcdef").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::ProcessHasExited);
+
+        let mut output = Vec::new();
+        run.read_to_end(&mut output).unwrap();
+        output
+    }, |mut server| {
+        server.initial_window = 8;
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        assert_eq!(server.recv_data(), b"01234567");
+        server.send_data(channel, b"012345");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"012345");
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use std::cell::Cell;
+use std::io::{Read, Write, Result as IoResult};
+use std::net::TcpStream;
+use coolssh::{Connection, RunResult, Stream, ExitStatus};
+use common::*;
+
+/// A stream which isn't a `TcpStream`, counting what it carries
+struct Counting {
+    inner: TcpStream,
+    read: usize,
+    written: usize,
+    /// calls to `write`
+    writes: usize,
+}
+
+impl Read for Counting {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        let len = self.inner.read(buf)?;
+        self.read += len;
+        Ok(len)
+    }
+}
+
+impl Write for Counting {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        let len = self.inner.write(buf)?;
+        self.written += len;
+        self.writes += 1;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        self.inner.flush()
+    }
+}
+
+impl Stream for Counting {}
+
+#[test]
+fn custom_stream() {
+    let (output, read, written) = scripted(|stream| {
+        let stream = Counting {
+            inner: stream,
+            read: 0,
+            written: 0,
+            writes: 0,
+        };
+
+        let mut conn = Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).unwrap();
+        let output = conn.quick_run("echo hello").unwrap();
+
+        let counters = Cell::new((0, 0));
+        conn.mutate_stream(|stream| counters.set((stream.read, stream.written)));
+        let (read, written) = counters.get();
+        (output, read, written)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"echo hello"));
+        server.channel_success(channel);
+        server.send_data(channel, b"hello\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, RunResult::Accepted(("hello\n".into(), ExitStatus::Code(0))));
+    assert!(read > 0 && written > 0);
+}
+
+#[test]
+fn channel_requests_are_coalesced() {
+    let writes = scripted(|stream| {
+        let stream = Counting {
+            inner: stream,
+            read: 0,
+            written: 0,
+            writes: 0,
+        };
+
+        let mut conn = Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).unwrap();
+        let writes = Cell::new(0);
+        conn.mutate_stream(|stream| writes.set(stream.writes));
+        let before = writes.get();
+
+        let env = [("LANG", "C"), ("TZ", "UTC")];
+        let RunResult::Accepted(run) = conn.run("date", &env).unwrap() else {
+            panic!("exec refused");
+        };
+        drop(run);
+
+        conn.mutate_stream(|stream| writes.set(stream.writes));
+        writes.get() - before
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("env");
+        server.expect_channel_request("env");
+        assert_eq!(server.expect_channel_request("exec"), string(b"date"));
+        // one reply per request
+        server.channel_success(channel);
+        server.channel_success(channel);
+        server.channel_success(channel);
+        server.expect(97);
+    });
+
+    // ChannelOpen, the requests together, then ChannelClose
+    assert_eq!(writes, 3);
+}
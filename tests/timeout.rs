@@ -0,0 +1,121 @@
+mod common;
+
+use std::time::Duration;
+use coolssh::{RunResult, RunEvent, ErrorKind, ExitStatus, WaitOutput};
+use common::*;
+
+#[test]
+fn partial_output() {
+    let output = scripted(|stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("yes", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        run.wait_with_timeout(Duration::from_millis(200)).unwrap()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"y\n");
+        server.send_stderr(channel, b"slow\n");
+
+        // sent when the Run is dropped
+        server.expect(97);
+    });
+
+    assert_eq!(output, WaitOutput {
+        stdout: b"y\n".to_vec(),
+        stderr: b"slow\n".to_vec(),
+        exit_status: None,
+    });
+}
+
+#[test]
+fn quick_run_with_timeout() {
+    let results = scripted(|stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        // ChannelClose and the next ChannelOpen are sent back to back
+        stream.set_nodelay(true).unwrap();
+        let conn = connect(stream);
+        let timeout = Duration::from_millis(200);
+        let hung = conn.quick_run_with_timeout("sleep 100", &[], timeout);
+        let done = conn.quick_run_with_timeout("echo done", &[], timeout);
+        (hung, done)
+    }, |mut server| {
+        server.accept_session();
+
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.expect(97);
+
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"done\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!((results.0.map_err(|e| e.kind()), results.1.map_err(|e| e.kind())), (
+        Err(ErrorKind::Timeout),
+        Ok(RunResult::Accepted(("done\n".into(), ExitStatus::Code(0)))),
+    ));
+}
+
+#[test]
+fn wait_event_without_read_timeout() {
+    let (events, read_timeout) = scripted(|stream| {
+        let conn = connect(stream.try_clone().unwrap());
+        let RunResult::Accepted(mut run) = conn.run("sleep 1; echo done", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut events = Vec::new();
+        loop {
+            match run.wait_event(Some(Duration::from_millis(50))).unwrap() {
+                RunEvent::None => events.push("none"),
+                RunEvent::Data(_) => events.push("data"),
+                RunEvent::Stopped(_) => break,
+                _ => (),
+            }
+        }
+
+        (events, stream.read_timeout().unwrap())
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        std::thread::sleep(Duration::from_millis(200));
+        server.send_data(channel, b"done\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(events.first(), Some(&"none"));
+    assert!(events.contains(&"data"));
+    assert_eq!(read_timeout, None);
+}
+
+#[test]
+fn quick_run_with_timeout_without_read_timeout() {
+    let result = scripted(|stream| {
+        let conn = connect(stream);
+        conn.quick_run_with_timeout("sleep 100", &[], Duration::from_millis(100))
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::Timeout));
+}
@@ -0,0 +1,98 @@
+#![cfg(feature = "tokio")]
+
+mod common;
+
+use coolssh::{AsyncConnection, RunResult, RunEvent, ExitStatus};
+use common::*;
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread().enable_io().build().unwrap()
+}
+
+#[test]
+fn quick_run() {
+    let output = scripted(|stream| {
+        stream.set_nonblocking(true).unwrap();
+        runtime().block_on(async move {
+            // spawned: the futures must be Send
+            tokio::spawn(async move {
+                let stream = tokio::net::TcpStream::from_std(stream).unwrap();
+                let conn = AsyncConnection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).await.unwrap();
+                conn.quick_run("echo hello").await.unwrap()
+            }).await.unwrap()
+        })
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"echo hello"));
+        server.channel_success(channel);
+        server.send_data(channel, b"hello\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, RunResult::Accepted(("hello\n".into(), ExitStatus::Code(0))));
+}
+
+#[test]
+fn concurrent_runs() {
+    let (first, second) = scripted(|stream| {
+        stream.set_nonblocking(true).unwrap();
+        runtime().block_on(async move {
+            let stream = tokio::net::TcpStream::from_std(stream).unwrap();
+            let conn = AsyncConnection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).await.unwrap();
+
+            let RunResult::Accepted(mut first) = conn.run("cat", &[]).await.unwrap() else {
+                panic!("exec refused");
+            };
+            let RunResult::Accepted(mut second) = conn.run("true", &[]).await.unwrap() else {
+                panic!("exec refused");
+            };
+
+            first.write_stdin(b"ping").await.unwrap();
+            first.close_write().await.unwrap();
+
+            // the output of the first one arrives meanwhile
+            let second_status = loop {
+                if let RunEvent::Stopped(status) = second.poll().await.unwrap() {
+                    break status;
+                }
+            };
+            drop(second);
+
+            let mut output = Vec::new();
+            loop {
+                match first.poll().await.unwrap() {
+                    RunEvent::Data(data) => output.extend_from_slice(data),
+                    RunEvent::Stopped(_) => break (output, second_status),
+                    _ => (),
+                }
+            }
+        })
+    }, |mut server| {
+        server.accept_session();
+
+        let (first, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"cat"));
+        server.channel_success(first);
+
+        let (second, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"true"));
+        server.channel_success(second);
+
+        assert_eq!(server.recv_data(), b"ping");
+        server.expect(96);
+
+        server.send_exit_status(second, 3);
+        server.send_data(first, b"ping");
+        server.send_close(second);
+        server.expect(97);
+
+        server.send_close(first);
+        server.expect(97);
+    });
+
+    assert_eq!(first, b"ping");
+    assert_eq!(second, ExitStatus::Code(3));
+}
@@ -0,0 +1,88 @@
+mod common;
+
+use std::io::{Result as IoResult, Write};
+use std::sync::{Arc, Mutex};
+use coolssh::{RunResult, ExitStatus};
+use common::*;
+
+#[derive(Clone, Default)]
+struct Sink(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+impl Sink {
+    /// The recorded lines, checking then stripping their timestamp
+    fn entries(&self) -> Vec<String> {
+        let transcript = String::from_utf8(self.0.lock().unwrap().clone()).unwrap();
+        transcript.lines().map(|line| {
+            let (timestamp, entry) = line.split_once(' ').unwrap();
+            let bytes = timestamp.as_bytes();
+            assert_eq!((bytes.len(), bytes[4], bytes[10], bytes[19], bytes[23]), (24, b'-', b'T', b'.', b'Z'), "{}", timestamp);
+            assert!(timestamp.starts_with("20"), "{}", timestamp);
+            entry.to_string()
+        }).collect()
+    }
+}
+
+#[test]
+fn commands_are_recorded() {
+    let sink = Sink::default();
+    let transcript = sink.clone();
+
+    scripted(move |stream| {
+        let conn = connect(stream);
+        conn.set_transcript(sink);
+
+        let RunResult::Accepted((output, exit_status)) = conn.quick_run("uptime").unwrap() else {
+            panic!("exec refused");
+        };
+        assert_eq!((output.as_str(), exit_status), ("up 3 days\nwarn", ExitStatus::Code(0)));
+
+        assert!(matches!(conn.quick_run("reboot").unwrap(), RunResult::Refused));
+
+        let RunResult::Accepted(mut run) = conn.run("cat > file", &[("SECRET", "hunter2")]).unwrap() else {
+            panic!("exec refused");
+        };
+        run.write_stdin(b"hello").unwrap();
+        drop(run);
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"up 3 days\n");
+        server.send_stderr(channel, b"warn");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_failure(channel);
+        server.expect(97);
+
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("env");
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.channel_success(channel);
+        assert_eq!(server.recv_data(), b"hello");
+        server.expect(97);
+    });
+
+    assert_eq!(transcript.entries(), [
+        "channel 0: exec \"uptime\"",
+        "channel 0: exited with Code(0), sent 0 bytes, received 14 bytes",
+        "channel 1: exec \"reboot\" refused",
+        "channel 2: exec \"cat > file\"",
+        "channel 2: closed, sent 5 bytes, received 0 bytes",
+    ]);
+}
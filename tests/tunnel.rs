@@ -0,0 +1,73 @@
+mod common;
+
+use std::io::{Read, Write, ErrorKind as IoErrorKind};
+use coolssh::{RunResult, ErrorKind, ConnectOptions};
+use common::*;
+
+/// Receives a direct-tcpip ChannelOpen; returns the client
+/// channel and the requested host and port
+fn expect_direct_tcpip(server: &mut Server) -> (u32, Vec<u8>, u32) {
+    let open = server.expect(90);
+    let mut i = 1;
+    assert_eq!(take_string(&open, &mut i), b"direct-tcpip");
+    let client_channel = take_u32(&open, &mut i);
+    let _window = take_u32(&open, &mut i);
+    let _max_packet = take_u32(&open, &mut i);
+    let host = take_string(&open, &mut i).to_vec();
+    let port = take_u32(&open, &mut i);
+    (client_channel, host, port)
+}
+
+#[test]
+fn tunnel_stream() {
+    let received = scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut tunnel) = conn.direct_tcpip("10.0.0.2", 22).unwrap() else {
+            panic!("direct-tcpip refused");
+        };
+
+        tunnel.write_all(b"SSH-2.0-coolssh\r\n").unwrap();
+        let mut received = String::new();
+        tunnel.read_to_string(&mut received).unwrap();
+        received
+    }, |mut server| {
+        server.accept_session();
+        let (channel, host, port) = expect_direct_tcpip(&mut server);
+        assert_eq!((host.as_slice(), port), (b"10.0.0.2".as_slice(), 22));
+
+        let mut confirmation = vec![91];
+        confirmation.extend_from_slice(&channel.to_be_bytes());
+        confirmation.extend_from_slice(&SERVER_CHANNEL.to_be_bytes());
+        confirmation.extend_from_slice(&0x10000u32.to_be_bytes());
+        confirmation.extend_from_slice(&0x8000u32.to_be_bytes());
+        server.send(&confirmation);
+
+        assert_eq!(server.recv_data(), b"SSH-2.0-coolssh\r\n");
+        server.send_data(channel, b"SSH-2.0-OpenSSH_9.6\r\n");
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(received, "SSH-2.0-OpenSSH_9.6\r\n");
+}
+
+#[test]
+fn jump_refused() {
+    let error = scripted(|stream| {
+        let conn = connect(stream);
+        conn.jump("10.0.0.2", 22, ("user", CLIENT_HEX_KEYPAIR).into(), ConnectOptions::default()).unwrap_err()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _, _) = expect_direct_tcpip(&mut server);
+
+        // SSH_OPEN_CONNECT_FAILED
+        let mut failure = vec![92];
+        failure.extend_from_slice(&channel.to_be_bytes());
+        failure.extend_from_slice(&2u32.to_be_bytes());
+        failure.extend(string(b"Connection refused"));
+        failure.extend(string(b""));
+        server.send(&failure);
+    });
+
+    assert_eq!(error.kind(), ErrorKind::TcpError(IoErrorKind::ConnectionRefused));
+}
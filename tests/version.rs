@@ -0,0 +1,60 @@
+mod common;
+
+use std::io::ErrorKind as IoErrorKind;
+use std::net::{SocketAddr, TcpStream};
+use coolssh::{Connection, ErrorKind};
+use common::*;
+
+fn try_connect(addr: SocketAddr) -> Result<(), ErrorKind> {
+    let stream = TcpStream::connect(addr).unwrap();
+    Connection::new(stream, ("user", CLIENT_HEX_KEYPAIR).into()).map(drop).map_err(|e| e.kind())
+}
+
+/// Sends `greeting`, then hangs up
+fn greet(greeting: Vec<u8>) -> Result<(), ErrorKind> {
+    scripted_listener(try_connect, |listener| drop(Server::accept_greeting(&listener, &greeting)))
+}
+
+#[test]
+fn pre_version_lines() {
+    let (lines, version) = scripted_listener(|addr| {
+        let conn = connect(TcpStream::connect(addr).unwrap());
+        (conn.pre_version_lines(), conn.server_version())
+    }, |listener| {
+        // LF alone ends lines too, the version's included
+        let greeting = b"Welcome to\r\n\r\n  the \xffbox\nSSH-2.0-ScriptedServer\n";
+        Server::accept_greeting(&listener, greeting).accept_session();
+    });
+
+    assert_eq!(lines, ["Welcome to", "", "  the \u{fffd}box"]);
+    assert_eq!(version, "SSH-2.0-ScriptedServer");
+}
+
+#[test]
+fn too_many_lines() {
+    let greeting = b"motd\r\n".repeat(1025);
+    assert_eq!(greet(greeting), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn line_too_long() {
+    let mut greeting = vec![b'#'; 10000];
+    greeting.extend_from_slice(b"\r\nSSH-2.0-ScriptedServer\r\n");
+    assert_eq!(greet(greeting), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn version_too_long() {
+    let greeting = format!("SSH-2.0-{}\r\n", "x".repeat(250)).into_bytes();
+    assert_eq!(greet(greeting), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn unsupported_version() {
+    assert_eq!(greet(b"SSH-1.5-Legacy\r\n".to_vec()), Err(ErrorKind::Unimplemented));
+}
+
+#[test]
+fn closed_before_version() {
+    assert_eq!(greet(b"Go away\r\n".to_vec()), Err(ErrorKind::TcpError(IoErrorKind::UnexpectedEof)));
+}
@@ -0,0 +1,181 @@
+#![cfg(feature = "websocket")]
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream, SocketAddr};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use sha1::{Sha1, Digest};
+use coolssh::{Connection, WebSocket, RunResult, ExitStatus, Error, ErrorKind};
+use common::*;
+
+/// Answers the handshake of a WebSocket client with `status` and
+/// `accept` (by default, the right Sec-WebSocket-Accept value)
+fn accept_websocket(stream: &mut TcpStream, status: &str, accept: Option<&str>) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut key = String::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        if line == "\r\n" {
+            break;
+        }
+
+        if let Some(value) = line.strip_prefix("Sec-WebSocket-Key:") {
+            key = value.trim().into();
+        }
+    }
+
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(b"258EAFA5-E914-47DA-95CA-C5AB0DC11B85");
+    let expected = STANDARD.encode(hasher.finalize());
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        status,
+        accept.unwrap_or(&expected),
+    );
+    stream.write_all(response.as_bytes()).unwrap();
+}
+
+/// Unmasks the binary frames of the client and forwards their payload
+fn unframe(mut websocket: TcpStream, mut tcp: TcpStream) {
+    loop {
+        let mut header = [0; 2];
+        if websocket.read_exact(&mut header).is_err() {
+            break;
+        }
+
+        assert!(matches!(header[0], 0x82 | 0x88 | 0x8a), "unfragmented binary frames, pongs and close frames only");
+        assert_eq!(header[1] & 0x80, 0x80, "clients must mask their frames");
+        let length = match header[1] & 0x7f {
+            126 => {
+                let mut extended = [0; 2];
+                websocket.read_exact(&mut extended).unwrap();
+                u16::from_be_bytes(extended) as usize
+            },
+            127 => {
+                let mut extended = [0; 8];
+                websocket.read_exact(&mut extended).unwrap();
+                u64::from_be_bytes(extended) as usize
+            },
+            length => length as usize,
+        };
+
+        let mut mask = [0; 4];
+        websocket.read_exact(&mut mask).unwrap();
+        let mut payload = vec![0; length];
+        websocket.read_exact(&mut payload).unwrap();
+        payload.iter_mut().zip(mask.iter().cycle()).for_each(|(byte, mask)| *byte ^= mask);
+
+        match header[0] {
+            0x8a => {
+                assert_eq!(payload, b"ping");
+                continue;
+            },
+            0x88 => break,
+            _ => (),
+        }
+
+        if tcp.write_all(&payload).is_err() {
+            break;
+        }
+    }
+}
+
+/// Sends what the server writes as (unmasked) binary frames, split
+/// in two and preceded by a ping, to exercise the client's parser
+fn frame(mut tcp: TcpStream, mut websocket: TcpStream) {
+    let mut buffer = [0; 4096];
+    loop {
+        let len = match tcp.read(&mut buffer) {
+            Ok(0) | Err(_) => break,
+            Ok(len) => len,
+        };
+
+        let mut frames = vec![0x89, 4];
+        frames.extend_from_slice(b"ping");
+
+        let (first, second) = buffer[..len].split_at(len / 2);
+        for (opcode, chunk) in [(0x02, first), (0x80, second)] {
+            frames.push(opcode);
+            match chunk.len() {
+                len @ 0..=125 => frames.push(len as u8),
+                len => {
+                    frames.push(126);
+                    frames.extend_from_slice(&(len as u16).to_be_bytes());
+                },
+            }
+            frames.extend_from_slice(chunk);
+        }
+
+        if websocket.write_all(&frames).is_err() {
+            break;
+        }
+    }
+
+    let _ = websocket.write_all(&[0x88, 0]);
+}
+
+/// Relays a WebSocket client to the plain TCP server at `target`
+fn gateway(target: SocketAddr) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        let (mut websocket, _) = listener.accept().unwrap();
+        accept_websocket(&mut websocket, "101 Switching Protocols", None);
+
+        let tcp = TcpStream::connect(target).unwrap();
+        let (websocket_reader, tcp_writer) = (websocket.try_clone().unwrap(), tcp.try_clone().unwrap());
+        std::thread::spawn(move || unframe(websocket_reader, tcp_writer));
+        frame(tcp, websocket);
+    });
+
+    addr
+}
+
+#[test]
+fn run_over_websocket() {
+    let output = scripted_at(|addr| {
+        let stream = TcpStream::connect(gateway(addr)).unwrap();
+        let websocket = WebSocket::handshake(stream, "gateway.example", "/ssh").unwrap();
+        let conn = Connection::new(websocket, ("user", CLIENT_HEX_KEYPAIR).into()).unwrap();
+        conn.quick_run("echo hello").unwrap()
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.expect_channel_request("exec"), string(b"echo hello"));
+        server.channel_success(channel);
+        server.send_data(channel, b"hello\n");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, RunResult::Accepted(("hello\n".into(), ExitStatus::Code(0))));
+}
+
+fn handshake_with(status: &'static str, accept: Option<&'static str>) -> Error {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let gateway = std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        accept_websocket(&mut stream, status, accept);
+    });
+
+    let stream = TcpStream::connect(addr).unwrap();
+    let result = WebSocket::handshake(stream, "gateway.example", "/ssh");
+    gateway.join().unwrap();
+    result.unwrap_err()
+}
+
+#[test]
+fn refused_upgrade() {
+    assert_eq!(handshake_with("404 Not Found", None).kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn wrong_accept_key() {
+    assert_eq!(handshake_with("101 Switching Protocols", Some("bm90IHRoZSByaWdodCBrZXk=")).kind(), ErrorKind::InvalidData);
+}
@@ -0,0 +1,156 @@
+mod common;
+
+use coolssh::{RunResult, RunOptions, RunEvent, ErrorKind};
+use common::*;
+
+#[test]
+fn default_window() {
+    scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(_run) = conn.run("true", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.client_window, (0x200000, 0x8000));
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+    });
+}
+
+#[test]
+fn configured_window_is_adjusted() {
+    let output = scripted(|stream| {
+        let conn = connect(stream);
+        conn.set_channel_window(16, 8).unwrap();
+        let RunResult::Accepted(mut run) = conn.run("cat file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let mut output = Vec::new();
+        loop {
+            match run.poll().unwrap() {
+                RunEvent::Data(data) | RunEvent::ExtDataStderr(data) => output.extend_from_slice(data),
+                RunEvent::Stopped(_) => break output,
+                _ => (),
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.client_window, (16, 8));
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send_data(channel, b"01234567");
+        // stderr uses the same window
+        server.send_stderr(channel, b"89abcd");
+
+        let adjust = server.expect(93);
+        let mut i = 1;
+        assert_eq!(take_u32(&adjust, &mut i), SERVER_CHANNEL);
+        assert_eq!(take_u32(&adjust, &mut i), 14);
+
+        server.send_data(channel, b"ef");
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(output, b"0123456789abcdef");
+}
+
+#[test]
+fn window_overrun_is_refused() {
+    let result = scripted(|stream| {
+        let conn = connect(stream);
+        conn.set_channel_window(16, 16).unwrap();
+        let RunResult::Accepted(mut run) = conn.run("cat file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        loop {
+            if let Err(error) = run.poll() {
+                break error;
+            }
+        }
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send_data(channel, b"0123456789");
+        server.send_data(channel, b"0123456789");
+    });
+
+    assert_eq!(result.kind(), ErrorKind::InvalidData);
+}
+
+#[test]
+fn oversized_packet_is_refused() {
+    let result = scripted(|stream| {
+        let conn = connect(stream);
+        conn.set_channel_window(1024, 8).unwrap();
+        let RunResult::Accepted(mut run) = conn.run("cat file", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        run.poll().map(drop)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        server.send_data(channel, b"0123456789");
+    });
+
+    assert_eq!(result.map_err(|e| e.kind()), Err(ErrorKind::InvalidData));
+}
+
+#[test]
+fn per_run_window() {
+    let (output, overrun) = scripted(|stream| {
+        let conn = connect(stream);
+        conn.set_channel_window(1024, 512).unwrap();
+
+        // only the window is overridden
+        let options = RunOptions::default().window(16);
+        let RunResult::Accepted((output, _)) = conn.quick_run("cat file").unwrap() else {
+            panic!("exec refused");
+        };
+
+        let RunResult::Accepted(mut run) = conn.run_with_options("cat file", &[], options.max_packet(8)).unwrap() else {
+            panic!("exec refused");
+        };
+
+        let overrun = loop {
+            if let Err(error) = run.poll() {
+                break error;
+            }
+        };
+
+        (output, overrun)
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.client_window, (1024, 512));
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, &[b'0'; 100]);
+        server.send_exit_status(channel, 0);
+        server.send_close(channel);
+        server.expect(97);
+
+        let (channel, _) = server.accept_channel();
+        assert_eq!(server.client_window, (16, 8));
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+        server.send_data(channel, b"0123456789");
+    });
+
+    assert_eq!(output.len(), 100);
+    assert_eq!(overrun.kind(), ErrorKind::InvalidData);
+}
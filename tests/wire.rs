@@ -0,0 +1,59 @@
+use coolssh::wire::{ParseDump, NameList, UnsignedMpInt, Blob, ChannelData, Message, MessageType};
+
+#[test]
+fn name_lists() {
+    let mut buffer = Vec::new();
+    NameList("curve25519-sha256,ext-info-c").dump(&mut buffer).unwrap();
+    let (list, len) = NameList::parse(&buffer).unwrap();
+    assert_eq!(len, buffer.len());
+    assert_eq!(list.iter().collect::<Vec<_>>(), ["curve25519-sha256", "ext-info-c"]);
+    assert!(!list.contains("curve25519"));
+
+    assert_eq!(NameList::parse(&[0, 0, 0, 0]).unwrap().0.iter().count(), 0);
+    for invalid in [&b"a,,b"[..], b"a,", b"caf\xc3\xa9", b"a b"] {
+        let mut buffer = (invalid.len() as u32).to_be_bytes().to_vec();
+        buffer.extend(invalid);
+        assert!(NameList::parse(&buffer).is_err(), "{:?}", invalid);
+    }
+}
+
+#[test]
+fn mpints_are_canonical() {
+    let mut buffer = Vec::new();
+    UnsignedMpInt(&[0, 0, 0x7f, 1]).dump(&mut buffer).unwrap();
+    UnsignedMpInt(&[0x80]).dump(&mut buffer).unwrap();
+    UnsignedMpInt(&[0, 0]).dump(&mut buffer).unwrap();
+    assert_eq!(buffer, [0, 0, 0, 2, 0x7f, 1, 0, 0, 0, 2, 0, 0x80, 0, 0, 0, 0]);
+}
+
+#[test]
+fn blobs_and_messages() {
+    let mut buffer = Vec::new();
+    Blob { header: "ssh-ed25519", content: b"signature" }.dump(&mut buffer).unwrap();
+    let (blob, len) = Blob::parse(&buffer).unwrap();
+    assert_eq!((blob.header, blob.content, len), ("ssh-ed25519", &b"signature"[..], buffer.len()));
+
+    let mut payload = Vec::new();
+    ChannelData { recipient_channel: 3, data: b"hello" }.dump(&mut payload).unwrap();
+    assert_eq!(payload[0], MessageType::ChannelData as u8);
+
+    let (message, _) = Message::parse(&payload).unwrap();
+    let Message::ChannelData(data) = message else {
+        panic!("{:?}", message);
+    };
+
+    assert_eq!((data.recipient_channel, data.data), (3, &b"hello"[..]));
+}
+
+#[test]
+fn name_list_preferences() {
+    let ours = NameList("chacha20-poly1305@openssh.com,aes256-gcm@openssh.com,aes256-ctr");
+    let theirs = NameList("aes128-ctr,aes256-ctr,aes256-gcm@openssh.com");
+
+    assert_eq!(ours.first_common(theirs), Some("aes256-gcm@openssh.com"));
+    assert_eq!(theirs.first_common(ours), Some("aes256-ctr"));
+    assert_eq!(ours.intersection(theirs).collect::<Vec<_>>(), ["aes256-gcm@openssh.com", "aes256-ctr"]);
+    assert_eq!(ours.first_common(NameList("none")), None);
+    assert_eq!(NameList("").first_common(theirs), None);
+    assert_eq!(theirs.to_string(), theirs.as_str());
+}
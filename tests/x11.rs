@@ -0,0 +1,116 @@
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+use coolssh::{RunResult, RunEvent, X11Forwarding, LocalStream};
+use common::*;
+
+const X11_CHANNEL: u32 = 7;
+
+#[test]
+fn relay_x11_connection() {
+    // stands for the local X server
+    let display = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = display.local_addr().unwrap();
+    let x_server = std::thread::spawn(move || {
+        let (mut stream, _) = display.accept().unwrap();
+        let mut request = [0; 5];
+        stream.read_exact(&mut request).unwrap();
+        stream.write_all(b"reply").unwrap();
+        request
+    });
+
+    scripted(move |stream| {
+        stream.set_read_timeout(Some(Duration::from_millis(20))).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let conn = connect(stream);
+        let x11 = X11Forwarding::new("MIT-MAGIC-COOKIE-1", "00112233")
+            .screen(1)
+            .connector(move || Ok(Box::new(TcpStream::connect(addr)?) as Box<dyn LocalStream>));
+        conn.set_x11_forwarding(Some(x11));
+
+        let RunResult::Accepted(mut run) = conn.run("xeyes", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+
+        let mut expected = vec![0];
+        expected.extend(string(b"MIT-MAGIC-COOKIE-1"));
+        expected.extend(string(b"00112233"));
+        expected.extend_from_slice(&1u32.to_be_bytes());
+        assert_eq!(server.expect_channel_request("x11-req"), expected);
+
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        let mut open = vec![90];
+        open.extend(string(b"x11"));
+        open.extend_from_slice(&X11_CHANNEL.to_be_bytes());
+        open.extend_from_slice(&0x10000u32.to_be_bytes());
+        open.extend_from_slice(&0x8000u32.to_be_bytes());
+        open.extend(string(b"127.0.0.1"));
+        open.extend_from_slice(&40000u32.to_be_bytes());
+        server.send(&open);
+
+        let confirmation = server.expect(91);
+        let mut i = 1;
+        assert_eq!(take_u32(&confirmation, &mut i), X11_CHANNEL);
+        let x11_channel = take_u32(&confirmation, &mut i);
+
+        server.send_data(x11_channel, b"setup");
+        let message = server.expect(94);
+        let mut i = 1;
+        assert_eq!(take_u32(&message, &mut i), X11_CHANNEL);
+        assert_eq!(take_string(&message, &mut i), b"reply");
+
+        // the X server closed the connection
+        server.expect(96);
+        server.expect(97);
+        server.send_close(x11_channel);
+
+        server.send_close(channel);
+        server.expect(97);
+    });
+
+    assert_eq!(&x_server.join().unwrap(), b"setup");
+}
+
+#[test]
+fn reject_unrequested_channels() {
+    scripted(|stream| {
+        let conn = connect(stream);
+        let RunResult::Accepted(mut run) = conn.run("xeyes", &[]).unwrap() else {
+            panic!("exec refused");
+        };
+
+        while !matches!(run.poll().unwrap(), RunEvent::Stopped(_)) {}
+    }, |mut server| {
+        server.accept_session();
+        let (channel, _) = server.accept_channel();
+        server.expect_channel_request("exec");
+        server.channel_success(channel);
+
+        let mut open = vec![90];
+        open.extend(string(b"x11"));
+        open.extend_from_slice(&X11_CHANNEL.to_be_bytes());
+        open.extend_from_slice(&0x10000u32.to_be_bytes());
+        open.extend_from_slice(&0x8000u32.to_be_bytes());
+        open.extend(string(b"127.0.0.1"));
+        open.extend_from_slice(&40000u32.to_be_bytes());
+        server.send(&open);
+
+        let failure = server.expect(92);
+        let mut i = 1;
+        assert_eq!(take_u32(&failure, &mut i), X11_CHANNEL);
+        // SSH_OPEN_ADMINISTRATIVELY_PROHIBITED
+        assert_eq!(take_u32(&failure, &mut i), 1);
+
+        server.send_close(channel);
+        server.expect(97);
+    });
+}